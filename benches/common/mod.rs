@@ -0,0 +1,81 @@
+//! Helpers shared by the benchmarks.
+use std::time::{Duration, Instant};
+use jaegercat::model::{Batch, Log, Process, Span, SpanId, Tag, TraceId, UnknownFields};
+
+const ITERATIONS: u32 = 2000;
+
+/// Runs `f` repeatedly and prints the time per iteration and the throughput,
+/// where `bytes` is the size of the data processed by each iteration.
+pub fn bench<F>(name: &str, bytes: usize, mut f: F)
+where
+    F: FnMut() -> usize,
+{
+    let mut spans = 0;
+    for _ in 0..ITERATIONS / 10 {
+        spans += f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        spans += f();
+    }
+    let elapsed = start.elapsed();
+    let per_iter = duration_to_ns(elapsed) / u64::from(ITERATIONS);
+    let mb_per_sec = bytes as f64 * f64::from(ITERATIONS) / duration_to_secs(elapsed) / 1e6;
+    println!(
+        "{:<24} {:>10} ns/iter {:>10.1} MB/s ({} spans)",
+        name, per_iter, mb_per_sec, spans
+    );
+}
+
+fn duration_to_ns(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000_000 + u64::from(d.subsec_nanos())
+}
+
+fn duration_to_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// Makes a batch of `spans` spans with tags and logs.
+pub fn sample_batch(spans: usize) -> Batch {
+    let start_time = 1_517_376_258_654_844;
+    Batch {
+        process: Process {
+            service_name: "bench".to_owned(),
+            tags: vec![
+                Tag::new("hostname", "localhost"),
+                Tag::new("jaeger.version", "Go-2.11.2"),
+            ],
+            unknown_fields: UnknownFields::new(),
+        },
+        spans: (0..spans as i64)
+            .map(|i| Span {
+                trace_id: TraceId {
+                    high: 0,
+                    low: 0x2ae6_4ad7_cd07_0e8e,
+                },
+                span_id: SpanId(0x07a5_fd20_7c06_5420 + i),
+                parent_span_id: Some(SpanId(0x07a5_fd20_7c06_5420)),
+                operation_name: format!("operation-{}", i % 10),
+                references: Vec::new(),
+                flags: 1,
+                start_time: start_time + i * 1000,
+                duration: 10_196,
+                tags: vec![
+                    Tag::new("http.method", "GET"),
+                    Tag::new("http.status_code", 200),
+                    Tag::new("error", false),
+                    Tag::new("sampler.param", 0.001),
+                ],
+                logs: vec![Log {
+                    timestamp: start_time + i * 1000 + 57,
+                    fields: vec![Tag::new("event", "cache miss")],
+                    unknown_fields: UnknownFields::new(),
+                }],
+                unknown_fields: UnknownFields::new(),
+            })
+            .collect(),
+        seq_no: None,
+        stats: None,
+        unknown_fields: UnknownFields::new(),
+    }
+}
@@ -0,0 +1,31 @@
+//! Decoding benchmarks.
+//!
+//! Run with `cargo bench`. Each benchmark decodes a batch of 100 spans
+//! (with tags and logs) encoded in the compact and the binary protocols.
+extern crate jaegercat;
+
+use jaegercat::borrowed::decode_borrowed;
+use jaegercat::thrift::{EmitBatchNotification, Protocol};
+
+use common::{bench, sample_batch};
+
+mod common;
+
+fn main() {
+    let message = EmitBatchNotification {
+        batch: sample_batch(100),
+    };
+    for &protocol in &[Protocol::Compact, Protocol::Binary] {
+        let bytes = message.encode(protocol);
+        bench(&format!("decode_borrowed/{:?}", protocol), bytes.len(), || {
+            decode_borrowed(&bytes, protocol).unwrap().spans.len()
+        });
+        bench(&format!("decode/{:?}", protocol), bytes.len(), || {
+            EmitBatchNotification::decode(&bytes, protocol)
+                .unwrap()
+                .batch
+                .spans
+                .len()
+        });
+    }
+}
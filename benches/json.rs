@@ -0,0 +1,29 @@
+//! JSON serialization benchmarks.
+//!
+//! Run with `cargo bench`. Each benchmark serializes a batch of 100 spans
+//! (with tags and logs) into a reused buffer.
+extern crate jaegercat;
+extern crate serde_json;
+
+use jaegercat::json::JsonWriter;
+
+use common::{bench, sample_batch};
+
+mod common;
+
+fn main() {
+    let batch = sample_batch(100);
+    let json_len = serde_json::to_vec(&batch).unwrap().len();
+    let mut out = Vec::new();
+    bench("serde_json", json_len, || {
+        out.clear();
+        serde_json::to_writer(&mut out, &batch).unwrap();
+        batch.spans.len()
+    });
+    let mut writer = JsonWriter::new();
+    bench("json_writer", json_len, || {
+        out.clear();
+        writer.write_batch(&mut out, &batch).unwrap();
+        batch.spans.len()
+    });
+}
@@ -0,0 +1,69 @@
+/*
+ * C bindings of the jaegercat decoder.
+ *
+ * Strings returned by this library must be released by jaegercat_string_free(),
+ * and batch handles by jaegercat_batch_free().
+ */
+#ifndef JAEGERCAT_H
+#define JAEGERCAT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define JAEGERCAT_OK 0
+#define JAEGERCAT_ERROR_INVALID_ARGUMENT 1
+#define JAEGERCAT_ERROR_TRUNCATED 2
+#define JAEGERCAT_ERROR_MALFORMED 3
+#define JAEGERCAT_ERROR_SERIALIZE 4
+#define JAEGERCAT_ERROR_PANIC 5 /* a bug of the library */
+
+#define JAEGERCAT_PROTOCOL_COMPACT 0
+#define JAEGERCAT_PROTOCOL_BINARY 1
+
+/* An opaque handle of a decoded batch. */
+typedef struct JaegercatBatch JaegercatBatch;
+
+/*
+ * A span of a decoded batch.
+ *
+ * `operation_name` is not NUL-terminated, and is valid until the batch is released.
+ */
+typedef struct JaegercatSpan {
+    int64_t trace_id_high;
+    int64_t trace_id_low;
+    int64_t span_id;
+    int64_t parent_span_id; /* 0 if the span is a root span */
+    const uint8_t *operation_name;
+    size_t operation_name_len;
+    int32_t flags;
+    int64_t start_time; /* microseconds since the UNIX epoch */
+    int64_t duration;   /* microseconds */
+    size_t tag_count;
+    size_t log_count;
+} JaegercatSpan;
+
+int jaegercat_decode(const uint8_t *buf, size_t len, int protocol, JaegercatBatch **out_batch);
+
+int jaegercat_decode_to_json(const uint8_t *buf, size_t len, int protocol, char **out_json);
+
+int jaegercat_batch_to_json(const JaegercatBatch *batch, char **out_json);
+
+const uint8_t *jaegercat_batch_service_name(const JaegercatBatch *batch, size_t *out_len);
+
+size_t jaegercat_batch_span_count(const JaegercatBatch *batch);
+
+int jaegercat_batch_span(const JaegercatBatch *batch, size_t index, JaegercatSpan *out_span);
+
+void jaegercat_batch_free(JaegercatBatch *batch);
+
+void jaegercat_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JAEGERCAT_H */
@@ -0,0 +1,350 @@
+//! C bindings of the `jaegercat` decoder.
+//!
+//! The declarations of the functions and types are in `include/jaegercat.h`.
+//!
+//! A message can be decoded either to JSON (`jaegercat_decode_to_json`),
+//! or to an opaque handle whose contents are read through accessor functions
+//! (`jaegercat_decode` and `jaegercat_batch_*`).
+//! Strings returned by this library must be released by `jaegercat_string_free`,
+//! and handles by `jaegercat_batch_free`.
+//!
+//! Panics (i.e., bugs of this library) do not unwind into the caller, which would abort
+//! the process; the functions return `JAEGERCAT_ERROR_PANIC` (or null or zero) instead.
+extern crate jaegercat;
+extern crate serdeconv;
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use jaegercat::DecodeError;
+use jaegercat::thrift::{EmitBatchNotification, Protocol};
+
+/// The function succeeded.
+pub const JAEGERCAT_OK: c_int = 0;
+
+/// A null pointer, an unknown protocol or an out of range index was given.
+pub const JAEGERCAT_ERROR_INVALID_ARGUMENT: c_int = 1;
+
+/// The message ended unexpectedly.
+pub const JAEGERCAT_ERROR_TRUNCATED: c_int = 2;
+
+/// The message is malformed or is not an `emitBatch` message.
+pub const JAEGERCAT_ERROR_MALFORMED: c_int = 3;
+
+/// The batch could not be converted to JSON.
+pub const JAEGERCAT_ERROR_SERIALIZE: c_int = 4;
+
+/// The library panicked (which is a bug of the library).
+pub const JAEGERCAT_ERROR_PANIC: c_int = 5;
+
+/// The Thrift compact protocol.
+pub const JAEGERCAT_PROTOCOL_COMPACT: c_int = 0;
+
+/// The Thrift binary protocol.
+pub const JAEGERCAT_PROTOCOL_BINARY: c_int = 1;
+
+/// An opaque handle of a decoded batch.
+#[derive(Debug)]
+pub struct JaegercatBatch(EmitBatchNotification);
+
+/// A span of a decoded batch.
+///
+/// `operation_name` is not NUL-terminated, and is valid until the batch is released.
+#[repr(C)]
+#[derive(Debug)]
+pub struct JaegercatSpan {
+    pub trace_id_high: i64,
+    pub trace_id_low: i64,
+    pub span_id: i64,
+
+    /// `0` if the span is a root span.
+    pub parent_span_id: i64,
+
+    pub operation_name: *const u8,
+    pub operation_name_len: usize,
+    pub flags: i32,
+
+    /// Microseconds since the UNIX epoch.
+    pub start_time: i64,
+
+    /// Microseconds.
+    pub duration: i64,
+
+    pub tag_count: usize,
+    pub log_count: usize,
+}
+
+/// Decodes an `emitBatch` message.
+///
+/// On success, `*out_batch` is set to a handle which must be released by `jaegercat_batch_free`.
+///
+/// # Safety
+///
+/// `buf` must point to `len` readable bytes, and `out_batch` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_decode(
+    buf: *const u8,
+    len: usize,
+    protocol: c_int,
+    out_batch: *mut *mut JaegercatBatch,
+) -> c_int {
+    catch_panic(JAEGERCAT_ERROR_PANIC, || {
+        if out_batch.is_null() {
+            return JAEGERCAT_ERROR_INVALID_ARGUMENT;
+        }
+        match decode(buf, len, protocol) {
+            Ok(message) => {
+                *out_batch = Box::into_raw(Box::new(JaegercatBatch(message)));
+                JAEGERCAT_OK
+            }
+            Err(code) => code,
+        }
+    })
+}
+
+/// Decodes an `emitBatch` message, and converts it to the JSON format of `jaegercat --format json`.
+///
+/// On success, `*out_json` is set to a NUL-terminated string which must be released by
+/// `jaegercat_string_free`.
+///
+/// # Safety
+///
+/// `buf` must point to `len` readable bytes, and `out_json` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_decode_to_json(
+    buf: *const u8,
+    len: usize,
+    protocol: c_int,
+    out_json: *mut *mut c_char,
+) -> c_int {
+    catch_panic(JAEGERCAT_ERROR_PANIC, || {
+        if out_json.is_null() {
+            return JAEGERCAT_ERROR_INVALID_ARGUMENT;
+        }
+        match decode(buf, len, protocol).and_then(|message| to_json(&message)) {
+            Ok(json) => {
+                *out_json = json;
+                JAEGERCAT_OK
+            }
+            Err(code) => code,
+        }
+    })
+}
+
+/// Converts a decoded batch to the JSON format of `jaegercat --format json`.
+///
+/// On success, `*out_json` is set to a NUL-terminated string which must be released by
+/// `jaegercat_string_free`.
+///
+/// # Safety
+///
+/// `batch` must be a handle returned by `jaegercat_decode`, and `out_json` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_batch_to_json(
+    batch: *const JaegercatBatch,
+    out_json: *mut *mut c_char,
+) -> c_int {
+    catch_panic(JAEGERCAT_ERROR_PANIC, || {
+        if batch.is_null() || out_json.is_null() {
+            return JAEGERCAT_ERROR_INVALID_ARGUMENT;
+        }
+        match to_json(&(&*batch).0) {
+            Ok(json) => {
+                *out_json = json;
+                JAEGERCAT_OK
+            }
+            Err(code) => code,
+        }
+    })
+}
+
+/// Returns the service name of the process which emitted the batch.
+///
+/// The returned string is not NUL-terminated; its length is stored in `*out_len`.
+///
+/// # Safety
+///
+/// `batch` must be a handle returned by `jaegercat_decode`, and `out_len` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_batch_service_name(
+    batch: *const JaegercatBatch,
+    out_len: *mut usize,
+) -> *const u8 {
+    catch_panic(ptr::null(), || {
+        if batch.is_null() || out_len.is_null() {
+            return ptr::null();
+        }
+        let name = &(&*batch).0.batch.process.service_name;
+        *out_len = name.len();
+        name.as_ptr()
+    })
+}
+
+/// Returns the number of the spans in the batch.
+///
+/// # Safety
+///
+/// `batch` must be a handle returned by `jaegercat_decode`.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_batch_span_count(batch: *const JaegercatBatch) -> usize {
+    catch_panic(0, || {
+        if batch.is_null() {
+            return 0;
+        }
+        (&*batch).0.batch.spans.len()
+    })
+}
+
+/// Stores the `index`-th span of the batch in `*out_span`.
+///
+/// # Safety
+///
+/// `batch` must be a handle returned by `jaegercat_decode`, and `out_span` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_batch_span(
+    batch: *const JaegercatBatch,
+    index: usize,
+    out_span: *mut JaegercatSpan,
+) -> c_int {
+    catch_panic(JAEGERCAT_ERROR_PANIC, || {
+        if batch.is_null() || out_span.is_null() {
+            return JAEGERCAT_ERROR_INVALID_ARGUMENT;
+        }
+        let span = match (&*batch).0.batch.spans.get(index) {
+            Some(span) => span,
+            None => return JAEGERCAT_ERROR_INVALID_ARGUMENT,
+        };
+        *out_span = JaegercatSpan {
+            trace_id_high: span.trace_id.high,
+            trace_id_low: span.trace_id.low,
+            span_id: span.span_id.0,
+            parent_span_id: span.parent_span_id.map_or(0, |id| id.0),
+            operation_name: span.operation_name.as_ptr(),
+            operation_name_len: span.operation_name.len(),
+            flags: span.flags,
+            start_time: span.start_time,
+            duration: span.duration,
+            tag_count: span.tags.len(),
+            log_count: span.logs.len(),
+        };
+        JAEGERCAT_OK
+    })
+}
+
+/// Releases a handle returned by `jaegercat_decode`.
+///
+/// # Safety
+///
+/// `batch` must be a handle returned by `jaegercat_decode` (or null), and must not be used
+/// after this call.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_batch_free(batch: *mut JaegercatBatch) {
+    catch_panic((), || {
+        if !batch.is_null() {
+            drop(Box::from_raw(batch));
+        }
+    })
+}
+
+/// Releases a string returned by this library.
+///
+/// # Safety
+///
+/// `s` must be a string returned by this library (or null), and must not be used
+/// after this call.
+#[no_mangle]
+pub unsafe extern "C" fn jaegercat_string_free(s: *mut c_char) {
+    catch_panic((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+/// Runs `f`, and returns `on_panic` if it panics.
+fn catch_panic<T, F: FnOnce() -> T>(on_panic: T, f: F) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+unsafe fn decode(
+    buf: *const u8,
+    len: usize,
+    protocol: c_int,
+) -> Result<EmitBatchNotification, c_int> {
+    if buf.is_null() && len != 0 {
+        return Err(JAEGERCAT_ERROR_INVALID_ARGUMENT);
+    }
+    let protocol = match protocol {
+        JAEGERCAT_PROTOCOL_COMPACT => Protocol::Compact,
+        JAEGERCAT_PROTOCOL_BINARY => Protocol::Binary,
+        _ => return Err(JAEGERCAT_ERROR_INVALID_ARGUMENT),
+    };
+    let bytes = if len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(buf, len)
+    };
+    EmitBatchNotification::decode(bytes, protocol).map_err(|e| match e {
+        DecodeError::Truncated { .. } => JAEGERCAT_ERROR_TRUNCATED,
+        _ => JAEGERCAT_ERROR_MALFORMED,
+    })
+}
+
+fn to_json(message: &EmitBatchNotification) -> Result<*mut c_char, c_int> {
+    let json = serdeconv::to_json_string(message).map_err(|_| JAEGERCAT_ERROR_SERIALIZE)?;
+    let json = CString::new(json).map_err(|_| JAEGERCAT_ERROR_SERIALIZE)?;
+    Ok(json.into_raw())
+}
+
+#[cfg(test)]
+mod test {
+    use jaegercat::emit::SpanBuilder;
+    use jaegercat::model::{Batch, Process, Span, UnknownFields};
+    use super::*;
+
+    fn encode(spans: Vec<Span>) -> Vec<u8> {
+        let message = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans,
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        message.encode(Protocol::Compact)
+    }
+
+    #[test]
+    fn catch_panic_works() {
+        assert_eq!(catch_panic(JAEGERCAT_OK, || JAEGERCAT_OK), JAEGERCAT_OK);
+        let code = catch_panic(JAEGERCAT_ERROR_PANIC, || -> c_int { panic!("on purpose") });
+        assert_eq!(code, JAEGERCAT_ERROR_PANIC);
+    }
+
+    #[test]
+    fn decode_to_json_works() {
+        let buf = encode(vec![SpanBuilder::new("foo").start_time(-1).build()]);
+        let mut json = ptr::null_mut();
+        unsafe {
+            let code = jaegercat_decode_to_json(
+                buf.as_ptr(),
+                buf.len(),
+                JAEGERCAT_PROTOCOL_COMPACT,
+                &mut json,
+            );
+            assert_eq!(code, JAEGERCAT_OK);
+            jaegercat_string_free(json);
+
+            let code =
+                jaegercat_decode_to_json(buf.as_ptr(), 3, JAEGERCAT_PROTOCOL_COMPACT, &mut json);
+            assert_eq!(code, JAEGERCAT_ERROR_TRUNCATED);
+        }
+    }
+}
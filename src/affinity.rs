@@ -0,0 +1,50 @@
+//! Thread affinity used by `Server`.
+use trackable::error::Failed;
+#[cfg(target_os = "linux")]
+use std::{io, mem};
+#[cfg(target_os = "linux")]
+use libc;
+#[cfg(target_os = "linux")]
+use trackable::error::Failure;
+
+use Result;
+
+/// Restricts the calling thread to run only on `cores`.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cores: &[usize]) -> Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    let max = mem::size_of_val(&set) * 8;
+    for &core in cores {
+        track_assert!(core < max, Failed, "Too large core ID: {}", core);
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+    let result = unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) };
+    if result != 0 {
+        let e = io::Error::last_os_error();
+        return Err(track!(Failure::from_error(e)));
+    }
+    Ok(())
+}
+
+/// Restricts the calling thread to run only on `cores`.
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cores: &[usize]) -> Result<()> {
+    track_panic!(Failed, "Thread affinity is only supported on Linux")
+}
+
+/// Returns the number of the online cores.
+#[cfg(target_os = "linux")]
+pub fn online_cores() -> usize {
+    let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if n < 1 {
+        1
+    } else {
+        n as usize
+    }
+}
+
+/// Returns the number of the online cores.
+#[cfg(not(target_os = "linux"))]
+pub fn online_cores() -> usize {
+    1
+}
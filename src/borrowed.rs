@@ -0,0 +1,734 @@
+//! Span data model whose strings and binaries borrow from the decoded buffer.
+//!
+//! `decode_borrowed` avoids allocating a `String` for every operation name and tag,
+//! which is useful for embedders that need to process a large number of batches.
+use std::collections::BTreeMap;
+use std::fmt;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use error::{DecodeError, DecodeResult as Result};
+use model::{self, ClientStats, SpanId, SpanRef, SpanRefType, ThriftValue, TraceId, UnknownFields};
+use thrift::{DecodeLimits, DecodeMode, Protocol};
+use wire::{Reader, Type, MESSAGE_KIND_ONEWAY};
+
+macro_rules! read_struct {
+    ($reader:ident, $name:expr, $unknown:ident, {
+        $($id:pat, $ty:ident => $action:expr),* $(,)*
+    }) => {{
+        $reader.read_struct_begin()?;
+        let mut offset = $reader.position();
+        while let Some(field) = $reader.read_field_begin()? {
+            match (field.id, field.ty) {
+                $(($id, Type::$ty) => { $action; })*
+                (id, ty) => {
+                    if $reader.is_strict() {
+                        let expected = match id {
+                            $($id => Some(Type::$ty.name()),)*
+                            _ => None,
+                        };
+                        return Err(if let Some(expected) = expected {
+                            DecodeError::UnexpectedType {
+                                struct_name: $name,
+                                field_id: id,
+                                offset,
+                                expected,
+                                actual: ty.name(),
+                            }
+                        } else {
+                            DecodeError::UnknownField {
+                                struct_name: $name,
+                                field_id: id,
+                                offset,
+                                actual: ty.name(),
+                            }
+                        });
+                    }
+                    let value = read_value($reader, ty)?;
+                    $unknown.insert(id, value);
+                }
+            }
+            offset = $reader.position();
+        }
+        $reader.read_struct_end();
+    }};
+}
+
+macro_rules! required {
+    ($value:expr, $name:expr) => {
+        match $value {
+            Some(v) => v,
+            None => return Err(DecodeError::MissingField { field: $name }),
+        }
+    };
+}
+
+/// Decodes an `emitBatch` message without copying strings and binaries.
+pub fn decode_borrowed<'a>(buf: &'a [u8], protocol: Protocol) -> Result<Batch<'a>> {
+    let limits = DecodeLimits::default();
+    decode_with_limits(buf, protocol, DecodeMode::Normal, &limits).map(|(batch, _)| batch)
+}
+
+/// Decodes an `emitBatch` message, rejecting anything not conforming to [jaeger.thrift].
+///
+/// Unlike `decode_borrowed`, unknown fields, fields of unexpected types and trailing bytes
+/// are reported as errors.
+/// The error messages name the struct, the field ID, the byte offset and the types involved.
+///
+/// [jaeger.thrift]: https://github.com/jaegertracing/jaeger-idl/blob/master/thrift/jaeger.thrift
+pub fn decode_strict<'a>(buf: &'a [u8], protocol: Protocol) -> Result<Batch<'a>> {
+    let limits = DecodeLimits::default();
+    decode_with_limits(buf, protocol, DecodeMode::Strict, &limits).map(|(batch, _)| batch)
+}
+
+/// Decodes an `emitBatch` message, salvaging the spans decoded before a failure.
+///
+/// If decoding fails in the middle of the spans, the successfully decoded spans are returned
+/// together with a `PartialDecodeError` describing the failure.
+/// If the process of the batch cannot be decoded, an error is returned instead.
+pub fn decode_lenient<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+) -> Result<(Batch<'a>, Option<PartialDecodeError>)> {
+    decode_with_limits(buf, protocol, DecodeMode::Lenient, &DecodeLimits::default())
+}
+
+/// Decodes an `emitBatch` message in the given mode, rejecting values which exceed `limits`.
+///
+/// The other `decode_*` functions use `DecodeLimits::default()`.
+/// A `PartialDecodeError` is returned only in `DecodeMode::Lenient`.
+pub fn decode_with_limits<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+    mode: DecodeMode,
+    limits: &DecodeLimits,
+) -> Result<(Batch<'a>, Option<PartialDecodeError>)> {
+    let mut reader = Reader::new(buf, protocol);
+    reader.set_limits(*limits);
+    match mode {
+        DecodeMode::Normal => read_message(&mut reader).map(|batch| (batch, None)),
+        DecodeMode::Strict => {
+            reader.set_strict(true);
+            let batch = read_message(&mut reader)?;
+            check_trailing_bytes(&reader, buf)?;
+            Ok((batch, None))
+        }
+        DecodeMode::Lenient => {
+            read_message_header(&mut reader)?;
+            reader.read_struct_begin()?;
+            while let Some(field) = reader.read_field_begin()? {
+                match (field.id, field.ty) {
+                    (1, Type::Struct) => return read_batch_lenient(&mut reader),
+                    _ => reader.skip(field.ty)?,
+                }
+            }
+            Err(DecodeError::MissingField {
+                field: "emitBatch_args.batch",
+            })
+        }
+    }
+}
+
+/// Decodes a bare `Batch` struct (not wrapped in an `emitBatch` message) in the given mode,
+/// rejecting values which exceed `limits`.
+///
+/// Jaeger clients which report spans to the collector over HTTP send batches in this form.
+pub fn decode_batch_with_limits<'a>(
+    buf: &'a [u8],
+    protocol: Protocol,
+    mode: DecodeMode,
+    limits: &DecodeLimits,
+) -> Result<(Batch<'a>, Option<PartialDecodeError>)> {
+    let mut reader = Reader::new(buf, protocol);
+    reader.set_limits(*limits);
+    match mode {
+        DecodeMode::Normal => read_batch(&mut reader).map(|batch| (batch, None)),
+        DecodeMode::Strict => {
+            reader.set_strict(true);
+            let batch = read_batch(&mut reader)?;
+            check_trailing_bytes(&reader, buf)?;
+            Ok((batch, None))
+        }
+        DecodeMode::Lenient => read_batch_lenient(&mut reader),
+    }
+}
+
+fn check_trailing_bytes(reader: &Reader, buf: &[u8]) -> Result<()> {
+    if reader.position() != buf.len() {
+        return Err(DecodeError::TrailingBytes {
+            offset: reader.position(),
+            size: buf.len() - reader.position(),
+        });
+    }
+    Ok(())
+}
+
+/// An error which occurred while decoding the spans of a batch.
+#[derive(Debug)]
+pub struct PartialDecodeError {
+    /// The offset from the beginning of the message at which decoding failed.
+    pub offset: usize,
+
+    /// `true` if the message ended unexpectedly (e.g., the datagram was truncated).
+    pub truncated: bool,
+
+    /// The reason of the failure.
+    pub cause: DecodeError,
+}
+impl fmt::Display for PartialDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "offset={}, truncated={}, cause={}",
+            self.offset, self.truncated, self.cause
+        )
+    }
+}
+
+pub(crate) fn read_message<'a>(reader: &mut Reader<'a>) -> Result<Batch<'a>> {
+    read_message_header(reader)?;
+
+    let mut batch = None;
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "emitBatch_args", unknown_fields, {
+        1, Struct => batch = Some(read_batch(reader)?),
+    });
+    Ok(required!(batch, "emitBatch_args.batch"))
+}
+
+fn read_message_header(reader: &mut Reader) -> Result<()> {
+    let header = reader.read_message_begin()?;
+    if header.name != "emitBatch" || header.kind != MESSAGE_KIND_ONEWAY {
+        return Err(DecodeError::UnexpectedMessage {
+            name: header.name.to_owned(),
+            kind: header.kind,
+        });
+    }
+    Ok(())
+}
+
+/// A batch of spans reported by a process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<'a> {
+    /// The process which emitted the spans.
+    pub process: Process<'a>,
+
+    /// The reported spans.
+    pub spans: Vec<Span<'a>>,
+
+    /// The sequence number of the batch, incremented by the client for every batch it emits.
+    pub seq_no: Option<i64>,
+
+    /// The statistics of the spans dropped by the client.
+    pub stats: Option<ClientStats>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+impl<'a> Batch<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::Batch {
+        model::Batch {
+            process: self.process.into_owned(),
+            spans: self.spans.into_iter().map(Span::into_owned).collect(),
+            seq_no: self.seq_no,
+            stats: self.stats,
+            unknown_fields: self.unknown_fields,
+        }
+    }
+}
+
+/// A traced process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Process<'a> {
+    /// The name of the service.
+    pub service_name: &'a str,
+
+    /// The tags associated with the process.
+    pub tags: Vec<Tag<'a>>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+impl<'a> Process<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::Process {
+        model::Process {
+            service_name: self.service_name.to_owned(),
+            tags: self.tags.into_iter().map(Tag::into_owned).collect(),
+            unknown_fields: self.unknown_fields,
+        }
+    }
+}
+
+/// A span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span<'a> {
+    /// The identifier of the trace which the span belongs to.
+    pub trace_id: TraceId,
+
+    /// The identifier of the span.
+    pub span_id: SpanId,
+
+    /// The identifier of the parent span.
+    ///
+    /// `None` if the span is a root span.
+    pub parent_span_id: Option<SpanId>,
+
+    /// The name of the operation.
+    pub operation_name: &'a str,
+
+    /// The references to other spans.
+    pub references: Vec<SpanRef>,
+
+    /// The flags of the span (e.g., sampled or debug).
+    pub flags: i32,
+
+    /// The start time of the span in microseconds since the UNIX epoch.
+    pub start_time: i64,
+
+    /// The duration of the span in microseconds.
+    pub duration: i64,
+
+    /// The tags of the span.
+    pub tags: Vec<Tag<'a>>,
+
+    /// The logs of the span.
+    pub logs: Vec<Log<'a>>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+impl<'a> Span<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::Span {
+        model::Span {
+            trace_id: self.trace_id,
+            span_id: self.span_id,
+            parent_span_id: self.parent_span_id,
+            operation_name: self.operation_name.to_owned(),
+            references: self.references,
+            flags: self.flags,
+            start_time: self.start_time,
+            duration: self.duration,
+            tags: self.tags.into_iter().map(Tag::into_owned).collect(),
+            logs: self.logs.into_iter().map(Log::into_owned).collect(),
+            unknown_fields: self.unknown_fields,
+        }
+    }
+}
+
+/// A log event of a span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log<'a> {
+    /// The timestamp of the event in microseconds since the UNIX epoch.
+    pub timestamp: i64,
+
+    /// The fields of the event.
+    pub fields: Vec<Tag<'a>>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+impl<'a> Log<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::Log {
+        model::Log {
+            timestamp: self.timestamp,
+            fields: self.fields.into_iter().map(Tag::into_owned).collect(),
+            unknown_fields: self.unknown_fields,
+        }
+    }
+}
+
+/// A key-value pair.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag<'a> {
+    /// The key of the tag.
+    pub key: &'a str,
+
+    /// The value of the tag.
+    pub value: TagValue<'a>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+impl<'a> Tag<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::Tag {
+        model::Tag {
+            key: self.key.to_owned(),
+            value: self.value.into_owned(),
+            unknown_fields: self.unknown_fields,
+        }
+    }
+}
+
+/// The value of a tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue<'a> {
+    /// `STRING`.
+    Str(&'a str),
+
+    /// `BOOL`.
+    Bool(bool),
+
+    /// `LONG`.
+    Long(i64),
+
+    /// `DOUBLE`.
+    Double(f64),
+
+    /// `BINARY`.
+    Binary(&'a [u8]),
+}
+impl<'a> TagValue<'a> {
+    /// Converts to the owned representation.
+    pub fn into_owned(self) -> model::TagValue {
+        match self {
+            TagValue::Str(v) => model::TagValue::Str(v.to_owned()),
+            TagValue::Bool(v) => model::TagValue::Bool(v),
+            TagValue::Long(v) => model::TagValue::Long(v),
+            TagValue::Double(v) => model::TagValue::Double(v),
+            TagValue::Binary(v) => model::TagValue::Binary(v.to_owned()),
+        }
+    }
+}
+
+fn read_list<'a, T, F>(reader: &mut Reader<'a>, name: &'static str, mut f: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut Reader<'a>) -> Result<T>,
+{
+    let offset = reader.position();
+    let (ty, size) = reader.read_list_begin()?;
+    check_element_type(name, offset, ty)?;
+    let mut list = Vec::with_capacity(size.min(1024));
+    reader.enter()?;
+    for _ in 0..size {
+        list.push(f(reader)?);
+    }
+    reader.leave();
+    Ok(list)
+}
+
+fn read_batch<'a>(reader: &mut Reader<'a>) -> Result<Batch<'a>> {
+    let mut process = None;
+    let mut spans = None;
+    let mut seq_no = None;
+    let mut stats = None;
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "Batch", unknown_fields, {
+        1, Struct => process = Some(read_process(reader)?),
+        2, List => spans = Some(read_list(reader, "Batch.spans", read_span)?),
+        3, I64 => seq_no = Some(reader.read_i64()?),
+        4, Struct => stats = Some(read_client_stats(reader)?),
+    });
+    Ok(Batch {
+        process: required!(process, "Batch.process"),
+        spans: required!(spans, "Batch.spans"),
+        seq_no,
+        stats,
+        unknown_fields,
+    })
+}
+
+/// The fields of a batch decoded so far.
+#[derive(Default)]
+struct PartialBatch<'a> {
+    process: Option<Process<'a>>,
+    spans: Vec<Span<'a>>,
+    seq_no: Option<i64>,
+    stats: Option<ClientStats>,
+    unknown_fields: UnknownFields,
+}
+
+fn read_batch_lenient<'a>(
+    reader: &mut Reader<'a>,
+) -> Result<(Batch<'a>, Option<PartialDecodeError>)> {
+    let mut partial = PartialBatch::default();
+    let error = match read_batch_fields(reader, &mut partial) {
+        Ok(()) => None,
+        Err(cause) => Some(PartialDecodeError {
+            offset: reader.position(),
+            truncated: reader.is_truncated(),
+            cause,
+        }),
+    };
+    match (partial.process, error) {
+        (Some(process), error) => {
+            let batch = Batch {
+                process,
+                spans: partial.spans,
+                seq_no: partial.seq_no,
+                stats: partial.stats,
+                unknown_fields: partial.unknown_fields,
+            };
+            Ok((batch, error))
+        }
+        (None, Some(e)) => Err(e.cause),
+        (None, None) => Err(DecodeError::MissingField {
+            field: "Batch.process",
+        }),
+    }
+}
+
+fn read_batch_fields<'a>(reader: &mut Reader<'a>, partial: &mut PartialBatch<'a>) -> Result<()> {
+    let unknown_fields = &mut partial.unknown_fields;
+    read_struct!(reader, "Batch", unknown_fields, {
+        1, Struct => partial.process = Some(read_process(reader)?),
+        2, List => {
+            let offset = reader.position();
+            let (ty, size) = reader.read_list_begin()?;
+            check_element_type("Batch.spans", offset, ty)?;
+            reader.enter()?;
+            for _ in 0..size {
+                partial.spans.push(read_span(reader)?);
+            }
+            reader.leave();
+        },
+        3, I64 => partial.seq_no = Some(reader.read_i64()?),
+        4, Struct => partial.stats = Some(read_client_stats(reader)?),
+    });
+    Ok(())
+}
+
+fn read_client_stats(reader: &mut Reader) -> Result<ClientStats> {
+    let mut full_queue_dropped_spans = None;
+    let mut too_large_dropped_spans = None;
+    let mut failed_to_emit_spans = None;
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "ClientStats", unknown_fields, {
+        1, I64 => full_queue_dropped_spans = Some(reader.read_i64()?),
+        2, I64 => too_large_dropped_spans = Some(reader.read_i64()?),
+        3, I64 => failed_to_emit_spans = Some(reader.read_i64()?),
+    });
+    Ok(ClientStats {
+        full_queue_dropped_spans: required!(
+            full_queue_dropped_spans,
+            "ClientStats.fullQueueDroppedSpans"
+        ),
+        too_large_dropped_spans: required!(
+            too_large_dropped_spans,
+            "ClientStats.tooLargeDroppedSpans"
+        ),
+        failed_to_emit_spans: required!(failed_to_emit_spans, "ClientStats.failedToEmitSpans"),
+        unknown_fields,
+    })
+}
+
+fn read_process<'a>(reader: &mut Reader<'a>) -> Result<Process<'a>> {
+    let mut service_name = None;
+    let mut tags = Vec::new();
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "Process", unknown_fields, {
+        1, Binary => service_name = Some(reader.read_str()?),
+        2, List => tags = read_list(reader, "Process.tags", read_tag)?,
+    });
+    Ok(Process {
+        service_name: required!(service_name, "Process.serviceName"),
+        tags,
+        unknown_fields,
+    })
+}
+
+fn read_tag<'a>(reader: &mut Reader<'a>) -> Result<Tag<'a>> {
+    let start = reader.position();
+    let mut key = None;
+    let mut kind = None;
+    let mut v_str = None;
+    let mut v_double = None;
+    let mut v_bool = None;
+    let mut v_long = None;
+    let mut v_binary = None;
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "Tag", unknown_fields, {
+        1, Binary => key = Some(reader.read_str()?),
+        2, I32 => kind = Some(reader.read_i32()?),
+        3, Binary => v_str = Some(reader.read_str()?),
+        4, Double => v_double = Some(reader.read_double()?),
+        5, Bool => v_bool = Some(reader.read_bool()?),
+        6, I64 => v_long = Some(reader.read_i64()?),
+        7, Binary => v_binary = Some(reader.read_binary()?),
+    });
+    let key = required!(key, "Tag.key");
+    let kind = required!(kind, "Tag.vType");
+    let value = match kind {
+        0 => TagValue::Str(required!(v_str, "Tag.vStr")),
+        1 => TagValue::Double(required!(v_double, "Tag.vDouble")),
+        2 => TagValue::Bool(required!(v_bool, "Tag.vBool")),
+        3 => TagValue::Long(required!(v_long, "Tag.vLong")),
+        4 => TagValue::Binary(required!(v_binary, "Tag.vBinary")),
+        _ => {
+            return Err(DecodeError::InvalidValue {
+                offset: start,
+                reason: format!("Unknown tag kind: {}", kind),
+            })
+        }
+    };
+    Ok(Tag {
+        key,
+        value,
+        unknown_fields,
+    })
+}
+
+fn read_span<'a>(reader: &mut Reader<'a>) -> Result<Span<'a>> {
+    let mut trace_id_low = None;
+    let mut trace_id_high = None;
+    let mut span_id = None;
+    let mut parent_span_id = None;
+    let mut operation_name = None;
+    let mut references = Vec::new();
+    let mut flags = None;
+    let mut start_time = None;
+    let mut duration = None;
+    let mut tags = Vec::new();
+    let mut logs = Vec::new();
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "Span", unknown_fields, {
+        1, I64 => trace_id_low = Some(reader.read_i64()?),
+        2, I64 => trace_id_high = Some(reader.read_i64()?),
+        3, I64 => span_id = Some(reader.read_i64()?),
+        4, I64 => parent_span_id = Some(reader.read_i64()?),
+        5, Binary => operation_name = Some(reader.read_str()?),
+        6, List => {
+            references = read_list(reader, "Span.references", read_span_ref)?
+        },
+        7, I32 => flags = Some(reader.read_i32()?),
+        8, I64 => start_time = Some(reader.read_i64()?),
+        9, I64 => duration = Some(reader.read_i64()?),
+        10, List => tags = read_list(reader, "Span.tags", read_tag)?,
+        11, List => logs = read_list(reader, "Span.logs", read_log)?,
+    });
+    let parent_span_id = required!(parent_span_id, "Span.parentSpanId");
+    Ok(Span {
+        trace_id: TraceId {
+            high: required!(trace_id_high, "Span.traceIdHigh"),
+            low: required!(trace_id_low, "Span.traceIdLow"),
+        },
+        span_id: SpanId(required!(span_id, "Span.spanId")),
+        parent_span_id: if parent_span_id == 0 {
+            None
+        } else {
+            Some(SpanId(parent_span_id))
+        },
+        operation_name: required!(operation_name, "Span.operationName"),
+        references,
+        flags: required!(flags, "Span.flags"),
+        start_time: required!(start_time, "Span.startTime"),
+        duration: required!(duration, "Span.duration"),
+        tags,
+        logs,
+        unknown_fields,
+    })
+}
+
+fn read_span_ref(reader: &mut Reader) -> Result<SpanRef> {
+    let start = reader.position();
+    let mut kind = None;
+    let mut trace_id_low = None;
+    let mut trace_id_high = None;
+    let mut span_id = None;
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "SpanRef", unknown_fields, {
+        1, I32 => kind = Some(reader.read_i32()?),
+        2, I64 => trace_id_low = Some(reader.read_i64()?),
+        3, I64 => trace_id_high = Some(reader.read_i64()?),
+        4, I64 => span_id = Some(reader.read_i64()?),
+    });
+    let kind = required!(kind, "SpanRef.refType");
+    let ref_type = match kind {
+        0 => SpanRefType::ChildOf,
+        1 => SpanRefType::FollowsFrom,
+        _ => {
+            return Err(DecodeError::InvalidValue {
+                offset: start,
+                reason: format!("Unknown span reference kind: {}", kind),
+            })
+        }
+    };
+    Ok(SpanRef {
+        ref_type,
+        trace_id: TraceId {
+            high: required!(trace_id_high, "SpanRef.traceIdHigh"),
+            low: required!(trace_id_low, "SpanRef.traceIdLow"),
+        },
+        span_id: SpanId(required!(span_id, "SpanRef.spanId")),
+        unknown_fields,
+    })
+}
+
+fn read_log<'a>(reader: &mut Reader<'a>) -> Result<Log<'a>> {
+    let mut timestamp = None;
+    let mut fields = Vec::new();
+    let mut unknown_fields = UnknownFields::new();
+    read_struct!(reader, "Log", unknown_fields, {
+        1, I64 => timestamp = Some(reader.read_i64()?),
+        2, List => fields = read_list(reader, "Log.fields", read_tag)?,
+    });
+    Ok(Log {
+        timestamp: required!(timestamp, "Log.timestamp"),
+        fields,
+        unknown_fields,
+    })
+}
+
+fn check_element_type(list: &'static str, offset: usize, ty: Type) -> Result<()> {
+    if ty == Type::Struct {
+        Ok(())
+    } else {
+        Err(DecodeError::UnexpectedElementType {
+            list,
+            offset,
+            expected: Type::Struct.name(),
+            actual: ty.name(),
+        })
+    }
+}
+
+fn read_value(reader: &mut Reader, ty: Type) -> Result<ThriftValue> {
+    Ok(match ty {
+        Type::Bool => ThriftValue::Bool(reader.read_bool()?),
+        Type::I8 => ThriftValue::I8(reader.read_i8()?),
+        Type::I16 => ThriftValue::I16(reader.read_i16()?),
+        Type::I32 => ThriftValue::I32(reader.read_i32()?),
+        Type::I64 => ThriftValue::I64(reader.read_i64()?),
+        Type::Double => ThriftValue::Double(reader.read_double()?),
+        Type::Binary => ThriftValue::Binary(reader.read_binary()?.to_owned()),
+        Type::Struct => {
+            let mut fields = BTreeMap::new();
+            reader.read_struct_begin()?;
+            while let Some(field) = reader.read_field_begin()? {
+                fields.insert(field.id, read_value(reader, field.ty)?);
+            }
+            reader.read_struct_end();
+            ThriftValue::Struct(fields)
+        }
+        Type::Map => {
+            let (key, value, size) = reader.read_map_begin()?;
+            let mut entries = Vec::new();
+            reader.enter()?;
+            for _ in 0..size {
+                let k = read_value(reader, key)?;
+                let v = read_value(reader, value)?;
+                entries.push((k, v));
+            }
+            reader.leave();
+            ThriftValue::Map(entries)
+        }
+        Type::Set | Type::List => {
+            let (elem, size) = reader.read_list_begin()?;
+            let mut elements = Vec::new();
+            reader.enter()?;
+            for _ in 0..size {
+                elements.push(read_value(reader, elem)?);
+            }
+            reader.leave();
+            if ty == Type::Set {
+                ThriftValue::Set(elements)
+            } else {
+                ThriftValue::List(elements)
+            }
+        }
+    })
+}
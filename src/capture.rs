@@ -0,0 +1,158 @@
+//! The `capture` subcommand, which passively decodes the datagrams sent to the agent ports
+//! on a network interface (e.g., while a real agent is bound to the ports).
+//!
+//! Packets are captured with an `AF_PACKET` socket, so this is only supported on Linux
+//! and requires the `CAP_NET_RAW` capability.
+use std::io::{self, Write};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::mem;
+use jaegercat::Result;
+use jaegercat::handler::Formatter;
+#[cfg(target_os = "linux")]
+use libc;
+use trackable::error::Failure;
+#[cfg(not(target_os = "linux"))]
+use trackable::error::Failed;
+
+use pcap::{self, Pcap};
+
+/// `ARPHRD_LOOPBACK` and `PACKET_OUTGOING` in `<linux/if_arp.h>` and `<linux/if_packet.h>`.
+#[cfg(target_os = "linux")]
+const ARPHRD_LOOPBACK: libc::c_ushort = 772;
+#[cfg(target_os = "linux")]
+const PACKET_OUTGOING: libc::c_uchar = 4;
+
+/// Settings of a capture run.
+pub struct Capture {
+    pub pcap: Pcap,
+
+    /// The interface to capture packets on (`any` for all the interfaces).
+    pub interface: String,
+}
+impl Capture {
+    /// Captures the packets on `interface`, and writes the messages in the UDP datagrams sent
+    /// to the ports of `pcap` to the standard output until the process is terminated.
+    ///
+    /// Malformed messages and skipped datagrams are reported to the standard error.
+    pub fn run(&self, formatter: &Formatter) -> Result<()> {
+        let socket = track!(RawSocket::open(&self.interface), "{}", self.interface)?;
+        let stdout = io::stdout();
+        let mut buf = vec![0; 65536];
+        for i in 1.. {
+            let (len, link) = track!(socket.recv(&mut buf), "{}", self.interface)?;
+            if link.outgoing_loopback {
+                // Packets on a loopback interface are captured twice (as outgoing and incoming)
+                continue;
+            }
+            let location = format!("{}: packet={}", self.interface, i);
+            let mut writer = stdout.lock();
+            let written = track!(self.pcap.write_packet(
+                pcap::LINKTYPE_RAW,
+                &buf[..len],
+                &location,
+                formatter,
+                &mut writer
+            ))?;
+            if written.is_some() {
+                track!(writer.flush().map_err(Failure::from_error))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The link-layer information of a captured packet.
+struct Link {
+    outgoing_loopback: bool,
+}
+
+/// An `AF_PACKET` socket receiving the network-layer packets (without link-layer headers).
+#[cfg(target_os = "linux")]
+struct RawSocket {
+    fd: libc::c_int,
+}
+#[cfg(target_os = "linux")]
+impl RawSocket {
+    fn open(interface: &str) -> Result<Self> {
+        let protocol = (libc::ETH_P_ALL as u16).to_be();
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_PACKET,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::c_int::from(protocol),
+            )
+        };
+        if fd < 0 {
+            return Err(track!(Failure::from_error(io::Error::last_os_error())));
+        }
+
+        // The socket is closed when `socket` is dropped
+        let socket = RawSocket { fd };
+        if interface != "any" {
+            let name = track!(CString::new(interface).map_err(Failure::from_error))?;
+            let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+            if index == 0 {
+                return Err(track!(Failure::from_error(io::Error::last_os_error())));
+            }
+            let mut sockaddr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+            sockaddr.sll_family = libc::AF_PACKET as libc::c_ushort;
+            sockaddr.sll_protocol = protocol;
+            sockaddr.sll_ifindex = index as libc::c_int;
+            let result = unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const _ as *const libc::sockaddr,
+                    mem::size_of_val(&sockaddr) as libc::socklen_t,
+                )
+            };
+            if result != 0 {
+                return Err(track!(Failure::from_error(io::Error::last_os_error())));
+            }
+        }
+        Ok(socket)
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<(usize, Link)> {
+        let mut sockaddr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        let mut sockaddr_len = mem::size_of_val(&sockaddr) as libc::socklen_t;
+        let len = unsafe {
+            libc::recvfrom(
+                self.fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+                &mut sockaddr as *mut _ as *mut libc::sockaddr,
+                &mut sockaddr_len,
+            )
+        };
+        if len < 0 {
+            return Err(track!(Failure::from_error(io::Error::last_os_error())));
+        }
+        let link = Link {
+            outgoing_loopback: sockaddr.sll_hatype == ARPHRD_LOOPBACK
+                && sockaddr.sll_pkttype == PACKET_OUTGOING,
+        };
+        Ok((len as usize, link))
+    }
+}
+#[cfg(target_os = "linux")]
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+struct RawSocket;
+#[cfg(not(target_os = "linux"))]
+impl RawSocket {
+    fn open(interface: &str) -> Result<Self> {
+        track_panic!(Failed, "Packet capture is only supported on Linux: {}", interface)
+    }
+
+    fn recv(&self, _buf: &mut [u8]) -> Result<(usize, Link)> {
+        unreachable!()
+    }
+}
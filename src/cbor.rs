@@ -0,0 +1,86 @@
+//! Encoding of JSON values into [CBOR] (RFC 8949).
+//!
+//! Numbers are encoded as integers if they are (and as 64-bit floats otherwise),
+//! and strings, arrays and objects as text strings, arrays and maps of definite lengths.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate jaegercat;
+//! #[macro_use]
+//! extern crate serde_json;
+//!
+//! use jaegercat::cbor;
+//!
+//! # fn main() {
+//! let mut out = Vec::new();
+//! cbor::write_value(&mut out, &json!({"a": [1, -1, true]}));
+//! assert_eq!(out, [0xA1, 0x61, b'a', 0x83, 0x01, 0x20, 0xF5]);
+//! # }
+//! ```
+//!
+//! [CBOR]: https://cbor.io/
+use serde_json::Value;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+
+/// Appends the CBOR encoding of `value` to `out`.
+pub fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match *value {
+        Value::Null => out.push(0xF6),
+        Value::Bool(false) => out.push(0xF4),
+        Value::Bool(true) => out.push(0xF5),
+        Value::Number(ref n) => {
+            if let Some(n) = n.as_u64() {
+                write_head(out, UNSIGNED, n);
+            } else if let Some(n) = n.as_i64() {
+                // -1 - n, which never overflows for negative `n`
+                write_head(out, NEGATIVE, !(n as u64));
+            } else {
+                out.push(0xFB);
+                out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_bits().to_be_bytes());
+            }
+        }
+        Value::String(ref s) => {
+            write_head(out, TEXT, s.len() as u64);
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Array(ref values) => {
+            write_head(out, ARRAY, values.len() as u64);
+            for v in values {
+                write_value(out, v);
+            }
+        }
+        Value::Object(ref object) => {
+            write_head(out, MAP, object.len() as u64);
+            for (k, v) in object {
+                write_head(out, TEXT, k.len() as u64);
+                out.extend_from_slice(k.as_bytes());
+                write_value(out, v);
+            }
+        }
+    }
+}
+
+/// Writes the initial byte (and the following argument bytes) of a data item.
+fn write_head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    if n < 24 {
+        out.push(major | n as u8);
+    } else if n <= u64::from(u8::MAX) {
+        out.extend_from_slice(&[major | 24, n as u8]);
+    } else if n <= u64::from(u16::MAX) {
+        out.push(major | 25);
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    } else if n <= u64::from(u32::MAX) {
+        out.push(major | 26);
+        out.extend_from_slice(&(n as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
@@ -0,0 +1,163 @@
+//! Encoding of Jaeger batches into the Chrome trace event format.
+//!
+//! The events can be loaded by [Perfetto UI] and `chrome://tracing` for the timelines of
+//! the spans. `to_events` maps a batch as follows:
+//!
+//! - The service becomes a process, whose ID is a hash of the service name.
+//! - Each trace becomes a thread of the process, whose ID is taken from the lower bits of
+//!   the trace ID. The names of the process and the threads are set by metadata (`M`) events.
+//! - Each span becomes a complete (`X`) event named after the operation, with the trace ID,
+//!   the span ID, the parent span ID and the tags in its arguments.
+//! - Each log becomes an instant (`i`) event on the thread, with the fields in its arguments.
+//!
+//! The metadata events are repeated in each batch, so the events of any batches can be
+//! concatenated. `Format::ChromeTrace` writes the events in the JSON array format without
+//! the closing `]`, which the format allows for traces that may be cut off.
+//!
+//! [Perfetto UI]: https://ui.perfetto.dev/
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::chrome;
+//! use jaegercat::model::{Batch, Process, Span, SpanId, TraceId, UnknownFields};
+//!
+//! let span = Span {
+//!     trace_id: TraceId { high: 0, low: 1 },
+//!     span_id: SpanId(2),
+//!     parent_span_id: None,
+//!     operation_name: "bar".to_owned(),
+//!     references: Vec::new(),
+//!     flags: 1,
+//!     start_time: 1_544_712_660_000_000,
+//!     duration: 1000,
+//!     tags: Vec::new(),
+//!     logs: Vec::new(),
+//!     unknown_fields: UnknownFields::new(),
+//! };
+//! let batch = Batch {
+//!     process: Process {
+//!         service_name: "foo".to_owned(),
+//!         tags: Vec::new(),
+//!         unknown_fields: UnknownFields::new(),
+//!     },
+//!     spans: vec![span],
+//!     seq_no: None,
+//!     stats: None,
+//!     unknown_fields: UnknownFields::new(),
+//! };
+//! let events = chrome::to_events(&batch);
+//! assert_eq!(events[0]["args"]["name"], "foo");
+//! assert_eq!(events[1]["args"]["name"], "0x1");
+//! assert_eq!(events[2]["ph"], "X");
+//! assert_eq!(events[2]["name"], "bar");
+//! assert_eq!(events[2]["ts"], 1_544_712_660_000_000i64);
+//! assert_eq!(events[2]["dur"], 1000);
+//! ```
+use serde_json::{Map, Value};
+
+use color::hash_name;
+use json::to_flat_value;
+use model::{Batch, Log, Span, Tag, TagValue, TraceId};
+
+/// The beginning of the output of `Format::ChromeTrace`.
+pub const HEADER: &[u8] = b"[\n";
+
+/// Converts a batch into trace events (the metadata events first).
+pub fn to_events(batch: &Batch) -> Vec<Value> {
+    let service = batch.process.service_name.as_str();
+    let pid = hash_name(service) & 0x7fff_ffff;
+
+    let mut events = vec![metadata("process_name", pid, None, service)];
+    let mut traces: Vec<TraceId> = Vec::new();
+    for span in &batch.spans {
+        if !traces.contains(&span.trace_id) {
+            traces.push(span.trace_id);
+            let tid = thread_id(span.trace_id);
+            let name = span.trace_id.to_string();
+            events.push(metadata("thread_name", pid, Some(tid), &name));
+        }
+    }
+    for span in &batch.spans {
+        events.push(span_to_event(span, service, pid));
+        for log in &span.logs {
+            events.push(log_to_event(log, span, service, pid));
+        }
+    }
+    events
+}
+
+fn metadata(name: &str, pid: u64, tid: Option<u64>, value: &str) -> Value {
+    let mut args = Map::new();
+    args.insert("name".to_owned(), Value::from(value));
+    let mut object = Map::new();
+    object.insert("name".to_owned(), Value::from(name));
+    object.insert("ph".to_owned(), Value::from("M"));
+    object.insert("pid".to_owned(), Value::from(pid));
+    if let Some(tid) = tid {
+        object.insert("tid".to_owned(), Value::from(tid));
+    }
+    object.insert("args".to_owned(), Value::Object(args));
+    Value::Object(object)
+}
+
+fn span_to_event(span: &Span, service: &str, pid: u64) -> Value {
+    let mut args = Map::new();
+    args.insert("traceID".to_owned(), Value::from(span.trace_id.to_string()));
+    args.insert("spanID".to_owned(), Value::from(span.span_id.to_string()));
+    if let Some(parent) = span.parent_span_id.filter(|id| id.0 != 0) {
+        args.insert("parentSpanID".to_owned(), Value::from(parent.to_string()));
+    }
+    insert_tags(&mut args, &span.tags);
+
+    let mut object = Map::new();
+    object.insert("name".to_owned(), Value::from(span.operation_name.as_str()));
+    object.insert("cat".to_owned(), Value::from(service));
+    object.insert("ph".to_owned(), Value::from("X"));
+    object.insert("ts".to_owned(), Value::from(span.start_time));
+    object.insert("dur".to_owned(), Value::from(span.duration));
+    object.insert("pid".to_owned(), Value::from(pid));
+    object.insert("tid".to_owned(), Value::from(thread_id(span.trace_id)));
+    object.insert("args".to_owned(), Value::Object(args));
+    Value::Object(object)
+}
+
+fn log_to_event(log: &Log, span: &Span, service: &str, pid: u64) -> Value {
+    let mut args = Map::new();
+    insert_tags(&mut args, &log.fields);
+
+    let mut object = Map::new();
+    object.insert("name".to_owned(), Value::from(log_name(log)));
+    object.insert("cat".to_owned(), Value::from(service));
+    object.insert("ph".to_owned(), Value::from("i"));
+    object.insert("s".to_owned(), Value::from("t"));
+    object.insert("ts".to_owned(), Value::from(log.timestamp));
+    object.insert("pid".to_owned(), Value::from(pid));
+    object.insert("tid".to_owned(), Value::from(thread_id(span.trace_id)));
+    object.insert("args".to_owned(), Value::Object(args));
+    Value::Object(object)
+}
+
+/// Returns the `event` (or `message`) field of `log`, which names the log by convention.
+fn log_name(log: &Log) -> &str {
+    ["event", "message"]
+        .iter()
+        .filter_map(|key| log.fields.iter().find(|f| f.key == *key))
+        .filter_map(|f| match f.value {
+            TagValue::Str(ref v) => Some(v.as_str()),
+            _ => None,
+        })
+        .next()
+        .unwrap_or("log")
+}
+
+fn insert_tags(args: &mut Map<String, Value>, tags: &[Tag]) {
+    for tag in tags {
+        args.insert(tag.key.clone(), to_flat_value(&tag.value));
+    }
+}
+
+/// Returns the ID of the thread of a trace (which viewers expect to be a 32-bit integer).
+fn thread_id(id: TraceId) -> u64 {
+    id.low as u64 & 0x7fff_ffff
+}
@@ -0,0 +1,90 @@
+//! ANSI colors of the human-readable outputs (`Format::Text` and `Format::Tree`).
+//!
+//! The spans having the `error` tag set to `true` are red, each service name has a color
+//! chosen by its hash (so that it is the same across lines and runs), and the durations
+//! longer than the slow threshold are bold yellow.
+//!
+//! # Examples
+//!
+//! ```
+//! use std::time::Duration;
+//! use jaegercat::color::Colors;
+//! use jaegercat::handler::{Format, Formatter};
+//!
+//! let mut colors = Colors::new();
+//! colors.slow_threshold(Duration::from_millis(500));
+//!
+//! let mut formatter = Formatter::new(Format::Text);
+//! formatter.colors(colors);
+//! ```
+use std::time::Duration;
+
+/// The colors of service names, which exclude red (for errors) and yellow (for slow spans).
+const SERVICE_COLORS: &[&str] = &["32", "34", "35", "36", "92", "94", "95", "96"];
+
+const RED: &str = "31";
+const BOLD_YELLOW: &str = "1;33";
+
+/// The settings of ANSI colors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    slow_threshold: i64,
+}
+impl Colors {
+    /// Makes a new `Colors` with the default settings.
+    pub fn new() -> Self {
+        Colors {
+            slow_threshold: 1_000_000,
+        }
+    }
+
+    /// Sets the duration above which spans are considered slow.
+    ///
+    /// The default value is 1 second.
+    pub fn slow_threshold(&mut self, threshold: Duration) -> &mut Self {
+        self.slow_threshold = threshold.as_micros().min(i64::MAX as u128) as i64;
+        self
+    }
+
+    /// Appends `service` in its color to `out`.
+    pub(crate) fn service(&self, out: &mut Vec<u8>, service: &str) {
+        let hash = hash_name(service);
+        let color = SERVICE_COLORS[(hash % SERVICE_COLORS.len() as u64) as usize];
+        paint(out, color, service.as_bytes());
+    }
+
+    /// Appends `text` (the representation of `duration` in microseconds) to `out`,
+    /// highlighted if the duration is above the slow threshold.
+    pub(crate) fn duration(&self, out: &mut Vec<u8>, duration: i64, text: &[u8]) {
+        if duration > self.slow_threshold {
+            paint(out, BOLD_YELLOW, text);
+        } else {
+            out.extend_from_slice(text);
+        }
+    }
+
+    /// Appends `text` of an error span to `out`.
+    pub(crate) fn error(&self, out: &mut Vec<u8>, text: &[u8]) {
+        paint(out, RED, text);
+    }
+}
+impl Default for Colors {
+    fn default() -> Self {
+        Colors::new()
+    }
+}
+
+/// Returns the FNV-1a hash of `name`, which is the same across runs (unlike `DefaultHasher`).
+pub(crate) fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn paint(out: &mut Vec<u8>, color: &str, text: &[u8]) {
+    out.extend_from_slice(b"\x1b[");
+    out.extend_from_slice(color.as_bytes());
+    out.push(b'm');
+    out.extend_from_slice(text);
+    out.extend_from_slice(b"\x1b[0m");
+}
@@ -0,0 +1,387 @@
+//! Tabular (CSV and TSV) output of spans, one row per span.
+//!
+//! The columns are chosen by `Column`s. Times are in seconds since the UNIX epoch
+//! and durations are in seconds (both with microsecond precision), as in the JSON output.
+//!
+//! In CSV, the fields which contain the separator, quotes or line breaks are quoted
+//! (as in RFC 4180). In TSV, tabs, line breaks and backslashes are escaped
+//! as `\t`, `\n`, `\r` and `\\`.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::csv::{self, Column};
+//!
+//! let columns = "trace_id,operation,tag:http.method"
+//!     .split(',')
+//!     .map(|c| c.parse())
+//!     .collect::<Result<Vec<Column>, _>>()
+//!     .unwrap();
+//! let mut out = Vec::new();
+//! csv::write_header(&mut out, &columns, b',');
+//! assert_eq!(out, b"trace_id,operation,tag:http.method\n");
+//! ```
+use std::fmt;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use trackable::error::{Failed, Failure};
+
+use model::{Span, TagValue};
+use otlp::encode_base64;
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// A column of the tabular output.
+///
+/// The names of the columns (used by `FromStr` and `Display`) are those of the variants
+/// in snake case (e.g., `trace_id`), and `tag:KEY` for `Tag`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    /// The trace ID in the format of the JSON output (e.g., `0x2ae64ad7cd070e8e`).
+    TraceId,
+
+    /// The span ID.
+    SpanId,
+
+    /// The parent span ID (empty if the span has no parent).
+    ParentSpanId,
+
+    /// The service name of the process.
+    Service,
+
+    /// The operation name.
+    Operation,
+
+    /// The start time of the span.
+    Start,
+
+    /// The duration of the span.
+    Duration,
+
+    /// The duration of the span in microseconds.
+    DurationUs,
+
+    /// The duration of the span in milliseconds (with microsecond precision).
+    DurationMs,
+
+    /// `true` if the span has the `error` tag set to `true`, otherwise `false`.
+    Error,
+
+    /// The value of the `span.kind` tag.
+    Kind,
+
+    /// The flags of the span.
+    Flags,
+
+    /// The source address of the batch.
+    Peer,
+
+    /// The value of the tag with the given key (empty if the span has no such tag).
+    Tag(String),
+}
+impl Column {
+    /// Returns the columns written by default
+    /// (`trace_id,span_id,parent_span_id,service,operation,start,duration,error`).
+    pub fn defaults() -> Vec<Column> {
+        vec![
+            Column::TraceId,
+            Column::SpanId,
+            Column::ParentSpanId,
+            Column::Service,
+            Column::Operation,
+            Column::Start,
+            Column::Duration,
+            Column::Error,
+        ]
+    }
+
+    /// Appends the value of the column for `span` to `out` (without escaping).
+    pub(crate) fn write_value(
+        &self,
+        out: &mut Vec<u8>,
+        batch: &EmitBatchNotification,
+        span: &Span,
+        meta: &ReceiveMeta,
+    ) {
+        // Writing to a `Vec` never fails
+        let _ = match *self {
+            Column::TraceId => write!(out, "{}", span.trace_id),
+            Column::SpanId => write!(out, "{}", span.span_id),
+            Column::ParentSpanId => match span.parent_span_id {
+                Some(id) if id.0 != 0 => write!(out, "{}", id),
+                _ => Ok(()),
+            },
+            Column::Service => out.write_all(batch.batch.process.service_name.as_bytes()),
+            Column::Operation => out.write_all(span.operation_name.as_bytes()),
+            Column::Start => write_seconds(out, span.start_time),
+            Column::Duration => write_seconds(out, span.duration),
+            Column::DurationUs => write!(out, "{}", span.duration),
+            Column::DurationMs => write_milliseconds(out, span.duration),
+            Column::Error => write!(out, "{}", is_error(span)),
+            Column::Kind => match find_tag(span, "span.kind") {
+                Some(value) => write_tag_value(out, value),
+                None => Ok(()),
+            },
+            Column::Flags => write!(out, "{}", span.flags),
+            Column::Peer => {
+                // IPv4 senders to a dual-stack socket appear as IPv4-mapped addresses
+                let peer = SocketAddr::new(meta.peer.ip().to_canonical(), meta.peer.port());
+                write!(out, "{}", peer)
+            }
+            Column::Tag(ref key) => match find_tag(span, key) {
+                Some(value) => write_tag_value(out, value),
+                None => Ok(()),
+            },
+        };
+    }
+}
+impl FromStr for Column {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "trace_id" => Column::TraceId,
+            "span_id" => Column::SpanId,
+            "parent_span_id" => Column::ParentSpanId,
+            "service" => Column::Service,
+            "operation" => Column::Operation,
+            "start" => Column::Start,
+            "duration" => Column::Duration,
+            "duration_us" => Column::DurationUs,
+            "duration_ms" => Column::DurationMs,
+            "error" => Column::Error,
+            "kind" => Column::Kind,
+            "flags" => Column::Flags,
+            "peer" => Column::Peer,
+            _ if s.starts_with("tag:") && s.len() > 4 => Column::Tag(s[4..].to_owned()),
+            _ => track_panic!(Failed, "Unknown column: {:?}", s),
+        })
+    }
+}
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Column::TraceId => "trace_id",
+            Column::SpanId => "span_id",
+            Column::ParentSpanId => "parent_span_id",
+            Column::Service => "service",
+            Column::Operation => "operation",
+            Column::Start => "start",
+            Column::Duration => "duration",
+            Column::DurationUs => "duration_us",
+            Column::DurationMs => "duration_ms",
+            Column::Error => "error",
+            Column::Kind => "kind",
+            Column::Flags => "flags",
+            Column::Peer => "peer",
+            Column::Tag(ref key) => return write!(f, "tag:{}", key),
+        };
+        f.write_str(name)
+    }
+}
+
+/// Appends the header row (the names of the columns) to `out`.
+pub fn write_header(out: &mut Vec<u8>, columns: &[Column], separator: u8) {
+    for (i, column) in columns.iter().enumerate() {
+        if i > 0 {
+            out.push(separator);
+        }
+        write_field(out, column.to_string().as_bytes(), separator);
+    }
+    out.push(b'\n');
+}
+
+/// Appends a row per span of `batch` to `out`.
+pub fn write_rows(
+    out: &mut Vec<u8>,
+    batch: &EmitBatchNotification,
+    meta: &ReceiveMeta,
+    columns: &[Column],
+    separator: u8,
+) {
+    let mut value = Vec::new();
+    for span in &batch.batch.spans {
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                out.push(separator);
+            }
+            value.clear();
+            column.write_value(&mut value, batch, span, meta);
+            write_field(out, &value, separator);
+        }
+        out.push(b'\n');
+    }
+}
+
+fn write_field(out: &mut Vec<u8>, value: &[u8], separator: u8) {
+    if separator == b'\t' {
+        for &b in value {
+            match b {
+                b'\t' => out.extend_from_slice(b"\\t"),
+                b'\n' => out.extend_from_slice(b"\\n"),
+                b'\r' => out.extend_from_slice(b"\\r"),
+                b'\\' => out.extend_from_slice(b"\\\\"),
+                _ => out.push(b),
+            }
+        }
+    } else if value
+        .iter()
+        .any(|&b| b == separator || b == b'"' || b == b'\n' || b == b'\r')
+    {
+        out.push(b'"');
+        for &b in value {
+            if b == b'"' {
+                out.push(b'"');
+            }
+            out.push(b);
+        }
+        out.push(b'"');
+    } else {
+        out.extend_from_slice(value);
+    }
+}
+
+/// Returns `true` if `span` has the `error` tag set to `true`.
+pub(crate) fn is_error(span: &Span) -> bool {
+    span.tags.iter().any(|t| {
+        t.key == "error" && match t.value {
+            TagValue::Bool(v) => v,
+            TagValue::Str(ref v) => v == "true",
+            _ => false,
+        }
+    })
+}
+
+fn find_tag<'a>(span: &'a Span, key: &str) -> Option<&'a TagValue> {
+    span.tags.iter().find(|t| t.key == key).map(|t| &t.value)
+}
+
+fn write_tag_value(out: &mut Vec<u8>, value: &TagValue) -> ::std::io::Result<()> {
+    match *value {
+        TagValue::Str(ref v) => out.write_all(v.as_bytes()),
+        TagValue::Bool(v) => write!(out, "{}", v),
+        TagValue::Long(v) => write!(out, "{}", v),
+        TagValue::Double(v) => write!(out, "{}", v),
+        TagValue::Binary(ref v) => out.write_all(encode_base64(v).as_bytes()),
+    }
+}
+
+/// Writes microseconds as seconds with six decimal places.
+fn write_seconds(out: &mut Vec<u8>, us: i64) -> ::std::io::Result<()> {
+    let sign = if us < 0 { "-" } else { "" };
+    let us = us.unsigned_abs();
+    write!(out, "{}{}.{:06}", sign, us / 1_000_000, us % 1_000_000)
+}
+
+/// Writes microseconds as milliseconds with three decimal places.
+pub(crate) fn write_milliseconds(out: &mut Vec<u8>, us: i64) -> ::std::io::Result<()> {
+    let sign = if us < 0 { "-" } else { "" };
+    let us = us.unsigned_abs();
+    write!(out, "{}{}.{:03}", sign, us / 1000, us % 1000)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::{Batch, Process, SpanId, Tag, TraceId, UnknownFields};
+    use thrift::Protocol;
+
+    /// Writes the rows of spans having the `v` tag of each of `values`.
+    fn rows(columns: &str, values: &[&str], separator: u8) -> String {
+        let columns = columns
+            .split(',')
+            .map(|c| c.parse())
+            .collect::<Result<Vec<Column>, _>>()
+            .unwrap();
+        let spans = values
+            .iter()
+            .map(|v| Span {
+                trace_id: TraceId { high: 0, low: 1 },
+                span_id: SpanId(2),
+                parent_span_id: None,
+                operation_name: "get".to_owned(),
+                references: Vec::new(),
+                flags: 1,
+                start_time: -1,
+                duration: 1_500_001,
+                tags: vec![Tag::new("v", *v)],
+                logs: Vec::new(),
+                unknown_fields: UnknownFields::new(),
+            })
+            .collect();
+        let batch = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans,
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            port: 6831,
+            protocol: &Protocol::Compact,
+            bytes: &[],
+        };
+        let mut out = Vec::new();
+        write_rows(&mut out, &batch, &meta, &columns, separator);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let values = ["plain", "a,b", "say \"hi\"", "a\nb", "a\rb", "a\tb", "a\\b", ""];
+        assert_eq!(
+            rows("tag:v", &values, b','),
+            "plain\n\"a,b\"\n\"say \"\"hi\"\"\"\n\"a\nb\"\n\"a\rb\"\na\tb\na\\b\n\n"
+        );
+
+        // Only the separator in use is quoted
+        assert_eq!(rows("tag:v", &["a,b", "a;b"], b';'), "a,b\n\"a;b\"\n");
+    }
+
+    #[test]
+    fn tsv_fields_are_escaped() {
+        let values = ["a,b", "say \"hi\"", "a\tb", "a\nb", "a\rb", "a\\b"];
+        assert_eq!(
+            rows("tag:v", &values, b'\t'),
+            "a,b\nsay \"hi\"\na\\tb\na\\nb\na\\rb\na\\\\b\n"
+        );
+    }
+
+    #[test]
+    fn values_are_formatted() {
+        assert_eq!(
+            rows("trace_id,span_id,parent_span_id,service,operation,start,duration", &["x"], b','),
+            "0x1,0x2,,foo,get,-0.000001,1.500001\n"
+        );
+        assert_eq!(
+            rows("duration_us,duration_ms,error,kind,flags,peer,tag:absent", &["x"], b','),
+            "1500001,1500.001,false,,1,127.0.0.1:1234,\n"
+        );
+    }
+
+    #[test]
+    fn header_is_quoted() {
+        let columns = vec![Column::TraceId, Column::Tag("a,b".to_owned())];
+        let mut out = Vec::new();
+        write_header(&mut out, &columns, b',');
+        assert_eq!(out, b"trace_id,\"tag:a,b\"\n");
+    }
+
+    #[test]
+    fn columns_are_parsed() {
+        for name in &["trace_id", "duration_ms", "peer", "tag:http.method", "tag:a:b"] {
+            assert_eq!(name.parse::<Column>().unwrap().to_string(), *name);
+        }
+        assert!("tag:".parse::<Column>().is_err());
+        assert!("Trace_id".parse::<Column>().is_err());
+        assert!("".parse::<Column>().is_err());
+    }
+}
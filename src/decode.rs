@@ -0,0 +1,375 @@
+//! The `decode` subcommand, which decodes captured messages from files (or the standard input)
+//! and outputs them in the same way as the server.
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use jaegercat::Result;
+use jaegercat::decoder;
+use jaegercat::filter::SpanFilter;
+use jaegercat::handler::{BatchFormatter, Formatter};
+use jaegercat::kafka::KafkaSpan;
+use jaegercat::protocol::WireProtocol;
+use jaegercat::server::ReceiveMeta;
+use jaegercat::thrift::{DecodeLimits, DecodeMode, Protocol};
+use trackable::error::{Failed, Failure};
+
+/// A message in an input and its offset.
+pub type Message<'a> = (usize, &'a [u8]);
+
+/// Settings of a decode run.
+#[derive(Debug, Clone)]
+pub struct Decode {
+    pub protocol: InputProtocol,
+    pub decode_mode: DecodeMode,
+    pub decode_limits: DecodeLimits,
+    pub filter: SpanFilter,
+}
+impl Decode {
+    /// Decodes the messages in `inputs` (the standard input if it is empty or `-`),
+    /// and writes them to the standard output.
+    ///
+    /// Each input may contain any number of messages, which are concatenated without framing
+    /// (as written by `--format raw`) or preceded by their 4 bytes big-endian lengths
+    /// (as written by `--format raw --length-prefix`).
+    /// The inputs are read as streams, and each message is written as soon as it has been read
+    /// (e.g., from `kcat` consuming a live topic).
+    /// Malformed messages are reported to the standard error, and make the run fail
+    /// after all the inputs are processed.
+    pub fn run(&self, inputs: &[&str], formatter: &Formatter) -> Result<()> {
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        let mut errors = 0;
+        let inputs = if inputs.is_empty() { &["-"][..] } else { inputs };
+        for &input in inputs {
+            let reader = track!(open_input(input))?;
+            errors += track!(self.decode_stream(input, reader, formatter, &mut writer))?;
+        }
+        track!(writer.flush().map_err(Failure::from_error))?;
+        track_assert_eq!(errors, 0, Failed, "{} messages could not be decoded", errors);
+        Ok(())
+    }
+
+    /// Decodes and writes the messages read from `reader` as they arrive,
+    /// and returns the number of the errors.
+    ///
+    /// The writer is flushed whenever the messages read so far have been written.
+    fn decode_stream<R: Read, W: Write>(
+        &self,
+        input: &str,
+        mut reader: R,
+        formatter: &Formatter,
+        writer: &mut W,
+    ) -> Result<usize> {
+        let mut buf = Vec::new();
+        let mut chunk = vec![0; 64 * 1024];
+        let mut offset = 0; // The offset of `buf` in the input
+        let mut errors = 0;
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(read) => read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(track!(Failure::from_error(e), "{}", input)),
+            };
+            let eof = read == 0;
+            buf.extend_from_slice(&chunk[..read]);
+
+            let mut pos = 0;
+            while pos < buf.len() {
+                match self.next_message(&buf[pos..], eof) {
+                    Ok(Some((start, len))) => {
+                        let message = &buf[pos + start..pos + start + len];
+                        let location = format!("{}: offset={}", input, offset + pos);
+                        if !track!(self.decode_message(message, &location, formatter, writer))? {
+                            errors += 1;
+                        }
+                        pos += start + len;
+                    }
+                    Ok(None) => break,
+                    Err(reason) => {
+                        // The boundaries of the following messages are unknown
+                        eprintln!("{}: offset={}: {}", input, offset + pos, reason);
+                        track!(writer.flush().map_err(Failure::from_error))?;
+                        return Ok(errors + 1);
+                    }
+                }
+            }
+            buf.drain(..pos);
+            offset += pos;
+            track!(writer.flush().map_err(Failure::from_error))?;
+            if eof {
+                return Ok(errors);
+            }
+        }
+    }
+
+    /// Decodes and writes a message in the input, and returns `false` if it is malformed.
+    fn decode_message<W: Write>(
+        &self,
+        message: &[u8],
+        location: &str,
+        formatter: &Formatter,
+        writer: &mut W,
+    ) -> Result<bool> {
+        let thrift;
+        let protocol: &dyn WireProtocol = match self.protocol {
+            InputProtocol::Auto => {
+                thrift = detect_protocol(message);
+                &thrift
+            }
+            InputProtocol::Thrift(ref protocol) => protocol,
+            InputProtocol::KafkaSpan => &KafkaSpan,
+        };
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([0, 0, 0, 0], 0)),
+            port: 0,
+            protocol,
+            bytes: message,
+        };
+        track!(self.write_message(&meta, location, formatter, writer))
+    }
+
+    /// Splits `buf` into messages, and returns them with their offsets.
+    ///
+    /// If the boundary of a message cannot be determined, the rest of `buf` is skipped,
+    /// and the offset and the reason are returned too.
+    pub fn split<'a>(&self, buf: &'a [u8]) -> (Vec<Message<'a>>, Option<(usize, String)>) {
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while offset < buf.len() {
+            match self.next_message(&buf[offset..], true) {
+                Ok(Some((start, len))) => {
+                    let start = offset + start;
+                    messages.push((offset, &buf[start..start + len]));
+                    offset = start + len;
+                }
+                Ok(None) => unreachable!(),
+                Err(reason) => return (messages, Some((offset, reason))),
+            }
+        }
+        (messages, None)
+    }
+
+    /// Decodes and writes a message, and returns `false` if it is malformed.
+    ///
+    /// `location` tells where the message comes from in the error messages.
+    pub fn write_message<W: Write>(
+        &self,
+        meta: &ReceiveMeta,
+        location: &str,
+        formatter: &Formatter,
+        writer: &mut W,
+    ) -> Result<bool> {
+        let mut out = Vec::new();
+        // The filter needs the spans, so decoding cannot be skipped
+        if !self.filter.is_empty() || !track!(formatter.format_undecoded(meta, &mut out))? {
+            let decoded = meta.protocol
+                .decode_batch(meta.bytes, self.decode_mode, &self.decode_limits);
+            match decoded {
+                Ok((mut batch, partial)) => {
+                    if let Some(e) = partial {
+                        eprintln!("{}: partially decoded: {}", location, e);
+                    }
+                    if self.filter.apply(&mut batch.batch) {
+                        track!(formatter.format(&batch, meta, &mut out))?;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", location, e);
+                    return Ok(false);
+                }
+            }
+        }
+        track!(writer.write_all(&out).map_err(Failure::from_error))?;
+        Ok(true)
+    }
+
+    /// Returns the offset and the size of the first message in `buf`.
+    ///
+    /// `None` means that the message continues beyond `buf`, which is returned only
+    /// unless `eof` (i.e., `buf` is the rest of the input).
+    fn next_message(
+        &self,
+        buf: &[u8],
+        eof: bool,
+    ) -> ::std::result::Result<Option<(usize, usize)>, String> {
+        let truncated = |reason: String| if eof { Err(reason) } else { Ok(None) };
+        if buf[0] == 0 {
+            // A length prefix (a message never starts with zero)
+            if buf.len() < 4 {
+                return truncated("Truncated length prefix".to_owned());
+            }
+            let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+            if buf.len() - 4 < len {
+                return truncated(format!("Truncated message of {} bytes", len));
+            }
+            return Ok(Some((4, len)));
+        }
+        let protocol = match self.protocol {
+            InputProtocol::Auto => detect_protocol(buf),
+            InputProtocol::Thrift(protocol) => protocol,
+            InputProtocol::KafkaSpan => {
+                // Unprefixed messages are accepted only in the JSON encoding (one per line)
+                if buf[0] != b'{' {
+                    return Err("Unprefixed message which is not a JSON object".to_owned());
+                }
+                return match buf.iter().position(|&b| b == b'\n') {
+                    Some(i) => Ok(Some((0, i + 1))),
+                    None if eof => Ok(Some((0, buf.len()))),
+                    None => Ok(None),
+                };
+            }
+        };
+        match decoder::message_len(buf, protocol, &self.decode_limits) {
+            Ok(Some(len)) => Ok(Some((0, len))),
+            Ok(None) => truncated("Truncated message".to_owned()),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+/// Opens an input file (the standard input if `input` is `-`).
+pub fn open_input(input: &str) -> Result<Box<dyn Read>> {
+    if input == "-" {
+        Ok(Box::new(io::stdin()))
+    } else {
+        let file = track!(File::open(input).map_err(Failure::from_error), "{}", input)?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Reads a whole input file (the standard input if `input` is `-`).
+pub fn read_input(input: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if input == "-" {
+        track!(io::stdin().read_to_end(&mut buf).map_err(Failure::from_error))?;
+    } else {
+        let mut file = track!(File::open(input).map_err(Failure::from_error), "{}", input)?;
+        track!(file.read_to_end(&mut buf).map_err(Failure::from_error), "{}", input)?;
+    }
+    Ok(buf)
+}
+
+/// The protocol of the messages given to the `decode` subcommand.
+#[derive(Debug, Clone, Copy)]
+pub enum InputProtocol {
+    /// Detects the Thrift protocol from each message.
+    Auto,
+    Thrift(Protocol),
+
+    /// Spans in the `jaeger-spans` Kafka topic (see `jaegercat::kafka`).
+    KafkaSpan,
+}
+
+/// Detects the protocol of a message from its first byte.
+///
+/// A message in the compact protocol starts with the protocol ID (`0x82`),
+/// and one in the binary protocol starts with the version (`0x80 0x01`).
+fn detect_protocol(message: &[u8]) -> Protocol {
+    if message.first() == Some(&0x80) {
+        Protocol::Binary
+    } else {
+        Protocol::Compact
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use jaegercat::handler::Format;
+
+    use super::*;
+
+    /// Writes to a buffer shared with `Source`.
+    struct Sink(Rc<RefCell<Vec<u8>>>);
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Yields `chunks`, and records the output written before each read.
+    struct Source {
+        chunks: Vec<Vec<u8>>,
+        output: Rc<RefCell<Vec<u8>>>,
+        seen: Vec<String>,
+    }
+    impl Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.seen.push(String::from_utf8(self.output.borrow().clone()).unwrap());
+            if self.chunks.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.chunks.remove(0);
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    /// Returns a `jaeger-spans` message terminated by a newline.
+    fn span(operation_name: &str) -> Vec<u8> {
+        format!(
+            concat!(
+                r#"{{"traceId": "W47/95gDgQPSabYzgT/GDA==", "spanId": "7uGbfsPBsXQ=", "#,
+                r#""operationName": "{}", "startTime": "2018-12-13T14:51:00.5Z", "#,
+                r#""process": {{"serviceName": "foo"}}}}"#,
+                "\n"
+            ),
+            operation_name
+        ).into_bytes()
+    }
+
+    fn decode(protocol: InputProtocol) -> Decode {
+        Decode {
+            protocol,
+            decode_mode: DecodeMode::Normal,
+            decode_limits: DecodeLimits::default(),
+            filter: SpanFilter::default(),
+        }
+    }
+
+    #[test]
+    fn decode_stream_writes_messages_before_eof() {
+        let (first, second) = (span("first"), span("second"));
+        let (head, tail) = second.split_at(10);
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let mut source = Source {
+            chunks: vec![[&first[..], head].concat(), tail.to_vec()],
+            output: output.clone(),
+            seen: Vec::new(),
+        };
+        let formatter = Formatter::new(Format::Json);
+        let errors = decode(InputProtocol::KafkaSpan)
+            .decode_stream("-", &mut source, &formatter, &mut Sink(output))
+            .unwrap();
+        assert_eq!(errors, 0);
+
+        assert_eq!(source.seen.len(), 3);
+        assert!(source.seen[0].is_empty());
+        assert!(source.seen[1].contains("first"));
+        assert!(!source.seen[1].contains("second"));
+        assert!(source.seen[2].contains("second"));
+    }
+
+    #[test]
+    fn decode_stream_reports_truncated_message_at_eof() {
+        let mut message = vec![0, 0, 0, 8];
+        message.extend_from_slice(&span("first")[..4]);
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let source = Source {
+            chunks: vec![message],
+            output: output.clone(),
+            seen: Vec::new(),
+        };
+        let formatter = Formatter::new(Format::Json);
+        let errors = decode(InputProtocol::Auto)
+            .decode_stream("-", source, &formatter, &mut Sink(output.clone()))
+            .unwrap();
+        assert_eq!(errors, 1);
+        assert!(output.borrow().is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use borrowed;
+use error::{DecodeError, DecodeResult as Result};
+use hook::DecodeHook;
+use thrift::{DecodeLimits, EmitBatchNotification, Protocol};
+use wire::{Reader, Type};
+
+/// A decoder which accepts a byte stream in arbitrary sized chunks.
+///
+/// This is useful for sources which do not preserve message boundaries
+/// (e.g., TCP connections or files).
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::decoder::StreamDecoder;
+/// use jaegercat::thrift::Protocol;
+///
+/// let mut decoder = StreamDecoder::new(Protocol::Compact);
+/// decoder.feed(&[0x82]);
+/// assert!(decoder.decode_next().unwrap().is_none()); // Needs more bytes
+/// ```
+#[derive(Debug)]
+pub struct StreamDecoder {
+    protocol: Protocol,
+    limits: DecodeLimits,
+    hook: Option<Arc<dyn DecodeHook>>,
+    buf: Vec<u8>,
+}
+impl StreamDecoder {
+    /// Makes a new `StreamDecoder` instance.
+    pub fn new(protocol: Protocol) -> Self {
+        StreamDecoder {
+            protocol,
+            limits: DecodeLimits::default(),
+            hook: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the limits on the sizes of the values in the messages.
+    ///
+    /// Because a message exceeding the limits is rejected without waiting for the rest of it,
+    /// the internal buffer does not grow indefinitely by a corrupt length prefix.
+    ///
+    /// The default value is `DecodeLimits::default()`.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    /// Sets the hook which observes the decoded messages and the decoding failures.
+    pub fn set_hook<H: DecodeHook + 'static>(&mut self, hook: H) {
+        self.hook = Some(Arc::new(hook));
+    }
+
+    /// Appends the given bytes to the internal buffer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Decodes the next message from the buffered bytes.
+    ///
+    /// `Ok(None)` means that more bytes are needed to complete the next message.
+    ///
+    /// If the buffered bytes are malformed, they are discarded and an error is returned,
+    /// because the boundary of the next message cannot be determined.
+    pub fn decode_next(&mut self) -> Result<Option<EmitBatchNotification>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let (result, consumed) = {
+            let mut reader = Reader::new(&self.buf, self.protocol);
+            reader.set_limits(self.limits);
+            match borrowed::read_message(&mut reader) {
+                Ok(batch) => (Ok(Some(batch.into_owned())), reader.position()),
+                Err(_) if reader.is_truncated() => (Ok(None), 0),
+                Err(e) => (Err(e), self.buf.len()),
+            }
+        };
+        if let Some(ref hook) = self.hook {
+            match result {
+                Ok(Some(ref batch)) => hook.on_decoded(consumed, batch.spans.len()),
+                Err(ref e) => hook.on_error(consumed, e),
+                Ok(None) => {}
+            }
+        }
+        self.buf.drain(..consumed);
+        result.map(|batch| batch.map(|batch| EmitBatchNotification { batch }))
+    }
+
+    /// Returns the number of the buffered bytes which have not been decoded yet.
+    pub fn buffered_len(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+/// Returns the size of the message at the beginning of `buf` without decoding its contents.
+///
+/// This finds the boundaries of messages which are concatenated without any framing.
+/// `Ok(None)` means that `buf` ends in the middle of the message.
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::decoder::message_len;
+/// use jaegercat::thrift::{DecodeLimits, Protocol};
+///
+/// let limits = DecodeLimits::default();
+/// assert_eq!(message_len(&[0x82], Protocol::Compact, &limits).unwrap(), None);
+/// assert!(message_len(&[0x00], Protocol::Compact, &limits).is_err());
+/// ```
+pub fn message_len(buf: &[u8], protocol: Protocol, limits: &DecodeLimits) -> Result<Option<usize>> {
+    let mut reader = Reader::new(buf, protocol);
+    reader.set_limits(*limits);
+    let result = reader
+        .read_message_begin()
+        .and_then(|_| reader.skip(Type::Struct));
+    match result {
+        Ok(()) => Ok(Some(reader.position())),
+        Err(_) if reader.is_truncated() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// An iterator which decodes successive messages from a byte stream.
+///
+/// The messages are expected to be concatenated without any framing (e.g., a capture file
+/// made by `jaegercat --format raw`).
+///
+/// The iteration ends when the stream reaches EOF or an error occurs.
+#[derive(Debug)]
+pub struct MessageIter<R> {
+    reader: R,
+    decoder: StreamDecoder,
+    buf: Vec<u8>,
+    eos: bool,
+}
+impl<R: Read> MessageIter<R> {
+    /// Makes a new `MessageIter` instance.
+    pub fn new(reader: R, protocol: Protocol) -> Self {
+        MessageIter {
+            reader,
+            decoder: StreamDecoder::new(protocol),
+            buf: vec![0; 4096],
+            eos: false,
+        }
+    }
+
+    /// Sets the limits on the sizes of the values in the messages.
+    ///
+    /// See `StreamDecoder::set_limits`.
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.decoder.set_limits(limits);
+    }
+
+    /// Sets the hook which observes the decoded messages and the decoding failures.
+    ///
+    /// See `StreamDecoder::set_hook`.
+    pub fn set_hook<H: DecodeHook + 'static>(&mut self, hook: H) {
+        self.decoder.set_hook(hook);
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn next_message(&mut self) -> Result<Option<EmitBatchNotification>> {
+        loop {
+            if let Some(message) = self.decoder.decode_next()? {
+                return Ok(Some(message));
+            }
+            let size = match self.reader.read(&mut self.buf) {
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => result?,
+            };
+            if size == 0 {
+                self.eos = true;
+                if self.decoder.buffered_len() != 0 {
+                    return Err(DecodeError::Truncated {
+                        offset: self.decoder.buffered_len(),
+                    });
+                }
+                return Ok(None);
+            }
+            self.decoder.feed(&self.buf[..size]);
+        }
+    }
+}
+impl<R: Read> Iterator for MessageIter<R> {
+    type Item = Result<EmitBatchNotification>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.eos {
+            return None;
+        }
+        match self.next_message() {
+            Ok(message) => message.map(Ok),
+            Err(e) => {
+                self.eos = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Directory into which `Server` writes each received datagram as a file.
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use trackable::error::Failure;
+
+use Result;
+use server::ReceiveMeta;
+
+/// A directory of datagrams, one file per datagram.
+///
+/// The files are named `SECONDS.MICROSECONDS-SEQUENCE-PROTOCOL-IP-PORT.bin` after the time of
+/// the write, a sequence number (which tells apart the datagrams written in the same microsecond),
+/// the wire protocol and the address of the sender, so that they sort by time,
+/// e.g., `1517376258.665418-000001-compact-127.0.0.1-53412.bin`.
+#[derive(Debug)]
+pub struct DumpDir {
+    path: PathBuf,
+    sequence: AtomicUsize,
+}
+impl DumpDir {
+    /// Creates the directory at `path` (and its parents) unless it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        track!(fs::create_dir_all(&path).map_err(Failure::from_error), "path={:?}", path)?;
+        Ok(DumpDir {
+            path,
+            sequence: AtomicUsize::new(0),
+        })
+    }
+
+    /// Writes the bytes of a datagram to a new file, and returns the path of the file.
+    pub fn write(&self, meta: &ReceiveMeta) -> Result<PathBuf> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed) + 1;
+        // IPv6 addresses are written with `_` instead of `:`, which some file systems reject
+        let ip = meta.peer.ip().to_canonical().to_string().replace(':', "_");
+        let name = format!(
+            "{}.{:06}-{:06}-{}-{}-{}.bin",
+            now.as_secs(),
+            now.subsec_micros(),
+            sequence,
+            meta.protocol.name(),
+            ip,
+            meta.peer.port()
+        );
+        let path = self.path.join(name);
+        let mut file = track!(
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(Failure::from_error),
+            "path={:?}",
+            path
+        )?;
+        track!(file.write_all(meta.bytes).map_err(Failure::from_error), "path={:?}", path)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,309 @@
+//! Client side API which sends spans to an agent (e.g., `jaegercat` itself).
+//!
+//! This is mainly intended for generating traffic in tests without a full tracing library.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use jaegercat::emit::{Emitter, SpanBuilder};
+//! use jaegercat::thrift::Protocol;
+//!
+//! let mut emitter = Emitter::new("127.0.0.1:6831".parse().unwrap(), Protocol::Compact, "foo")
+//!     .unwrap();
+//! emitter.process_tag("hostname", "localhost");
+//!
+//! let parent = SpanBuilder::new("parent").duration(2000).build();
+//! let child = SpanBuilder::new("child").child_of(&parent).tag("retry", 3).build();
+//! emitter.emit(vec![parent, child]).unwrap();
+//! ```
+//!
+//! `Emitter::http` sends the batches to a collector (e.g., `jaegercat --collector-http-port`)
+//! instead.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use trackable::error::{Failed, Failure};
+
+use Result;
+use model::{Batch, Log, Process, Span, SpanId, SpanRef, SpanRefType, Tag, TagValue, TraceId,
+            UnknownFields};
+use thrift::{EmitBatchNotification, Protocol};
+
+/// An emitter which sends batches of spans to an agent over UDP, or to a collector over HTTP.
+#[derive(Debug)]
+pub struct Emitter {
+    transport: Transport,
+    process: Process,
+}
+impl Emitter {
+    /// Makes a new `Emitter` instance which sends batches to `agent_addr`
+    /// on behalf of the service named `service_name`.
+    pub fn new<S: Into<String>>(
+        agent_addr: SocketAddr,
+        protocol: Protocol,
+        service_name: S,
+    ) -> Result<Self> {
+        let bind_addr: SocketAddr = if agent_addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = track!(UdpSocket::bind(bind_addr).map_err(Failure::from_error))?;
+        let transport = Transport::Udp {
+            socket,
+            agent_addr,
+            protocol,
+        };
+        Ok(Emitter::with_transport(transport, service_name))
+    }
+
+    /// Makes a new `Emitter` instance which sends batches to the collector at `collector_addr`
+    /// on behalf of the service named `service_name`.
+    ///
+    /// Each batch is sent by a `POST /api/traces` request on a new connection,
+    /// whose body is a bare `Batch` struct in the binary protocol
+    /// (see `protocol::CollectorThrift`).
+    pub fn http<S: Into<String>>(collector_addr: SocketAddr, service_name: S) -> Self {
+        Emitter::with_transport(Transport::Http { collector_addr }, service_name)
+    }
+
+    fn with_transport<S: Into<String>>(transport: Transport, service_name: S) -> Self {
+        Emitter {
+            transport,
+            process: Process {
+                service_name: service_name.into(),
+                tags: Vec::new(),
+                unknown_fields: UnknownFields::new(),
+            },
+        }
+    }
+
+    /// Adds a tag to the process which is reported with every batch.
+    pub fn process_tag<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<String>,
+        V: Into<TagValue>,
+    {
+        self.process.tags.push(Tag::new(key, value));
+        self
+    }
+
+    /// Returns the process which is reported with every batch.
+    pub fn process(&self) -> &Process {
+        &self.process
+    }
+
+    /// Returns the local address of the socket used to send batches over UDP.
+    ///
+    /// This fails if the emitter sends batches over HTTP, which connects for every batch.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        match self.transport {
+            Transport::Udp { ref socket, .. } => {
+                track!(socket.local_addr().map_err(Failure::from_error))
+            }
+            Transport::Http { .. } => track_panic!(Failed, "HTTP emitters have no socket"),
+        }
+    }
+
+    /// Sends the given spans as a batch of the process of the emitter.
+    pub fn emit(&self, spans: Vec<Span>) -> Result<()> {
+        let batch = Batch {
+            process: self.process.clone(),
+            spans,
+            seq_no: None,
+            stats: None,
+            unknown_fields: UnknownFields::new(),
+        };
+        track!(self.emit_batch(&batch))
+    }
+
+    /// Sends the given batch as is.
+    pub fn emit_batch(&self, batch: &Batch) -> Result<()> {
+        let message = EmitBatchNotification {
+            batch: batch.clone(),
+        };
+        match self.transport {
+            Transport::Udp {
+                ref socket,
+                agent_addr,
+                protocol,
+            } => {
+                let bytes = message.encode(protocol);
+                let size =
+                    track!(socket.send_to(&bytes, agent_addr).map_err(Failure::from_error))?;
+                track_assert_eq!(size, bytes.len(), Failed, "Datagram was truncated");
+                Ok(())
+            }
+            Transport::Http { collector_addr } => {
+                let body = message.encode_batch(Protocol::Binary);
+                track!(post(collector_addr, &body), "collector_addr={}", collector_addr)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp {
+        socket: UdpSocket,
+        agent_addr: SocketAddr,
+        protocol: Protocol,
+    },
+    Http {
+        collector_addr: SocketAddr,
+    },
+}
+
+/// Sends `body` by a `POST /api/traces` request, and fails unless the response is successful.
+fn post(addr: SocketAddr, body: &[u8]) -> Result<()> {
+    let mut stream = track!(TcpStream::connect(addr).map_err(Failure::from_error))?;
+    let header = format!(
+        "POST /api/traces HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-thrift\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n",
+        addr,
+        body.len()
+    );
+    track!(stream.write_all(header.as_bytes()).map_err(Failure::from_error))?;
+    track!(stream.write_all(body).map_err(Failure::from_error))?;
+
+    let mut response = Vec::new();
+    track!(stream.read_to_end(&mut response).map_err(Failure::from_error))?;
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    let success = status_line.split(' ').nth(1).is_some_and(|s| s.starts_with('2'));
+    track_assert!(success, Failed, "Unexpected response: {:?}", status_line);
+    Ok(())
+}
+
+/// A builder for `Span`.
+///
+/// By default, the span is a sampled root span of a new trace, which starts at the current time
+/// and has zero duration.
+#[derive(Debug, Clone)]
+pub struct SpanBuilder {
+    span: Span,
+}
+impl SpanBuilder {
+    /// Makes a new `SpanBuilder` instance.
+    pub fn new<S: Into<String>>(operation_name: S) -> Self {
+        SpanBuilder {
+            span: Span {
+                trace_id: TraceId {
+                    high: 0,
+                    low: random_id(),
+                },
+                span_id: SpanId(random_id()),
+                parent_span_id: None,
+                operation_name: operation_name.into(),
+                references: Vec::new(),
+                flags: 1,
+                start_time: now_us(),
+                duration: 0,
+                tags: Vec::new(),
+                logs: Vec::new(),
+                unknown_fields: UnknownFields::new(),
+            },
+        }
+    }
+
+    /// Sets the trace identifier of the span.
+    pub fn trace_id(&mut self, trace_id: TraceId) -> &mut Self {
+        self.span.trace_id = trace_id;
+        self
+    }
+
+    /// Sets the identifier of the span.
+    pub fn span_id(&mut self, span_id: SpanId) -> &mut Self {
+        self.span.span_id = span_id;
+        self
+    }
+
+    /// Makes the span a child of `parent`.
+    ///
+    /// The trace identifier and the parent span identifier are taken from `parent`.
+    pub fn child_of(&mut self, parent: &Span) -> &mut Self {
+        self.span.trace_id = parent.trace_id;
+        self.span.parent_span_id = Some(parent.span_id);
+        self
+    }
+
+    /// Adds a `FOLLOWS_FROM` reference to `span`.
+    pub fn follows_from(&mut self, span: &Span) -> &mut Self {
+        self.span.references.push(SpanRef {
+            ref_type: SpanRefType::FollowsFrom,
+            trace_id: span.trace_id,
+            span_id: span.span_id,
+            unknown_fields: UnknownFields::new(),
+        });
+        self
+    }
+
+    /// Sets the flags of the span.
+    ///
+    /// The default value is `1` (sampled).
+    pub fn flags(&mut self, flags: i32) -> &mut Self {
+        self.span.flags = flags;
+        self
+    }
+
+    /// Sets the start time of the span in microseconds since the UNIX epoch.
+    pub fn start_time(&mut self, start_time: i64) -> &mut Self {
+        self.span.start_time = start_time;
+        self
+    }
+
+    /// Sets the duration of the span in microseconds.
+    pub fn duration(&mut self, duration: i64) -> &mut Self {
+        self.span.duration = duration;
+        self
+    }
+
+    /// Adds a tag to the span.
+    pub fn tag<K, V>(&mut self, key: K, value: V) -> &mut Self
+    where
+        K: Into<String>,
+        V: Into<TagValue>,
+    {
+        self.span.tags.push(Tag::new(key, value));
+        self
+    }
+
+    /// Adds a log event which occurred at `timestamp` (in microseconds since the UNIX epoch).
+    pub fn log(&mut self, timestamp: i64, fields: Vec<Tag>) -> &mut Self {
+        self.span.logs.push(Log {
+            timestamp,
+            fields,
+            unknown_fields: UnknownFields::new(),
+        });
+        self
+    }
+
+    /// Builds a `Span` instance.
+    pub fn build(&self) -> Span {
+        self.span.clone()
+    }
+}
+
+/// Returns a random non-zero identifier.
+///
+/// The per-process random keys of `RandomState` are mixed with a counter,
+/// so that successive calls return distinct values.
+fn random_id() -> i64 {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+    match hasher.finish() as i64 {
+        0 => 1,
+        id => id,
+    }
+}
+
+fn now_us() -> i64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    elapsed.as_secs() as i64 * 1_000_000 + i64::from(elapsed.subsec_micros())
+}
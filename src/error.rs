@@ -0,0 +1,264 @@
+#[cfg(feature = "std")]
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "std")]
+use std::io;
+use std::str::Utf8Error;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+/// This crate specific `Result` type for decoding.
+pub type DecodeResult<T> = ::std::result::Result<T, DecodeError>;
+
+/// An error which occurred while decoding a message.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The input ended before the message was complete.
+    Truncated {
+        /// The offset at which more bytes were required.
+        offset: usize,
+    },
+
+    /// The message header is not valid for the protocol.
+    InvalidHeader {
+        /// The offset of the invalid part.
+        offset: usize,
+
+        /// The description of the problem.
+        reason: String,
+    },
+
+    /// The message is not a one-way `emitBatch` call.
+    UnexpectedMessage {
+        /// The method name of the message.
+        name: String,
+
+        /// The kind of the message (`4` means one-way).
+        kind: u8,
+    },
+
+    /// An unknown Thrift type code was found.
+    UnknownTypeCode {
+        /// The offset of the type code.
+        offset: usize,
+
+        /// The type code.
+        code: u8,
+    },
+
+    /// A field has a type different from the one defined in the schema.
+    UnexpectedType {
+        /// The name of the struct containing the field.
+        struct_name: &'static str,
+
+        /// The ID of the field.
+        field_id: i16,
+
+        /// The offset of the field header.
+        offset: usize,
+
+        /// The type defined in the schema.
+        expected: &'static str,
+
+        /// The type found in the message.
+        actual: &'static str,
+    },
+
+    /// The elements of a list have a type different from the one defined in the schema.
+    UnexpectedElementType {
+        /// The name of the list (e.g., `Batch.spans`).
+        list: &'static str,
+
+        /// The offset of the list header.
+        offset: usize,
+
+        /// The type defined in the schema.
+        expected: &'static str,
+
+        /// The type found in the message.
+        actual: &'static str,
+    },
+
+    /// A field which is not defined in the schema was found.
+    ///
+    /// This is only reported in strict mode.
+    UnknownField {
+        /// The name of the struct containing the field.
+        struct_name: &'static str,
+
+        /// The ID of the field.
+        field_id: i16,
+
+        /// The offset of the field header.
+        offset: usize,
+
+        /// The type of the field.
+        actual: &'static str,
+    },
+
+    /// A required field is missing.
+    MissingField {
+        /// The name of the field (e.g., `Span.spanId`).
+        field: &'static str,
+    },
+
+    /// A string is not valid UTF-8.
+    InvalidUtf8 {
+        /// The offset of the string.
+        offset: usize,
+
+        /// The underlying error.
+        error: Utf8Error,
+    },
+
+    /// A value is out of the valid range.
+    InvalidValue {
+        /// The offset of the value.
+        offset: usize,
+
+        /// The description of the problem.
+        reason: String,
+    },
+
+    /// A value exceeds one of the `DecodeLimits`.
+    LimitExceeded {
+        /// The name of the limit (e.g., `max_string_len`).
+        limit: &'static str,
+
+        /// The offset of the value.
+        offset: usize,
+
+        /// The size (or the depth) of the value.
+        size: u64,
+
+        /// The value of the limit.
+        max: usize,
+    },
+
+    /// There are unexpected bytes after the message.
+    ///
+    /// This is only reported in strict mode.
+    TrailingBytes {
+        /// The offset of the first trailing byte.
+        offset: usize,
+
+        /// The number of the trailing bytes.
+        size: usize,
+    },
+
+    /// An I/O error occurred while reading a stream.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+impl DecodeError {
+    /// Returns `true` if the error was caused by insufficient input.
+    pub fn is_truncated(&self) -> bool {
+        matches!(*self, DecodeError::Truncated { .. })
+    }
+
+    /// Returns the offset in the message at which the error was detected, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            DecodeError::Truncated { offset }
+            | DecodeError::InvalidHeader { offset, .. }
+            | DecodeError::UnknownTypeCode { offset, .. }
+            | DecodeError::UnexpectedType { offset, .. }
+            | DecodeError::UnexpectedElementType { offset, .. }
+            | DecodeError::UnknownField { offset, .. }
+            | DecodeError::InvalidUtf8 { offset, .. }
+            | DecodeError::InvalidValue { offset, .. }
+            | DecodeError::LimitExceeded { offset, .. }
+            | DecodeError::TrailingBytes { offset, .. } => Some(offset),
+            DecodeError::UnexpectedMessage { .. } | DecodeError::MissingField { .. } => None,
+            #[cfg(feature = "std")]
+            DecodeError::Io(_) => None,
+        }
+    }
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::Truncated { offset } => {
+                write!(f, "Unexpected end of input: offset={}", offset)
+            }
+            DecodeError::InvalidHeader { offset, ref reason } => {
+                write!(f, "Invalid message header: offset={}, {}", offset, reason)
+            }
+            DecodeError::UnexpectedMessage { ref name, kind } => {
+                write!(f, "Unexpected message: name={:?}, kind={}", name, kind)
+            }
+            DecodeError::UnknownTypeCode { offset, code } => {
+                write!(f, "Unknown type code: offset={}, code={}", offset, code)
+            }
+            DecodeError::UnexpectedType {
+                struct_name,
+                field_id,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Unexpected field type: struct={}, field={}, offset={}, expected={}, actual={}",
+                struct_name, field_id, offset, expected, actual
+            ),
+            DecodeError::UnexpectedElementType {
+                list,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Unexpected element type: list={}, offset={}, expected={}, actual={}",
+                list, offset, expected, actual
+            ),
+            DecodeError::UnknownField {
+                struct_name,
+                field_id,
+                offset,
+                actual,
+            } => write!(
+                f,
+                "Unknown field: struct={}, field={}, offset={}, type={}",
+                struct_name, field_id, offset, actual
+            ),
+            DecodeError::MissingField { field } => write!(f, "Missing field: {}", field),
+            DecodeError::InvalidUtf8 { offset, ref error } => {
+                write!(f, "Invalid UTF-8 string: offset={}, {}", offset, error)
+            }
+            DecodeError::InvalidValue { offset, ref reason } => {
+                write!(f, "Invalid value: offset={}, {}", offset, reason)
+            }
+            DecodeError::LimitExceeded {
+                limit,
+                offset,
+                size,
+                max,
+            } => write!(
+                f,
+                "Limit exceeded: limit={}, offset={}, size={}, max={}",
+                limit, offset, size, max
+            ),
+            DecodeError::TrailingBytes { offset, size } => {
+                write!(f, "Trailing bytes: offset={}, size={}", offset, size)
+            }
+            #[cfg(feature = "std")]
+            DecodeError::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            DecodeError::InvalidUtf8 { ref error, .. } => Some(error),
+            DecodeError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl From<io::Error> for DecodeError {
+    fn from(f: io::Error) -> Self {
+        DecodeError::Io(f)
+    }
+}
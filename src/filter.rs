@@ -0,0 +1,373 @@
+//! Filtering of the decoded spans.
+//!
+//! `SpanFilter` decides which spans are output. `Server` (see `ServerBuilder::span_filter`)
+//! applies it to each decoded batch before formatting or handling it, and drops the batches
+//! whose spans are all filtered out, so that the unrelated traffic on a shared agent port
+//! costs no formatting and no output.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::filter::SpanFilter;
+//! use jaegercat::model::{Batch, Process, UnknownFields};
+//!
+//! let mut batch = Batch {
+//!     process: Process {
+//!         service_name: "foo".to_owned(),
+//!         tags: Vec::new(),
+//!         unknown_fields: UnknownFields::new(),
+//!     },
+//!     spans: Vec::new(),
+//!     seq_no: None,
+//!     stats: None,
+//!     unknown_fields: UnknownFields::new(),
+//! };
+//! let mut filter = SpanFilter::new();
+//! filter.service("foo").service("bar");
+//! assert!(filter.apply(&mut batch));
+//!
+//! batch.process.service_name = "baz".to_owned();
+//! assert!(!filter.apply(&mut batch));
+//! ```
+use std::str::FromStr;
+use std::time::Duration;
+use trackable::error::{Failed, Failure};
+
+use Result;
+use model::{Batch, Process, Span, Tag, TagValue, TraceId};
+use otlp::encode_base64;
+
+/// A filter of spans.
+///
+/// All the spans pass the default filter.
+#[derive(Debug, Clone, Default)]
+pub struct SpanFilter {
+    services: Vec<String>,
+    trace_ids: Vec<TraceId>,
+    tags: Vec<TagCondition>,
+    min_duration: Option<Duration>,
+    error_tags: Option<Vec<TagCondition>>,
+    sample_rate: Option<f64>,
+}
+impl SpanFilter {
+    /// Makes a new `SpanFilter` instance which passes all the spans.
+    pub fn new() -> Self {
+        SpanFilter::default()
+    }
+
+    /// Adds a service whose spans pass the filter.
+    ///
+    /// If any services are added, only the batches whose processes have one of
+    /// their names pass the filter.
+    pub fn service(&mut self, name: &str) -> &mut Self {
+        self.services.push(name.to_owned());
+        self
+    }
+
+    /// Adds a trace whose spans pass the filter.
+    ///
+    /// If any traces are added, only the spans of them pass the filter.
+    /// Note that the trace IDs are compared in all their 128 bits (e.g., `0x2ae64ad7cd070e8e`
+    /// does not match `0x154050ce43d48b612ae64ad7cd070e8e`).
+    pub fn trace_id(&mut self, id: TraceId) -> &mut Self {
+        self.trace_ids.push(id);
+        self
+    }
+
+    /// Adds a condition on the tags of the spans.
+    ///
+    /// If any conditions are added, only the spans satisfying all of them pass the filter.
+    pub fn tag(&mut self, condition: TagCondition) -> &mut Self {
+        self.tags.push(condition);
+        self
+    }
+
+    /// Sets the duration below which the spans are filtered out.
+    pub fn min_duration(&mut self, duration: Duration) -> &mut Self {
+        self.min_duration = Some(duration);
+        self
+    }
+
+    /// Sets the conditions on the tags which make spans errors, and filters out the other spans.
+    ///
+    /// A span is an error if its tags satisfy any of `conditions`
+    /// (e.g., `SpanFilter::default_error_tags()`).
+    pub fn errors_only(&mut self, conditions: Vec<TagCondition>) -> &mut Self {
+        self.error_tags = Some(conditions);
+        self
+    }
+
+    /// Returns the conditions of error spans by the conventions of OpenTracing and
+    /// OpenTelemetry, which are `error=true` and `otel.status_code=ERROR`.
+    pub fn default_error_tags() -> Vec<TagCondition> {
+        vec![
+            TagCondition {
+                key: "error".to_owned(),
+                value: Some("true".to_owned()),
+            },
+            TagCondition {
+                key: "otel.status_code".to_owned(),
+                value: Some("ERROR".to_owned()),
+            },
+        ]
+    }
+
+    /// Sets the fraction (from `0.0` to `1.0`) of the traces whose spans pass the filter.
+    ///
+    /// The traces are chosen by a hash of their IDs rather than at random, so that all
+    /// the spans of a chosen trace pass (even if they are received in different batches or
+    /// by different instances of `jaegercat` with the same rate).
+    pub fn sample(&mut self, rate: f64) -> &mut Self {
+        self.sample_rate = Some(rate);
+        self
+    }
+
+    /// Returns `true` if all the spans pass the filter.
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty() && !self.filters_spans()
+    }
+
+    /// Removes the spans which do not pass the filter from `batch`,
+    /// and returns `false` if the whole batch is filtered out.
+    ///
+    /// If the filter has conditions on the spans, the batches without any spans passing them
+    /// (including the batches without spans) are filtered out.
+    pub fn apply(&self, batch: &mut Batch) -> bool {
+        if !self.services.is_empty() && !self.services.contains(&batch.process.service_name) {
+            return false;
+        }
+        if self.filters_spans() {
+            let process = &batch.process;
+            batch.spans.retain(|span| self.passes(span, process));
+            return !batch.spans.is_empty();
+        }
+        true
+    }
+
+    fn filters_spans(&self) -> bool {
+        !self.trace_ids.is_empty() || !self.tags.is_empty() || self.min_duration.is_some()
+            || self.error_tags.is_some() || self.sample_rate.is_some()
+    }
+
+    fn passes(&self, span: &Span, process: &Process) -> bool {
+        if !self.trace_ids.is_empty() && !self.trace_ids.contains(&span.trace_id) {
+            return false;
+        }
+        if let Some(rate) = self.sample_rate {
+            if !is_sampled(span.trace_id, rate) {
+                return false;
+            }
+        }
+        if let Some(min) = self.min_duration {
+            // Negative durations (which broken clients may report) are below any threshold
+            if span.duration < 0 || (span.duration as u64) < min.as_micros() as u64 {
+                return false;
+            }
+        }
+        if let Some(ref conditions) = self.error_tags {
+            if !conditions.iter().any(|c| c.matches(&span.tags)) {
+                return false;
+            }
+        }
+        self.tags
+            .iter()
+            .all(|c| c.matches(&span.tags) || c.matches(&process.tags))
+    }
+}
+
+/// Returns `true` if the trace falls in the fraction `rate` of the trace IDs.
+fn is_sampled(id: TraceId, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    // The IDs generated by clients may not be uniformly random (e.g., in their higher bits),
+    // so they are hashed
+    let hash = mix(id.low as u64 ^ mix(id.high as u64));
+    (hash as f64) < rate * (u64::MAX as f64)
+}
+
+/// The finalizer of SplitMix64, which maps similar values to distant ones.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// A condition on the tags of a span (or of its process).
+///
+/// The string representation is `KEY=VALUE`, which is satisfied by a tag of the key and
+/// the value, or `KEY`, which is satisfied by a tag of the key.
+/// The value is compared according to the type of the tag: as a number for long and double
+/// values (so that `500` and `500.0` are equal), as `true` or `false` for bool values,
+/// and in base64 for binary values (e.g., `http.status_code=500` is satisfied by
+/// the long value `500`, the double value `500.0` and the string value `"500"`).
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::filter::TagCondition;
+/// use jaegercat::model::Tag;
+///
+/// let tags = vec![Tag::new("http.status_code", 500), Tag::new("error", true)];
+/// let condition: TagCondition = "http.status_code=500".parse().unwrap();
+/// assert!(condition.matches(&tags));
+///
+/// let condition: TagCondition = "error=false".parse().unwrap();
+/// assert!(!condition.matches(&tags));
+///
+/// let condition: TagCondition = "http.status_code=500.0".parse().unwrap();
+/// assert!(condition.matches(&tags));
+///
+/// let condition: TagCondition = "user.id".parse().unwrap();
+/// assert!(!condition.matches(&tags));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagCondition {
+    /// The key of the tag.
+    pub key: String,
+
+    /// The value of the tag, or `None` if any value satisfies the condition.
+    pub value: Option<String>,
+}
+impl TagCondition {
+    /// Returns `true` if any of `tags` satisfies the condition.
+    pub fn matches(&self, tags: &[Tag]) -> bool {
+        tags.iter()
+            .filter(|tag| tag.key == self.key)
+            .any(|tag| match self.value {
+                None => true,
+                Some(ref value) => value_matches(&tag.value, value),
+            })
+    }
+}
+impl FromStr for TagCondition {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        track_assert!(!key.is_empty(), Failed, "No tag key: {:?}", s);
+        Ok(TagCondition {
+            key: key.to_owned(),
+            value: parts.next().map(|v| v.to_owned()),
+        })
+    }
+}
+
+/// A duration given on the command line, such as `250ms`, `1.5ms`, `500us`, `60s` or `2m`.
+///
+/// A bare number means seconds. Fractions below a nanosecond are truncated.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use jaegercat::filter::HumanDuration;
+///
+/// let duration: HumanDuration = "1.5ms".parse().unwrap();
+/// assert_eq!(duration.0, Duration::from_micros(1500));
+///
+/// let duration: HumanDuration = "2m".parse().unwrap();
+/// assert_eq!(duration.0, Duration::from_secs(120));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HumanDuration(pub Duration);
+impl FromStr for HumanDuration {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let i = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let (number, unit) = s.split_at(i);
+        let unit_nanos: u64 = match unit {
+            "us" => 1_000,
+            "ms" => 1_000_000,
+            "s" | "" => 1_000_000_000,
+            "m" => 60_000_000_000,
+            _ => track_panic!(Failed, "Unknown unit of duration: {:?}", s),
+        };
+        let (integer, fraction) = match number.find('.') {
+            Some(i) => (&number[..i], &number[i + 1..]),
+            None => (number, ""),
+        };
+        track_assert!(
+            (!integer.is_empty() || !fraction.is_empty()) && !fraction.contains('.'),
+            Failed,
+            "Invalid duration: {:?}",
+            s
+        );
+
+        let integer: u64 = if integer.is_empty() {
+            0
+        } else {
+            track!(integer.parse().map_err(Failure::from_error), "duration={:?}", s)?
+        };
+        let mut nanos = integer.checked_mul(unit_nanos);
+        let mut scale = unit_nanos;
+        for digit in fraction.bytes() {
+            scale /= 10;
+            nanos = nanos.and_then(|n| n.checked_add(u64::from(digit - b'0') * scale));
+        }
+        let nanos = track_assert_some!(nanos, Failed, "Too long duration: {:?}", s);
+        Ok(HumanDuration(Duration::from_nanos(nanos)))
+    }
+}
+
+fn value_matches(tag_value: &TagValue, value: &str) -> bool {
+    match *tag_value {
+        TagValue::Str(ref v) => v == value,
+        TagValue::Bool(v) => value.parse() == Ok(v),
+        TagValue::Long(v) => {
+            // Compared in `i128` to tell the floats out of the range of `i64` (which saturate)
+            value.parse() == Ok(v)
+                || value.parse::<f64>().is_ok_and(|f| f == f.trunc() && f as i128 == v.into())
+        }
+        TagValue::Double(v) => value.parse() == Ok(v),
+        TagValue::Binary(ref v) => encode_base64(v) == value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(s: &str) -> Option<Duration> {
+        s.parse::<HumanDuration>().ok().map(|d| d.0)
+    }
+
+    #[test]
+    fn human_duration_works() {
+        assert_eq!(parse("250us"), Some(Duration::from_micros(250)));
+        assert_eq!(parse("1.5ms"), Some(Duration::from_micros(1500)));
+        assert_eq!(parse(".25s"), Some(Duration::from_millis(250)));
+        assert_eq!(parse("60"), Some(Duration::from_secs(60)));
+        assert_eq!(parse("0.5m"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("1.0000000009s"), Some(Duration::from_secs(1)));
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("ms"), None);
+        assert_eq!(parse(".ms"), None);
+        assert_eq!(parse("1.2.3s"), None);
+        assert_eq!(parse("-1s"), None);
+        assert_eq!(parse("1h"), None);
+        assert_eq!(parse("1 s"), None);
+        assert_eq!(parse("99999999999m"), None);
+    }
+
+    #[test]
+    fn tag_condition_compares_numbers() {
+        let matches = |condition: &str, value: TagValue| {
+            let condition: TagCondition = condition.parse().unwrap();
+            condition.matches(&[Tag::new("n", value)])
+        };
+        assert!(matches("n=500", TagValue::Long(500)));
+        assert!(matches("n=500.0", TagValue::Long(500)));
+        assert!(matches("n=5e2", TagValue::Long(500)));
+        assert!(matches("n=500", TagValue::Double(500.0)));
+        assert!(matches("n=0.5", TagValue::Double(0.5)));
+
+        assert!(!matches("n=500.5", TagValue::Long(500)));
+        assert!(!matches("n=9223372036854775808.0", TagValue::Long(i64::MAX)));
+        assert!(!matches("n=NaN", TagValue::Long(0)));
+        assert!(!matches("n=500", TagValue::Str("500.0".to_owned())));
+    }
+}
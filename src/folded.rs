@@ -0,0 +1,123 @@
+//! Output of traces as folded stacks, the input format of flamegraph tools.
+//!
+//! Each span is written as a line consisting of the service name of the root span of
+//! its trace and the operation names of the spans from the root to the span, separated by `;`,
+//! followed by the self time of the span (its duration minus the durations of its children)
+//! in microseconds:
+//!
+//! ```text
+//! example;main 7571
+//! example;main;sub 10196
+//! example;main;query 3012
+//! ```
+//!
+//! The spans are assembled into trees in the same way as `Format::Tree`
+//! (the spans whose parents have not been received are written as roots), and the spans
+//! without self time are omitted. `;` and line breaks in the names are replaced with `_`.
+//!
+//! The output can be passed to [flamegraph.pl] or [inferno] as is.
+//!
+//! [flamegraph.pl]: https://github.com/brendangregg/FlameGraph
+//! [inferno]: https://github.com/jonhoo/inferno
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::io;
+//! use jaegercat::server::ServerBuilder;
+//! use jaegercat::tree::TreeHandler;
+//!
+//! let mut handler = TreeHandler::new(io::stdout());
+//! handler.folded(true);
+//! let server = ServerBuilder::new().build(handler).unwrap();
+//! server.join().unwrap();
+//! ```
+use std::io::Write;
+
+use model::Span;
+use thrift::EmitBatchNotification;
+use tree::{group_by_trace, Relations};
+
+/// Appends the folded stacks of the spans of `batch` to `out`.
+///
+/// This is used by `Format::Folded`, which cannot join the spans of a trace
+/// received in different batches (unlike `TreeHandler`).
+pub fn write_stacks(out: &mut Vec<u8>, batch: &EmitBatchNotification) {
+    for (_, mut spans) in group_by_trace(batch) {
+        write_trace(out, &mut spans);
+    }
+}
+
+/// Appends the folded stacks of a trace (the pairs of its spans and their service names)
+/// to `out`.
+pub(crate) fn write_trace(out: &mut Vec<u8>, spans: &mut [(&Span, &str)]) {
+    spans.sort_by_key(|s| s.0.start_time);
+    let relations = Relations::new(spans);
+    let mut stacks = Stacks {
+        spans,
+        relations: &relations,
+        visited: vec![false; spans.len()],
+        stack: Vec::new(),
+    };
+    for &i in &relations.roots {
+        stacks.write_root(out, i);
+    }
+    // The spans in reference cycles (which are never reached from the roots)
+    for i in 0..spans.len() {
+        if !stacks.visited[i] {
+            stacks.write_root(out, i);
+        }
+    }
+}
+
+struct Stacks<'a> {
+    spans: &'a [(&'a Span, &'a str)],
+    relations: &'a Relations,
+    visited: Vec<bool>,
+    stack: Vec<u8>,
+}
+impl<'a> Stacks<'a> {
+    fn write_root(&mut self, out: &mut Vec<u8>, i: usize) {
+        self.stack.clear();
+        push_frame(&mut self.stack, self.spans[i].1);
+        self.write(out, i);
+    }
+
+    fn write(&mut self, out: &mut Vec<u8>, i: usize) {
+        self.visited[i] = true;
+        let span = self.spans[i].0;
+        let stack_len = self.stack.len();
+        self.stack.push(b';');
+        push_frame(&mut self.stack, &span.operation_name);
+
+        let children = self.relations
+            .children
+            .get(&span.span_id)
+            .map(|c| c.iter().filter(|&&c| !self.visited[c]).cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let children_duration = children
+            .iter()
+            .map(|&c| self.spans[c].0.duration)
+            .fold(0i64, |a, b| a.saturating_add(b));
+        let self_time = span.duration.saturating_sub(children_duration);
+        if self_time > 0 {
+            out.extend_from_slice(&self.stack);
+            // Writing to a `Vec` never fails
+            let _ = writeln!(out, " {}", self_time);
+        }
+        for &c in &children {
+            if !self.visited[c] {
+                self.write(out, c);
+            }
+        }
+        self.stack.truncate(stack_len);
+    }
+}
+
+/// Appends `name` to `stack`, replacing the characters which have meanings in folded stacks.
+fn push_frame(stack: &mut Vec<u8>, name: &str) {
+    stack.extend(name.bytes().map(|b| match b {
+        b';' | b'\n' | b'\r' => b'_',
+        _ => b,
+    }));
+}
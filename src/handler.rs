@@ -0,0 +1,887 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, BufWriter, Stdout, Write};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use serde_json::{self, Value};
+use trackable::error::{Failed, Failure};
+
+use Result;
+use cbor;
+use chrome;
+use color::Colors;
+use csv::{self, Column};
+use folded;
+use jaeger_ui;
+use jq::Filter;
+use json::{to_flat_value, to_typed_value, DurationUnit, JsonWriter, TimeFormat};
+use kafka;
+use logfmt;
+use model::{Batch, Hex, Tag, TagValue};
+use otlp;
+use proto::write_varint;
+use rmp_serde;
+use server::ReceiveMeta;
+use template::Template;
+use text;
+use tree;
+use thrift::EmitBatchNotification;
+use zipkin;
+
+/// This trait allows for processing batches decoded by `Server`.
+pub trait BatchHandler {
+    /// Handles a decoded batch.
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta);
+
+    /// Invoked when there are no more batches to be handled for the moment.
+    ///
+    /// Handlers which buffer their outputs should flush them here.
+    /// The default implementation does nothing.
+    fn on_idle(&mut self) {}
+}
+impl<F> BatchHandler for F
+where
+    F: FnMut(&EmitBatchNotification, &ReceiveMeta),
+{
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        self(batch, meta)
+    }
+}
+
+/// Output format of `PrintHandler` and `Formatter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Writes the received bytes as is.
+    Raw,
+
+    /// Writes a summary line of each batch followed by a hexdump of the received bytes
+    /// in the format of `xxd`.
+    Hex,
+
+    /// Writes a JSON object per line.
+    Json,
+
+    /// Writes pretty printed JSON objects.
+    JsonPretty,
+
+    /// Writes a MessagePack map per batch, which has the same keys and values as
+    /// the JSON objects of `Format::Json`.
+    MessagePack,
+
+    /// Writes a CBOR map per batch, which has the same keys and values as
+    /// the JSON objects of `Format::Json` (see `cbor`).
+    Cbor,
+
+    /// Writes a `Span` message of Jaeger's model.proto per span (see `kafka::encode_span`),
+    /// preceded by its length as a varint (or as 4 bytes big-endian with `length_prefix`).
+    ModelProto,
+
+    /// Writes an OTLP `TracesData` JSON object per line (see `otlp::to_json`).
+    OtlpJson,
+
+    /// Writes a Zipkin v2 JSON array of spans per line (see `zipkin::to_json`).
+    ZipkinJson,
+
+    /// Writes a response of the `/api/traces` endpoint of the Jaeger query service per line,
+    /// which the Jaeger UI can import (see `jaeger_ui::to_json`).
+    JaegerUi,
+
+    /// Writes a comma-separated row per span (see `csv`).
+    Csv,
+
+    /// Writes a tab-separated row per span (see `csv`).
+    Tsv,
+
+    /// Writes a line of `key=value` pairs per span (see `logfmt`).
+    Logfmt,
+
+    /// Writes a line per span shaped by the template given to `Formatter::template`
+    /// (see `template`).
+    Template,
+
+    /// Writes a human-readable line per span with its start time, duration, service,
+    /// operation, status and trace ID (see `text`).
+    Text,
+
+    /// Writes a tree of the spans per trace of each batch (see `tree`).
+    ///
+    /// Note that the spans of a trace received in different batches are written
+    /// in different trees (`tree::TreeHandler` joins them).
+    Tree,
+
+    /// Writes the folded stacks of the spans per trace of each batch for flamegraph tools
+    /// (see `folded`).
+    ///
+    /// Like `Format::Tree`, the spans of a trace received in different batches are not joined.
+    Folded,
+
+    /// Writes the trace events of each batch for Perfetto UI and `chrome://tracing`
+    /// (see `chrome`).
+    ChromeTrace,
+}
+impl FromStr for Format {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "raw" => Format::Raw,
+            "hex" => Format::Hex,
+            "json" => Format::Json,
+            "json-pretty" => Format::JsonPretty,
+            "msgpack" => Format::MessagePack,
+            "cbor" => Format::Cbor,
+            "model-proto" => Format::ModelProto,
+            "otlp-json" => Format::OtlpJson,
+            "zipkin" => Format::ZipkinJson,
+            "jaeger-ui" => Format::JaegerUi,
+            "csv" => Format::Csv,
+            "tsv" => Format::Tsv,
+            "logfmt" => Format::Logfmt,
+            "template" => Format::Template,
+            "text" => Format::Text,
+            "tree" => Format::Tree,
+            "folded" => Format::Folded,
+            "chrome-trace" => Format::ChromeTrace,
+            _ => track_panic!(Failed, "Unknown format: {:?}", s),
+        })
+    }
+}
+
+/// This trait allows for formatting batches decoded by `Server` in its decode workers.
+///
+/// See `ServerBuilder::build_with_formatter`.
+pub trait BatchFormatter: Send + Sync {
+    /// Appends the formatted representation of the given batch to `out`.
+    ///
+    /// The appended bytes are written to the output as a single record.
+    /// `out` may be a reused buffer, so implementations should serialize into it directly
+    /// instead of allocating intermediate strings.
+    fn format(&self, batch: &EmitBatchNotification, meta: &ReceiveMeta, out: &mut Vec<u8>)
+        -> Result<()>;
+
+    /// Appends the formatted representation of the given datagram to `out` without decoding it,
+    /// if the formatter does not need the decoded batch.
+    ///
+    /// `Ok(false)` means that the batch is needed; then, `out` must be left untouched,
+    /// and the datagram is decoded and passed to `format`.
+    /// Skipping decoding saves most of the CPU time spent on each datagram.
+    ///
+    /// The default implementation returns `Ok(false)`.
+    fn format_undecoded(&self, meta: &ReceiveMeta, out: &mut Vec<u8>) -> Result<bool> {
+        let _ = (meta, out);
+        Ok(false)
+    }
+}
+
+/// A `BatchFormatter` implementation which formats batches in the given `Format`.
+#[derive(Debug, Clone)]
+pub struct Formatter {
+    format: Format,
+    columns: Vec<Column>,
+    template: Template,
+    fields: Vec<String>,
+    filter: Option<Filter>,
+    colors: Option<Colors>,
+    include_peer: bool,
+    include_unknown_fields: bool,
+    hex_ids: bool,
+    flat_tags: bool,
+    typed_tags: bool,
+    time_format: TimeFormat,
+    duration_unit: DurationUnit,
+    length_prefix: bool,
+    validate: bool,
+}
+impl Formatter {
+    /// Makes a new `Formatter` instance.
+    pub fn new(format: Format) -> Self {
+        Formatter {
+            format,
+            columns: if format == Format::Logfmt {
+                logfmt::default_columns()
+            } else {
+                Column::defaults()
+            },
+            template: Template::default(),
+            fields: Vec::new(),
+            filter: None,
+            colors: None,
+            include_peer: false,
+            include_unknown_fields: false,
+            hex_ids: false,
+            flat_tags: false,
+            typed_tags: false,
+            time_format: TimeFormat::Datetime,
+            duration_unit: DurationUnit::Seconds,
+            length_prefix: false,
+            validate: true,
+        }
+    }
+
+    /// If `true`, the source address and the receiving listener of each batch are included in the output.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn include_peer(&mut self, include: bool) -> &mut Self {
+        self.include_peer = include;
+        self
+    }
+
+    /// If `true`, the fields which are not defined in the schema are included in the output.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn include_unknown_fields(&mut self, include: bool) -> &mut Self {
+        self.include_unknown_fields = include;
+        self
+    }
+
+    /// If `true`, the trace IDs and the span IDs are written as Jaeger UI shows them
+    /// (see `model::Hex`), e.g., `154050ce43d48b612ae64ad7cd070e8e` instead of
+    /// `0x154050ce43d48b612ae64ad7cd070e8e`.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn hex_ids(&mut self, enabled: bool) -> &mut Self {
+        self.hex_ids = enabled;
+        self
+    }
+
+    /// If `true`, the binary values of the tags (and of the fields of the logs) are written
+    /// in base64 instead of as arrays of bytes.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn flat_tags(&mut self, enabled: bool) -> &mut Self {
+        self.flat_tags = enabled;
+        self
+    }
+
+    /// If `true`, the values of the tags (and of the fields of the logs) are written as objects
+    /// of their types and values, e.g., `{"http.status_code":{"type":"long","value":200}}`
+    /// instead of `{"http.status_code":200}`. This takes precedence over `flat_tags`.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn typed_tags(&mut self, enabled: bool) -> &mut Self {
+        self.typed_tags = enabled;
+        self
+    }
+
+    /// Sets the format of the start times of the spans and the timestamps of the logs.
+    ///
+    /// The default value is `TimeFormat::Datetime`.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn time_format(&mut self, format: TimeFormat) -> &mut Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Sets the unit of the durations of the spans.
+    ///
+    /// The default value is `DurationUnit::Seconds`.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn duration_unit(&mut self, unit: DurationUnit) -> &mut Self {
+        self.duration_unit = unit;
+        self
+    }
+
+    /// If `true`, each received message (or each CBOR encoded batch, or each model.proto span)
+    /// is preceded by its length (4 bytes big-endian),
+    /// so that the concatenated messages can be split again.
+    ///
+    /// This only has an effect on `Format::Raw`, `Format::Cbor` and `Format::ModelProto`.
+    pub fn length_prefix(&mut self, enabled: bool) -> &mut Self {
+        self.length_prefix = enabled;
+        self
+    }
+
+    /// If `false`, received messages are written without being decoded,
+    /// so malformed ones are written too.
+    ///
+    /// The default value is `true`.
+    ///
+    /// This only has an effect on `Format::Raw`.
+    pub fn validate(&mut self, enabled: bool) -> &mut Self {
+        self.validate = enabled;
+        self
+    }
+
+    /// Sets the keys to be kept in the JSON objects of the batches (all if empty).
+    ///
+    /// Each key is a dot-separated path in the span objects (e.g., `operation_name` or
+    /// `tags.http.status_code`, where a tag key may contain dots),
+    /// or in the batch objects if it starts with `process`, `seq_no` or `stats`
+    /// (e.g., `process.service_name`). The paths in arrays (e.g., `logs.fields.event`)
+    /// apply to each of their elements. The peer is kept if it is included.
+    ///
+    /// The default value is empty.
+    ///
+    /// This only has an effect on `Format::Json` and `Format::JsonPretty`.
+    pub fn fields(&mut self, fields: Vec<String>) -> &mut Self {
+        self.fields = fields
+            .into_iter()
+            .map(|field| {
+                let batch_level = ["process", "seq_no", "stats"]
+                    .iter()
+                    .any(|&key| field == key || field.starts_with(&format!("{}.", key)));
+                if batch_level {
+                    format!("emit_batch.{}", field)
+                } else {
+                    format!("emit_batch.spans.{}", field)
+                }
+            })
+            .collect();
+        if !self.fields.is_empty() {
+            self.fields.push("peer".to_owned());
+        }
+        self
+    }
+
+    /// Sets the jq filter applied to the JSON object of each batch (see `jq`).
+    ///
+    /// The filter is applied after the keys are chosen by `fields`, and each of its outputs is
+    /// written as a JSON value per line (nothing is written if the filter outputs nothing,
+    /// e.g., by `select`). By default, no filter is applied.
+    ///
+    /// This only has an effect on `Format::Json` and `Format::JsonPretty`.
+    pub fn filter(&mut self, filter: Filter) -> &mut Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sets the columns of the rows (or the keys of the lines of `Format::Logfmt`).
+    ///
+    /// The default value is `Column::defaults()` (`logfmt::default_columns()` for
+    /// `Format::Logfmt`).
+    ///
+    /// This only has an effect on `Format::Csv`, `Format::Tsv` and `Format::Logfmt`.
+    pub fn columns(&mut self, columns: Vec<Column>) -> &mut Self {
+        self.columns = columns;
+        self
+    }
+
+    /// Sets the template of the lines.
+    ///
+    /// The default value is `Template::default()`.
+    ///
+    /// This only has an effect on `Format::Template`.
+    pub fn template(&mut self, template: Template) -> &mut Self {
+        self.template = template;
+        self
+    }
+
+    /// Sets the ANSI colors of the output.
+    ///
+    /// By default, the output is not colored.
+    ///
+    /// This only has an effect on `Format::Text` and `Format::Tree`.
+    pub fn colors(&mut self, colors: Colors) -> &mut Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// Appends the header of the output to `out`, which is the names of the columns
+    /// for `Format::Csv` and `Format::Tsv`, the opening `[` for `Format::ChromeTrace`,
+    /// and nothing for the other formats.
+    ///
+    /// The header is written once before the first batch, which `Server` does not do.
+    pub fn write_header(&self, out: &mut Vec<u8>) {
+        match self.format {
+            Format::Csv => csv::write_header(out, &self.columns, b','),
+            Format::Tsv => csv::write_header(out, &self.columns, b'\t'),
+            Format::ChromeTrace => out.extend_from_slice(chrome::HEADER),
+            _ => {}
+        }
+    }
+}
+impl BatchFormatter for Formatter {
+    fn format(
+        &self,
+        batch: &EmitBatchNotification,
+        meta: &ReceiveMeta,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        match self.format {
+            Format::Raw => self.write_raw(meta, out),
+            Format::Hex => write_hex(batch, meta, out),
+            Format::Json | Format::JsonPretty | Format::MessagePack | Format::Cbor => {
+                let peer = if self.include_peer {
+                    Some(Peer {
+                        // IPv4 senders to a dual-stack socket appear as IPv4-mapped addresses
+                        ip: meta.peer.ip().to_canonical().to_string(),
+                        port: meta.peer.port(),
+                        listener_port: meta.port,
+                        protocol: meta.protocol.name(),
+                    })
+                } else {
+                    None
+                };
+                let start = out.len();
+                let fast = self.format == Format::Json && self.fields.is_empty()
+                    && self.filter.is_none();
+                let result = if self.format == Format::MessagePack {
+                    self.write_msgpack(&batch.batch, peer, out)
+                } else if self.format == Format::Cbor {
+                    self.write_cbor(&batch.batch, peer, out)
+                } else if fast {
+                    self.write_json(&batch.batch, peer.as_ref(), out)
+                        .map(|()| out.push(b'\n'))
+                } else {
+                    self.write_json_value(&batch.batch, peer, out)
+                };
+                if result.is_err() {
+                    out.truncate(start);
+                }
+                track!(result)?;
+            }
+            Format::OtlpJson | Format::ZipkinJson | Format::JaegerUi => {
+                let data = match self.format {
+                    Format::OtlpJson => otlp::to_json(&batch.batch),
+                    Format::ZipkinJson => zipkin::to_json(&batch.batch),
+                    _ => jaeger_ui::to_json(&batch.batch),
+                };
+                track!(serde_json::to_writer(&mut *out, &data).map_err(Failure::from_error))?;
+                out.push(b'\n');
+            }
+            Format::ModelProto => {
+                let mut message = Vec::new();
+                for span in &batch.batch.spans {
+                    message.clear();
+                    kafka::encode_span(&mut message, span, &batch.batch.process);
+                    if self.length_prefix {
+                        out.extend_from_slice(&(message.len() as u32).to_be_bytes());
+                    } else {
+                        write_varint(out, message.len() as u64);
+                    }
+                    out.extend_from_slice(&message);
+                }
+            }
+            Format::Csv => csv::write_rows(out, batch, meta, &self.columns, b','),
+            Format::Tsv => csv::write_rows(out, batch, meta, &self.columns, b'\t'),
+            Format::Logfmt => logfmt::write_lines(out, batch, meta, &self.columns),
+            Format::Template => self.template.write_lines(out, batch, meta),
+            Format::Text => text::write_lines(out, batch, self.colors.as_ref()),
+            Format::Tree => tree::write_trees(out, batch, self.colors.as_ref()),
+            Format::Folded => folded::write_stacks(out, batch),
+            Format::ChromeTrace => {
+                for event in chrome::to_events(&batch.batch) {
+                    track!(serde_json::to_writer(&mut *out, &event).map_err(Failure::from_error))?;
+                    out.extend_from_slice(b",\n");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn format_undecoded(&self, meta: &ReceiveMeta, out: &mut Vec<u8>) -> Result<bool> {
+        if self.format != Format::Raw || self.validate {
+            return Ok(false);
+        }
+        self.write_raw(meta, out);
+        Ok(true)
+    }
+}
+impl Formatter {
+    fn write_raw(&self, meta: &ReceiveMeta, out: &mut Vec<u8>) {
+        if self.length_prefix {
+            let len = meta.bytes.len() as u32;
+            out.extend_from_slice(&len.to_be_bytes());
+        }
+        out.extend_from_slice(meta.bytes);
+    }
+
+    fn write_json(&self, batch: &Batch, peer: Option<&Peer>, out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(b"{\"emit_batch\":");
+        track!(JSON_WRITER.with(|writer| {
+            writer
+                .borrow_mut()
+                .include_unknown_fields(self.include_unknown_fields)
+                .hex_ids(self.hex_ids)
+                .flat_tags(self.flat_tags)
+                .typed_tags(self.typed_tags)
+                .time_format(self.time_format)
+                .duration_unit(self.duration_unit)
+                .write_batch(out, batch)
+        }))?;
+        if let Some(peer) = peer {
+            out.extend_from_slice(b",\"peer\":");
+            track!(serde_json::to_writer(&mut *out, peer).map_err(Failure::from_error))?;
+        }
+        out.push(b'}');
+        Ok(())
+    }
+
+    /// Writes a batch with `serde_json`, which is needed for pretty printing, `fields` and
+    /// `filter`. Each output is followed by a line break.
+    fn write_json_value(
+        &self,
+        batch: &Batch,
+        peer: Option<Peer>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let batch = self.strip_unknown_fields(batch);
+        let output = Output {
+            batch: &batch,
+            peer,
+        };
+        if self.fields.is_empty() && self.filter.is_none() && !self.converts_values() {
+            let result = serde_json::to_writer_pretty(&mut *out, &output);
+            track!(result.map_err(Failure::from_error))?;
+            out.push(b'\n');
+            return Ok(());
+        }
+        let mut value = track!(self.to_value(&output))?;
+        if !self.fields.is_empty() {
+            let paths = self.fields.iter().map(|f| f.as_str()).collect::<Vec<_>>();
+            value = project(&value, &paths).unwrap_or(Value::Null);
+        }
+        let values = match self.filter {
+            Some(ref filter) => track!(filter.apply(&value))?,
+            None => vec![value],
+        };
+        for value in values {
+            let result = if self.format == Format::JsonPretty {
+                serde_json::to_writer_pretty(&mut *out, &value)
+            } else {
+                serde_json::to_writer(&mut *out, &value)
+            };
+            track!(result.map_err(Failure::from_error))?;
+            out.push(b'\n');
+        }
+        Ok(())
+    }
+
+    /// Writes a batch with `rmp_serde`, as a map keyed by the same names as the JSON output.
+    fn write_msgpack(&self, batch: &Batch, peer: Option<Peer>, out: &mut Vec<u8>) -> Result<()> {
+        let batch = self.strip_unknown_fields(batch);
+        let output = Output {
+            batch: &batch,
+            peer,
+        };
+        // `rmp_serde` encodes enums (e.g., references) as arrays of variant indices and values,
+        // so the batch goes through a JSON value to have the same shape as the JSON output
+        let value = track!(self.to_value(&output))?;
+        track!(rmp_serde::encode::write_named(out, &value).map_err(Failure::from_error))
+    }
+
+    /// Writes a batch in CBOR (preceded by its length if `length_prefix` is enabled).
+    fn write_cbor(&self, batch: &Batch, peer: Option<Peer>, out: &mut Vec<u8>) -> Result<()> {
+        let batch = self.strip_unknown_fields(batch);
+        let output = Output {
+            batch: &batch,
+            peer,
+        };
+        let value = track!(self.to_value(&output))?;
+        let start = out.len();
+        if self.length_prefix {
+            out.extend_from_slice(&[0; 4]);
+        }
+        cbor::write_value(out, &value);
+        if self.length_prefix {
+            let len = (out.len() - start - 4) as u32;
+            out[start..start + 4].copy_from_slice(&len.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn converts_values(&self) -> bool {
+        self.hex_ids || self.flat_tags || self.typed_tags
+            || self.time_format != TimeFormat::Datetime
+            || self.duration_unit != DurationUnit::Seconds
+    }
+
+    /// Serializes `output` into a JSON value with the identifiers in `Hex` if `hex_ids` is
+    /// enabled, the tags converted by `tags`, the times in `time_format` and
+    /// the durations in `duration_unit` (which `Serialize` implementations do not know of).
+    fn to_value(&self, output: &Output) -> Result<Value> {
+        let mut value = track!(serde_json::to_value(output).map_err(Failure::from_error))?;
+        if !self.converts_values() {
+            return Ok(value);
+        }
+        let batch = track_assert_some!(value.get_mut("emit_batch"), Failed);
+        if let Some(tags) = self.tags(&output.batch.process.tags) {
+            let process = track_assert_some!(batch.get_mut("process"), Failed);
+            process["tags"] = tags;
+        }
+        let spans = batch.get_mut("spans").and_then(|spans| spans.as_array_mut());
+        for (value, span) in spans.into_iter().flatten().zip(&output.batch.spans) {
+            let value = track_assert_some!(value.as_object_mut(), Failed);
+            // The empty tags of spans are omitted
+            if !span.tags.is_empty() {
+                if let Some(tags) = self.tags(&span.tags) {
+                    value.insert("tags".to_owned(), tags);
+                }
+            }
+            if self.hex_ids {
+                value.insert("trace_id".to_owned(), hex(span.trace_id));
+                value.insert("span_id".to_owned(), hex(span.span_id));
+                if let Some(parent_span_id) = span.parent_span_id {
+                    value.insert("parent_span_id".to_owned(), hex(parent_span_id));
+                }
+                let references = value.get_mut("references").and_then(|r| r.as_array_mut());
+                for (value, reference) in references.into_iter().flatten().zip(&span.references) {
+                    // Each reference is an object keyed by its type
+                    let value = value
+                        .as_object_mut()
+                        .and_then(|v| v.iter_mut().next())
+                        .and_then(|(_, v)| v.as_object_mut());
+                    let value = track_assert_some!(value, Failed);
+                    value.insert("trace_id".to_owned(), hex(reference.trace_id));
+                    value.insert("span_id".to_owned(), hex(reference.span_id));
+                }
+            }
+            if let Some(start_time) = self.time_format.to_value(span.start_time) {
+                value.remove("start_datetime");
+                value.remove("start_unixtime");
+                value.insert("start_time".to_owned(), start_time);
+            }
+            let duration = self.duration_unit.to_value(span.duration);
+            value.insert("duration".to_owned(), duration);
+            let logs = value.get_mut("logs").and_then(|logs| logs.as_array_mut());
+            for (value, log) in logs.into_iter().flatten().zip(&span.logs) {
+                let value = track_assert_some!(value.as_object_mut(), Failed);
+                if let Some(fields) = self.tags(&log.fields) {
+                    value.insert("fields".to_owned(), fields);
+                }
+                if let Some(timestamp) = self.time_format.to_value(log.timestamp) {
+                    value.remove("datetime");
+                    value.remove("unixtime");
+                    value.insert("timestamp".to_owned(), timestamp);
+                }
+            }
+        }
+        Ok(value)
+    }
+
+    /// Returns the map of `tags` converted by `typed_tags` or `flat_tags` (if either is enabled),
+    /// where the last one of duplicate keys wins.
+    fn tags(&self, tags: &[Tag]) -> Option<Value> {
+        let convert: fn(&TagValue) -> Value = if self.typed_tags {
+            to_typed_value
+        } else if self.flat_tags {
+            to_flat_value
+        } else {
+            return None;
+        };
+        let tags = tags
+            .iter()
+            .map(|tag| (tag.key.clone(), convert(&tag.value)))
+            .collect();
+        Some(Value::Object(tags))
+    }
+
+    fn strip_unknown_fields<'a>(&self, batch: &'a Batch) -> Cow<'a, Batch> {
+        if !self.include_unknown_fields && batch.has_unknown_fields() {
+            let mut batch = batch.clone();
+            batch.clear_unknown_fields();
+            Cow::Owned(batch)
+        } else {
+            Cow::Borrowed(batch)
+        }
+    }
+}
+
+fn hex<T>(id: T) -> Value
+where
+    Hex<T>: fmt::Display,
+{
+    Value::from(Hex(id).to_string())
+}
+
+/// Returns the parts of `value` at the given dot-separated paths, or `None` if there are none.
+///
+/// An empty path selects the whole value, and the paths in arrays apply to their elements.
+/// Since keys may contain dots, a path is matched against the keys of an object
+/// instead of being split beforehand.
+fn project(value: &Value, paths: &[&str]) -> Option<Value> {
+    if paths.contains(&"") {
+        return Some(value.clone());
+    }
+    match *value {
+        Value::Object(ref object) => {
+            let object = object
+                .iter()
+                .filter_map(|(key, value)| {
+                    let rest = paths
+                        .iter()
+                        .filter_map(|path| match path.strip_prefix(key.as_str()) {
+                            Some("") => Some(""),
+                            Some(rest) => rest.strip_prefix('.'),
+                            None => None,
+                        })
+                        .collect::<Vec<_>>();
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    project(value, &rest).map(|value| (key.clone(), value))
+                })
+                .collect();
+            Some(Value::Object(object))
+        }
+        Value::Array(ref values) => Some(Value::Array(
+            values.iter().filter_map(|v| project(v, paths)).collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// Writes a summary line of `batch` (starting with `#`) and a hexdump of the bytes of `meta`
+/// (16 bytes per line, with the offsets and the printable ASCII characters) followed by
+/// an empty line.
+fn write_hex(batch: &EmitBatchNotification, meta: &ReceiveMeta, out: &mut Vec<u8>) {
+    // IPv4 senders to a dual-stack socket appear as IPv4-mapped addresses
+    let peer = SocketAddr::new(meta.peer.ip().to_canonical(), meta.peer.port());
+    // Writing to a `Vec` never fails
+    let _ = writeln!(
+        out,
+        "# {} -> :{} ({}): {} bytes, service {:?}, {} spans",
+        peer,
+        meta.port,
+        meta.protocol.name(),
+        meta.bytes.len(),
+        batch.batch.process.service_name,
+        batch.batch.spans.len()
+    );
+    for (i, line) in meta.bytes.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x}:", i * 16);
+        for j in 0..16 {
+            if j % 2 == 0 {
+                out.push(b' ');
+            }
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, "{:02x}", b);
+                }
+                None => out.extend_from_slice(b"  "),
+            }
+        }
+        out.extend_from_slice(b"  ");
+        out.extend(line.iter().map(|&b| {
+            if b == b' ' || b.is_ascii_graphic() {
+                b
+            } else {
+                b'.'
+            }
+        }));
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+}
+
+thread_local! {
+    // `JsonWriter` retains a scratch buffer, so it is reused by each decode worker
+    static JSON_WRITER: RefCell<JsonWriter> = RefCell::new(JsonWriter::new());
+}
+
+/// A `BatchHandler` implementation which prints batches to the standard output.
+///
+/// The output is buffered, and is flushed when the server becomes idle
+/// or when the flush interval has elapsed since the last flush.
+#[derive(Debug)]
+pub struct PrintHandler {
+    formatter: Formatter,
+    flush_interval: Duration,
+    last_flush: Instant,
+    buf: Vec<u8>,
+    stdout: BufWriter<Stdout>,
+}
+impl PrintHandler {
+    /// Makes a new `PrintHandler` instance.
+    pub fn new(format: Format) -> Self {
+        PrintHandler {
+            formatter: Formatter::new(format),
+            flush_interval: Duration::from_millis(100),
+            last_flush: Instant::now(),
+            buf: Vec::new(),
+            stdout: BufWriter::with_capacity(64 * 1024, io::stdout()),
+        }
+    }
+
+    /// Sets the maximum interval between flushes of the output while batches keep arriving.
+    ///
+    /// The default value is 100 milliseconds.
+    pub fn flush_interval(&mut self, interval: Duration) -> &mut Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// If `true`, the source address and the receiving listener of each batch are included in the output.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn include_peer(&mut self, include: bool) -> &mut Self {
+        self.formatter.include_peer(include);
+        self
+    }
+
+    /// If `true`, the fields which are not defined in the schema are included in the output.
+    ///
+    /// This only has an effect on `Format::Json`, `Format::JsonPretty`, `Format::MessagePack`
+    /// and `Format::Cbor`.
+    pub fn include_unknown_fields(&mut self, include: bool) -> &mut Self {
+        self.formatter.include_unknown_fields(include);
+        self
+    }
+
+    /// If `true`, each received message (or each CBOR encoded batch, or each model.proto span)
+    /// is preceded by its length (4 bytes big-endian),
+    /// so that the concatenated messages can be split again.
+    ///
+    /// This only has an effect on `Format::Raw`, `Format::Cbor` and `Format::ModelProto`.
+    pub fn length_prefix(&mut self, enabled: bool) -> &mut Self {
+        self.formatter.length_prefix(enabled);
+        self
+    }
+
+    /// Prints the given batch.
+    ///
+    /// The output may be buffered until `flush` is called.
+    pub fn print(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) -> Result<()> {
+        self.buf.clear();
+        track!(self.formatter.format(batch, meta, &mut self.buf))?;
+        track!(self.stdout.write_all(&self.buf).map_err(Failure::from_error))?;
+        if self.last_flush.elapsed() >= self.flush_interval {
+            track!(self.flush())?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the buffered output.
+    pub fn flush(&mut self) -> Result<()> {
+        track!(self.stdout.flush().map_err(Failure::from_error))?;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+impl BatchHandler for PrintHandler {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        track_try_unwrap!(self.print(batch, meta));
+    }
+
+    fn on_idle(&mut self) {
+        track_try_unwrap!(self.flush());
+    }
+}
+
+#[derive(Serialize)]
+struct Output<'a> {
+    #[serde(rename = "emit_batch")]
+    batch: &'a Batch,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer: Option<Peer>,
+}
+
+#[derive(Serialize)]
+struct Peer {
+    ip: String,
+    port: u16,
+    listener_port: u16,
+    protocol: &'static str,
+}
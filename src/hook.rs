@@ -0,0 +1,100 @@
+//! Hooks for observing the behavior of the decoder and the server.
+use std::fmt;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::time::Duration;
+
+use error::DecodeError;
+
+/// This trait allows for observing decoded messages and decoding failures
+/// (e.g., for collecting metrics).
+///
+/// The methods are invoked by the threads which decode messages,
+/// so implementations should be cheap and use interior mutability (e.g., atomic counters).
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use jaegercat::DecodeError;
+/// use jaegercat::decoder::StreamDecoder;
+/// use jaegercat::hook::DecodeHook;
+/// use jaegercat::thrift::Protocol;
+///
+/// #[derive(Debug, Default)]
+/// struct Errors(AtomicUsize);
+/// impl DecodeHook for Errors {
+///     fn on_error(&self, _bytes: usize, _error: &DecodeError) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let errors = Arc::new(Errors::default());
+/// let mut decoder = StreamDecoder::new(Protocol::Compact);
+/// decoder.set_hook(errors.clone());
+/// decoder.feed(&[0xFF]);
+/// assert!(decoder.decode_next().is_err());
+/// assert_eq!(errors.0.load(Ordering::Relaxed), 1);
+/// ```
+pub trait DecodeHook: fmt::Debug + Send + Sync {
+    /// Invoked when a message of `bytes` bytes containing `spans` spans is decoded.
+    fn on_decoded(&self, _bytes: usize, _spans: usize) {}
+
+    /// Invoked when decoding a message fails.
+    ///
+    /// `bytes` is the number of the bytes consumed by the failed attempt
+    /// (i.e., the whole datagram, or the bytes discarded by `StreamDecoder`).
+    /// The offset of the failure is available through `DecodeError::offset`.
+    ///
+    /// In lenient mode, this is also invoked for the failure which ended a salvaged message
+    /// (after `on_decoded`).
+    fn on_error(&self, _bytes: usize, _error: &DecodeError) {}
+}
+#[cfg(feature = "std")]
+impl<T: DecodeHook + ?Sized> DecodeHook for Arc<T> {
+    fn on_decoded(&self, bytes: usize, spans: usize) {
+        (**self).on_decoded(bytes, spans);
+    }
+
+    fn on_error(&self, bytes: usize, error: &DecodeError) {
+        (**self).on_error(bytes, error);
+    }
+}
+
+/// This trait allows for observing the latencies of the stages of `Server`
+/// (e.g., for collecting metrics).
+///
+/// As with `DecodeHook`, the methods are invoked by the threads of the server,
+/// so implementations should be cheap and use interior mutability.
+#[cfg(feature = "server")]
+pub trait StageHook: fmt::Debug + Send + Sync {
+    /// Invoked when a decode worker takes a datagram from the queue,
+    /// `elapsed` after the datagram was received.
+    fn on_dequeued(&self, _elapsed: Duration) {}
+
+    /// Invoked when a decode worker has decoded (and formatted) a datagram,
+    /// which took `elapsed`.
+    fn on_processed(&self, _elapsed: Duration) {}
+
+    /// Invoked when the output stage (i.e., the handler or the writer) takes a batch,
+    /// `elapsed` after the datagram was received.
+    ///
+    /// This is not invoked for the records read back from the spill file.
+    fn on_output(&self, _elapsed: Duration) {}
+}
+#[cfg(feature = "server")]
+impl<T: StageHook + ?Sized> StageHook for Arc<T> {
+    fn on_dequeued(&self, elapsed: Duration) {
+        (**self).on_dequeued(elapsed);
+    }
+
+    fn on_processed(&self, elapsed: Duration) {
+        (**self).on_processed(elapsed);
+    }
+
+    fn on_output(&self, elapsed: Duration) {
+        (**self).on_output(elapsed);
+    }
+}
@@ -0,0 +1,265 @@
+//! A minimal HTTP/1.1 server side used by the HTTP listeners of `Server`,
+//! which only needs to read request bodies and answer them.
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use error::DecodeError;
+use inflate;
+use zstd;
+
+/// The maximum size of the request line and the headers.
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// A request read by `read_request`.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+
+    /// The path without the query string.
+    pub path: String,
+
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub body: Vec<u8>,
+
+    /// `false` if the connection must be closed after the response.
+    pub keep_alive: bool,
+}
+
+/// An error which occurred while reading a request.
+#[derive(Debug)]
+pub enum RequestError {
+    /// The request is invalid, and is answered with the given status.
+    Status(u16, &'static str),
+
+    /// The connection failed.
+    Io(io::Error),
+}
+impl From<io::Error> for RequestError {
+    fn from(f: io::Error) -> Self {
+        RequestError::Io(f)
+    }
+}
+
+/// An error which occurred while decompressing a request body.
+#[derive(Debug)]
+pub enum BodyError {
+    /// The body is compressed by an unsupported content coding.
+    UnsupportedEncoding(String),
+
+    /// The body cannot be decompressed (or is too large after decompression).
+    Decode(DecodeError),
+}
+
+/// Reads a request from `stream`.
+///
+/// `Ok(None)` means that the connection was closed before a new request.
+/// `100 Continue` is sent back if the client expects it.
+pub fn read_request<S: Read + Write>(
+    stream: &mut BufReader<S>,
+    max_body_size: usize,
+) -> Result<Option<Request>, RequestError> {
+    let mut header_size = 0;
+    let mut line = Vec::new();
+    if !read_line(stream, &mut line, &mut header_size)? {
+        return Ok(None);
+    }
+    let (method, path, http10) = {
+        let line = parse_str(&line)?;
+        let mut parts = line.split(' ');
+        let method = parts.next().unwrap_or("").to_owned();
+        let target = parts.next().unwrap_or("");
+        let version = parts.next().unwrap_or("");
+        if method.is_empty() || !target.starts_with('/') || !version.starts_with("HTTP/1.") {
+            return Err(RequestError::Status(400, "Bad Request"));
+        }
+        let path = target.split('?').next().unwrap_or("").to_owned();
+        (method, path, version == "HTTP/1.0")
+    };
+
+    let mut request = Request {
+        method,
+        path,
+        content_type: None,
+        content_encoding: None,
+        body: Vec::new(),
+        keep_alive: !http10,
+    };
+    let mut content_length = None;
+    let mut chunked = false;
+    let mut expect_continue = false;
+    loop {
+        line.clear();
+        if !read_line(stream, &mut line, &mut header_size)? {
+            return Err(RequestError::Status(400, "Bad Request"));
+        }
+        if line.is_empty() {
+            break;
+        }
+        let line = parse_str(&line)?;
+        let colon = line.find(':').ok_or(RequestError::Status(400, "Bad Request"))?;
+        let name = line[..colon].trim().to_ascii_lowercase();
+        let value = line[colon + 1..].trim();
+        match name.as_str() {
+            "content-length" => {
+                let length = value
+                    .parse::<usize>()
+                    .map_err(|_| RequestError::Status(400, "Bad Request"))?;
+                content_length = Some(length);
+            }
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            "content-type" => request.content_type = Some(value.to_owned()),
+            "content-encoding" => request.content_encoding = Some(value.to_owned()),
+            "connection" => {
+                if value.eq_ignore_ascii_case("close") {
+                    request.keep_alive = false;
+                } else if value.eq_ignore_ascii_case("keep-alive") {
+                    request.keep_alive = true;
+                }
+            }
+            "expect" => expect_continue = value.eq_ignore_ascii_case("100-continue"),
+            _ => {}
+        }
+    }
+
+    if content_length.is_some_and(|n| n > max_body_size) {
+        return Err(RequestError::Status(413, "Payload Too Large"));
+    }
+    if expect_continue && !http10 {
+        stream.get_mut().write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    if chunked {
+        request.body = read_chunked_body(stream, max_body_size)?;
+    } else if let Some(length) = content_length {
+        request.body = vec![0; length];
+        stream.read_exact(&mut request.body)?;
+    }
+    Ok(Some(request))
+}
+
+/// Writes a response with `body`.
+pub fn write_response<W: Write>(
+    writer: &mut W,
+    status: u16,
+    reason: &str,
+    content_type: Option<&str>,
+    body: &[u8],
+    keep_alive: bool,
+) -> io::Result<()> {
+    let mut response = Vec::with_capacity(128 + body.len());
+    write!(response, "HTTP/1.1 {} {}\r\n", status, reason)?;
+    if let Some(content_type) = content_type {
+        write!(response, "Content-Type: {}\r\n", content_type)?;
+    }
+    write!(response, "Content-Length: {}\r\n", body.len())?;
+    if !keep_alive {
+        response.extend_from_slice(b"Connection: close\r\n");
+    }
+    response.extend_from_slice(b"\r\n");
+    response.extend_from_slice(body);
+    writer.write_all(&response)?;
+    writer.flush()
+}
+
+/// Reads a line without the trailing CRLF (or LF) into `line`.
+///
+/// `Ok(false)` means that the stream ended.
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+    header_size: &mut usize,
+) -> Result<bool, RequestError> {
+    let limit = (MAX_HEADER_SIZE - *header_size) as u64 + 1;
+    let size = reader.by_ref().take(limit).read_until(b'\n', line)?;
+    *header_size += size;
+    if size == 0 {
+        return Ok(false);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if *header_size > MAX_HEADER_SIZE {
+            RequestError::Status(431, "Request Header Fields Too Large")
+        } else {
+            RequestError::Status(400, "Bad Request")
+        });
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(true)
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    max_body_size: usize,
+) -> Result<Vec<u8>, RequestError> {
+    let mut body = Vec::new();
+    let mut line = Vec::new();
+    let mut header_size;
+    loop {
+        line.clear();
+        header_size = 0;
+        if !read_line(reader, &mut line, &mut header_size)? {
+            return Err(RequestError::Status(400, "Bad Request"));
+        }
+        let size = {
+            let line = parse_str(&line)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            usize::from_str_radix(size, 16).map_err(|_| RequestError::Status(400, "Bad Request"))?
+        };
+        if size == 0 {
+            break;
+        }
+        if body.len() + size > max_body_size {
+            return Err(RequestError::Status(413, "Payload Too Large"));
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        line.clear();
+        if !read_line(reader, &mut line, &mut header_size)? || !line.is_empty() {
+            return Err(RequestError::Status(400, "Bad Request"));
+        }
+    }
+
+    // Trailers are ignored
+    loop {
+        line.clear();
+        if !read_line(reader, &mut line, &mut header_size)? {
+            return Err(RequestError::Status(400, "Bad Request"));
+        }
+        if line.is_empty() {
+            return Ok(body);
+        }
+    }
+}
+
+fn parse_str(bytes: &[u8]) -> Result<&str, RequestError> {
+    ::std::str::from_utf8(bytes).map_err(|_| RequestError::Status(400, "Bad Request"))
+}
+
+/// Decompresses the body of `request` according to its `Content-Encoding`
+/// (`gzip`, `deflate`, `zstd`, `identity`, or a list of them in the order they were applied)
+/// into at most `max_body_size` bytes.
+pub fn decode_body(request: &mut Request, max_body_size: usize) -> Result<(), BodyError> {
+    let encodings = match request.content_encoding {
+        Some(ref encodings) => encodings.clone(),
+        None => return Ok(()),
+    };
+    for encoding in encodings.rsplit(',').map(|e| e.trim()) {
+        let body = if encoding.eq_ignore_ascii_case("gzip")
+            || encoding.eq_ignore_ascii_case("x-gzip")
+        {
+            inflate::gunzip(&request.body, max_body_size)
+        } else if encoding.eq_ignore_ascii_case("deflate") {
+            inflate::zlib_decompress(&request.body, max_body_size)
+        } else if encoding.eq_ignore_ascii_case("zstd") {
+            zstd::decompress(&request.body, max_body_size)
+        } else if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+            continue;
+        } else {
+            return Err(BodyError::UnsupportedEncoding(encoding.to_owned()));
+        };
+        request.body = body.map_err(BodyError::Decode)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,494 @@
+//! A decoder of the DEFLATE format ([RFC 1951]) and its gzip ([RFC 1952]) and
+//! zlib ([RFC 1950]) wrappers, which decompresses the bodies of HTTP requests
+//! with `Content-Encoding: gzip` or `deflate`.
+//!
+//! [RFC 1951]: https://www.rfc-editor.org/rfc/rfc1951
+//! [RFC 1952]: https://www.rfc-editor.org/rfc/rfc1952
+//! [RFC 1950]: https://www.rfc-editor.org/rfc/rfc1950
+use error::{DecodeError, DecodeResult};
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order of the code lengths of the code length alphabet in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Decompresses gzip members (possibly concatenated) into at most `max_size` bytes.
+pub fn gunzip(buf: &[u8], max_size: usize) -> DecodeResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let start = out.len();
+        pos = read_gzip_header(buf, pos)?;
+        pos = inflate(buf, pos, &mut out, max_size)?;
+        if buf.len() - pos < 8 {
+            return Err(DecodeError::Truncated { offset: buf.len() });
+        }
+        let crc = le_u32(&buf[pos..]);
+        if crc != crc32(&out[start..]) {
+            return Err(invalid(pos, "CRC-32 mismatch"));
+        }
+        if le_u32(&buf[pos + 4..]) != (out.len() - start) as u32 {
+            return Err(invalid(pos + 4, "Size mismatch"));
+        }
+        pos += 8;
+    }
+    Ok(out)
+}
+
+/// Decompresses a zlib stream (or a raw DEFLATE stream, which some clients send instead)
+/// into at most `max_size` bytes.
+pub fn zlib_decompress(buf: &[u8], max_size: usize) -> DecodeResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let is_zlib = buf.len() >= 2
+        && buf[0] & 0x0F == 8
+        && (u16::from(buf[0]) << 8 | u16::from(buf[1])).is_multiple_of(31);
+    if !is_zlib {
+        inflate(buf, 0, &mut out, max_size)?;
+        return Ok(out);
+    }
+    if buf[1] & 0x20 != 0 {
+        return Err(invalid(1, "Preset dictionaries are not supported"));
+    }
+    let pos = inflate(buf, 2, &mut out, max_size)?;
+    if buf.len() - pos < 4 {
+        return Err(DecodeError::Truncated { offset: buf.len() });
+    }
+    if be_u32(&buf[pos..]) != adler32(&out) {
+        return Err(invalid(pos, "Adler-32 mismatch"));
+    }
+    Ok(out)
+}
+
+/// Skips a gzip member header, and returns the offset of the compressed data.
+fn read_gzip_header(buf: &[u8], offset: usize) -> DecodeResult<usize> {
+    let header = &buf[offset..];
+    if header.len() < 10 {
+        return Err(DecodeError::Truncated { offset: buf.len() });
+    }
+    if header[0] != 0x1F || header[1] != 0x8B || header[2] != 8 {
+        return Err(invalid(offset, "Not a gzip member"));
+    }
+    let flags = header[3];
+    let mut pos = 10;
+    if flags & 0x04 != 0 {
+        if header.len() < pos + 2 {
+            return Err(DecodeError::Truncated { offset: buf.len() });
+        }
+        pos += 2 + usize::from(header[pos]) + (usize::from(header[pos + 1]) << 8);
+    }
+    for &flag in &[0x08, 0x10] {
+        // A zero-terminated file name or comment
+        if flags & flag != 0 {
+            match header.get(pos..).and_then(|h| h.iter().position(|&b| b == 0)) {
+                Some(len) => pos += len + 1,
+                None => return Err(DecodeError::Truncated { offset: buf.len() }),
+            }
+        }
+    }
+    if flags & 0x02 != 0 {
+        pos += 2;
+    }
+    if header.len() < pos {
+        return Err(DecodeError::Truncated { offset: buf.len() });
+    }
+    Ok(offset + pos)
+}
+
+/// Decompresses a DEFLATE stream starting at `offset` into `out`,
+/// and returns the offset of the byte following the stream.
+fn inflate(buf: &[u8], offset: usize, out: &mut Vec<u8>, max_size: usize) -> DecodeResult<usize> {
+    let mut bits = BitReader {
+        buf,
+        pos: offset,
+        bit_buf: 0,
+        bit_count: 0,
+    };
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let len = bits.bits(16)?;
+                let nlen = bits.bits(16)?;
+                if len != !nlen & 0xFFFF {
+                    return Err(invalid(bits.pos, "Invalid stored block length"));
+                }
+                let start = bits.pos;
+                let end = start + len as usize;
+                if buf.len() < end {
+                    return Err(DecodeError::Truncated { offset: buf.len() });
+                }
+                check_size(out.len() + len as usize, max_size, start)?;
+                out.extend_from_slice(&buf[start..end]);
+                bits.pos = end;
+            }
+            1 => {
+                let mut lengths = [0; 288 + 32];
+                for (i, length) in lengths.iter_mut().enumerate() {
+                    *length = match i {
+                        0..=143 => 8,
+                        144..=255 => 9,
+                        256..=279 => 7,
+                        280..=287 => 8,
+                        _ => 5,
+                    };
+                }
+                let literals = Huffman::new(&lengths[..288], bits.pos)?;
+                let distances = Huffman::new(&lengths[288..], bits.pos)?;
+                inflate_block(&mut bits, &literals, &distances, out, max_size)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &literals, &distances, out, max_size)?;
+            }
+            _ => return Err(invalid(bits.pos, "Invalid block type")),
+        }
+        if last {
+            return Ok(bits.pos);
+        }
+    }
+}
+
+fn read_dynamic_codes(bits: &mut BitReader) -> DecodeResult<(Huffman, Huffman)> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid(bits.pos, "Too many codes"));
+    }
+    let mut code_lengths = [0; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths, bits.pos)?;
+
+    let mut lengths = [0; 286 + 30];
+    let mut i = 0;
+    while i < literal_count + distance_count {
+        let symbol = bits.decode(&code_lengths)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(invalid(bits.pos, "No length to repeat"));
+                }
+                (lengths[i - 1], 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        if i + repeat as usize > literal_count + distance_count {
+            return Err(invalid(bits.pos, "Too many code lengths"));
+        }
+        for _ in 0..repeat {
+            lengths[i] = length;
+            i += 1;
+        }
+    }
+    if lengths[256] == 0 {
+        return Err(invalid(bits.pos, "No end-of-block code"));
+    }
+    let literals = Huffman::new(&lengths[..literal_count], bits.pos)?;
+    let distances = Huffman::new(&lengths[literal_count..i], bits.pos)?;
+    Ok((literals, distances))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+    max_size: usize,
+) -> DecodeResult<()> {
+    loop {
+        let symbol = bits.decode(literals)? as usize;
+        match symbol {
+            0..=255 => {
+                check_size(out.len() + 1, max_size, bits.pos)?;
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let i = symbol - 257;
+                let length = LENGTH_BASES[i] as usize + bits.bits(LENGTH_EXTRA_BITS[i])? as usize;
+                let i = bits.decode(distances)? as usize;
+                if i >= 30 {
+                    return Err(invalid(bits.pos, "Invalid distance code"));
+                }
+                let distance =
+                    DISTANCE_BASES[i] as usize + bits.bits(DISTANCE_EXTRA_BITS[i])? as usize;
+                if distance > out.len() {
+                    return Err(invalid(bits.pos, "Too far distance"));
+                }
+                check_size(out.len() + length, max_size, bits.pos)?;
+                let start = out.len() - distance;
+                for j in 0..length {
+                    let b = out[start + j];
+                    out.push(b);
+                }
+            }
+            _ => return Err(invalid(bits.pos, "Invalid literal/length code")),
+        }
+    }
+}
+
+/// A canonical Huffman code.
+struct Huffman {
+    /// The number of the codes of each length.
+    counts: [u16; 16],
+
+    /// The symbols ordered by their codes.
+    symbols: Vec<u16>,
+}
+impl Huffman {
+    fn new(lengths: &[u8], offset: usize) -> DecodeResult<Self> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid(offset, "Over-subscribed Huffman code"));
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+/// A reader of the bits of a DEFLATE stream (from the least significant bit of each byte).
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u8,
+}
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u8) -> DecodeResult<u32> {
+        while self.bit_count < count {
+            let b = *self.buf.get(self.pos).ok_or(DecodeError::Truncated {
+                offset: self.buf.len(),
+            })?;
+            self.bit_buf |= u32::from(b) << self.bit_count;
+            self.bit_count += 8;
+            self.pos += 1;
+        }
+        let value = self.bit_buf & ((1u64 << count) - 1) as u32;
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Discards the bits remaining in the current byte.
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> DecodeResult<u16> {
+        // Huffman codes are packed from their most significant bits
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &huffman.counts[1..] {
+            code |= self.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid(self.pos, "Invalid Huffman code"))
+    }
+}
+
+fn check_size(size: usize, max_size: usize, offset: usize) -> DecodeResult<()> {
+    if size > max_size {
+        return Err(DecodeError::LimitExceeded {
+            limit: "max_body_size",
+            offset,
+            size: size as u64,
+            max: max_size,
+        });
+    }
+    Ok(())
+}
+
+fn invalid(offset: usize, reason: &str) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset,
+        reason: reason.to_owned(),
+    }
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (!(crc & 1)).wrapping_add(1));
+        }
+    }
+    !crc
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &[u8] = b"hello hello hello world";
+
+    /// `TEXT` compressed by `gzip.compress(TEXT, 9, mtime=0)` (a fixed Huffman block).
+    const GZIP_FIXED: [u8; 33] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x22, 0xcb, 0xf3, 0x8b, 0x72, 0x52, 0x00, 0x26, 0xe6, 0x5a, 0x81, 0x17,
+        0x00, 0x00, 0x00,
+    ];
+
+    /// `TEXT` compressed by `zlib.compress(TEXT, 0)` (a stored block).
+    const ZLIB_STORED: [u8; 34] = [
+        0x78, 0x01, 0x01, 0x17, 0x00, 0xe8, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65,
+        0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64,
+        0x68, 0x7d, 0x08, 0xc5,
+    ];
+
+    /// `pattern()` compressed by `zlib.compress(pattern, 9)` (a dynamic Huffman block).
+    const ZLIB_DYNAMIC: [u8; 137] = [
+        0x78, 0xda, 0x6d, 0xd2, 0x5b, 0x0a, 0x02, 0x41, 0x0c, 0x05, 0xd1, 0xad, 0x64, 0x09, 0x9d,
+        0xdc, 0x7e, 0xe2, 0x6a, 0x06, 0xd1, 0x4f, 0x15, 0xc7, 0xfd, 0x23, 0xc2, 0xc0, 0x20, 0xa9,
+        0xdf, 0x0b, 0x07, 0x42, 0xa8, 0xfd, 0xb5, 0x3d, 0xac, 0xd8, 0xf3, 0x6e, 0x9f, 0xf7, 0x76,
+        0xbd, 0x59, 0xb9, 0xd8, 0xfe, 0x9b, 0xfc, 0x9c, 0xfc, 0x98, 0xe2, 0x9c, 0xe2, 0x98, 0x94,
+        0x61, 0xcd, 0xb0, 0x65, 0xd8, 0x33, 0x1c, 0x19, 0xce, 0x0c, 0x17, 0x9c, 0x5a, 0xb2, 0x74,
+        0xcf, 0xd4, 0x03, 0xac, 0xc0, 0x56, 0xb0, 0x0d, 0x6c, 0x07, 0x3b, 0xc0, 0x4e, 0xb0, 0x0b,
+        0xfe, 0x5b, 0xb2, 0x0d, 0xcf, 0x36, 0x02, 0xac, 0xc0, 0x56, 0xb0, 0x0d, 0x6c, 0x07, 0x3b,
+        0xc0, 0x4e, 0xb0, 0x0b, 0xa2, 0x80, 0x9c, 0x04, 0x3d, 0x89, 0x82, 0x82, 0xa2, 0x04, 0x49,
+        0x09, 0x9a, 0x12, 0x44, 0x25, 0xa8, 0x4a, 0x90, 0x95, 0xfe, 0xbb, 0xfa, 0x02, 0xb7, 0x04,
+        0xef, 0x60,
+    ];
+
+    fn pattern() -> Vec<u8> {
+        (0..40)
+            .flat_map(|i| format!("span {} of trace {}; ", i, i % 3).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn gunzip_works() {
+        assert_eq!(gunzip(&GZIP_FIXED, 1024).unwrap(), TEXT);
+
+        // Concatenated members
+        let members = [&GZIP_FIXED[..], &GZIP_FIXED[..]].concat();
+        assert_eq!(gunzip(&members, 1024).unwrap(), [TEXT, TEXT].concat());
+    }
+
+    #[test]
+    fn zlib_decompress_works() {
+        assert_eq!(zlib_decompress(&ZLIB_STORED, 1024).unwrap(), TEXT);
+        assert_eq!(zlib_decompress(&ZLIB_DYNAMIC, 1024).unwrap(), pattern());
+
+        // A raw DEFLATE stream
+        let deflate = &GZIP_FIXED[10..GZIP_FIXED.len() - 8];
+        assert_eq!(zlib_decompress(deflate, 1024).unwrap(), TEXT);
+    }
+
+    #[test]
+    fn corrupt_input_is_rejected() {
+        let mut buf = GZIP_FIXED;
+        buf[25] ^= 1; // The CRC-32
+        assert!(matches!(gunzip(&buf, 1024), Err(DecodeError::InvalidValue { .. })));
+
+        let mut buf = ZLIB_DYNAMIC;
+        buf[136] ^= 1; // The Adler-32
+        assert!(matches!(zlib_decompress(&buf, 1024), Err(DecodeError::InvalidValue { .. })));
+
+        let mut buf = ZLIB_STORED;
+        buf[2] = 0x07; // The reserved block type
+        assert!(zlib_decompress(&buf, 1024).is_err());
+
+        for len in 1..GZIP_FIXED.len() {
+            assert!(gunzip(&GZIP_FIXED[..len], 1024).is_err(), "len={}", len);
+        }
+        for len in 2..ZLIB_DYNAMIC.len() {
+            assert!(zlib_decompress(&ZLIB_DYNAMIC[..len], 1024).is_err(), "len={}", len);
+        }
+
+        // Flipping any bit never panics
+        for i in 0..ZLIB_DYNAMIC.len() * 8 {
+            let mut buf = ZLIB_DYNAMIC;
+            buf[i / 8] ^= 1 << (i % 8);
+            let _ = zlib_decompress(&buf, 1024);
+        }
+    }
+
+    #[test]
+    fn max_size_is_enforced() {
+        assert!(gunzip(&GZIP_FIXED, TEXT.len()).is_ok());
+        assert!(matches!(
+            gunzip(&GZIP_FIXED, TEXT.len() - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+
+        let size = pattern().len();
+        assert!(zlib_decompress(&ZLIB_DYNAMIC, size).is_ok());
+        assert!(matches!(
+            zlib_decompress(&ZLIB_DYNAMIC, size - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+        assert!(matches!(
+            zlib_decompress(&ZLIB_STORED, TEXT.len() - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+    }
+}
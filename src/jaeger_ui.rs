@@ -0,0 +1,175 @@
+//! Encoding of Jaeger batches into the JSON which the Jaeger UI imports.
+//!
+//! The Jaeger UI can load traces from JSON files (in the search page) which have the shape of
+//! the responses of the `/api/traces` endpoint of the Jaeger query service.
+//! `to_json` groups the spans of a batch per trace, and maps them in the same way as
+//! the query service does:
+//!
+//! - The process becomes the `p1` process of each trace.
+//! - The parent span ID becomes a `CHILD_OF` reference (unless the span already has it),
+//!   since the UI finds the parent of a span from its references.
+//! - Tag values keep their types (`string`, `bool`, `int64`, `float64` and `binary`,
+//!   the last being base64 encoded).
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::jaeger_ui;
+//! use jaegercat::model::{Batch, Process, Span, SpanId, TraceId, UnknownFields};
+//!
+//! let span = Span {
+//!     trace_id: TraceId { high: 0, low: 1 },
+//!     span_id: SpanId(2),
+//!     parent_span_id: Some(SpanId(3)),
+//!     operation_name: "bar".to_owned(),
+//!     references: Vec::new(),
+//!     flags: 1,
+//!     start_time: 1_544_712_660_000_000,
+//!     duration: 1000,
+//!     tags: Vec::new(),
+//!     logs: Vec::new(),
+//!     unknown_fields: UnknownFields::new(),
+//! };
+//! let batch = Batch {
+//!     process: Process {
+//!         service_name: "foo".to_owned(),
+//!         tags: Vec::new(),
+//!         unknown_fields: UnknownFields::new(),
+//!     },
+//!     spans: vec![span],
+//!     seq_no: None,
+//!     stats: None,
+//!     unknown_fields: UnknownFields::new(),
+//! };
+//! let response = jaeger_ui::to_json(&batch);
+//! let trace = &response["data"][0];
+//! assert_eq!(trace["traceID"], "0000000000000001");
+//! assert_eq!(trace["processes"]["p1"]["serviceName"], "foo");
+//! assert_eq!(trace["spans"][0]["references"][0]["spanID"], "0000000000000003");
+//! ```
+use serde_json::{Map, Value};
+
+use model::{Batch, Hex, Log, Span, SpanId, SpanRefType, Tag, TagValue, TraceId};
+use otlp::encode_base64;
+
+/// The ID of the process of the spans in the traces.
+pub const PROCESS_ID: &str = "p1";
+
+/// Converts a batch into a response of `/api/traces` holding the traces of its spans.
+///
+/// The traces are in the order of their first spans in the batch.
+pub fn to_json(batch: &Batch) -> Value {
+    let mut traces: Vec<(TraceId, Vec<Value>)> = Vec::new();
+    for span in &batch.spans {
+        let json = span_to_json(span);
+        match traces.iter_mut().find(|t| t.0 == span.trace_id) {
+            Some(t) => t.1.push(json),
+            None => traces.push((span.trace_id, vec![json])),
+        }
+    }
+
+    let mut process = Map::new();
+    process.insert(
+        "serviceName".to_owned(),
+        Value::from(batch.process.service_name.as_str()),
+    );
+    process.insert("tags".to_owned(), tags_to_json(&batch.process.tags));
+    let mut processes = Map::new();
+    processes.insert(PROCESS_ID.to_owned(), Value::Object(process));
+    let data = traces
+        .into_iter()
+        .map(|(trace_id, spans)| {
+            let mut trace = Map::new();
+            trace.insert("traceID".to_owned(), Value::from(Hex(trace_id).to_string()));
+            trace.insert("spans".to_owned(), Value::Array(spans));
+            trace.insert("processes".to_owned(), Value::Object(processes.clone()));
+            trace.insert("warnings".to_owned(), Value::Null);
+            Value::Object(trace)
+        })
+        .collect();
+
+    let mut response = Map::new();
+    response.insert("data".to_owned(), Value::Array(data));
+    response.insert("total".to_owned(), Value::from(0));
+    response.insert("limit".to_owned(), Value::from(0));
+    response.insert("offset".to_owned(), Value::from(0));
+    response.insert("errors".to_owned(), Value::Null);
+    Value::Object(response)
+}
+
+fn span_to_json(span: &Span) -> Value {
+    let mut references = Vec::with_capacity(span.references.len() + 1);
+    if let Some(parent) = span.parent_span_id.filter(|id| id.0 != 0) {
+        let has_parent = span.references.iter().any(|r| {
+            r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id
+                && r.span_id == parent
+        });
+        if !has_parent {
+            references.push(reference_to_json(SpanRefType::ChildOf, span.trace_id, parent));
+        }
+    }
+    references.extend(
+        span.references
+            .iter()
+            .map(|r| reference_to_json(r.ref_type, r.trace_id, r.span_id)),
+    );
+
+    let mut object = Map::new();
+    object.insert("traceID".to_owned(), Value::from(Hex(span.trace_id).to_string()));
+    object.insert("spanID".to_owned(), Value::from(Hex(span.span_id).to_string()));
+    object.insert("flags".to_owned(), Value::from(span.flags));
+    object.insert(
+        "operationName".to_owned(),
+        Value::from(span.operation_name.as_str()),
+    );
+    object.insert("references".to_owned(), Value::Array(references));
+    object.insert("startTime".to_owned(), Value::from(span.start_time));
+    object.insert("duration".to_owned(), Value::from(span.duration));
+    object.insert("tags".to_owned(), tags_to_json(&span.tags));
+    object.insert(
+        "logs".to_owned(),
+        Value::Array(span.logs.iter().map(log_to_json).collect()),
+    );
+    object.insert("processID".to_owned(), Value::from(PROCESS_ID));
+    object.insert("warnings".to_owned(), Value::Null);
+    Value::Object(object)
+}
+
+fn reference_to_json(ref_type: SpanRefType, trace_id: TraceId, span_id: SpanId) -> Value {
+    let ref_type = match ref_type {
+        SpanRefType::ChildOf => "CHILD_OF",
+        SpanRefType::FollowsFrom => "FOLLOWS_FROM",
+    };
+    let mut object = Map::new();
+    object.insert("refType".to_owned(), Value::from(ref_type));
+    object.insert("traceID".to_owned(), Value::from(Hex(trace_id).to_string()));
+    object.insert("spanID".to_owned(), Value::from(Hex(span_id).to_string()));
+    Value::Object(object)
+}
+
+fn log_to_json(log: &Log) -> Value {
+    let mut object = Map::new();
+    object.insert("timestamp".to_owned(), Value::from(log.timestamp));
+    object.insert("fields".to_owned(), tags_to_json(&log.fields));
+    Value::Object(object)
+}
+
+fn tags_to_json(tags: &[Tag]) -> Value {
+    let tags = tags.iter()
+        .map(|tag| {
+            let (type_name, value) = match tag.value {
+                TagValue::Str(ref v) => ("string", Value::from(v.as_str())),
+                TagValue::Bool(v) => ("bool", Value::from(v)),
+                TagValue::Long(v) => ("int64", Value::from(v)),
+                TagValue::Double(v) => ("float64", Value::from(v)),
+                TagValue::Binary(ref v) => ("binary", Value::from(encode_base64(v))),
+            };
+            let mut object = Map::new();
+            object.insert("key".to_owned(), Value::from(tag.key.as_str()));
+            object.insert("type".to_owned(), Value::from(type_name));
+            object.insert("value".to_owned(), value);
+            Value::Object(object)
+        })
+        .collect();
+    Value::Array(tags)
+}
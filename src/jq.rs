@@ -0,0 +1,1317 @@
+//! A subset of the [jq] language, for filtering and transforming the JSON output
+//! without an external `jq` process.
+//!
+//! `Filter` supports:
+//!
+//! - Paths: `.`, `.foo`, `."foo"`, `.[expr]`, `.[]` and `?` (e.g., `.emit_batch.spans[0]`).
+//! - Literals: numbers, strings (without interpolation), `true`, `false` and `null`.
+//! - Constructions: `[expr]` and `{key: expr, "key": expr, (expr): expr, key}`.
+//! - Operators: `|`, `,`, `//`, `or`, `and`, `==`, `!=`, `<`, `<=`, `>`, `>=`,
+//!   `+`, `-`, `*`, `/`, `%` and unary `-`.
+//! - `if cond then expr (elif cond then expr)* (else expr)? end`.
+//! - The functions `select(f)`, `map(f)`, `map_values(f)`, `with_entries(f)`, `has(key)`,
+//!   `contains(x)`, `startswith(s)`, `endswith(s)`, `join(s)`, `split(s)`, `empty`, `not`,
+//!   `length`, `keys`, `values`, `type`, `add`, `any`, `all`, `min`, `max`, `sort`, `unique`,
+//!   `reverse`, `first`, `last`, `to_entries`, `from_entries`, `tostring`, `tonumber`,
+//!   `ascii_downcase` and `ascii_upcase`.
+//!
+//! Variables, `reduce`, string interpolation, formats (e.g., `@csv`) and regular expressions
+//! are not supported.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate jaegercat;
+//! #[macro_use]
+//! extern crate serde_json;
+//!
+//! use jaegercat::jq::Filter;
+//!
+//! # fn main() {
+//! let filter: Filter = r#".spans[] | select(.duration > 1) | {name: .operation_name}"#
+//!     .parse()
+//!     .unwrap();
+//! let input = json!({"spans": [
+//!     {"operation_name": "foo", "duration": 0.5},
+//!     {"operation_name": "bar", "duration": 2},
+//! ]});
+//! assert_eq!(filter.apply(&input).unwrap(), vec![json!({"name": "bar"})]);
+//! # }
+//! ```
+//!
+//! [jq]: https://jqlang.github.io/jq/manual/
+use std::cmp::Ordering;
+use std::str::FromStr;
+use serde_json::{self, Map, Number, Value};
+use trackable::error::{Failed, Failure};
+
+use Result;
+
+/// A parsed jq filter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+impl Filter {
+    /// Applies the filter to `input`, and returns the outputs.
+    pub fn apply(&self, input: &Value) -> Result<Vec<Value>> {
+        match self.expr.eval(input) {
+            Ok(outputs) => Ok(outputs),
+            Err(e) => track_panic!(Failed, "jq: {}", e),
+        }
+    }
+}
+impl FromStr for Filter {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let tokens = match tokenize(s) {
+            Ok(tokens) => tokens,
+            Err(e) => track_panic!(Failed, "jq: {}", e),
+        };
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = match parser.parse_all() {
+            Ok(expr) => expr,
+            Err(e) => track_panic!(Failed, "jq: {}: {:?}", e, s),
+        };
+        Ok(Filter { expr })
+    }
+}
+
+type EvalResult = ::std::result::Result<Vec<Value>, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    Field(String),
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Punct(&'static str),
+}
+
+const PUNCTS: &[&str] = &[
+    "//", "==", "!=", "<=", ">=", "|", ",", "(", ")", "[", "]", "{", "}", ":", ";", "?", "<",
+    ">", "+", "-", "*", "/", "%",
+];
+
+fn tokenize(s: &str) -> ::std::result::Result<Vec<Token>, String> {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '.' && chars.get(i + 1).is_some_and(|&c| is_ident_start(c)) {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Field(chars[start..i].iter().collect()));
+        } else if c == '.' && !chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+            tokens.push(Token::Dot);
+            i += 1;
+        } else if is_ident_start(c) {
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            if i < chars.len() && (chars[i] == 'e' || chars[i] == 'E') {
+                i += 1;
+                if i < chars.len() && (chars[i] == '+' || chars[i] == '-') {
+                    i += 1;
+                }
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text = chars[start..i].iter().collect::<String>();
+            let n = text.parse().map_err(|_| format!("Invalid number: {}", text))?;
+            tokens.push(Token::Num(n));
+        } else if c == '"' {
+            let (string, end) = parse_string(&chars, i)?;
+            tokens.push(Token::Str(string));
+            i = end;
+        } else {
+            let rest = chars[i..].iter().take(2).collect::<String>();
+            let punct = PUNCTS
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| format!("Unexpected character: {:?}", c))?;
+            tokens.push(Token::Punct(punct));
+            i += punct.len();
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Parses the string literal starting at `chars[start]` (a quote),
+/// and returns it with the index next to the closing quote.
+fn parse_string(chars: &[char], start: usize) -> ::std::result::Result<(String, usize), String> {
+    let mut string = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i) {
+            None => return Err("Unterminated string".to_owned()),
+            Some('"') => return Ok((string, i + 1)),
+            Some('\\') => {
+                let c = match chars.get(i + 1) {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('u') => {
+                        let hex = chars.get(i + 2..i + 6).ok_or("Truncated \\u escape")?;
+                        let code = u32::from_str_radix(&hex.iter().collect::<String>(), 16)
+                            .map_err(|_| "Invalid \\u escape".to_owned())?;
+                        i += 4;
+                        ::std::char::from_u32(code).unwrap_or('\u{FFFD}')
+                    }
+                    Some('(') => return Err("String interpolation is not supported".to_owned()),
+                    _ => return Err("Invalid escape in string".to_owned()),
+                };
+                string.push(c);
+                i += 2;
+            }
+            Some(&c) => {
+                string.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Identity,
+    Literal(Value),
+    Index(Box<Expr>, Box<Expr>),
+    Iterate(Box<Expr>),
+    Try(Box<Expr>),
+    Array(Option<Box<Expr>>),
+    Object(Vec<(Expr, Expr)>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Alternative(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    If(Vec<(Expr, Expr)>, Option<Box<Expr>>),
+    Call(String, Vec<Expr>),
+}
+impl Expr {
+    fn eval(&self, input: &Value) -> EvalResult {
+        match *self {
+            Expr::Identity => Ok(vec![input.clone()]),
+            Expr::Literal(ref v) => Ok(vec![v.clone()]),
+            Expr::Index(ref target, ref index) => {
+                let mut outputs = Vec::new();
+                for index in index.eval(input)? {
+                    for target in target.eval(input)? {
+                        outputs.push(index_value(&target, &index)?);
+                    }
+                }
+                Ok(outputs)
+            }
+            Expr::Iterate(ref target) => {
+                let mut outputs = Vec::new();
+                for target in target.eval(input)? {
+                    outputs.extend(iterate(&target)?);
+                }
+                Ok(outputs)
+            }
+            Expr::Try(ref expr) => Ok(expr.eval(input).unwrap_or_default()),
+            Expr::Array(None) => Ok(vec![Value::Array(Vec::new())]),
+            Expr::Array(Some(ref expr)) => Ok(vec![Value::Array(expr.eval(input)?)]),
+            Expr::Object(ref entries) => {
+                let mut objects = vec![Map::new()];
+                for (key, value) in entries {
+                    let keys = key.eval(input)?;
+                    let values = value.eval(input)?;
+                    let mut next = Vec::new();
+                    for object in &objects {
+                        for key in &keys {
+                            let key = match *key {
+                                Value::String(ref key) => key,
+                                ref key => {
+                                    return Err(format!("Object keys must be strings: {}", key))
+                                }
+                            };
+                            for value in &values {
+                                let mut object = object.clone();
+                                object.insert(key.clone(), value.clone());
+                                next.push(object);
+                            }
+                        }
+                    }
+                    objects = next;
+                }
+                Ok(objects.into_iter().map(Value::Object).collect())
+            }
+            Expr::Pipe(ref lhs, ref rhs) => {
+                let mut outputs = Vec::new();
+                for v in lhs.eval(input)? {
+                    outputs.extend(rhs.eval(&v)?);
+                }
+                Ok(outputs)
+            }
+            Expr::Comma(ref lhs, ref rhs) => {
+                let mut outputs = lhs.eval(input)?;
+                outputs.extend(rhs.eval(input)?);
+                Ok(outputs)
+            }
+            Expr::Alternative(ref lhs, ref rhs) => {
+                let outputs = lhs.eval(input)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(is_truthy)
+                    .collect::<Vec<_>>();
+                if outputs.is_empty() {
+                    rhs.eval(input)
+                } else {
+                    Ok(outputs)
+                }
+            }
+            Expr::And(ref lhs, ref rhs) | Expr::Or(ref lhs, ref rhs) => {
+                let is_and = matches!(*self, Expr::And(..));
+                let mut outputs = Vec::new();
+                for l in lhs.eval(input)? {
+                    if is_truthy(&l) != is_and {
+                        outputs.push(Value::Bool(!is_and));
+                        continue;
+                    }
+                    for r in rhs.eval(input)? {
+                        outputs.push(Value::Bool(is_truthy(&r)));
+                    }
+                }
+                Ok(outputs)
+            }
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let mut outputs = Vec::new();
+                for r in rhs.eval(input)? {
+                    for l in lhs.eval(input)? {
+                        outputs.push(binary(op, &l, &r)?);
+                    }
+                }
+                Ok(outputs)
+            }
+            Expr::Negate(ref expr) => expr.eval(input)?
+                .into_iter()
+                .map(|v| match v.as_f64() {
+                    Some(n) => Ok(number(-n)),
+                    None => Err(format!("{} cannot be negated", v)),
+                })
+                .collect(),
+            Expr::If(ref branches, ref otherwise) => eval_if(branches, otherwise, input),
+            Expr::Call(ref name, ref args) => call(name, args, input),
+        }
+    }
+}
+
+fn eval_if(branches: &[(Expr, Expr)], otherwise: &Option<Box<Expr>>, input: &Value) -> EvalResult {
+    let (cond, then) = match branches.split_first() {
+        Some((first, _)) => first,
+        None => {
+            return match *otherwise {
+                Some(ref expr) => expr.eval(input),
+                None => Ok(vec![input.clone()]),
+            }
+        }
+    };
+    let mut outputs = Vec::new();
+    for c in cond.eval(input)? {
+        if is_truthy(&c) {
+            outputs.extend(then.eval(input)?);
+        } else {
+            outputs.extend(eval_if(&branches[1..], otherwise, input)?);
+        }
+    }
+    Ok(outputs)
+}
+
+fn call(name: &str, args: &[Expr], input: &Value) -> EvalResult {
+    let one = |v: Value| Ok(vec![v]);
+    match (name, args.len()) {
+        ("empty", 0) => Ok(Vec::new()),
+        ("not", 0) => one(Value::Bool(!is_truthy(input))),
+        ("select", 1) => Ok(args[0]
+            .eval(input)?
+            .iter()
+            .filter(|v| is_truthy(v))
+            .map(|_| input.clone())
+            .collect()),
+        ("map", 1) => {
+            let mut outputs = Vec::new();
+            for v in iterate(input)? {
+                outputs.extend(args[0].eval(&v)?);
+            }
+            one(Value::Array(outputs))
+        }
+        ("map_values", 1) => match *input {
+            Value::Object(ref object) => {
+                let mut mapped = Map::new();
+                for (k, v) in object {
+                    if let Some(v) = args[0].eval(v)?.into_iter().next() {
+                        mapped.insert(k.clone(), v);
+                    }
+                }
+                one(Value::Object(mapped))
+            }
+            Value::Array(ref values) => {
+                let mut mapped = Vec::new();
+                for v in values {
+                    mapped.extend(args[0].eval(v)?.into_iter().next());
+                }
+                one(Value::Array(mapped))
+            }
+            ref v => Err(format!("Cannot iterate over {}", v)),
+        },
+        ("with_entries", 1) => {
+            let mut entries = Vec::new();
+            for entry in to_entries(input)? {
+                entries.extend(args[0].eval(&entry)?);
+            }
+            one(from_entries(&entries)?)
+        }
+        ("has", 1) => args[0]
+            .eval(input)?
+            .iter()
+            .map(|key| match (input, key) {
+                (Value::Object(o), Value::String(k)) => Ok(Value::Bool(o.contains_key(k))),
+                (Value::Array(a), k) if k.is_number() => {
+                    let i = k.as_f64().unwrap_or(-1.0);
+                    Ok(Value::Bool(i >= 0.0 && (i as usize) < a.len()))
+                }
+                (v, k) => Err(format!("Cannot check whether {} has {}", v, k)),
+            })
+            .collect(),
+        ("contains", 1) => args[0]
+            .eval(input)?
+            .iter()
+            .map(|b| Ok(Value::Bool(contains(input, b)?)))
+            .collect(),
+        ("startswith", 1) | ("endswith", 1) => args[0]
+            .eval(input)?
+            .iter()
+            .map(|affix| match (input, affix) {
+                (Value::String(s), Value::String(a)) => Ok(Value::Bool(
+                    if name == "startswith" {
+                        s.starts_with(a.as_str())
+                    } else {
+                        s.ends_with(a.as_str())
+                    },
+                )),
+                _ => Err(format!("{}() requires string inputs", name)),
+            })
+            .collect(),
+        ("join", 1) => args[0]
+            .eval(input)?
+            .iter()
+            .map(|separator| {
+                let separator = separator.as_str().ok_or("join() requires a string separator")?;
+                let strings = iterate(input)?
+                    .iter()
+                    .map(|v| match *v {
+                        Value::Null => Ok(String::new()),
+                        Value::String(ref s) => Ok(s.clone()),
+                        Value::Bool(_) | Value::Number(_) => Ok(v.to_string()),
+                        _ => Err(format!("Cannot join {}", v)),
+                    })
+                    .collect::<::std::result::Result<Vec<_>, String>>()?;
+                Ok(Value::from(strings.join(separator)))
+            })
+            .collect(),
+        ("split", 1) => args[0]
+            .eval(input)?
+            .iter()
+            .map(|separator| match (input, separator) {
+                (Value::String(s), Value::String(sep)) => Ok(Value::Array(
+                    if s.is_empty() {
+                        Vec::new()
+                    } else {
+                        s.split(sep.as_str()).map(Value::from).collect()
+                    },
+                )),
+                _ => Err("split() requires string inputs".to_owned()),
+            })
+            .collect(),
+        ("length", 0) => one(match *input {
+            Value::Null => Value::from(0),
+            Value::Bool(_) => return Err(format!("{} has no length", input)),
+            Value::Number(ref n) => number(n.as_f64().unwrap_or(0.0).abs()),
+            Value::String(ref s) => Value::from(s.chars().count()),
+            Value::Array(ref a) => Value::from(a.len()),
+            Value::Object(ref o) => Value::from(o.len()),
+        }),
+        ("keys", 0) => one(match *input {
+            Value::Object(ref o) => Value::Array(o.keys().cloned().map(Value::from).collect()),
+            Value::Array(ref a) => Value::Array((0..a.len()).map(Value::from).collect()),
+            ref v => return Err(format!("{} has no keys", v)),
+        }),
+        ("values", 0) => Ok(if matches!(*input, Value::Null) {
+            Vec::new()
+        } else {
+            vec![input.clone()]
+        }),
+        ("type", 0) => one(Value::from(type_name(input))),
+        ("add", 0) => {
+            let mut sum = Value::Null;
+            for v in iterate(input)? {
+                sum = binary("+", &sum, &v)?;
+            }
+            one(sum)
+        }
+        ("any", 0) => one(Value::Bool(iterate(input)?.iter().any(is_truthy))),
+        ("all", 0) => one(Value::Bool(iterate(input)?.iter().all(is_truthy))),
+        ("min", 0) | ("max", 0) => {
+            let values = sorted(input)?;
+            let v = if name == "min" {
+                values.first()
+            } else {
+                values.last()
+            };
+            one(v.cloned().unwrap_or(Value::Null))
+        }
+        ("sort", 0) => one(Value::Array(sorted(input)?)),
+        ("unique", 0) => {
+            let mut values = sorted(input)?;
+            values.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+            one(Value::Array(values))
+        }
+        ("reverse", 0) => one(match *input {
+            Value::Null => Value::Array(Vec::new()),
+            Value::String(ref s) => Value::from(s.chars().rev().collect::<String>()),
+            Value::Array(ref a) => Value::Array(a.iter().rev().cloned().collect()),
+            ref v => return Err(format!("Cannot reverse {}", v)),
+        }),
+        ("first", 0) => one(index_value(input, &Value::from(0))?),
+        ("last", 0) => one(index_value(input, &Value::from(-1))?),
+        ("to_entries", 0) => one(Value::Array(to_entries(input)?)),
+        ("from_entries", 0) => one(from_entries(&iterate(input)?)?),
+        ("tostring", 0) => one(match *input {
+            Value::String(_) => input.clone(),
+            ref v => Value::from(v.to_string()),
+        }),
+        ("tonumber", 0) => one(match *input {
+            Value::Number(_) => input.clone(),
+            Value::String(ref s) => number(
+                s.trim()
+                    .parse()
+                    .map_err(|_| format!("Cannot parse {:?} as a number", s))?,
+            ),
+            ref v => return Err(format!("Cannot parse {} as a number", v)),
+        }),
+        ("ascii_downcase", 0) | ("ascii_upcase", 0) => match *input {
+            Value::String(ref s) => one(Value::from(if name == "ascii_downcase" {
+                s.to_ascii_lowercase()
+            } else {
+                s.to_ascii_uppercase()
+            })),
+            _ => Err(format!("{}() requires a string input", name)),
+        },
+        _ => Err(format!("{}/{} is not defined", name, args.len())),
+    }
+}
+
+fn index_value(target: &Value, index: &Value) -> ::std::result::Result<Value, String> {
+    match (target, index) {
+        (Value::Null, Value::String(_)) | (Value::Null, Value::Number(_)) => Ok(Value::Null),
+        (Value::Object(o), Value::String(k)) => {
+            Ok(o.get(k).cloned().unwrap_or(Value::Null))
+        }
+        (Value::Array(a), Value::Number(n)) => {
+            let i = n.as_f64().unwrap_or(0.0).floor() as i64;
+            let i = if i < 0 { i + a.len() as i64 } else { i };
+            Ok(if i < 0 {
+                Value::Null
+            } else {
+                a.get(i as usize).cloned().unwrap_or(Value::Null)
+            })
+        }
+        (t, i) => Err(format!("Cannot index {} with {}", type_name(t), i)),
+    }
+}
+
+fn iterate(value: &Value) -> EvalResult {
+    match *value {
+        Value::Array(ref a) => Ok(a.clone()),
+        Value::Object(ref o) => Ok(o.values().cloned().collect()),
+        ref v => Err(format!("Cannot iterate over {}", v)),
+    }
+}
+
+fn sorted(value: &Value) -> EvalResult {
+    match *value {
+        Value::Array(ref a) => {
+            let mut a = a.clone();
+            a.sort_by(compare);
+            Ok(a)
+        }
+        ref v => Err(format!("{} cannot be sorted", v)),
+    }
+}
+
+fn to_entries(value: &Value) -> EvalResult {
+    match *value {
+        Value::Object(ref o) => Ok(o.iter()
+            .map(|(k, v)| {
+                let mut entry = Map::new();
+                entry.insert("key".to_owned(), Value::from(k.as_str()));
+                entry.insert("value".to_owned(), v.clone());
+                Value::Object(entry)
+            })
+            .collect()),
+        ref v => Err(format!("{} has no entries", v)),
+    }
+}
+
+fn from_entries(entries: &[Value]) -> ::std::result::Result<Value, String> {
+    let mut object = Map::new();
+    for entry in entries {
+        let get = |keys: &[&str]| {
+            keys.iter()
+                .filter_map(|k| entry.get(k))
+                .find(|v| !v.is_null())
+                .cloned()
+        };
+        let key = match get(&["key", "k", "name", "Name", "Key", "K"]) {
+            Some(Value::String(key)) => key,
+            Some(Value::Bool(_)) | Some(Value::Number(_)) => {
+                get(&["key", "k", "name", "Name", "Key", "K"]).unwrap_or_default().to_string()
+            }
+            _ => return Err(format!("Invalid entry: {}", entry)),
+        };
+        let value = get(&["value", "v", "Value", "V"]).unwrap_or(Value::Null);
+        object.insert(key, value);
+    }
+    Ok(Value::Object(object))
+}
+
+fn contains(a: &Value, b: &Value) -> ::std::result::Result<bool, String> {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (k, bv) in b {
+                match a.get(k) {
+                    Some(av) if contains(av, bv)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for bv in b {
+                let mut found = false;
+                for av in a {
+                    if contains(av, bv)? {
+                        found = true;
+                        break;
+                    }
+                }
+                if !found {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        (Value::String(a), Value::String(b)) => Ok(a.contains(b.as_str())),
+        (a, b) if type_name(a) == type_name(b) => Ok(a == b),
+        (a, b) => Err(format!("{} and {} cannot have their containment checked", a, b)),
+    }
+}
+
+fn binary(op: &str, l: &Value, r: &Value) -> ::std::result::Result<Value, String> {
+    match op {
+        "==" => return Ok(Value::Bool(compare(l, r) == Ordering::Equal)),
+        "!=" => return Ok(Value::Bool(compare(l, r) != Ordering::Equal)),
+        "<" => return Ok(Value::Bool(compare(l, r) == Ordering::Less)),
+        "<=" => return Ok(Value::Bool(compare(l, r) != Ordering::Greater)),
+        ">" => return Ok(Value::Bool(compare(l, r) == Ordering::Greater)),
+        ">=" => return Ok(Value::Bool(compare(l, r) != Ordering::Less)),
+        _ => {}
+    }
+    match (op, l, r) {
+        ("+", Value::Null, v) | ("+", v, Value::Null) => Ok(v.clone()),
+        (_, Value::Number(a), Value::Number(b)) => {
+            let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+            match op {
+                "+" => Ok(number(a + b)),
+                "-" => Ok(number(a - b)),
+                "*" => Ok(number(a * b)),
+                "/" if b == 0.0 => Err("Division by zero".to_owned()),
+                "/" => Ok(number(a / b)),
+                "%" if b as i64 == 0 => Err("Division by zero".to_owned()),
+                _ => Ok(number(((a as i64) % (b as i64)) as f64)),
+            }
+        }
+        ("+", Value::String(a), Value::String(b)) => Ok(Value::from(a.clone() + b)),
+        ("+", Value::Array(a), Value::Array(b)) => {
+            Ok(Value::Array(a.iter().chain(b).cloned().collect()))
+        }
+        ("-", Value::Array(a), Value::Array(b)) => {
+            Ok(Value::Array(a.iter().filter(|v| !b.contains(v)).cloned().collect()))
+        }
+        ("+", Value::Object(a), Value::Object(b)) => {
+            let mut o = a.clone();
+            o.extend(b.iter().map(|(k, v)| (k.clone(), v.clone())));
+            Ok(Value::Object(o))
+        }
+        ("/", Value::String(a), Value::String(b)) => {
+            Ok(Value::Array(a.split(b.as_str()).map(Value::from).collect()))
+        }
+        _ => Err(format!("{} and {} cannot be used with {}", type_name(l), type_name(r), op)),
+    }
+}
+
+/// Compares values in the order of jq
+/// (`null < false < true < numbers < strings < arrays < objects`).
+fn compare(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match *v {
+            Value::Null => 0,
+            Value::Bool(false) => 1,
+            Value::Bool(true) => 2,
+            Value::Number(_) => 3,
+            Value::String(_) => 4,
+            Value::Array(_) => 5,
+            Value::Object(_) => 6,
+        }
+    }
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()
+            .partial_cmp(&b.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a.iter()
+            .zip(b)
+            .map(|(a, b)| compare(a, b))
+            .find(|&o| o != Ordering::Equal)
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => {
+            let keys = |o: &Map<String, Value>| o.keys().cloned().map(Value::from).collect();
+            compare(&Value::Array(keys(a)), &Value::Array(keys(b))).then_with(|| {
+                a.values()
+                    .zip(b.values())
+                    .map(|(a, b)| compare(a, b))
+                    .find(|&o| o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            })
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn is_truthy(v: &Value) -> bool {
+    !matches!(*v, Value::Null | Value::Bool(false))
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match *v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Makes a number value, which is an integer if `n` is integral (as jq prints it).
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 {
+        Value::from(n as i64)
+    } else {
+        Number::from_f64(n).map_or(Value::Null, Value::Number)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+impl Parser {
+    fn parse_all(&mut self) -> ::std::result::Result<Expr, String> {
+        if self.tokens.is_empty() {
+            return Ok(Expr::Identity);
+        }
+        let expr = self.parse_pipe()?;
+        match self.peek() {
+            None => Ok(expr),
+            Some(t) => Err(format!("Unexpected {}", describe(t))),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        if self.peek() == Some(&Token::Punct(punct_str(punct))) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(s)) if s == ident => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> ::std::result::Result<(), String> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("{:?}", punct)))
+        }
+    }
+
+    fn expect_ident(&mut self, ident: &str) -> ::std::result::Result<(), String> {
+        if self.eat_ident(ident) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("{:?}", ident)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.peek() {
+            None => format!("Expected {} but reached the end", expected),
+            Some(t) => format!("Expected {} but found {}", expected, describe(t)),
+        }
+    }
+
+    fn parse_pipe(&mut self) -> ::std::result::Result<Expr, String> {
+        let lhs = self.parse_comma()?;
+        if self.eat_punct("|") {
+            let rhs = self.parse_pipe()?;
+            return Ok(Expr::Pipe(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comma(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut lhs = self.parse_alternative()?;
+        while self.eat_punct(",") {
+            let rhs = self.parse_alternative()?;
+            lhs = Expr::Comma(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_alternative(&mut self) -> ::std::result::Result<Expr, String> {
+        let lhs = self.parse_or()?;
+        if self.eat_punct("//") {
+            let rhs = self.parse_alternative()?;
+            return Ok(Expr::Alternative(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_or(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_ident("or") {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat_ident("and") {
+            let rhs = self.parse_comparison()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> ::std::result::Result<Expr, String> {
+        let lhs = self.parse_additive()?;
+        for &op in &["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat_punct(op) {
+                let rhs = self.parse_additive()?;
+                return Ok(Expr::Binary(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn parse_additive(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = if self.eat_punct("+") {
+                "+"
+            } else if self.eat_punct("-") {
+                "-"
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_multiplicative()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = if self.eat_punct("*") {
+                "*"
+            } else if self.eat_punct("/") {
+                "/"
+            } else if self.eat_punct("%") {
+                "%"
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> ::std::result::Result<Expr, String> {
+        if self.eat_punct("-") {
+            let expr = self.parse_unary()?;
+            return Ok(Expr::Negate(Box::new(expr)));
+        }
+        self.parse_postfix()
+    }
+
+    fn parse_postfix(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut expr = self.parse_term()?;
+        loop {
+            match self.peek().cloned() {
+                Some(Token::Field(name)) => {
+                    self.pos += 1;
+                    expr = Expr::Index(Box::new(expr), Box::new(Expr::Literal(Value::from(name))));
+                }
+                Some(Token::Dot) => match self.tokens.get(self.pos + 1).cloned() {
+                    Some(Token::Str(name)) => {
+                        self.pos += 2;
+                        let name = Box::new(Expr::Literal(Value::from(name)));
+                        expr = Expr::Index(Box::new(expr), name);
+                    }
+                    Some(Token::Punct("[")) => {
+                        self.pos += 1;
+                    }
+                    _ => return Ok(expr),
+                },
+                Some(Token::Punct("[")) => {
+                    self.pos += 1;
+                    if self.eat_punct("]") {
+                        expr = Expr::Iterate(Box::new(expr));
+                    } else {
+                        let index = self.parse_pipe()?;
+                        self.expect_punct("]")?;
+                        expr = Expr::Index(Box::new(expr), Box::new(index));
+                    }
+                }
+                Some(Token::Punct("?")) => {
+                    self.pos += 1;
+                    expr = Expr::Try(Box::new(expr));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn parse_term(&mut self) -> ::std::result::Result<Expr, String> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err("Unexpected end of the filter".to_owned()),
+        };
+        match token {
+            Token::Dot => match self.peek().cloned() {
+                Some(Token::Str(name)) => {
+                    self.pos += 1;
+                    let name = Box::new(Expr::Literal(Value::from(name)));
+                    Ok(Expr::Index(Box::new(Expr::Identity), name))
+                }
+                _ => Ok(Expr::Identity),
+            },
+            Token::Field(name) => Ok(Expr::Index(
+                Box::new(Expr::Identity),
+                Box::new(Expr::Literal(Value::from(name))),
+            )),
+            Token::Str(s) => Ok(Expr::Literal(Value::from(s))),
+            Token::Num(n) => Ok(Expr::Literal(number(n))),
+            Token::Punct("(") => {
+                let expr = self.parse_pipe()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => {
+                if self.eat_punct("]") {
+                    return Ok(Expr::Array(None));
+                }
+                let expr = self.parse_pipe()?;
+                self.expect_punct("]")?;
+                Ok(Expr::Array(Some(Box::new(expr))))
+            }
+            Token::Punct("{") => self.parse_object(),
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "null" => Ok(Expr::Literal(Value::Null)),
+                "if" => self.parse_if(),
+                _ => {
+                    let mut args = Vec::new();
+                    if self.eat_punct("(") {
+                        loop {
+                            args.push(self.parse_pipe()?);
+                            if !self.eat_punct(";") {
+                                break;
+                            }
+                        }
+                        self.expect_punct(")")?;
+                    }
+                    Ok(Expr::Call(ident, args))
+                }
+            },
+            t => Err(format!("Unexpected {}", describe(&t))),
+        }
+    }
+
+    fn parse_object(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut entries = Vec::new();
+        if self.eat_punct("}") {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            let (key, shorthand) = match self.next() {
+                Some(Token::Ident(name)) | Some(Token::Str(name)) => {
+                    let shorthand = Expr::Index(
+                        Box::new(Expr::Identity),
+                        Box::new(Expr::Literal(Value::from(name.as_str()))),
+                    );
+                    (Expr::Literal(Value::from(name)), Some(shorthand))
+                }
+                Some(Token::Punct("(")) => {
+                    let key = self.parse_pipe()?;
+                    self.expect_punct(")")?;
+                    (key, None)
+                }
+                Some(t) => return Err(format!("Unexpected {} as an object key", describe(&t))),
+                None => return Err("Unexpected end of the filter".to_owned()),
+            };
+            let value = if self.eat_punct(":") {
+                self.parse_alternative()?
+            } else {
+                shorthand.ok_or_else(|| self.unexpected("\":\""))?
+            };
+            entries.push((key, value));
+            if self.eat_punct("}") {
+                return Ok(Expr::Object(entries));
+            }
+            self.expect_punct(",")?;
+        }
+    }
+
+    fn parse_if(&mut self) -> ::std::result::Result<Expr, String> {
+        let mut branches = Vec::new();
+        loop {
+            let cond = self.parse_pipe()?;
+            self.expect_ident("then")?;
+            let then = self.parse_pipe()?;
+            branches.push((cond, then));
+            if !self.eat_ident("elif") {
+                break;
+            }
+        }
+        let otherwise = if self.eat_ident("else") {
+            Some(Box::new(self.parse_pipe()?))
+        } else {
+            None
+        };
+        self.expect_ident("end")?;
+        Ok(Expr::If(branches, otherwise))
+    }
+}
+
+fn punct_str(punct: &str) -> &'static str {
+    PUNCTS.iter().find(|p| **p == punct).cloned().unwrap_or("")
+}
+
+fn describe(token: &Token) -> String {
+    match *token {
+        Token::Dot => "\".\"".to_owned(),
+        Token::Field(ref name) => format!("\".{}\"", name),
+        Token::Ident(ref name) => format!("{:?}", name),
+        Token::Str(ref s) => serde_json::to_string(s).unwrap_or_default(),
+        Token::Num(n) => n.to_string(),
+        Token::Punct(p) => format!("{:?}", p),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(filter: &str, input: Value) -> Vec<Value> {
+        let filter: Filter = filter.parse().unwrap();
+        filter.apply(&input).unwrap()
+    }
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn field(target: Expr, name: &str) -> Expr {
+        Expr::Index(Box::new(target), Box::new(Expr::Literal(Value::from(name))))
+    }
+
+    #[test]
+    fn paths_are_parsed() {
+        let parse = |s: &str| s.parse::<Filter>().unwrap().expr;
+        let foo = field(Expr::Identity, "foo");
+        assert_eq!(parse(""), Expr::Identity);
+        assert_eq!(parse("."), Expr::Identity);
+        assert_eq!(parse(".foo"), foo);
+        assert_eq!(parse(".\"foo\""), foo);
+        assert_eq!(parse(".[\"foo\"]"), foo);
+        assert_eq!(parse(".foo.bar"), field(foo.clone(), "bar"));
+        assert_eq!(parse(".foo.\"bar\""), field(foo.clone(), "bar"));
+        assert_eq!(parse(".foo[]"), Expr::Iterate(Box::new(foo.clone())));
+        assert_eq!(parse(".foo.[]"), Expr::Iterate(Box::new(foo.clone())));
+        assert_eq!(parse(".foo?"), Expr::Try(Box::new(foo.clone())));
+        assert_eq!(
+            parse(".foo[0]"),
+            Expr::Index(Box::new(foo.clone()), Box::new(Expr::Literal(Value::from(0))))
+        );
+        assert_eq!(
+            parse(".foo | .bar | ."),
+            Expr::Pipe(
+                Box::new(foo),
+                Box::new(Expr::Pipe(
+                    Box::new(field(Expr::Identity, "bar")),
+                    Box::new(Expr::Identity)
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn operators_have_precedence() {
+        let parse = |s: &str| s.parse::<Filter>().unwrap().expr;
+        let num = |n: i64| Box::new(Expr::Literal(Value::from(n)));
+        assert_eq!(
+            parse("1 + 2 * 3 == 7"),
+            Expr::Binary(
+                "==",
+                Box::new(Expr::Binary("+", num(1), Box::new(Expr::Binary("*", num(2), num(3))))),
+                num(7)
+            )
+        );
+        assert_eq!(
+            parse("1, 2 | 3"),
+            Expr::Pipe(Box::new(Expr::Comma(num(1), num(2))), num(3))
+        );
+        assert_eq!(
+            parse("1 or 2 and 3"),
+            Expr::Or(num(1), Box::new(Expr::And(num(2), num(3))))
+        );
+        assert_eq!(parse("-1 - 2"), Expr::Binary("-", Box::new(Expr::Negate(num(1))), num(2)));
+
+        assert_eq!(run("1 + 2 * 3 == 7", Value::Null), vec![Value::Bool(true)]);
+        assert_eq!(run("(1 + 2) * 3", Value::Null), vec![Value::from(9)]);
+        assert_eq!(run("10 - 4 - 3", Value::Null), vec![Value::from(3)]);
+        assert_eq!(run("7 % 4, 1 / 4", Value::Null), vec![Value::from(3), Value::from(0.25)]);
+    }
+
+    #[test]
+    fn string_literals_are_unescaped() {
+        let tokens = tokenize(r#""a\"b\\c\/d\n\t\r\b\f\u00e9\ud800" "#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![Token::Str("a\"b\\c/d\n\t\r\u{8}\u{c}\u{e9}\u{FFFD}".to_owned())]
+        );
+        assert_eq!(
+            tokenize(r#".a."b c" # comment"#).unwrap(),
+            vec![
+                Token::Field("a".to_owned()),
+                Token::Dot,
+                Token::Str("b c".to_owned()),
+            ]
+        );
+        assert_eq!(
+            run(r#"."b c", ."\u0041""#, json(r#"{"b c": 1, "A": 2}"#)),
+            vec![Value::from(1), Value::from(2)]
+        );
+    }
+
+    #[test]
+    fn paths_are_evaluated() {
+        let input = json(r#"{
+            "spans": [{"name": "foo", "tags": {"retry": 3}}, {"name": "bar", "tags": {}}]
+        }"#);
+        assert_eq!(run(".spans[0].name", input.clone()), vec![Value::from("foo")]);
+        assert_eq!(run(".spans[-1].name", input.clone()), vec![Value::from("bar")]);
+        assert_eq!(run(".spans[5]", input.clone()), vec![Value::Null]);
+        assert_eq!(run(".missing.name", input.clone()), vec![Value::Null]);
+        assert_eq!(
+            run(".spans[].tags.retry", input.clone()),
+            vec![Value::from(3), Value::Null]
+        );
+        assert_eq!(run(".spans[0].name[]?", input.clone()), Vec::<Value>::new());
+        assert_eq!(
+            run(".spans[] | .tags.retry // 0", input.clone()),
+            vec![Value::from(3), Value::from(0)]
+        );
+        assert_eq!(
+            run("[.spans[].name] | join(\",\")", input),
+            vec![Value::from("foo,bar")]
+        );
+    }
+
+    #[test]
+    fn select_filters_inputs() {
+        let input = json(r#"[
+            {"name": "foo", "duration": 0.5, "error": true},
+            {"name": "bar", "duration": 2},
+            {"name": "baz", "duration": 3, "error": false}
+        ]"#);
+        let names = |filter: &str| run(&format!("[.[] | {} | .name]", filter), input.clone());
+        let expected = |names: &[&str]| vec![Value::from(names.to_vec())];
+        assert_eq!(names("select(.duration > 1)"), expected(&["bar", "baz"]));
+        assert_eq!(names("select(.duration >= 2)"), expected(&["bar", "baz"]));
+        assert_eq!(names("select(.duration <= 2)"), expected(&["foo", "bar"]));
+        assert_eq!(names("select(.duration < 1)"), expected(&["foo"]));
+        assert_eq!(names("select(.name == \"bar\")"), expected(&["bar"]));
+        assert_eq!(names("select(.name != \"bar\")"), expected(&["foo", "baz"]));
+        assert_eq!(names("select(.error)"), expected(&["foo"]));
+        assert_eq!(names("select(.error | not)"), expected(&["bar", "baz"]));
+        assert_eq!(names("select(.error == null)"), expected(&["bar"]));
+        assert_eq!(names("select(.name | startswith(\"ba\"))"), expected(&["bar", "baz"]));
+        assert_eq!(names("select(.duration > 1 and .error != false)"), expected(&["bar"]));
+        assert_eq!(names("select(empty)"), expected(&[]));
+    }
+
+    #[test]
+    fn values_are_compared_in_jq_order() {
+        let input = json(r#"[{"a": 1}, [1], "a", 1, true, false, null, [0, 1], 0.5]"#);
+        assert_eq!(
+            run("sort", input.clone()),
+            vec![json(r#"[null, false, true, 0.5, 1, "a", [0, 1], [1], {"a": 1}]"#)]
+        );
+        assert_eq!(run("min, max", input), vec![Value::Null, json(r#"{"a": 1}"#)]);
+        assert_eq!(
+            run("1 == 1.0, \"1\" == 1, [] < {}", Value::Null),
+            vec![Value::Bool(true), Value::Bool(false), Value::Bool(true)]
+        );
+    }
+
+    #[test]
+    fn constructions_work() {
+        let input = json(r#"{"name": "foo", "tags": {"a": 1, "b": 2}}"#);
+        assert_eq!(
+            run("{name, \"n\": .name, (.name): 1, t: (.tags | keys)}", input.clone()),
+            vec![json(r#"{"name": "foo", "n": "foo", "foo": 1, "t": ["a", "b"]}"#)]
+        );
+        assert_eq!(
+            run("{k: (1, 2)}", Value::Null),
+            vec![json(r#"{"k": 1}"#), json(r#"{"k": 2}"#)]
+        );
+        assert_eq!(
+            run(".tags | with_entries({key: (.key | ascii_upcase), value})", input.clone()),
+            vec![json(r#"{"A": 1, "B": 2}"#)]
+        );
+        assert_eq!(
+            run(".tags | to_entries | map(.key)", input.clone()),
+            vec![json(r#"["a", "b"]"#)]
+        );
+        assert_eq!(
+            run(".tags | map_values(. * 10)", input.clone()),
+            vec![json(r#"{"a": 10, "b": 20}"#)]
+        );
+        assert_eq!(
+            run("if .name == \"bar\" then 1 elif .tags.a then 2 else 3 end", input),
+            vec![Value::from(2)]
+        );
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        let error = |s: &str| s.parse::<Filter>().err().map(|e| e.to_string());
+        for filter in &[
+            ".foo |",
+            ".[",
+            ".foo[0",
+            "(.foo",
+            "[1, 2",
+            "{a: }",
+            "{a: 1 | 2}",
+            "{a",
+            "{1: 2}",
+            "{a: 1 b: 2}",
+            "\"abc",
+            "\"\\(.foo)\"",
+            "\"\\q\"",
+            "\"\\u12\"",
+            "if . then 1",
+            "if . 1 end",
+            "1 2",
+            "select(.a; )",
+            ")",
+            "@csv",
+            "1.2.3",
+        ] {
+            assert!(error(filter).is_some(), "{:?} is accepted", filter);
+        }
+        assert!(error(".foo |").unwrap().contains("Unexpected end of the filter"));
+        assert!(error("\"abc").unwrap().contains("Unterminated string"));
+        assert!(error("1 2").unwrap().contains("Unexpected 2"));
+        assert!(error("{a: 1 b: 2}").unwrap().contains("Expected \",\" but found \"b\""));
+    }
+
+    #[test]
+    fn evaluation_errors_are_reported() {
+        let error = |filter: &str, input: Value| {
+            let filter: Filter = filter.parse().unwrap();
+            filter.apply(&input).err().map(|e| e.to_string())
+        };
+        assert!(error(".foo", Value::from(1)).unwrap().contains("Cannot index number with"));
+        assert!(error(".[]", Value::from(1)).unwrap().contains("Cannot iterate over 1"));
+        assert!(error("1 / 0", Value::Null).unwrap().contains("Division by zero"));
+        assert!(error("{} - 1", Value::Null).unwrap().contains("cannot be used with -"));
+        assert!(error("foo", Value::Null).unwrap().contains("foo/0 is not defined"));
+        assert!(error("select(1; 2)", Value::Null).unwrap().contains("select/2 is not defined"));
+        assert!(error("{(1): 2}", Value::Null).unwrap().contains("Object keys must be strings"));
+        assert_eq!(error(".foo?, (1 / 0)?, (.[] // 2)", Value::from(1)), None);
+    }
+}
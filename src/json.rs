@@ -0,0 +1,620 @@
+//! Low-allocation JSON serialization of batches, and `JsonBatch`, which parses it back.
+//!
+//! The functions in this module write the same JSON as `serde_json::to_writer` does
+//! for the corresponding `Serialize` implementations of `model`,
+//! but write it directly into the output buffer without the intermediate maps and strings
+//! which the `Serialize` implementations build (e.g., for ordering tags by key).
+//! `JsonWriter` can also write the identifiers as Jaeger UI shows them, the binary tag values
+//! in base64, the tag values with their types, and the times and the durations in other units
+//! (see `TimeFormat` and `DurationUnit`).
+//!
+//! # Examples
+//!
+//! ```
+//! # extern crate jaegercat;
+//! # extern crate serde_json;
+//! use jaegercat::json::JsonWriter;
+//! use jaegercat::model::{Batch, Process};
+//!
+//! # fn main() {
+//! let batch = Batch {
+//!     process: Process {
+//!         service_name: "foo".to_owned(),
+//!         tags: Vec::new(),
+//!         unknown_fields: Default::default(),
+//!     },
+//!     spans: Vec::new(),
+//!     seq_no: None,
+//!     stats: None,
+//!     unknown_fields: Default::default(),
+//! };
+//! let mut out = Vec::new();
+//! JsonWriter::new().write_batch(&mut out, &batch).unwrap();
+//! assert_eq!(out, serde_json::to_vec(&batch).unwrap());
+//! # }
+//! ```
+use std::io::{self, Write};
+use std::str::FromStr;
+use serde::Serialize;
+use serde_json::{self, Map, Value};
+use trackable::error::{Failed, Failure};
+
+use Result;
+use borrowed::PartialDecodeError;
+use error::{DecodeError, DecodeResult};
+use protocol::WireProtocol;
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification};
+use model::{Batch, ClientStats, Datetime, Hex, Log, Process, Rfc3339, Span, SpanId, SpanRef,
+            SpanRefType, Tag, TagValue, TraceId, UnknownFields};
+use model::us_to_seconds;
+use otlp::encode_base64;
+
+/// The format of the start times of spans and the timestamps of logs in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// The datetime (`start_datetime` of spans and `datetime` of logs, e.g.,
+    /// `"2018-01-31 14:24:18"`) and the seconds since the UNIX epoch as a float
+    /// (`start_unixtime` of spans and `unixtime` of logs), which `JsonBatch` reads back.
+    Datetime,
+
+    /// The microseconds since the UNIX epoch as an integer
+    /// (`start_time` of spans and `timestamp` of logs).
+    UnixUs,
+
+    /// The milliseconds since the UNIX epoch as an integer (rounded down)
+    /// (`start_time` of spans and `timestamp` of logs).
+    UnixMs,
+
+    /// A string in RFC 3339 with microseconds (e.g., `"2018-01-31T14:24:18.665418+09:00"`)
+    /// (`start_time` of spans and `timestamp` of logs).
+    Rfc3339,
+}
+impl TimeFormat {
+    /// Returns the value of a time (microseconds since the UNIX epoch) in this format,
+    /// or `None` for `TimeFormat::Datetime`, which consists of two fields.
+    pub(crate) fn to_value(self, us: i64) -> Option<Value> {
+        match self {
+            TimeFormat::Datetime => None,
+            TimeFormat::UnixUs => Some(Value::from(us)),
+            TimeFormat::UnixMs => Some(Value::from(us.div_euclid(1000))),
+            TimeFormat::Rfc3339 => Some(Value::from(Rfc3339(us).to_string())),
+        }
+    }
+}
+impl FromStr for TimeFormat {
+    type Err = Failure;
+
+    /// Parses `datetime`, `unix-us`, `unix-ms` or `rfc3339`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "datetime" => TimeFormat::Datetime,
+            "unix-us" => TimeFormat::UnixUs,
+            "unix-ms" => TimeFormat::UnixMs,
+            "rfc3339" => TimeFormat::Rfc3339,
+            _ => track_panic!(Failed, "Unknown time format: {:?}", s),
+        })
+    }
+}
+
+/// The unit of the durations of spans in JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DurationUnit {
+    /// Microseconds as an integer.
+    Microseconds,
+
+    /// Milliseconds as a float.
+    Milliseconds,
+
+    /// Seconds as a float.
+    Seconds,
+}
+impl DurationUnit {
+    /// Returns the value of a duration (in microseconds) in this unit.
+    pub(crate) fn to_value(self, us: i64) -> Value {
+        match self {
+            DurationUnit::Microseconds => Value::from(us),
+            DurationUnit::Milliseconds => Value::from(us as f64 / 1000.0),
+            DurationUnit::Seconds => Value::from(us_to_seconds(us)),
+        }
+    }
+}
+impl FromStr for DurationUnit {
+    type Err = Failure;
+
+    /// Parses `us`, `ms` or `s`.
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "us" => DurationUnit::Microseconds,
+            "ms" => DurationUnit::Milliseconds,
+            "s" => DurationUnit::Seconds,
+            _ => track_panic!(Failed, "Unknown duration unit: {:?}", s),
+        })
+    }
+}
+
+/// A writer of batches in JSON.
+///
+/// It retains a scratch buffer used for ordering tags, so it should be reused across batches.
+#[derive(Debug)]
+pub struct JsonWriter {
+    include_unknown_fields: bool,
+    hex_ids: bool,
+    flat_tags: bool,
+    typed_tags: bool,
+    time_format: TimeFormat,
+    duration_unit: DurationUnit,
+    tag_indices: Vec<usize>,
+}
+impl JsonWriter {
+    /// Makes a new `JsonWriter` instance.
+    pub fn new() -> Self {
+        JsonWriter {
+            include_unknown_fields: true,
+            hex_ids: false,
+            flat_tags: false,
+            typed_tags: false,
+            time_format: TimeFormat::Datetime,
+            duration_unit: DurationUnit::Seconds,
+            tag_indices: Vec::new(),
+        }
+    }
+
+    /// If `true`, the fields which are not defined in the schema are included in the output.
+    ///
+    /// The default value is `true`, which is the same as `Serialize` implementations.
+    pub fn include_unknown_fields(&mut self, include: bool) -> &mut Self {
+        self.include_unknown_fields = include;
+        self
+    }
+
+    /// If `true`, the trace IDs and the span IDs are written as Jaeger UI shows them (see `Hex`)
+    /// instead of in the format of their `Display` implementations (e.g., `0x2ae64ad7cd070e8e`).
+    ///
+    /// The default value is `false`, which is the same as `Serialize` implementations.
+    pub fn hex_ids(&mut self, enabled: bool) -> &mut Self {
+        self.hex_ids = enabled;
+        self
+    }
+
+    /// If `true`, the binary values of tags (and of the fields of logs) are written in base64
+    /// instead of as arrays of bytes, so that all of the values are plain JSON scalars.
+    ///
+    /// The default value is `false`, which is the same as `Serialize` implementations.
+    pub fn flat_tags(&mut self, enabled: bool) -> &mut Self {
+        self.flat_tags = enabled;
+        self
+    }
+
+    /// If `true`, the values of tags (and of the fields of logs) are written as objects of
+    /// their types and values (e.g., `{"http.status_code":{"type":"long","value":200}}`)
+    /// instead of their plain values (e.g., `{"http.status_code":200}`), so that a double
+    /// such as `1.0` or a binary value does not look like another type.
+    /// This takes precedence over `flat_tags`.
+    ///
+    /// The default value is `false`, which is the same as `Serialize` implementations.
+    pub fn typed_tags(&mut self, enabled: bool) -> &mut Self {
+        self.typed_tags = enabled;
+        self
+    }
+
+    /// Sets the format of the start times of spans and the timestamps of logs.
+    ///
+    /// The default value is `TimeFormat::Datetime`, which is the same as `Serialize`
+    /// implementations.
+    pub fn time_format(&mut self, format: TimeFormat) -> &mut Self {
+        self.time_format = format;
+        self
+    }
+
+    /// Sets the unit of the durations of spans.
+    ///
+    /// The default value is `DurationUnit::Seconds`, which is the same as `Serialize`
+    /// implementations.
+    pub fn duration_unit(&mut self, unit: DurationUnit) -> &mut Self {
+        self.duration_unit = unit;
+        self
+    }
+
+    /// Appends `batch` as a JSON object to `out`.
+    pub fn write_batch(&mut self, out: &mut Vec<u8>, batch: &Batch) -> Result<()> {
+        track!(self.batch(out, batch).map_err(Failure::from_error))
+    }
+
+    fn batch(&mut self, out: &mut Vec<u8>, batch: &Batch) -> io::Result<()> {
+        out.extend_from_slice(b"{\"process\":");
+        self.process(out, &batch.process)?;
+        out.extend_from_slice(b",\"spans\":[");
+        for (i, span) in batch.spans.iter().enumerate() {
+            if i != 0 {
+                out.push(b',');
+            }
+            self.span(out, span)?;
+        }
+        out.push(b']');
+        if let Some(seq_no) = batch.seq_no {
+            out.extend_from_slice(b",\"seq_no\":");
+            value(out, &seq_no)?;
+        }
+        if let Some(ref stats) = batch.stats {
+            out.extend_from_slice(b",\"stats\":");
+            self.stats(out, stats)?;
+        }
+        self.unknown_fields(out, &batch.unknown_fields)?;
+        out.push(b'}');
+        Ok(())
+    }
+
+    fn process(&mut self, out: &mut Vec<u8>, process: &Process) -> io::Result<()> {
+        out.extend_from_slice(b"{\"service_name\":");
+        value(out, &process.service_name)?;
+        out.extend_from_slice(b",\"tags\":");
+        self.tags(out, &process.tags)?;
+        self.unknown_fields(out, &process.unknown_fields)?;
+        out.push(b'}');
+        Ok(())
+    }
+
+    fn span(&mut self, out: &mut Vec<u8>, span: &Span) -> io::Result<()> {
+        out.extend_from_slice(b"{\"trace_id\":");
+        self.trace_id(out, span.trace_id)?;
+        out.extend_from_slice(b",\"span_id\":");
+        self.span_id(out, span.span_id)?;
+        if let Some(parent_span_id) = span.parent_span_id {
+            out.extend_from_slice(b",\"parent_span_id\":");
+            self.span_id(out, parent_span_id)?;
+        }
+        out.extend_from_slice(b",\"operation_name\":");
+        value(out, &span.operation_name)?;
+        if !span.references.is_empty() {
+            out.extend_from_slice(b",\"references\":[");
+            for (i, reference) in span.references.iter().enumerate() {
+                if i != 0 {
+                    out.push(b',');
+                }
+                self.span_ref(out, reference)?;
+            }
+            out.push(b']');
+        }
+        out.extend_from_slice(b",\"flags\":");
+        value(out, &span.flags)?;
+        if self.time_format == TimeFormat::Datetime {
+            write!(out, ",\"start_datetime\":\"{}\"", Datetime(span.start_time))?;
+            out.extend_from_slice(b",\"start_unixtime\":");
+            value(out, &us_to_seconds(span.start_time))?;
+        } else {
+            out.extend_from_slice(b",\"start_time\":");
+            self.time(out, span.start_time)?;
+        }
+        out.extend_from_slice(b",\"duration\":");
+        match self.duration_unit {
+            DurationUnit::Microseconds => value(out, &span.duration)?,
+            DurationUnit::Milliseconds => value(out, &(span.duration as f64 / 1000.0))?,
+            DurationUnit::Seconds => value(out, &us_to_seconds(span.duration))?,
+        }
+        if !span.tags.is_empty() {
+            out.extend_from_slice(b",\"tags\":");
+            self.tags(out, &span.tags)?;
+        }
+        if !span.logs.is_empty() {
+            out.extend_from_slice(b",\"logs\":[");
+            for (i, log) in span.logs.iter().enumerate() {
+                if i != 0 {
+                    out.push(b',');
+                }
+                self.log(out, log)?;
+            }
+            out.push(b']');
+        }
+        self.unknown_fields(out, &span.unknown_fields)?;
+        out.push(b'}');
+        Ok(())
+    }
+
+    fn span_ref(&mut self, out: &mut Vec<u8>, reference: &SpanRef) -> io::Result<()> {
+        let ref_type = match reference.ref_type {
+            SpanRefType::ChildOf => "ChildOf",
+            SpanRefType::FollowsFrom => "FollowsFrom",
+        };
+        write!(out, "{{\"{}\":{{\"trace_id\":", ref_type)?;
+        self.trace_id(out, reference.trace_id)?;
+        out.extend_from_slice(b",\"span_id\":");
+        self.span_id(out, reference.span_id)?;
+        self.unknown_fields(out, &reference.unknown_fields)?;
+        out.extend_from_slice(b"}}");
+        Ok(())
+    }
+
+    fn log(&mut self, out: &mut Vec<u8>, log: &Log) -> io::Result<()> {
+        if self.time_format == TimeFormat::Datetime {
+            write!(out, "{{\"datetime\":\"{}\"", Datetime(log.timestamp))?;
+            out.extend_from_slice(b",\"unixtime\":");
+            value(out, &us_to_seconds(log.timestamp))?;
+        } else {
+            out.extend_from_slice(b"{\"timestamp\":");
+            self.time(out, log.timestamp)?;
+        }
+        out.extend_from_slice(b",\"fields\":");
+        self.tags(out, &log.fields)?;
+        self.unknown_fields(out, &log.unknown_fields)?;
+        out.push(b'}');
+        Ok(())
+    }
+
+    fn trace_id(&self, out: &mut Vec<u8>, id: TraceId) -> io::Result<()> {
+        if self.hex_ids {
+            write!(out, "\"{}\"", Hex(id))
+        } else {
+            write!(out, "\"{}\"", id)
+        }
+    }
+
+    fn span_id(&self, out: &mut Vec<u8>, id: SpanId) -> io::Result<()> {
+        if self.hex_ids {
+            write!(out, "\"{}\"", Hex(id))
+        } else {
+            write!(out, "\"{}\"", id)
+        }
+    }
+
+    /// Writes a time in `time_format` other than `TimeFormat::Datetime`.
+    fn time(&self, out: &mut Vec<u8>, us: i64) -> io::Result<()> {
+        match self.time_format {
+            TimeFormat::UnixMs => value(out, &us.div_euclid(1000)),
+            TimeFormat::Rfc3339 => write!(out, "\"{}\"", Rfc3339(us)),
+            _ => value(out, &us),
+        }
+    }
+
+    fn stats(&mut self, out: &mut Vec<u8>, stats: &ClientStats) -> io::Result<()> {
+        write!(
+            out,
+            "{{\"full_queue_dropped_spans\":{},\"too_large_dropped_spans\":{},\
+             \"failed_to_emit_spans\":{}",
+            stats.full_queue_dropped_spans,
+            stats.too_large_dropped_spans,
+            stats.failed_to_emit_spans
+        )?;
+        self.unknown_fields(out, &stats.unknown_fields)?;
+        out.push(b'}');
+        Ok(())
+    }
+
+    /// Writes tags as a map ordered by key, where the last one of duplicate keys wins.
+    fn tags(&mut self, out: &mut Vec<u8>, tags: &[Tag]) -> io::Result<()> {
+        let indices = &mut self.tag_indices;
+        indices.clear();
+        indices.extend(0..tags.len());
+
+        // The stable sort keeps duplicate keys in order, so the last one of each run wins
+        indices.sort_by(|&a, &b| tags[a].key.cmp(&tags[b].key));
+        out.push(b'{');
+        let mut first = true;
+        for (i, &index) in indices.iter().enumerate() {
+            let tag = &tags[index];
+            if indices.get(i + 1).is_some_and(|&next| tags[next].key == tag.key) {
+                continue;
+            }
+            if !first {
+                out.push(b',');
+            }
+            first = false;
+            value(out, &tag.key)?;
+            out.push(b':');
+            if self.typed_tags {
+                typed_tag_value(out, &tag.value)?;
+            } else if self.flat_tags {
+                flat_tag_value(out, &tag.value)?;
+            } else {
+                value(out, &tag.value)?;
+            }
+        }
+        out.push(b'}');
+        Ok(())
+    }
+
+    fn unknown_fields(&self, out: &mut Vec<u8>, fields: &UnknownFields) -> io::Result<()> {
+        if self.include_unknown_fields && !fields.is_empty() {
+            out.extend_from_slice(b",\"unknown_fields\":");
+            value(out, fields)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for JsonWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn typed_tag_value(out: &mut Vec<u8>, tag_value: &TagValue) -> io::Result<()> {
+    match *tag_value {
+        TagValue::Str(ref v) => {
+            out.extend_from_slice(b"{\"type\":\"string\",\"value\":");
+            value(out, v)?;
+        }
+        TagValue::Bool(v) => {
+            out.extend_from_slice(b"{\"type\":\"bool\",\"value\":");
+            value(out, &v)?;
+        }
+        TagValue::Long(v) => {
+            out.extend_from_slice(b"{\"type\":\"long\",\"value\":");
+            value(out, &v)?;
+        }
+        TagValue::Double(v) => {
+            out.extend_from_slice(b"{\"type\":\"double\",\"value\":");
+            value(out, &v)?;
+        }
+        TagValue::Binary(ref v) => {
+            out.extend_from_slice(b"{\"type\":\"binary\",\"value\":");
+            value(out, v)?;
+        }
+    }
+    out.push(b'}');
+    Ok(())
+}
+
+fn flat_tag_value(out: &mut Vec<u8>, tag_value: &TagValue) -> io::Result<()> {
+    match *tag_value {
+        TagValue::Str(ref v) => value(out, v),
+        TagValue::Bool(v) => value(out, &v),
+        TagValue::Long(v) => value(out, &v),
+        TagValue::Double(v) => value(out, &v),
+        TagValue::Binary(ref v) => value(out, &encode_base64(v)),
+    }
+}
+
+/// Returns the plain JSON value of a tag (which `JsonWriter::flat_tags` writes),
+/// where binary values are encoded in base64.
+pub(crate) fn to_flat_value(tag_value: &TagValue) -> Value {
+    match *tag_value {
+        TagValue::Str(ref v) => Value::from(v.as_str()),
+        TagValue::Bool(v) => Value::from(v),
+        TagValue::Long(v) => Value::from(v),
+        TagValue::Double(v) => Value::from(v),
+        TagValue::Binary(ref v) => Value::from(encode_base64(v)),
+    }
+}
+
+/// Returns the JSON object of the type and the value of a tag
+/// (which `JsonWriter::typed_tags` writes).
+pub(crate) fn to_typed_value(tag_value: &TagValue) -> Value {
+    let (type_name, value) = match *tag_value {
+        TagValue::Str(ref v) => ("string", Value::from(v.as_str())),
+        TagValue::Bool(v) => ("bool", Value::from(v)),
+        TagValue::Long(v) => ("long", Value::from(v)),
+        TagValue::Double(v) => ("double", Value::from(v)),
+        TagValue::Binary(ref v) => ("binary", Value::from(v.clone())),
+    };
+    let mut object = Map::new();
+    object.insert("type".to_owned(), Value::from(type_name));
+    object.insert("value".to_owned(), value);
+    Value::Object(object)
+}
+
+/// Writes a leaf value (e.g., an escaped string or a float) as `serde_json` does.
+fn value<T: Serialize + ?Sized>(out: &mut Vec<u8>, v: &T) -> io::Result<()> {
+    serde_json::to_writer(out, v).map_err(io::Error::from)
+}
+
+/// The `WireProtocol` of batches in the JSON format of `jaegercat --format json`
+/// (see `EmitBatchNotification::from_json_str`).
+///
+/// This lets test harnesses submit synthetic spans over HTTP without encoding Thrift.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonBatch;
+impl WireProtocol for JsonBatch {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        _mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let notification: EmitBatchNotification =
+            serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidValue {
+                offset: 0,
+                reason: format!("Invalid JSON: {}", e),
+            })?;
+        let spans = notification.batch.spans.len();
+        if spans > limits.max_collection_size {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_collection_size",
+                offset: 0,
+                size: spans as u64,
+                max: limits.max_collection_size,
+            });
+        }
+        Ok((notification, None))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn batch() -> Batch {
+        Batch {
+            process: Process {
+                service_name: "foo".to_owned(),
+                // In the order of the keys, in which tags are written
+                tags: vec![
+                    Tag::new("b", true),
+                    Tag::new("d", 1.0),
+                    Tag::new("l", 200i64),
+                    Tag::new("s", "x"),
+                    Tag::new("y", vec![0, 255]),
+                ],
+                unknown_fields: UnknownFields::new(),
+            },
+            spans: Vec::new(),
+            seq_no: None,
+            stats: None,
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    fn write(writer: &mut JsonWriter) -> String {
+        let mut out = Vec::new();
+        writer.write_batch(&mut out, &batch()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn tags_are_written_as_plain_values() {
+        let plain = write(&mut JsonWriter::new());
+        assert_eq!(
+            plain,
+            r#"{"process":{"service_name":"foo","#.to_owned()
+                + r#""tags":{"b":true,"d":1.0,"l":200,"s":"x","y":[0,255]}},"spans":[]}"#
+        );
+        assert_eq!(plain, serde_json::to_string(&batch()).unwrap());
+
+        let flat = write(JsonWriter::new().flat_tags(true));
+        assert!(flat.contains(r#""tags":{"b":true,"d":1.0,"l":200,"s":"x","y":"AP8="}"#));
+    }
+
+    #[test]
+    fn tags_are_written_with_types() {
+        let typed = write(JsonWriter::new().typed_tags(true).flat_tags(true));
+        assert!(typed.contains(
+            r#""tags":{"b":{"type":"bool","value":true},"d":{"type":"double","value":1.0},"#
+        ));
+        assert!(typed.contains(
+            r#""l":{"type":"long","value":200},"s":{"type":"string","value":"x"},"#
+        ));
+        assert!(typed.contains(r#""y":{"type":"binary","value":[0,255]}}"#));
+
+        let tags = batch().process.tags;
+        let values = tags.iter().map(|t| to_typed_value(&t.value)).collect::<Vec<_>>();
+        let written: Value = serde_json::from_str(&typed).unwrap();
+        for (tag, value) in tags.iter().zip(values) {
+            assert_eq!(written["process"]["tags"][&tag.key], value);
+        }
+    }
+
+    #[test]
+    fn plain_and_typed_tags_are_parsed() {
+        let mut writer = JsonWriter::new();
+        for typed in &[false, true] {
+            let json = format!("{{\"emit_batch\":{}}}", write(writer.typed_tags(*typed)));
+            let parsed = EmitBatchNotification::from_json_str(&json).unwrap();
+            assert_eq!(parsed.batch.process.tags, batch().process.tags, "{}", json);
+        }
+
+        // The types of typed values are kept even if the values look like other types
+        let json = r#"{"emit_batch":{"process":{"service_name":"foo","tags":{
+            "d":{"type":"double","value":1},"y":{"type":"binary","value":[]}}},"spans":[]}}"#;
+        let parsed = EmitBatchNotification::from_json_str(json).unwrap();
+        assert_eq!(
+            parsed.batch.process.tags,
+            vec![Tag::new("d", 1.0), Tag::new("y", Vec::new())]
+        );
+        let json = r#"{"emit_batch":{"process":{"service_name":"foo","tags":{
+            "x":{"type":"long","value":"1"}}},"spans":[]}}"#;
+        assert!(EmitBatchNotification::from_json_str(json).is_err());
+    }
+}
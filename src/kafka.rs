@@ -0,0 +1,868 @@
+//! Decoding of the spans which the Jaeger collector writes to Kafka.
+//!
+//! With the Kafka span storage, the collector writes each span to the `jaeger-spans` topic
+//! as a `Span` message of [model.proto] in the protobuf encoding (`--kafka.producer.encoding
+//! protobuf`, the default) or in the JSON encoding of protobuf (`json`).
+//! `KafkaSpan` decodes a message in either encoding into a batch holding the span
+//! and its process:
+//!
+//! - The type of each key-value decides the type of the tag.
+//! - The first `CHILD_OF` reference in the same trace becomes the parent span ID
+//!   (the references are kept as they are).
+//! - `process_id` and `warnings` are ignored.
+//!
+//! `jaegercat` has no Kafka client, so the messages are read by another consumer
+//! (e.g., `kcat`) and given to the `decode` subcommand.
+//!
+//! Conversely, `encode_span` encodes a span and its process into a `Span` message
+//! in the protobuf encoding.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::kafka::KafkaSpan;
+//! use jaegercat::protocol::WireProtocol;
+//! use jaegercat::thrift::{DecodeLimits, DecodeMode};
+//!
+//! let message = br#"{
+//!     "traceId": "W47/95gDgQPSabYzgT/GDA==",
+//!     "spanId": "7uGbfsPBsXQ=",
+//!     "operationName": "bar",
+//!     "startTime": "2018-12-13T14:51:00.5Z",
+//!     "duration": "1.000001s",
+//!     "tags": [{"key": "n", "vType": "INT64", "vInt64": "42"}],
+//!     "process": {"serviceName": "foo"}
+//! }"#;
+//! let (message, _) = KafkaSpan
+//!     .decode_batch(message, DecodeMode::Normal, &DecodeLimits::default())
+//!     .unwrap();
+//! assert_eq!(message.batch.process.service_name, "foo");
+//! assert_eq!(message.batch.spans[0].operation_name, "bar");
+//! assert_eq!(message.batch.spans[0].start_time, 1544712660500000);
+//! assert_eq!(message.batch.spans[0].duration, 1000001);
+//! ```
+//!
+//! [model.proto]: https://github.com/jaegertracing/jaeger-idl/blob/main/proto/api_v2/model.proto
+use serde_json::{self, Value};
+
+use borrowed::PartialDecodeError;
+use error::{DecodeError, DecodeResult};
+use model::{Batch, Log, Process, Span, SpanId, SpanRef, SpanRefType, Tag, TagValue, TraceId,
+            UnknownFields};
+use otlp::decode_base64;
+use proto::{check_collection, check_string, write_bytes_field, write_fixed64_field,
+            write_varint_field, Field, Reader};
+use protocol::WireProtocol;
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification};
+
+/// The `WireProtocol` of the `Span` messages in the `jaeger-spans` Kafka topic.
+///
+/// The encoding is detected from the first byte of the message,
+/// since a JSON object starts with `{` (or whitespace),
+/// which cannot start a protobuf `Span`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KafkaSpan;
+impl WireProtocol for KafkaSpan {
+    fn name(&self) -> &'static str {
+        "kafka-span"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let is_json = buf.iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|&b| b == b'{');
+        let (span, process) = if is_json {
+            decode_json(buf, limits)?
+        } else {
+            read_span(&Reader::new("Span", mode, limits), buf, 0)?
+        };
+        let batch = Batch {
+            process,
+            spans: vec![span],
+            seq_no: None,
+            stats: None,
+            unknown_fields: UnknownFields::new(),
+        };
+        Ok((EmitBatchNotification { batch }, None))
+    }
+}
+
+/// Appends a `Span` message of [model.proto] holding `span` and `process` to `out`.
+///
+/// The parent span ID becomes the first `CHILD_OF` reference (unless the span already has it),
+/// since `Span` has no field of the parent. So `KafkaSpan` decodes the message into
+/// the same span, except that the reference may be added.
+///
+/// [model.proto]: https://github.com/jaegertracing/jaeger-idl/blob/main/proto/api_v2/model.proto
+pub fn encode_span(out: &mut Vec<u8>, span: &Span, process: &Process) {
+    write_bytes_field(out, 1, &trace_id_bytes(span.trace_id));
+    write_bytes_field(out, 2, &span.span_id.0.to_be_bytes());
+    write_bytes_field(out, 3, span.operation_name.as_bytes());
+    if let Some(parent) = span.parent_span_id.filter(|id| id.0 != 0) {
+        let has_parent = span.references.iter().any(|r| {
+            r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id
+                && r.span_id == parent
+        });
+        if !has_parent {
+            write_message(out, 4, |buf| {
+                write_span_ref(buf, SpanRefType::ChildOf, span.trace_id, parent)
+            });
+        }
+    }
+    for r in &span.references {
+        write_message(out, 4, |buf| {
+            write_span_ref(buf, r.ref_type, r.trace_id, r.span_id)
+        });
+    }
+    write_varint_field(out, 5, u64::from(span.flags as u32));
+    write_message(out, 6, |buf| write_timestamp(buf, span.start_time));
+    write_message(out, 7, |buf| write_duration(buf, span.duration));
+    for tag in &span.tags {
+        write_message(out, 8, |buf| write_key_value(buf, tag));
+    }
+    for log in &span.logs {
+        write_message(out, 9, |buf| {
+            write_message(buf, 1, |buf| write_timestamp(buf, log.timestamp));
+            for field in &log.fields {
+                write_message(buf, 2, |buf| write_key_value(buf, field));
+            }
+        });
+    }
+    write_message(out, 10, |buf| {
+        write_bytes_field(buf, 1, process.service_name.as_bytes());
+        for tag in &process.tags {
+            write_message(buf, 2, |buf| write_key_value(buf, tag));
+        }
+    });
+}
+
+/// Appends a field of the message written by `f`.
+fn write_message<F>(out: &mut Vec<u8>, number: u64, f: F)
+where
+    F: FnOnce(&mut Vec<u8>),
+{
+    let mut buf = Vec::new();
+    f(&mut buf);
+    write_bytes_field(out, number, &buf);
+}
+
+fn write_span_ref(out: &mut Vec<u8>, ref_type: SpanRefType, trace_id: TraceId, span_id: SpanId) {
+    write_bytes_field(out, 1, &trace_id_bytes(trace_id));
+    write_bytes_field(out, 2, &span_id.0.to_be_bytes());
+    let ref_type = match ref_type {
+        SpanRefType::ChildOf => 0,
+        SpanRefType::FollowsFrom => 1,
+    };
+    write_varint_field(out, 3, ref_type);
+}
+
+fn write_key_value(out: &mut Vec<u8>, tag: &Tag) {
+    write_bytes_field(out, 1, tag.key.as_bytes());
+    match tag.value {
+        TagValue::Str(ref v) => write_bytes_field(out, 3, v.as_bytes()),
+        TagValue::Bool(v) => {
+            write_varint_field(out, 2, 1);
+            write_varint_field(out, 4, u64::from(v));
+        }
+        TagValue::Long(v) => {
+            write_varint_field(out, 2, 2);
+            write_varint_field(out, 5, v as u64);
+        }
+        TagValue::Double(v) => {
+            write_varint_field(out, 2, 3);
+            write_fixed64_field(out, 6, v.to_bits());
+        }
+        TagValue::Binary(ref v) => {
+            write_varint_field(out, 2, 4);
+            write_bytes_field(out, 7, v);
+        }
+    }
+}
+
+/// Writes microseconds since the UNIX epoch as a `google.protobuf.Timestamp`
+/// (whose nanoseconds are never negative).
+fn write_timestamp(out: &mut Vec<u8>, us: i64) {
+    write_varint_field(out, 1, us.div_euclid(1_000_000) as u64);
+    write_varint_field(out, 2, us.rem_euclid(1_000_000) as u64 * 1000);
+}
+
+/// Writes microseconds as a `google.protobuf.Duration`
+/// (whose seconds and nanoseconds have the same sign).
+fn write_duration(out: &mut Vec<u8>, us: i64) {
+    write_varint_field(out, 1, (us / 1_000_000) as u64);
+    write_varint_field(out, 2, (us % 1_000_000 * 1000) as u64);
+}
+
+fn trace_id_bytes(id: TraceId) -> [u8; 16] {
+    let mut bytes = [0; 16];
+    bytes[..8].copy_from_slice(&id.high.to_be_bytes());
+    bytes[8..].copy_from_slice(&id.low.to_be_bytes());
+    bytes
+}
+
+fn new_span() -> Span {
+    Span {
+        trace_id: TraceId { high: 0, low: 0 },
+        span_id: SpanId(0),
+        parent_span_id: None,
+        operation_name: String::new(),
+        references: Vec::new(),
+        flags: 0,
+        start_time: 0,
+        duration: 0,
+        tags: Vec::new(),
+        logs: Vec::new(),
+        unknown_fields: UnknownFields::new(),
+    }
+}
+
+fn new_process() -> Process {
+    Process {
+        service_name: String::new(),
+        tags: Vec::new(),
+        unknown_fields: UnknownFields::new(),
+    }
+}
+
+/// Sets the parent span ID of `span` from its first `CHILD_OF` reference in the same trace.
+fn set_parent(span: &mut Span) {
+    span.parent_span_id = span.references
+        .iter()
+        .find(|r| r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id)
+        .map(|r| r.span_id);
+}
+
+fn to_trace_id(bytes: &[u8], field: &'static str, offset: usize) -> DecodeResult<TraceId> {
+    if bytes.len() != 16 {
+        return Err(invalid_id(bytes, field, offset));
+    }
+    Ok(TraceId {
+        high: be_i64(&bytes[..8]),
+        low: be_i64(&bytes[8..]),
+    })
+}
+
+fn to_span_id(bytes: &[u8], field: &'static str, offset: usize) -> DecodeResult<SpanId> {
+    if bytes.len() != 8 {
+        return Err(invalid_id(bytes, field, offset));
+    }
+    Ok(SpanId(be_i64(bytes)))
+}
+
+fn invalid_id(bytes: &[u8], field: &'static str, offset: usize) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset,
+        reason: format!("{} must not be {} bytes", field, bytes.len()),
+    }
+}
+
+fn be_i64(bytes: &[u8]) -> i64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(bytes);
+    i64::from_be_bytes(b)
+}
+
+fn read_span(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<(Span, Process)> {
+    let mut span = new_span();
+    let mut process = new_process();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, _)) => {
+                span.trace_id = to_trace_id(b, "Span.trace_id", field_offset)?;
+            }
+            (2, Field::Bytes(b, _)) => {
+                span.span_id = to_span_id(b, "Span.span_id", field_offset)?;
+            }
+            (3, Field::Bytes(b, o)) => span.operation_name = r.string(b, o)?,
+            (4, Field::Bytes(b, o)) => {
+                check_collection(&span.references, r.limits, field_offset)?;
+                span.references.push(read_span_ref(r, b, o)?);
+            }
+            (5, Field::Varint(v)) => span.flags = v as u32 as i32,
+            (6, Field::Bytes(b, o)) => span.start_time = read_micros(r, b, o)?,
+            (7, Field::Bytes(b, o)) => span.duration = read_micros(r, b, o)?,
+            (8, Field::Bytes(b, o)) => {
+                check_collection(&span.tags, r.limits, field_offset)?;
+                span.tags.push(read_key_value(r, b, o)?);
+            }
+            (9, Field::Bytes(b, o)) => {
+                check_collection(&span.logs, r.limits, field_offset)?;
+                span.logs.push(read_log(r, b, o)?);
+            }
+            (10, Field::Bytes(b, o)) => process = read_process(r, b, o)?,
+            (11, Field::Bytes(..)) | (12, Field::Bytes(..)) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    set_parent(&mut span);
+    Ok((span, process))
+}
+
+fn read_span_ref(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<SpanRef> {
+    let mut span_ref = SpanRef {
+        ref_type: SpanRefType::ChildOf,
+        trace_id: TraceId { high: 0, low: 0 },
+        span_id: SpanId(0),
+        unknown_fields: UnknownFields::new(),
+    };
+    reader.fields(buf, offset, |_, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, _)) => {
+                span_ref.trace_id = to_trace_id(b, "SpanRef.trace_id", field_offset)?;
+            }
+            (2, Field::Bytes(b, _)) => {
+                span_ref.span_id = to_span_id(b, "SpanRef.span_id", field_offset)?;
+            }
+            (3, Field::Varint(v)) => span_ref.ref_type = to_ref_type(v as i64, field_offset)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(span_ref)
+}
+
+fn to_ref_type(value: i64, offset: usize) -> DecodeResult<SpanRefType> {
+    match value {
+        0 => Ok(SpanRefType::ChildOf),
+        1 => Ok(SpanRefType::FollowsFrom),
+        _ => Err(DecodeError::InvalidValue {
+            offset,
+            reason: format!("Unknown SpanRefType: {}", value),
+        }),
+    }
+}
+
+fn read_process(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Process> {
+    let mut process = new_process();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => process.service_name = r.string(b, o)?,
+            (2, Field::Bytes(b, o)) => {
+                check_collection(&process.tags, r.limits, field_offset)?;
+                process.tags.push(read_key_value(r, b, o)?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(process)
+}
+
+fn read_log(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Log> {
+    let mut log = Log {
+        timestamp: 0,
+        fields: Vec::new(),
+        unknown_fields: UnknownFields::new(),
+    };
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => log.timestamp = read_micros(r, b, o)?,
+            (2, Field::Bytes(b, o)) => {
+                check_collection(&log.fields, r.limits, field_offset)?;
+                log.fields.push(read_key_value(r, b, o)?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(log)
+}
+
+fn read_key_value(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Tag> {
+    let mut key = String::new();
+    let mut value_type = 0;
+    let mut value = KeyValue::default();
+    reader.fields(buf, offset, |r, number, field, _| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => key = r.string(b, o)?,
+            (2, Field::Varint(v)) => value_type = v as i64,
+            (3, Field::Bytes(b, o)) => value.str = r.string(b, o)?,
+            (4, Field::Varint(v)) => value.bool = v != 0,
+            (5, Field::Varint(v)) => value.int64 = v as i64,
+            (6, Field::Fixed64(v)) => value.float64 = f64::from_bits(v),
+            (7, Field::Bytes(b, o)) => value.binary = r.bytes(b, o)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    let value = value.into_tag_value(value_type, &key, offset)?;
+    Ok(Tag::new(key, value))
+}
+
+/// Reads a `google.protobuf.Timestamp` or a `google.protobuf.Duration` in microseconds.
+fn read_micros(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<i64> {
+    let mut seconds = 0i64;
+    let mut nanos = 0i64;
+    reader.fields(buf, offset, |_, number, field, _| {
+        match (number, field) {
+            (1, Field::Varint(v)) => seconds = v as i64,
+            (2, Field::Varint(v)) => nanos = i64::from(v as i32),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(seconds.saturating_mul(1_000_000).saturating_add(nanos / 1000))
+}
+
+/// The values of a `KeyValue`, one of which is selected by its `v_type`.
+#[derive(Default)]
+struct KeyValue {
+    str: String,
+    bool: bool,
+    int64: i64,
+    float64: f64,
+    binary: Vec<u8>,
+}
+impl KeyValue {
+    fn into_tag_value(self, value_type: i64, key: &str, offset: usize) -> DecodeResult<TagValue> {
+        Ok(match value_type {
+            0 => TagValue::Str(self.str),
+            1 => TagValue::Bool(self.bool),
+            2 => TagValue::Long(self.int64),
+            3 => TagValue::Double(self.float64),
+            4 => TagValue::Binary(self.binary),
+            _ => {
+                return Err(DecodeError::InvalidValue {
+                    offset,
+                    reason: format!("Unknown ValueType of {:?}: {}", key, value_type),
+                })
+            }
+        })
+    }
+}
+
+/// Decodes the JSON encoding of protobuf, in which IDs and binaries are base64 strings,
+/// 64-bit integers may be strings, and timestamps and durations are strings
+/// such as `"2018-12-13T14:51:00.5Z"` and `"1.000001s"`.
+fn decode_json(buf: &[u8], limits: &DecodeLimits) -> DecodeResult<(Span, Process)> {
+    let value: Value = serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Invalid JSON: {}", e),
+    })?;
+    let json = Json { limits };
+    json.span(&value)
+}
+
+struct Json<'b> {
+    limits: &'b DecodeLimits,
+}
+impl<'b> Json<'b> {
+    fn span(&self, value: &Value) -> DecodeResult<(Span, Process)> {
+        let mut span = Span {
+            trace_id: to_trace_id(&self.base64(value, "traceId")?, "traceId", 0)?,
+            span_id: to_span_id(&self.base64(value, "spanId")?, "spanId", 0)?,
+            parent_span_id: None,
+            operation_name: self.string(value, "operationName")?,
+            references: self.array(value, "references", |v| self.span_ref(v))?,
+            flags: self.int(value, "flags")? as u32 as i32,
+            start_time: self.micros(value, "startTime", parse_timestamp)?,
+            duration: self.micros(value, "duration", parse_duration)?,
+            tags: self.array(value, "tags", |v| self.key_value(v))?,
+            logs: self.array(value, "logs", |v| {
+                Ok(Log {
+                    timestamp: self.micros(v, "timestamp", parse_timestamp)?,
+                    fields: self.array(v, "fields", |v| self.key_value(v))?,
+                    unknown_fields: UnknownFields::new(),
+                })
+            })?,
+            unknown_fields: UnknownFields::new(),
+        };
+        set_parent(&mut span);
+        let process = match value.get("process") {
+            None | Some(Value::Null) => new_process(),
+            Some(v) => Process {
+                service_name: self.string(v, "serviceName")?,
+                tags: self.array(v, "tags", |v| self.key_value(v))?,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        Ok((span, process))
+    }
+
+    fn span_ref(&self, value: &Value) -> DecodeResult<SpanRef> {
+        let ref_type = match value.get("refType") {
+            None | Some(Value::Null) => SpanRefType::ChildOf,
+            Some(Value::String(s)) if s == "CHILD_OF" => SpanRefType::ChildOf,
+            Some(Value::String(s)) if s == "FOLLOWS_FROM" => SpanRefType::FollowsFrom,
+            Some(Value::Number(n)) => to_ref_type(n.as_i64().unwrap_or(-1), 0)?,
+            Some(v) => return Err(json_error("refType", v)),
+        };
+        Ok(SpanRef {
+            ref_type,
+            trace_id: to_trace_id(&self.base64(value, "traceId")?, "traceId", 0)?,
+            span_id: to_span_id(&self.base64(value, "spanId")?, "spanId", 0)?,
+            unknown_fields: UnknownFields::new(),
+        })
+    }
+
+    fn key_value(&self, value: &Value) -> DecodeResult<Tag> {
+        let key = self.string(value, "key")?;
+        let value_type = match value.get("vType") {
+            None | Some(Value::Null) => 0,
+            Some(Value::String(s)) => match s.as_str() {
+                "STRING" => 0,
+                "BOOL" => 1,
+                "INT64" => 2,
+                "FLOAT64" => 3,
+                "BINARY" => 4,
+                _ => -1,
+            },
+            Some(Value::Number(n)) => n.as_i64().unwrap_or(-1),
+            Some(v) => return Err(json_error("vType", v)),
+        };
+        let float64 = match value.get("vFloat64") {
+            None | Some(Value::Null) => 0.0,
+            Some(Value::Number(n)) => n.as_f64().unwrap_or(0.0),
+            // Including "NaN", "Infinity" and "-Infinity"
+            Some(Value::String(s)) => s.parse()
+                .map_err(|_| json_error("vFloat64", &value["vFloat64"]))?,
+            Some(v) => return Err(json_error("vFloat64", v)),
+        };
+        let key_value = KeyValue {
+            str: self.string(value, "vStr")?,
+            bool: value.get("vBool").and_then(|v| v.as_bool()).unwrap_or(false),
+            int64: self.int(value, "vInt64")?,
+            float64,
+            binary: self.base64(value, "vBinary")?,
+        };
+        let value = key_value.into_tag_value(value_type, &key, 0)?;
+        Ok(Tag::new(key, value))
+    }
+
+    fn array<T, F>(&self, object: &Value, key: &str, f: F) -> DecodeResult<Vec<T>>
+    where
+        F: Fn(&Value) -> DecodeResult<T>,
+    {
+        match object.get(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Array(values)) => {
+                let mut items = Vec::with_capacity(values.len());
+                for v in values {
+                    check_collection(&items, self.limits, 0)?;
+                    items.push(f(v)?);
+                }
+                Ok(items)
+            }
+            Some(v) => Err(json_error(key, v)),
+        }
+    }
+
+    fn string(&self, object: &Value, key: &str) -> DecodeResult<String> {
+        match object.get(key) {
+            None | Some(Value::Null) => Ok(String::new()),
+            Some(Value::String(s)) => {
+                check_string(s.len(), self.limits, 0)?;
+                Ok(s.clone())
+            }
+            Some(v) => Err(json_error(key, v)),
+        }
+    }
+
+    fn int(&self, object: &Value, key: &str) -> DecodeResult<i64> {
+        let v = match object.get(key) {
+            None | Some(Value::Null) => return Ok(0),
+            Some(v) => v,
+        };
+        let n = match *v {
+            Value::Number(ref n) => n.as_i64().or_else(|| n.as_u64().map(|n| n as i64)),
+            Value::String(ref s) => s.parse::<i64>()
+                .ok()
+                .or_else(|| s.parse::<u64>().ok().map(|n| n as i64)),
+            _ => None,
+        };
+        n.ok_or_else(|| json_error(key, v))
+    }
+
+    fn base64(&self, object: &Value, key: &str) -> DecodeResult<Vec<u8>> {
+        let s = self.string(object, key)?;
+        decode_base64(&s).ok_or_else(|| DecodeError::InvalidValue {
+            offset: 0,
+            reason: format!("{} is not a base64 string: {:?}", key, s),
+        })
+    }
+
+    fn micros<F>(&self, object: &Value, key: &str, parse: F) -> DecodeResult<i64>
+    where
+        F: Fn(&str) -> Option<i64>,
+    {
+        match self.string(object, key)?.as_str() {
+            "" => Ok(0),
+            s => parse(s).ok_or_else(|| json_error(key, &object[key])),
+        }
+    }
+}
+
+fn json_error(key: &str, value: &Value) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Unexpected value of {:?}: {}", key, value),
+    }
+}
+
+/// Parses an RFC 3339 timestamp (e.g., `"2018-12-13T14:51:00.5Z"`) in microseconds
+/// since the Unix epoch.
+fn parse_timestamp(s: &str) -> Option<i64> {
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || (b[10] != b'T' && b[10] != b't') {
+        return None;
+    }
+    if b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    let number = |range: ::std::ops::Range<usize>| -> Option<i64> {
+        let digits = s.get(range)?;
+        if digits.bytes().all(|c| c.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+    let days = days_from_civil(number(0..4)?, number(5..7)?, number(8..10)?);
+    let seconds = number(11..13)? * 3600 + number(14..16)? * 60 + number(17..19)?;
+    let (micros, zone) = parse_fraction(&s[19..])?;
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ if zone.len() == 6 && zone.as_bytes()[3] == b':' => {
+            let hours = number(s.len() - 5..s.len() - 3)?;
+            let minutes = number(s.len() - 2..s.len())?;
+            let offset = (hours * 60 + minutes) * 60;
+            match zone.as_bytes()[0] {
+                b'+' => offset,
+                b'-' => -offset,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some((days * 86_400 + seconds - offset) * 1_000_000 + micros)
+}
+
+/// Parses a duration in seconds with an `s` suffix (e.g., `"1.000001s"`) in microseconds.
+fn parse_duration(s: &str) -> Option<i64> {
+    let (negative, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let seconds: i64 = s[..end].parse().ok()?;
+    let (micros, suffix) = parse_fraction(&s[end..])?;
+    if suffix != "s" {
+        return None;
+    }
+    let micros = seconds.checked_mul(1_000_000)?.checked_add(micros)?;
+    Some(if negative { -micros } else { micros })
+}
+
+/// Parses an optional fraction of a second (e.g., `".5"`) in microseconds,
+/// and returns it with the rest of `s`.
+fn parse_fraction(s: &str) -> Option<(i64, &str)> {
+    let s = match s.strip_prefix('.') {
+        Some(s) => s,
+        None => return Some((0, s)),
+    };
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    if end == 0 {
+        return None;
+    }
+    let micros = s[..end]
+        .bytes()
+        .chain(b"000000".iter().cloned())
+        .take(6)
+        .fold(0, |n, c| n * 10 + i64::from(c - b'0'));
+    Some((micros, &s[end..]))
+}
+
+/// Returns the number of the days from the Unix epoch to a date in the Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Wire {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    /// Splits a message into its fields (in the order of the encoding).
+    fn fields(buf: &[u8]) -> Vec<(u64, Wire)> {
+        let limits = DecodeLimits::default();
+        let reader = Reader::new("Test", DecodeMode::Strict, &limits);
+        let mut fields = Vec::new();
+        reader
+            .fields(buf, 0, |_, number, field, _| {
+                fields.push((
+                    number,
+                    match field {
+                        Field::Varint(v) => Wire::Varint(v),
+                        Field::Fixed64(v) => Wire::Fixed64(v),
+                        Field::Bytes(b, _) => Wire::Bytes(b.to_vec()),
+                        Field::Fixed32 => panic!("Unexpected fixed32 field {}", number),
+                    },
+                ));
+                Ok(true)
+            })
+            .unwrap();
+        fields
+    }
+
+    fn bytes(s: &str) -> Wire {
+        Wire::Bytes(s.as_bytes().to_vec())
+    }
+
+    /// Returns the fields of a field holding a message.
+    fn message(field: &(u64, Wire)) -> Vec<(u64, Wire)> {
+        match field.1 {
+            Wire::Bytes(ref b) => fields(b),
+            ref wire => panic!("Field {} is not a message: {:?}", field.0, wire),
+        }
+    }
+
+    #[test]
+    fn encoded_span_has_fields_of_model_proto() {
+        let trace_id = TraceId { high: 1, low: -2 };
+        let span = Span {
+            trace_id,
+            span_id: SpanId(0x0102_0304_0506_0708),
+            parent_span_id: Some(SpanId(9)),
+            operation_name: "bar".to_owned(),
+            references: vec![SpanRef {
+                ref_type: SpanRefType::FollowsFrom,
+                trace_id: TraceId { high: 0, low: 7 },
+                span_id: SpanId(8),
+                unknown_fields: UnknownFields::new(),
+            }],
+            flags: 3,
+            start_time: 1_544_712_660_500_001,
+            duration: -1_500_001,
+            tags: vec![
+                Tag::new("s", "x"),
+                Tag::new("b", true),
+                Tag::new("l", -1i64),
+                Tag::new("d", 0.5),
+                Tag::new("y", vec![0, 255]),
+            ],
+            logs: vec![Log {
+                timestamp: -1,
+                fields: vec![Tag::new("event", "retry")],
+                unknown_fields: UnknownFields::new(),
+            }],
+            unknown_fields: UnknownFields::new(),
+        };
+        let process = Process {
+            service_name: "foo".to_owned(),
+            tags: vec![Tag::new("pid", 42i64)],
+            unknown_fields: UnknownFields::new(),
+        };
+        let mut out = Vec::new();
+        encode_span(&mut out, &span, &process);
+
+        let trace_id_bytes = vec![0, 0, 0, 0, 0, 0, 0, 1, 255, 255, 255, 255, 255, 255, 255, 254];
+        let fields = fields(&out);
+        let numbers = fields.iter().map(|f| f.0).collect::<Vec<_>>();
+        assert_eq!(numbers, [1, 2, 3, 4, 4, 5, 6, 7, 8, 8, 8, 8, 8, 9, 10]);
+
+        // IDs are big-endian bytes
+        assert_eq!(fields[0].1, Wire::Bytes(trace_id_bytes.clone()));
+        assert_eq!(fields[1].1, Wire::Bytes(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(fields[2].1, bytes("bar"));
+
+        // The parent comes first as a `CHILD_OF` reference
+        // (whose `ref_type` is omitted as the default value 0)
+        assert_eq!(
+            message(&fields[3]),
+            vec![
+                (1, Wire::Bytes(trace_id_bytes)),
+                (2, Wire::Bytes(vec![0, 0, 0, 0, 0, 0, 0, 9])),
+            ]
+        );
+        assert_eq!(
+            message(&fields[4]),
+            vec![
+                (1, Wire::Bytes(vec![0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7])),
+                (2, Wire::Bytes(vec![0, 0, 0, 0, 0, 0, 0, 8])),
+                (3, Wire::Varint(1)),
+            ]
+        );
+        assert_eq!(fields[5].1, Wire::Varint(3));
+
+        // `google.protobuf.Timestamp` and `google.protobuf.Duration`
+        assert_eq!(
+            message(&fields[6]),
+            vec![(1, Wire::Varint(1_544_712_660)), (2, Wire::Varint(500_001_000))]
+        );
+        assert_eq!(
+            message(&fields[7]),
+            vec![(1, Wire::Varint(-1i64 as u64)), (2, Wire::Varint(-500_001_000i64 as u64))]
+        );
+
+        // `KeyValue` has the type (`v_type`) and the value field of the type
+        assert_eq!(message(&fields[8]), vec![(1, bytes("s")), (3, bytes("x"))]);
+        assert_eq!(
+            message(&fields[9]),
+            vec![(1, bytes("b")), (2, Wire::Varint(1)), (4, Wire::Varint(1))]
+        );
+        assert_eq!(
+            message(&fields[10]),
+            vec![(1, bytes("l")), (2, Wire::Varint(2)), (5, Wire::Varint(u64::MAX))]
+        );
+        assert_eq!(
+            message(&fields[11]),
+            vec![(1, bytes("d")), (2, Wire::Varint(3)), (6, Wire::Fixed64(0.5f64.to_bits()))]
+        );
+        assert_eq!(
+            message(&fields[12]),
+            vec![(1, bytes("y")), (2, Wire::Varint(4)), (7, Wire::Bytes(vec![0, 255]))]
+        );
+
+        // The nanoseconds of a timestamp before the epoch are positive
+        let log = message(&fields[13]);
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            message(&log[0]),
+            vec![(1, Wire::Varint(-1i64 as u64)), (2, Wire::Varint(999_999_000))]
+        );
+        assert_eq!(message(&log[1]), vec![(1, bytes("event")), (3, bytes("retry"))]);
+
+        let process_fields = message(&fields[14]);
+        assert_eq!(process_fields[0], (1, bytes("foo")));
+        assert_eq!(
+            message(&process_fields[1]),
+            vec![(1, bytes("pid")), (2, Wire::Varint(2)), (5, Wire::Varint(42))]
+        );
+
+        // `KafkaSpan` decodes the message into the same span (with the parent as a reference)
+        let limits = DecodeLimits::default();
+        let (message, _) = KafkaSpan
+            .decode_batch(&out, DecodeMode::Strict, &limits)
+            .unwrap();
+        let mut expected = span.clone();
+        expected.references.insert(
+            0,
+            SpanRef {
+                ref_type: SpanRefType::ChildOf,
+                trace_id,
+                span_id: SpanId(9),
+                unknown_fields: UnknownFields::new(),
+            },
+        );
+        assert_eq!(message.batch.spans, vec![expected]);
+        assert_eq!(message.batch.process.service_name, "foo");
+        assert_eq!(message.batch.process.tags, process.tags);
+    }
+}
@@ -1,11 +1,130 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![allow(non_local_definitions)]
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
+#[cfg(feature = "local-time")]
 extern crate chrono;
+#[cfg(feature = "server")]
+extern crate rmp_serde;
+#[cfg(feature = "std")]
 extern crate serde;
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate serde_derive;
-extern crate thrift_codec;
+#[cfg(feature = "server")]
+extern crate serde_json;
+#[cfg(feature = "std")]
+extern crate serdeconv;
+#[cfg(feature = "server")]
+extern crate libc;
+#[cfg(feature = "server")]
 #[macro_use]
+extern crate slog;
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "server", macro_use)]
 extern crate trackable;
 
+pub use error::{DecodeError, DecodeResult};
+
+#[cfg(feature = "std")]
 pub type Result<T> = std::result::Result<T, trackable::error::Failure>;
 
+pub mod borrowed;
+#[cfg(feature = "server")]
+pub mod cbor;
+#[cfg(feature = "server")]
+pub mod chrome;
+#[cfg(feature = "server")]
+pub mod color;
+#[cfg(feature = "server")]
+pub mod csv;
+#[cfg(feature = "std")]
+pub mod decoder;
+#[cfg(feature = "server")]
+pub mod emit;
+pub mod error;
+#[cfg(feature = "server")]
+pub mod filter;
+#[cfg(feature = "server")]
+pub mod handler;
+pub mod hook;
+#[cfg(feature = "server")]
+pub mod jaeger_ui;
+#[cfg(feature = "server")]
+pub mod jq;
+#[cfg(feature = "server")]
+pub mod folded;
+#[cfg(feature = "server")]
+pub mod json;
+#[cfg(feature = "server")]
+pub mod kafka;
+#[cfg(feature = "server")]
+pub mod logfmt;
+pub mod model;
+#[cfg(feature = "server")]
+pub mod otlp;
+#[cfg(feature = "server")]
+pub mod parquet;
+pub mod protocol;
+#[cfg(feature = "server")]
+pub mod rotate;
+#[cfg(feature = "server")]
+pub mod sampling;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "server")]
+pub mod sink;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "server")]
+pub mod template;
+#[cfg(feature = "server")]
+pub mod text;
+#[cfg(feature = "server")]
+pub mod tree;
 pub mod thrift;
+#[cfg(feature = "server")]
+pub mod zipkin;
+
+#[cfg(feature = "server")]
+mod affinity;
+#[cfg(feature = "server")]
+mod dump;
+#[cfg(feature = "server")]
+mod http;
+#[cfg(feature = "server")]
+mod inflate;
+#[cfg(feature = "server")]
+mod pool;
+#[cfg(feature = "server")]
+mod proto;
+#[cfg(feature = "server")]
+mod queue;
+#[cfg(feature = "server")]
+mod ratelimit;
+#[cfg(feature = "server")]
+mod socket;
+#[cfg(feature = "server")]
+mod spill;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod wire;
+#[cfg(feature = "server")]
+mod zstd;
+
+/// Re-exports of `core` and `alloc` under the paths of `std`, for building without `std`.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::{fmt, num, result, str};
+
+    pub mod collections {
+        pub use alloc::collections::BTreeMap;
+    }
+
+    pub mod prelude {
+        pub use alloc::borrow::ToOwned;
+        pub use alloc::string::String;
+        pub use alloc::vec::Vec;
+    }
+}
@@ -0,0 +1,189 @@
+//! [logfmt] output of spans, one line of `key=value` pairs per span.
+//!
+//! The pairs are chosen by the same `Column`s as the CSV output.
+//! The key of a `tag:KEY` column is the tag key itself, and the pairs whose values are empty
+//! (e.g., the parent span ID of a root span, or an absent tag) are omitted.
+//! Values which contain spaces, `=`, quotes or control characters are quoted,
+//! and the characters in keys other than those are replaced with `_`.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::logfmt;
+//!
+//! let mut out = Vec::new();
+//! logfmt::write_pair(&mut out, "operation", b"GET /api");
+//! assert_eq!(out, b"operation=\"GET /api\"");
+//! ```
+//!
+//! [logfmt]: https://brandur.org/logfmt
+use std::io::Write;
+
+use csv::Column;
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// Returns the columns written by default
+/// (`trace_id,span_id,parent_span_id,service,operation,start,duration_us,error`).
+pub fn default_columns() -> Vec<Column> {
+    vec![
+        Column::TraceId,
+        Column::SpanId,
+        Column::ParentSpanId,
+        Column::Service,
+        Column::Operation,
+        Column::Start,
+        Column::DurationUs,
+        Column::Error,
+    ]
+}
+
+/// Appends a line per span of `batch` to `out`.
+pub fn write_lines(
+    out: &mut Vec<u8>,
+    batch: &EmitBatchNotification,
+    meta: &ReceiveMeta,
+    columns: &[Column],
+) {
+    let mut value = Vec::new();
+    for span in &batch.batch.spans {
+        let start = out.len();
+        for column in columns {
+            value.clear();
+            column.write_value(&mut value, batch, span, meta);
+            if value.is_empty() {
+                continue;
+            }
+            if out.len() > start {
+                out.push(b' ');
+            }
+            match *column {
+                Column::Tag(ref key) => write_pair(out, key, &value),
+                ref column => write_pair(out, &column.to_string(), &value),
+            }
+        }
+        out.push(b'\n');
+    }
+}
+
+/// Appends a `key=value` pair to `out`, quoting the value if needed.
+pub fn write_pair(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    for &b in key.as_bytes() {
+        out.push(if needs_quote(b) { b'_' } else { b });
+    }
+    out.push(b'=');
+    if !value.is_empty() && !value.iter().any(|&b| needs_quote(b)) {
+        out.extend_from_slice(value);
+        return;
+    }
+    out.push(b'"');
+    for &b in value {
+        match b {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b if b < 0x20 || b == 0x7F => {
+                let _ = write!(out, "\\u{:04x}", b);
+            }
+            b => out.push(b),
+        }
+    }
+    out.push(b'"');
+}
+
+fn needs_quote(b: u8) -> bool {
+    b <= b' ' || b == b'=' || b == b'"' || b == b'\\' || b == 0x7F
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use model::{Batch, Process, Span, SpanId, Tag, TraceId, UnknownFields};
+    use thrift::Protocol;
+
+    fn pair(key: &str, value: &str) -> String {
+        let mut out = Vec::new();
+        write_pair(&mut out, key, value.as_bytes());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn values_are_quoted_if_needed() {
+        assert_eq!(pair("k", "v"), "k=v");
+        assert_eq!(pair("k", "/api?a=b"), "k=\"/api?a=b\"");
+        assert_eq!(pair("k", "GET /api"), "k=\"GET /api\"");
+        assert_eq!(pair("k", "say \"hi\""), "k=\"say \\\"hi\\\"\"");
+        assert_eq!(pair("k", "a\\b"), "k=\"a\\\\b\"");
+        assert_eq!(pair("k", "a\tb\nc\rd"), "k=\"a\\tb\\nc\\rd\"");
+        assert_eq!(pair("k", "a\u{1}b\u{7f}"), "k=\"a\\u0001b\\u007f\"");
+        assert_eq!(pair("k", ""), "k=\"\"");
+
+        // Non-ASCII characters are written as they are
+        assert_eq!(pair("k", "caf\u{e9}"), "k=caf\u{e9}");
+    }
+
+    #[test]
+    fn keys_are_sanitized() {
+        assert_eq!(pair("http.method", "GET"), "http.method=GET");
+        assert_eq!(pair("a b=c\"d", "v"), "a_b_c_d=v");
+    }
+
+    #[test]
+    fn empty_values_are_omitted() {
+        let span = |span_id, parent_span_id, tags| Span {
+            trace_id: TraceId { high: 0, low: 1 },
+            span_id: SpanId(span_id),
+            parent_span_id,
+            operation_name: "GET /api".to_owned(),
+            references: Vec::new(),
+            flags: 1,
+            start_time: 1_000_000,
+            duration: 1500,
+            tags,
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        };
+        let batch = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans: vec![
+                    span(2, None, vec![Tag::new("http.url", "/api?a=b c")]),
+                    span(3, Some(SpanId(2)), Vec::new()),
+                ],
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            port: 6831,
+            protocol: &Protocol::Compact,
+            bytes: &[],
+        };
+        let mut columns = default_columns();
+        columns.push(Column::Tag("http.url".to_owned()));
+        let mut out = Vec::new();
+        write_lines(&mut out, &batch, &meta, &columns);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "trace_id=0x1 span_id=0x2 service=foo operation=\"GET /api\" start=1.000000 \
+             duration_us=1500 error=false http.url=\"/api?a=b c\"\n\
+             trace_id=0x1 span_id=0x3 parent_span_id=0x2 service=foo operation=\"GET /api\" \
+             start=1.000000 duration_us=1500 error=false\n"
+        );
+
+        // A line is written even if all of the values are empty
+        let mut out = Vec::new();
+        write_lines(&mut out, &batch, &meta, &[Column::Kind]);
+        assert_eq!(out, b"\n\n");
+    }
+}
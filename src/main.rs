@@ -1,30 +1,94 @@
 extern crate clap;
 extern crate jaegercat;
-extern crate serdeconv;
-#[macro_use]
-extern crate slog;
+extern crate libc;
 extern crate sloggers;
 #[macro_use]
 extern crate trackable;
 
-use std::io::{self, Write};
-use std::net::{SocketAddr, UdpSocket};
+use std::env;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process;
+use std::ptr;
 use std::thread;
-use clap::{App, Arg};
-use jaegercat::thrift::{EmitBatchNotification, Protocol};
+use std::time::Duration;
+use clap::{App, Arg, SubCommand};
+use jaegercat::color::Colors;
+use jaegercat::filter::{HumanDuration, SpanFilter};
+use jaegercat::handler::{Format, Formatter};
+use jaegercat::json::JsonBatch;
+use jaegercat::otlp::Otlp;
+use jaegercat::parquet::ParquetHandler;
+use jaegercat::protocol::{CollectorThrift, WireProtocol};
+use jaegercat::rotate::RotatingFile;
+use jaegercat::server::{OverflowPolicy, Server, ServerBuilder};
+use jaegercat::sink::{self, Sink, Tee, UdpWriter, WriterHandler};
+#[cfg(feature = "sqlite")]
+use jaegercat::sqlite::SqliteHandler;
+use jaegercat::thrift::{DecodeLimits, DecodeMode, Protocol};
+use jaegercat::tree::TreeHandler;
+use jaegercat::zipkin::{ZipkinThrift, ZipkinV2};
 use sloggers::Build;
 use sloggers::terminal::{Destination, TerminalLoggerBuilder};
 use sloggers::types::SourceLocation;
-use trackable::error::Failure;
+use trackable::error::{Failed, Failure};
+
+use capture::Capture;
+use decode::{Decode, InputProtocol};
+use pcap::Pcap;
+use replay::Replay;
+use soak::Soak;
+use tail::Tail;
 
 macro_rules! try_parse {
     ($expr:expr) => { track_try_unwrap!($expr.parse().map_err(Failure::from_error)) }
 }
 
+mod capture;
+mod decode;
+mod pcap;
+mod replay;
+mod soak;
+mod tail;
+
 fn main() {
     let matches = App::new("jaegercat")
         .version(env!("CARGO_PKG_VERSION"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::with_name("NO_COMPACT")
+                .long("no-compact")
+                .help("Disables the compact protocol listener on --compact-thrift-port"),
+        )
+        .arg(
+            Arg::with_name("NO_BINARY")
+                .long("no-binary")
+                .help("Disables the binary protocol listener on --binary-thrift-port"),
+        )
+        .arg(
+            Arg::with_name("ZIPKIN_THRIFT")
+                .long("zipkin-thrift")
+                .help(
+                    "Enables the Zipkin Thrift listener on --zipkin-thrift-port \
+                     (for the emitZipkinBatch messages of old agents)",
+                ),
+        )
+        .arg(
+            // The listener used to be enabled by default
+            Arg::with_name("NO_ZIPKIN_THRIFT")
+                .long("no-zipkin-thrift")
+                .conflicts_with("ZIPKIN_THRIFT")
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("BIND_ADDRESS")
+                .long("bind-address")
+                .takes_value(true)
+                .default_value("0.0.0.0")
+                .help("Local address of all the listeners (e.g., 127.0.0.1 or [::])"),
+        )
         .arg(
             Arg::with_name("COMPACT_THRIFT_PORT")
                 .long("compact-thrift-port")
@@ -37,20 +101,451 @@ fn main() {
                 .takes_value(true)
                 .default_value("6832"),
         )
+        .arg(
+            Arg::with_name("ZIPKIN_THRIFT_PORT")
+                .long("zipkin-thrift-port")
+                .takes_value(true)
+                .default_value("5775"),
+        )
+        .arg(
+            Arg::with_name("LISTEN")
+                .long("listen")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PROTOCOL:PORT")
+                .help(
+                    "Additional UDP port of compact, binary or zipkin-thrift \
+                     (e.g., compact:16831; can be repeated)",
+                ),
+        )
+        .arg(
+            Arg::with_name("COMPACT_TCP_PORT")
+                .long("compact-tcp-port")
+                .takes_value(true)
+                .help("Port accepting framed (length-prefixed) compact Thrift messages over TCP"),
+        )
+        .arg(
+            Arg::with_name("BINARY_TCP_PORT")
+                .long("binary-tcp-port")
+                .takes_value(true)
+                .help("Port accepting framed (length-prefixed) binary Thrift messages over TCP"),
+        )
+        .arg(
+            Arg::with_name("UNIX_SOCKET")
+                .long("unix-socket")
+                .takes_value(true)
+                .help("Unix domain datagram socket to create for the compact protocol"),
+        )
+        .arg(
+            Arg::with_name("COLLECTOR_HTTP_PORT")
+                .long("collector-http-port")
+                .takes_value(true)
+                .help("Port accepting Jaeger Thrift batches on /api/traces (e.g., 14268)"),
+        )
+        .arg(
+            Arg::with_name("OTLP_HTTP_PORT")
+                .long("otlp-http-port")
+                .takes_value(true)
+                .help("Port accepting OTLP/HTTP (protobuf or JSON) on /v1/traces (e.g., 4318)"),
+        )
+        .arg(
+            Arg::with_name("ZIPKIN_HTTP_PORT")
+                .long("zipkin-http-port")
+                .takes_value(true)
+                .help("Port accepting Zipkin v2 JSON or proto3 on /api/v2/spans (e.g., 9411)"),
+        )
+        .arg(
+            Arg::with_name("JSON_HTTP_PORT")
+                .long("json-http-port")
+                .takes_value(true)
+                .help("Port accepting batches in the JSON output format on /spans (for testing)"),
+        )
         .arg(
             Arg::with_name("FORMAT")
                 .short("f")
                 .long("format")
                 .takes_value(true)
                 .default_value("json")
-                .possible_values(&["raw", "json", "json-pretty"]),
+                .possible_values(&[
+                    "raw",
+                    "hex",
+                    "json",
+                    "json-pretty",
+                    "msgpack",
+                    "cbor",
+                    "model-proto",
+                    "otlp-json",
+                    "zipkin",
+                    "jaeger-ui",
+                    "csv",
+                    "tsv",
+                    "logfmt",
+                    "text",
+                    "tree",
+                    "folded",
+                    "chrome-trace",
+                ]),
+        )
+        .arg(
+            Arg::with_name("FIELDS")
+                .long("fields")
+                .takes_value(true)
+                .help(
+                    "Comma-separated keys kept in --format json/json-pretty, as paths in spans \
+                     (e.g., operation_name,tags.http.status_code) or in batches \
+                     (e.g., process.service_name)",
+                ),
+        )
+        .arg(
+            Arg::with_name("JQ")
+                .long("jq")
+                .takes_value(true)
+                .value_name("FILTER")
+                .help(
+                    "Applies a jq filter to each batch of --format json/json-pretty, writing \
+                     each output as a line (e.g., '.emit_batch.spans[] | select(.duration > 1)'; \
+                     a subset of jq is supported)",
+                ),
+        )
+        .arg(
+            Arg::with_name("SERVICE")
+                .long("service")
+                .takes_value(true)
+                .value_name("NAME")
+                .multiple(true)
+                .number_of_values(1)
+                .help("Outputs only the spans of the service (can be repeated)"),
+        )
+        .arg(
+            Arg::with_name("TRACE_ID")
+                .long("trace-id")
+                .takes_value(true)
+                .value_name("HEX")
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Outputs only the spans of the trace, given in 16 or 32 hex digits \
+                     (optionally prefixed with 0x; can be repeated)",
+                ),
+        )
+        .arg(
+            Arg::with_name("TAG")
+                .long("tag")
+                .takes_value(true)
+                .value_name("KEY[=VALUE]")
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Outputs only the spans having the tag (or whose process has it) \
+                     with the value if given, comparing numbers numerically (e.g., 500 and 500.0; \
+                     can be repeated; all of them must match)",
+                ),
+        )
+        .arg(
+            Arg::with_name("MIN_DURATION")
+                .long("min-duration")
+                .takes_value(true)
+                .value_name("DURATION")
+                .help(
+                    "Outputs only the spans taking DURATION or longer \
+                     (e.g., 250ms, 1.5ms or 500us)",
+                ),
+        )
+        .arg(
+            Arg::with_name("SAMPLE_OUTPUT")
+                .long("sample-output")
+                .takes_value(true)
+                .value_name("RATE")
+                .help(
+                    "Outputs only the spans of the fraction RATE (from 0.0 to 1.0) of the traces, \
+                     chosen by their IDs so that the traces are kept complete",
+                ),
+        )
+        .arg(
+            Arg::with_name("ERRORS_ONLY")
+                .long("errors-only")
+                .help(
+                    "Outputs only the error spans, which have the error=true or \
+                     otel.status_code=ERROR tag (or one of --error-tag)",
+                ),
+        )
+        .arg(
+            Arg::with_name("ERROR_TAG")
+                .long("error-tag")
+                .takes_value(true)
+                .value_name("KEY[=VALUE]")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("ERRORS_ONLY")
+                .help("Tag of the error spans for --errors-only (can be repeated)"),
+        )
+        .arg(
+            Arg::with_name("MAX_SPANS_PER_SEC")
+                .long("max-spans-per-sec")
+                .takes_value(true)
+                .value_name("N")
+                .help(
+                    "Outputs at most N spans per second (in bursts of up to N spans), \
+                     dropping the excess ones and logging how many have been dropped",
+                ),
+        )
+        .arg(
+            Arg::with_name("COUNT")
+                .long("count")
+                .takes_value(true)
+                .value_name("N")
+                .help("Exits after outputting N spans (or batches with --count-batches)"),
+        )
+        .arg(
+            Arg::with_name("COUNT_BATCHES")
+                .long("count-batches")
+                .requires("COUNT")
+                .help("Counts batches instead of spans for --count"),
+        )
+        .arg(
+            Arg::with_name("TEMPLATE")
+                .long("template")
+                .takes_value(true)
+                .help(
+                    "Writes a line per span from a template like '{{service}} {{operation}} \
+                     {{duration_ms}}ms' (overrides --format; the names are those of --columns)",
+                ),
+        )
+        .arg(
+            Arg::with_name("COLUMNS")
+                .long("columns")
+                .takes_value(true)
+                .help(
+                    "Comma-separated columns of --format csv/tsv/logfmt and --parquet [default: \
+                     trace_id,span_id,parent_span_id,service,operation,start,duration,error, \
+                     with duration_us instead of duration for logfmt, and duration_us and kind \
+                     for --parquet] [values: trace_id, span_id, \
+                     parent_span_id, service, operation, start, duration, duration_us, \
+                     duration_ms, error, kind, flags, peer, tag:KEY]",
+                ),
+        )
+        .arg(
+            Arg::with_name("PARQUET")
+                .long("parquet")
+                .takes_value(true)
+                .value_name("FILE")
+                .help(
+                    "Writes the received spans into a Parquet file (one row per span) instead \
+                     of the standard output",
+                ),
+        )
+        .arg(
+            Arg::with_name("PARQUET_ROW_GROUP_SIZE")
+                .long("parquet-row-group-size")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Number of the rows of each row group of --parquet"),
+        )
+        .arg(
+            Arg::with_name("SQLITE")
+                .long("sqlite")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("PARQUET")
+                .help(
+                    "Inserts the received spans into the spans, tags and logs tables of \
+                     a SQLite database instead of the standard output (requires the sqlite \
+                     feature)",
+                ),
+        )
+        .arg(
+            Arg::with_name("OUTPUT_FILE")
+                .long("output-file")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["PARQUET", "SQLITE"])
+                .help(
+                    "Appends the output to FILE instead of the standard output, renaming it to \
+                     FILE.1, FILE.2, ... on rotation",
+                ),
+        )
+        .arg(
+            Arg::with_name("OUTPUT")
+                .long("output")
+                .takes_value(true)
+                .value_name("SINK")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["PARQUET", "SQLITE", "OUTPUT_FILE"])
+                .help(
+                    "Writes the output to SINK instead of the standard output (can be repeated): \
+                     stdout:FORMAT, stderr:FORMAT, file:PATH:FORMAT or udp:HOST:PORT:FORMAT \
+                     (e.g., --output stdout:json --output file:capture.raw:raw)",
+                ),
+        )
+        .arg(
+            Arg::with_name("ROTATE_SIZE")
+                .long("rotate-size")
+                .takes_value(true)
+                .help(
+                    "Rotates --output-file (or the files of --output) once it has reached \
+                     this size in bytes",
+                ),
+        )
+        .arg(
+            Arg::with_name("ROTATE_INTERVAL")
+                .long("rotate-interval")
+                .takes_value(true)
+                .help(
+                    "Rotates --output-file (or the files of --output) once it has been open \
+                     for this many seconds",
+                ),
+        )
+        .arg(
+            Arg::with_name("ROTATE_KEEP")
+                .long("rotate-keep")
+                .takes_value(true)
+                .default_value("10")
+                .help("Number of rotated files of --output-file (or --output) kept"),
+        )
+        .arg(
+            Arg::with_name("COLOR")
+                .long("color")
+                .takes_value(true)
+                .default_value("auto")
+                .possible_values(&["auto", "always", "never"])
+                .help(
+                    "Colors --format text/tree written to the standard output (auto: if it is \
+                     a terminal and NO_COLOR is not set)",
+                ),
+        )
+        .arg(
+            Arg::with_name("SLOW_THRESHOLD")
+                .long("slow-threshold")
+                .takes_value(true)
+                .default_value("1000")
+                .help("Milliseconds above which the durations are highlighted by --color"),
+        )
+        .arg(
+            Arg::with_name("TREE_WAIT")
+                .long("tree-wait")
+                .takes_value(true)
+                .default_value("1000")
+                .help(
+                    "Milliseconds to wait for more spans of a trace before writing it with \
+                     --format tree/folded (without subcommands)",
+                ),
+        )
+        .arg(
+            Arg::with_name("FLUSH_INTERVAL")
+                .long("flush-interval")
+                .takes_value(true)
+                .default_value("100")
+                .help("Maximum interval between flushes of the output in milliseconds"),
+        )
+        .arg(
+            Arg::with_name("FLUSH_LINES")
+                .long("flush-lines")
+                .takes_value(true)
+                .default_value("0")
+                .help(
+                    "Flushes the output after this many lines even before --flush-interval \
+                     elapses (0: only by the interval)",
+                ),
         )
         .arg(
             Arg::with_name("UDP_BUFFER_SIZE")
                 .short("b")
                 .long("udp-buffer-size")
                 .takes_value(true)
-                .default_value("65000"),
+                .default_value("65507")
+                .help("Size of the buffer receiving each datagram (larger ones are dropped)"),
+        )
+        .arg(
+            Arg::with_name("SOCKET_BUFFER_SIZE")
+                .long("socket-buffer-size")
+                .takes_value(true)
+                .help("Size of the kernel receive buffer (SO_RCVBUF) of each socket in bytes"),
+        )
+        .arg(
+            Arg::with_name("DECODE_WORKERS")
+                .long("decode-workers")
+                .visible_alias("decode-threads")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of the threads which decode and output the received datagrams"),
+        )
+        .arg(
+            Arg::with_name("RECV_THREADS")
+                .long("recv-threads")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of the threads which receive datagrams from each socket"),
+        )
+        .arg(
+            Arg::with_name("SOCKETS_PER_PORT")
+                .long("sockets-per-port")
+                .visible_alias("reuse-port")
+                .takes_value(true)
+                .default_value("1")
+                .help("Number of the sockets bound to each port with SO_REUSEPORT (Linux only)"),
+        )
+        .arg(
+            Arg::with_name("IO_URING_DEPTH")
+                .long("io-uring-depth")
+                .takes_value(true)
+                .default_value("0")
+                .help("Number of receives kept in flight with io_uring (0 disables io_uring)"),
+        )
+        .arg(
+            Arg::with_name("QUEUE_SIZE")
+                .long("queue-size")
+                .takes_value(true)
+                .default_value("1024")
+                .help("Capacity of the queues between receiving, decoding and output"),
+        )
+        .arg(
+            Arg::with_name("OVERFLOW")
+                .long("overflow")
+                .takes_value(true)
+                .default_value("block")
+                .possible_values(&["block", "drop-new", "drop-old"])
+                .help("What to do when a queue is full"),
+        )
+        .arg(
+            Arg::with_name("PIN_CORES")
+                .long("pin-cores")
+                .takes_value(true)
+                .help("Comma separated IDs of the cores to pin receiving and decoding threads to"),
+        )
+        .arg(
+            Arg::with_name("SPILL_FILE")
+                .long("spill-file")
+                .takes_value(true)
+                .help("File into which the output is spilled while the output queue is full"),
+        )
+        .arg(
+            Arg::with_name("SPILL_MAX_SIZE")
+                .long("spill-max-size")
+                .takes_value(true)
+                .default_value("1073741824")
+                .help("Maximum size of the spill file in bytes"),
+        )
+        .arg(
+            Arg::with_name("DUMP_DIR")
+                .long("dump-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help(
+                    "Writes each received datagram (before decoding) to its own file in DIR, \
+                     named after the time, the protocol and the sender",
+                ),
+        )
+        .arg(
+            Arg::with_name("PREALLOCATE")
+                .long("preallocate")
+                .help("Allocates the queues and the buffers for a burst filling them at startup"),
+        )
+        .arg(
+            Arg::with_name("WARMUP")
+                .long("warmup")
+                .help("Decodes and formats a sample batch in each decode worker at startup"),
         )
         .arg(
             Arg::with_name("LOG_LEVEL")
@@ -59,17 +554,277 @@ fn main() {
                 .default_value("info")
                 .possible_values(&["debug", "info", "error"]),
         )
+        .arg(
+            Arg::with_name("LENIENT")
+                .long("lenient")
+                .help("Outputs the spans decoded before a decoding failure"),
+        )
+        .arg(
+            Arg::with_name("STRICT")
+                .long("strict")
+                .conflicts_with("LENIENT")
+                .help("Rejects messages containing unknown fields or trailing bytes"),
+        )
+        .arg(
+            Arg::with_name("MAX_STRING_LENGTH")
+                .long("max-string-length")
+                .takes_value(true)
+                .default_value("1048576")
+                .help("Rejects messages containing longer strings or binaries (in bytes)"),
+        )
+        .arg(
+            Arg::with_name("MAX_COLLECTION_SIZE")
+                .long("max-collection-size")
+                .takes_value(true)
+                .default_value("1048576")
+                .help("Rejects messages containing lists, sets or maps with more elements"),
+        )
+        .arg(
+            Arg::with_name("MAX_DEPTH")
+                .long("max-depth")
+                .takes_value(true)
+                .default_value("64")
+                .help("Rejects messages containing more deeply nested structs or collections"),
+        )
+        .arg(
+            Arg::with_name("UNKNOWN_FIELDS")
+                .long("unknown-fields")
+                .help("Includes the fields which are not defined in jaeger.thrift"),
+        )
+        .arg(
+            Arg::with_name("HEX_IDS")
+                .long("hex-ids")
+                .help(
+                    "Writes the trace IDs and the span IDs in the JSON-based formats as Jaeger UI \
+                     shows them (zero-padded lowercase hex without 0x)",
+                ),
+        )
+        .arg(
+            Arg::with_name("FLAT_TAGS")
+                .long("flat-tags")
+                .help(
+                    "Writes the binary values of the tags (and of the fields of the logs) in \
+                     the JSON-based formats in base64 instead of as arrays of bytes",
+                ),
+        )
+        .arg(
+            Arg::with_name("TYPED_TAGS")
+                .long("typed-tags")
+                .conflicts_with("FLAT_TAGS")
+                .help(
+                    "Writes the values of the tags (and of the fields of the logs) in the \
+                     JSON-based formats as objects of their types and values \
+                     (e.g., {\"type\":\"long\",\"value\":200})",
+                ),
+        )
+        .arg(
+            Arg::with_name("TIME_FORMAT")
+                .long("time-format")
+                .takes_value(true)
+                .possible_values(&["unix-us", "unix-ms", "rfc3339"])
+                .help(
+                    "Writes the start times of the spans (and the timestamps of the logs) \
+                     in the JSON-based formats as start_time (and timestamp) in this format \
+                     instead of the datetimes and the seconds since the UNIX epoch",
+                ),
+        )
+        .arg(
+            Arg::with_name("DURATION_UNIT")
+                .long("duration-unit")
+                .takes_value(true)
+                .default_value("s")
+                .possible_values(&["us", "ms", "s"])
+                .help("Unit of the durations of the spans in the JSON-based formats"),
+        )
+        .arg(
+            Arg::with_name("LENGTH_PREFIX")
+                .long("length-prefix")
+                .help(
+                    "Precedes each message in the raw format (or each batch in the cbor format, \
+                     or each span in the model-proto format) with its 4 bytes big-endian length",
+                ),
+        )
+        .arg(
+            Arg::with_name("VALIDATE")
+                .long("validate")
+                .help("Drops malformed messages in the raw format (implied by --strict)"),
+        )
+        .arg(
+            Arg::with_name("INCLUDE_PEER")
+                .long("include-peer")
+                .help("Includes the source address and the receiving listener of each batch"),
+        )
+        .subcommand(
+            SubCommand::with_name("decode")
+                .about(
+                    "Decodes the messages captured in files (or the standard input) \
+                     and outputs them in the format given by the options",
+                )
+                .arg(
+                    Arg::with_name("PROTOCOL")
+                        .long("protocol")
+                        .takes_value(true)
+                        .default_value("auto")
+                        .possible_values(&["auto", "compact", "binary", "kafka-span"]),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .multiple(true)
+                        .help("Files containing the messages (`-` for the standard input)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pcap")
+                .about(
+                    "Decodes the UDP datagrams sent to the agent ports (given by the port \
+                     options) in a pcap or pcapng capture file",
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .help("Capture file written by tcpdump (`-` for the standard input)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("capture")
+                .about(
+                    "Captures the UDP datagrams sent to the agent ports (given by the port \
+                     options) on a network interface without binding them (Linux only)",
+                )
+                .arg(
+                    Arg::with_name("INTERFACE")
+                        .default_value("any")
+                        .help("Network interface to capture packets on (e.g., eth0 or lo)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about(
+                    "Sends the messages captured in files (or the standard input) \
+                     to an agent over UDP",
+                )
+                .arg(
+                    Arg::with_name("TARGET")
+                        .long("target")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:6831")
+                        .help("Address of the agent"),
+                )
+                .arg(
+                    Arg::with_name("RATE")
+                        .long("rate")
+                        .takes_value(true)
+                        .help("Number of the messages sent per second (unlimited by default)"),
+                )
+                .arg(
+                    Arg::with_name("PROTOCOL")
+                        .long("protocol")
+                        .takes_value(true)
+                        .default_value("auto")
+                        .possible_values(&["auto", "compact", "binary"]),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .multiple(true)
+                        .help("Files containing the messages (`-` for the standard input)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tail")
+                .about(
+                    "Reads the JSON lines written by `--format json` from a file \
+                     (or the standard input) and outputs them in the format given by the options",
+                )
+                .arg(
+                    Arg::with_name("FOLLOW")
+                        .short("f")
+                        .long("follow")
+                        .help("Waits for the lines appended to the file after reaching its end"),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .default_value("-")
+                        .help("File containing the JSON lines (`-` for the standard input)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("soak")
+                .about(
+                    "Runs the pipeline configured by the options with generated traffic \
+                     on ephemeral ports, and reports the throughput, drops and latencies",
+                )
+                .arg(
+                    Arg::with_name("SPANS_PER_SEC")
+                        .long("spans-per-sec")
+                        .takes_value(true)
+                        .default_value("10000"),
+                )
+                .arg(
+                    Arg::with_name("SPANS_PER_BATCH")
+                        .long("spans-per-batch")
+                        .takes_value(true)
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::with_name("DURATION")
+                        .long("duration")
+                        .takes_value(true)
+                        .default_value("10s")
+                        .help("Duration of the traffic (e.g., 500ms, 1.5s, 60s or 2m)"),
+                ),
+        )
         .get_matches();
 
     let compact_thrift_port: u16 = try_parse!(matches.value_of("COMPACT_THRIFT_PORT").unwrap());
     let binary_thrift_port: u16 = try_parse!(matches.value_of("BINARY_THRIFT_PORT").unwrap());
+    let zipkin_thrift_port: u16 = try_parse!(matches.value_of("ZIPKIN_THRIFT_PORT").unwrap());
+    let listens: Vec<(&str, u16)> = matches
+        .values_of("LISTEN")
+        .map(|values| values.map(|v| track_try_unwrap!(parse_listen(v))).collect())
+        .unwrap_or_default();
+    let bind_address: IpAddr = {
+        let addr = matches.value_of("BIND_ADDRESS").unwrap();
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        try_parse!(addr)
+    };
     let udp_buffer_size: usize = try_parse!(matches.value_of("UDP_BUFFER_SIZE").unwrap());
-    let format = match matches.value_of("FORMAT").unwrap() {
-        "raw" => Format::Raw,
-        "json" => Format::Json,
-        "json-pretty" => Format::JsonPretty,
+    let decode_workers: usize = try_parse!(matches.value_of("DECODE_WORKERS").unwrap());
+    let recv_threads: usize = try_parse!(matches.value_of("RECV_THREADS").unwrap());
+    let sockets_per_port: usize = try_parse!(matches.value_of("SOCKETS_PER_PORT").unwrap());
+    let io_uring_depth: usize = try_parse!(matches.value_of("IO_URING_DEPTH").unwrap());
+    let queue_size: usize = try_parse!(matches.value_of("QUEUE_SIZE").unwrap());
+    let overflow_policy = match matches.value_of("OVERFLOW").unwrap() {
+        "block" => OverflowPolicy::Block,
+        "drop-new" => OverflowPolicy::DropNew,
+        "drop-old" => OverflowPolicy::DropOld,
         _ => unreachable!(),
     };
+    let format: Format = try_parse!(matches.value_of("FORMAT").unwrap());
+    let format = if matches.is_present("TEMPLATE") {
+        Format::Template
+    } else {
+        format
+    };
+    let flush_interval: u64 = try_parse!(matches.value_of("FLUSH_INTERVAL").unwrap());
+    let decode_mode = if matches.is_present("LENIENT") {
+        DecodeMode::Lenient
+    } else if matches.is_present("STRICT") {
+        DecodeMode::Strict
+    } else {
+        DecodeMode::Normal
+    };
+    let decode_limits = DecodeLimits {
+        max_string_len: try_parse!(matches.value_of("MAX_STRING_LENGTH").unwrap()),
+        max_collection_size: try_parse!(matches.value_of("MAX_COLLECTION_SIZE").unwrap()),
+        max_depth: try_parse!(matches.value_of("MAX_DEPTH").unwrap()),
+    };
+    // Blocked before any threads (including the one of the logger) are spawned,
+    // so that only `exit_on_signal` receives them
+    let signals = if matches.subcommand_name().is_none() {
+        Some(block_exit_signals())
+    } else {
+        None
+    };
     let log_level = try_parse!(matches.value_of("LOG_LEVEL").unwrap());
     let logger = track_try_unwrap!(
         TerminalLoggerBuilder::new()
@@ -79,67 +834,469 @@ fn main() {
             .build()
     );
 
-    let mut threads = Vec::new();
-    for (port, protocol) in [
-        (compact_thrift_port, Protocol::Compact),
-        (binary_thrift_port, Protocol::Binary),
+    let mut formatter = new_formatter(&matches, format);
+    // Only the standard output is colored (as by `add_sink`)
+    let colors = match matches.value_of("COLOR").unwrap() {
+        _ if matches.is_present("OUTPUT_FILE") => false,
+        "always" => true,
+        "never" => false,
+        _ => env::var_os("NO_COLOR").is_none() && unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1,
+    };
+    let colors = if colors {
+        let slow_threshold: u64 = try_parse!(matches.value_of("SLOW_THRESHOLD").unwrap());
+        let mut colors = Colors::new();
+        colors.slow_threshold(Duration::from_millis(slow_threshold));
+        formatter.colors(colors.clone());
+        Some(colors)
+    } else {
+        None
+    };
+    // Only the server (which `soak` runs too) limits its output
+    let limit = ["count", "max-spans-per-sec"]
+        .iter()
+        .find(|&&name| matches.is_present(name.to_uppercase().replace('-', "_")));
+    if let (Some(limit), Some(name)) = (limit, matches.subcommand_name()) {
+        if name != "soak" {
+            let message = format!("The argument '--{} <N>' cannot be used with '{}'", limit, name);
+            clap::Error::with_description(&message, clap::ErrorKind::ArgumentConflict).exit();
+        }
+    }
+    let sink = [
+        ("parquet", "FILE"),
+        ("sqlite", "FILE"),
+        ("output-file", "FILE"),
+        ("output", "SINK"),
     ].iter()
-        .cloned()
-    {
-        let addr: SocketAddr = try_parse!(format!("0.0.0.0:{}", port));
-        let socket = track_try_unwrap!(UdpSocket::bind(addr).map_err(Failure::from_error));
-        let logger = logger.new(o!("port" => port, "thrift_protocol" => format!("{:?}", protocol)));
-        info!(logger, "UDP server started");
-
-        let thread = thread::spawn(move || {
-            let mut buf = vec![0; udp_buffer_size];
-            loop {
-                let (recv_size, peer) =
-                    track_try_unwrap!(socket.recv_from(&mut buf).map_err(Failure::from_error));
-                debug!(logger, "Received {} bytes from {}", recv_size, peer);
-                let mut bytes = &buf[..recv_size];
-                match track!(EmitBatchNotification::decode(bytes, protocol)) {
-                    Err(e) => {
-                        error!(logger, "Received malformed or unknown message: {}", e);
-                        debug!(logger, "Bytes: {:?}", bytes);
-                    }
-                    Ok(message) => {
-                        let stdout = io::stdout();
-                        let mut stdout = stdout.lock();
-                        match format {
-                            Format::Raw => {
-                                track_try_unwrap!(
-                                    io::copy(&mut bytes, &mut stdout).map_err(Failure::from_error)
-                                );
-                            }
-                            Format::Json => {
-                                let json = track_try_unwrap!(serdeconv::to_json_string(&message));
-                                track_try_unwrap!(
-                                    writeln!(stdout, "{}", json).map_err(Failure::from_error)
-                                );
-                            }
-                            Format::JsonPretty => {
-                                let json =
-                                    track_try_unwrap!(serdeconv::to_json_string_pretty(&message));
-                                track_try_unwrap!(
-                                    writeln!(stdout, "{}", json).map_err(Failure::from_error)
-                                );
-                            }
-                        }
-                    }
-                }
-            }
+        .find(|&&(name, _)| matches.is_present(name.to_uppercase().replace('-', "_")));
+    if let Some(&(sink, value_name)) = sink {
+        if let Some(name) = matches.subcommand_name() {
+            let message = format!(
+                "The argument '--{} <{}>' cannot be used with '{}'",
+                sink, value_name, name
+            );
+            clap::Error::with_description(&message, clap::ErrorKind::ArgumentConflict).exit();
+        }
+    } else if !["replay", "soak"].contains(&matches.subcommand_name().unwrap_or("")) {
+        // Written once here, since the formatter only formats each batch
+        let mut header = Vec::new();
+        formatter.write_header(&mut header);
+        track_try_unwrap!(io::stdout().write_all(&header).map_err(Failure::from_error));
+    }
+    let span_filter = span_filter(&matches);
+    let mut builder = ServerBuilder::new();
+    builder
+        .bind_address(bind_address)
+        .compact_thrift_port(compact_thrift_port)
+        .binary_thrift_port(binary_thrift_port)
+        .udp_buffer_size(udp_buffer_size)
+        .recv_threads(recv_threads)
+        .sockets_per_port(sockets_per_port)
+        .io_uring_depth(io_uring_depth)
+        .decode_workers(decode_workers)
+        .queue_capacity(queue_size)
+        .overflow_policy(overflow_policy)
+        .flush_interval(Duration::from_millis(flush_interval))
+        .flush_lines(try_parse!(matches.value_of("FLUSH_LINES").unwrap()))
+        .decode_mode(decode_mode)
+        .decode_limits(decode_limits)
+        .preallocate(matches.is_present("PREALLOCATE"))
+        .warmup(matches.is_present("WARMUP"))
+        .span_filter(span_filter.clone())
+        .logger(logger);
+    if matches.is_present("NO_COMPACT") {
+        builder.remove_listeners(&Protocol::Compact);
+    }
+    if matches.is_present("NO_BINARY") {
+        builder.remove_listeners(&Protocol::Binary);
+    }
+    if matches.is_present("ZIPKIN_THRIFT") {
+        builder.listener(zipkin_thrift_port, ZipkinThrift);
+    }
+    for &(protocol, port) in &listens {
+        match protocol {
+            "compact" => builder.extra_listener(port, Protocol::Compact),
+            "binary" => builder.extra_listener(port, Protocol::Binary),
+            _ => builder.extra_listener(port, ZipkinThrift),
+        };
+    }
+    if let Some(size) = matches.value_of("SOCKET_BUFFER_SIZE") {
+        builder.socket_recv_buffer_size(try_parse!(size));
+    }
+    if let Some(cores) = matches.value_of("PIN_CORES") {
+        builder.pin_cores(cores.split(',').map(|c| try_parse!(c.trim())).collect());
+    }
+    if let Some(path) = matches.value_of("SPILL_FILE") {
+        let max_size: u64 = try_parse!(matches.value_of("SPILL_MAX_SIZE").unwrap());
+        builder.spill_file(path, max_size);
+    }
+    if let Some(path) = matches.value_of("DUMP_DIR") {
+        builder.dump_dir(path);
+    }
+    if let Some(rate) = matches.value_of("MAX_SPANS_PER_SEC") {
+        builder.max_spans_per_sec(try_parse!(rate));
+    }
+    if let Some(count) = matches.value_of("COUNT") {
+        if matches.is_present("COUNT_BATCHES") {
+            builder.max_batches(try_parse!(count));
+        } else {
+            builder.max_spans(try_parse!(count));
+        }
+    }
+    if let Some(port) = matches.value_of("COMPACT_TCP_PORT") {
+        builder.tcp_listener(try_parse!(port), Protocol::Compact);
+    }
+    if let Some(port) = matches.value_of("BINARY_TCP_PORT") {
+        builder.tcp_listener(try_parse!(port), Protocol::Binary);
+    }
+    if let Some(path) = matches.value_of("UNIX_SOCKET") {
+        builder.unix_socket(path, Protocol::Compact);
+    }
+    if let Some(port) = matches.value_of("COLLECTOR_HTTP_PORT") {
+        builder.http_endpoint(try_parse!(port), "/api/traces", CollectorThrift);
+    }
+    if let Some(port) = matches.value_of("OTLP_HTTP_PORT") {
+        builder.http_endpoint(try_parse!(port), "/v1/traces", Otlp);
+    }
+    if let Some(port) = matches.value_of("ZIPKIN_HTTP_PORT") {
+        builder.http_endpoint(try_parse!(port), "/api/v2/spans", ZipkinV2);
+    }
+    if let Some(port) = matches.value_of("JSON_HTTP_PORT") {
+        builder.http_endpoint(try_parse!(port), "/spans", JsonBatch);
+    }
+    if let Some(matches) = matches.subcommand_matches("decode") {
+        let decode = Decode {
+            protocol: match matches.value_of("PROTOCOL").unwrap() {
+                "compact" => InputProtocol::Thrift(Protocol::Compact),
+                "binary" => InputProtocol::Thrift(Protocol::Binary),
+                "kafka-span" => InputProtocol::KafkaSpan,
+                _ => InputProtocol::Auto,
+            },
+            decode_mode,
+            decode_limits,
+            filter: span_filter.clone(),
+        };
+        let inputs = matches
+            .values_of("FILE")
+            .map(|files| files.collect())
+            .unwrap_or_else(Vec::new);
+        track_try_unwrap!(decode.run(&inputs, &formatter));
+        return;
+    }
+    let mut pcap = Pcap {
+        decode: Decode {
+            protocol: InputProtocol::Auto,
+            decode_mode,
+            decode_limits,
+            filter: span_filter.clone(),
+        },
+        ports: Vec::new(),
+    };
+    if !matches.is_present("NO_COMPACT") {
+        pcap.ports.push((compact_thrift_port, Box::new(Protocol::Compact)));
+    }
+    if !matches.is_present("NO_BINARY") {
+        pcap.ports.push((binary_thrift_port, Box::new(Protocol::Binary)));
+    }
+    if matches.is_present("ZIPKIN_THRIFT") {
+        pcap.ports.push((zipkin_thrift_port, Box::new(ZipkinThrift)));
+    }
+    for &(protocol, port) in &listens {
+        let protocol: Box<dyn WireProtocol> = match protocol {
+            "compact" => Box::new(Protocol::Compact),
+            "binary" => Box::new(Protocol::Binary),
+            _ => Box::new(ZipkinThrift),
+        };
+        pcap.ports.push((port, protocol));
+    }
+    if let Some(matches) = matches.subcommand_matches("pcap") {
+        track_try_unwrap!(pcap.run(matches.value_of("FILE").unwrap(), &formatter));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("capture") {
+        let capture = Capture {
+            pcap,
+            interface: matches.value_of("INTERFACE").unwrap().to_owned(),
+        };
+        track_try_unwrap!(capture.run(&formatter));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("replay") {
+        let replay = Replay {
+            decode: Decode {
+                protocol: match matches.value_of("PROTOCOL").unwrap() {
+                    "compact" => InputProtocol::Thrift(Protocol::Compact),
+                    "binary" => InputProtocol::Thrift(Protocol::Binary),
+                    _ => InputProtocol::Auto,
+                },
+                decode_mode,
+                decode_limits,
+                filter: SpanFilter::new(),
+            },
+            target: matches.value_of("TARGET").unwrap().to_owned(),
+            rate: matches.value_of("RATE").map(|rate| try_parse!(rate)),
+        };
+        let inputs = matches
+            .values_of("FILE")
+            .map(|files| files.collect())
+            .unwrap_or_else(Vec::new);
+        track_try_unwrap!(replay.run(&inputs));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("tail") {
+        let tail = Tail {
+            follow: matches.is_present("FOLLOW"),
+            filter: span_filter,
+        };
+        track_try_unwrap!(tail.run(matches.value_of("FILE").unwrap(), &formatter));
+        return;
+    }
+    if let Some(soak_matches) = matches.subcommand_matches("soak") {
+        if matches.is_present("NO_COMPACT") {
+            // The generated traffic is sent to the compact protocol listener
+            let message = "The argument '--no-compact' cannot be used with 'soak'";
+            clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict).exit();
+        }
+        let matches = soak_matches;
+        let duration: HumanDuration = try_parse!(matches.value_of("DURATION").unwrap());
+        let soak = Soak {
+            spans_per_sec: try_parse!(matches.value_of("SPANS_PER_SEC").unwrap()),
+            spans_per_batch: try_parse!(matches.value_of("SPANS_PER_BATCH").unwrap()),
+            duration: duration.0,
+        };
+        track_try_unwrap!(soak.run(&mut builder, formatter));
+        return;
+    }
+    let server = if let Some(path) = matches.value_of("PARQUET") {
+        let mut handler = track_try_unwrap!(ParquetHandler::create(path));
+        handler.row_group_size(try_parse!(matches.value_of("PARQUET_ROW_GROUP_SIZE").unwrap()));
+        if let Some(columns) = matches.value_of("COLUMNS") {
+            handler.columns(columns.split(',').map(|c| try_parse!(c.trim())).collect());
+        }
+        track_try_unwrap!(builder.build(handler))
+    } else if let Some(path) = matches.value_of("SQLITE") {
+        build_sqlite_server(&builder, path)
+    } else if let Some(sinks) = matches.values_of("OUTPUT") {
+        let mut tee = Tee::new();
+        for sink in sinks {
+            add_sink(&mut tee, &matches, &try_parse!(sink), colors.as_ref());
+        }
+        track_try_unwrap!(builder.build(tee))
+    } else if format == Format::Tree || format == Format::Folded {
+        // Joins the spans of each trace received in different batches
+        let handler = tree_handler(&matches, output(&matches, &formatter), format, colors);
+        track_try_unwrap!(builder.build(handler))
+    } else {
+        let output = output(&matches, &formatter);
+        track_try_unwrap!(builder.build_with_formatter(formatter, output))
+    };
+    if let Some(signals) = signals {
+        exit_on_signal(&server, signals);
+    }
+    if matches.is_present("COUNT") {
+        // The listeners keep running, and are terminated by the exit
+        track_try_unwrap!(server.join_output());
+    } else {
+        track_try_unwrap!(server.join());
+    }
+}
+
+/// Blocks SIGINT and SIGTERM in the current thread (and the threads spawned by it later).
+fn block_exit_signals() -> libc::sigset_t {
+    unsafe {
+        let mut signals = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+        signals
+    }
+}
+
+/// Spawns a thread which stops the output of `server` (writing and flushing what has been
+/// received) and exits when one of the blocked `signals` is received.
+///
+/// The second signal exits immediately, in case the output is stuck.
+fn exit_on_signal(server: &Server, signals: libc::sigset_t) {
+    let handle = server.shutdown_handle();
+    thread::spawn(move || {
+        let mut signal = 0;
+        unsafe { libc::sigwait(&signals, &mut signal) };
+        thread::spawn(move || {
+            handle.shutdown();
+            process::exit(128 + signal);
         });
-        threads.push(thread);
+        unsafe { libc::sigwait(&signals, &mut signal) };
+        process::exit(128 + signal);
+    });
+}
+
+/// Makes a formatter in `format` with the options of the formatters.
+fn new_formatter(matches: &clap::ArgMatches, format: Format) -> Formatter {
+    let mut formatter = Formatter::new(format);
+    formatter.include_peer(matches.is_present("INCLUDE_PEER"));
+    formatter.include_unknown_fields(matches.is_present("UNKNOWN_FIELDS"));
+    formatter.hex_ids(matches.is_present("HEX_IDS"));
+    formatter.flat_tags(matches.is_present("FLAT_TAGS"));
+    formatter.typed_tags(matches.is_present("TYPED_TAGS"));
+    if let Some(time_format) = matches.value_of("TIME_FORMAT") {
+        formatter.time_format(try_parse!(time_format));
+    }
+    formatter.duration_unit(try_parse!(matches.value_of("DURATION_UNIT").unwrap()));
+    formatter.length_prefix(matches.is_present("LENGTH_PREFIX"));
+    formatter.validate(matches.is_present("VALIDATE") || matches.is_present("STRICT"));
+    if let Some(columns) = matches.value_of("COLUMNS") {
+        formatter.columns(columns.split(',').map(|c| try_parse!(c.trim())).collect());
+    }
+    if let Some(fields) = matches.value_of("FIELDS") {
+        formatter.fields(fields.split(',').map(|f| f.trim().to_owned()).collect());
+    }
+    if let Some(filter) = matches.value_of("JQ") {
+        formatter.filter(try_parse!(filter));
+    }
+    if let Some(template) = matches.value_of("TEMPLATE") {
+        formatter.template(try_parse!(template));
+    }
+    formatter
+}
+
+/// Makes the `SpanFilter` of the filtering options.
+fn span_filter(matches: &clap::ArgMatches) -> SpanFilter {
+    let mut filter = SpanFilter::new();
+    for service in matches.values_of("SERVICE").into_iter().flatten() {
+        filter.service(service);
+    }
+    for id in matches.values_of("TRACE_ID").into_iter().flatten() {
+        filter.trace_id(try_parse!(id));
+    }
+    for condition in matches.values_of("TAG").into_iter().flatten() {
+        filter.tag(try_parse!(condition));
+    }
+    if let Some(duration) = matches.value_of("MIN_DURATION") {
+        let duration: HumanDuration = try_parse!(duration);
+        filter.min_duration(duration.0);
+    }
+    if let Some(rate) = matches.value_of("SAMPLE_OUTPUT") {
+        let rate: f64 = try_parse!(rate);
+        if !(0.0..=1.0).contains(&rate) {
+            let message = format!("The sampling rate must be from 0.0 to 1.0: {}", rate);
+            clap::Error::with_description(&message, clap::ErrorKind::InvalidValue).exit();
+        }
+        filter.sample(rate);
+    }
+    if matches.is_present("ERRORS_ONLY") {
+        let conditions = match matches.values_of("ERROR_TAG") {
+            Some(conditions) => conditions.map(|c| try_parse!(c)).collect(),
+            None => SpanFilter::default_error_tags(),
+        };
+        filter.errors_only(conditions);
+    }
+    filter
+}
+
+/// Makes a `TreeHandler` writing the traces in `format` (`Format::Tree` or `Format::Folded`).
+fn tree_handler(
+    matches: &clap::ArgMatches,
+    writer: Box<dyn Write + Send>,
+    format: Format,
+    colors: Option<Colors>,
+) -> TreeHandler {
+    let mut handler = TreeHandler::new(writer);
+    handler.folded(format == Format::Folded);
+    let wait: u64 = try_parse!(matches.value_of("TREE_WAIT").unwrap());
+    handler.wait(Duration::from_millis(wait));
+    if let Some(colors) = colors {
+        handler.colors(colors);
     }
-    for t in threads {
-        let _ = t.join();
+    handler
+}
+
+/// Returns `--output-file` (with the header of the output) if given, or the standard output.
+fn output(matches: &clap::ArgMatches, formatter: &Formatter) -> Box<dyn Write + Send> {
+    let path = match matches.value_of("OUTPUT_FILE") {
+        None => return Box::new(io::stdout()),
+        Some(path) => path,
+    };
+    let mut header = Vec::new();
+    formatter.write_header(&mut header);
+    Box::new(rotating_file(matches, path, header))
+}
+
+/// Opens `path` with the rotation options.
+fn rotating_file<P: AsRef<Path>>(
+    matches: &clap::ArgMatches,
+    path: P,
+    header: Vec<u8>,
+) -> RotatingFile {
+    let mut file = track_try_unwrap!(RotatingFile::open(path));
+    if let Some(size) = matches.value_of("ROTATE_SIZE") {
+        file.max_size(try_parse!(size));
     }
+    if let Some(interval) = matches.value_of("ROTATE_INTERVAL") {
+        file.interval(Duration::from_secs(try_parse!(interval)));
+    }
+    file.keep(try_parse!(matches.value_of("ROTATE_KEEP").unwrap()));
+    file.header(header);
+    file
 }
 
-#[derive(Clone, Copy)]
-enum Format {
-    Raw,
-    Json,
-    JsonPretty,
+/// Adds the handler of `sink` (given by `--output`) to `tee`.
+///
+/// Only the standard output is colored.
+fn add_sink(tee: &mut Tee, matches: &clap::ArgMatches, sink: &Sink, colors: Option<&Colors>) {
+    let mut formatter = new_formatter(matches, sink.format);
+    let colors = match sink.destination {
+        sink::Destination::Stdout => colors.cloned(),
+        _ => None,
+    };
+    if let Some(ref colors) = colors {
+        formatter.colors(colors.clone());
+    }
+    let mut header = Vec::new();
+    formatter.write_header(&mut header);
+    let writer: Box<dyn Write + Send> = match sink.destination {
+        sink::Destination::Stdout => {
+            let mut writer = BufWriter::new(io::stdout());
+            track_try_unwrap!(writer.write_all(&header).map_err(Failure::from_error));
+            Box::new(writer)
+        }
+        sink::Destination::Stderr => {
+            let mut writer = io::stderr();
+            track_try_unwrap!(writer.write_all(&header).map_err(Failure::from_error));
+            Box::new(writer)
+        }
+        sink::Destination::File(ref path) => Box::new(rotating_file(matches, path, header)),
+        sink::Destination::Udp(ref addr) => Box::new(track_try_unwrap!(UdpWriter::new(addr))),
+    };
+    if sink.format == Format::Tree || sink.format == Format::Folded {
+        tee.push(tree_handler(matches, writer, sink.format, colors));
+    } else {
+        tee.push(WriterHandler::new(formatter, writer));
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn build_sqlite_server(builder: &ServerBuilder, path: &str) -> Server {
+    let handler = track_try_unwrap!(SqliteHandler::open(path));
+    track_try_unwrap!(builder.build(handler))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn build_sqlite_server(_builder: &ServerBuilder, _path: &str) -> Server {
+    let message = "The argument '--sqlite <FILE>' requires installing jaegercat with \
+                   the sqlite feature";
+    clap::Error::with_description(message, clap::ErrorKind::InvalidValue).exit()
+}
+
+/// Parses a `--listen` value (e.g., `compact:16831`) into the protocol name and the port.
+fn parse_listen(value: &str) -> jaegercat::Result<(&str, u16)> {
+    let i = track_assert_some!(value.rfind(':'), Failed, "No port: {}", value);
+    let protocol = &value[..i];
+    track_assert!(
+        ["compact", "binary", "zipkin-thrift"].contains(&protocol),
+        Failed,
+        "Unknown protocol: {}",
+        value
+    );
+    let port = track!(value[i + 1..].parse().map_err(Failure::from_error), "{}", value)?;
+    Ok((protocol, port))
 }
@@ -0,0 +1,879 @@
+//! Span data model of [jaeger.thrift].
+//!
+//! [jaeger.thrift]: https://github.com/jaegertracing/jaeger-idl/blob/master/thrift/jaeger.thrift
+use std::collections::BTreeMap;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+#[cfg(feature = "local-time")]
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+#[cfg(feature = "std")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+#[cfg(feature = "std")]
+use serde::de::Error as DeError;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+/// A batch of spans reported by a process.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Batch {
+    /// The process which emitted the spans.
+    pub process: Process,
+
+    /// The reported spans.
+    pub spans: Vec<Span>,
+
+    /// The sequence number of the batch, incremented by the client for every batch it emits.
+    #[cfg_attr(feature = "std", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub seq_no: Option<i64>,
+
+    /// The statistics of the spans dropped by the client.
+    #[cfg_attr(feature = "std", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub stats: Option<ClientStats>,
+
+    /// The fields which are not defined in the schema.
+    ///
+    /// Note that these are not deserialized, because their Thrift types cannot be recovered
+    /// from JSON.
+    #[cfg_attr(
+        feature = "std",
+        serde(skip_serializing_if = "BTreeMap::is_empty", skip_deserializing)
+    )]
+    pub unknown_fields: UnknownFields,
+}
+impl Batch {
+    /// Returns `true` if the batch or any of its descendants has unknown fields.
+    pub fn has_unknown_fields(&self) -> bool {
+        fn tags_have(tags: &[Tag]) -> bool {
+            tags.iter().any(|t| !t.unknown_fields.is_empty())
+        }
+        let span_has = |s: &Span| {
+            !s.unknown_fields.is_empty() || tags_have(&s.tags)
+                || s.references.iter().any(|r| !r.unknown_fields.is_empty())
+                || s.logs
+                    .iter()
+                    .any(|l| !l.unknown_fields.is_empty() || tags_have(&l.fields))
+        };
+        !self.unknown_fields.is_empty() || !self.process.unknown_fields.is_empty()
+            || tags_have(&self.process.tags) || self.spans.iter().any(span_has)
+            || self.stats.iter().any(|s| !s.unknown_fields.is_empty())
+    }
+
+    /// Removes the unknown fields of the batch and all of its descendants.
+    pub fn clear_unknown_fields(&mut self) {
+        fn clear_tags(tags: &mut [Tag]) {
+            for t in tags {
+                t.unknown_fields.clear();
+            }
+        }
+        self.unknown_fields.clear();
+        if let Some(ref mut stats) = self.stats {
+            stats.unknown_fields.clear();
+        }
+        self.process.unknown_fields.clear();
+        clear_tags(&mut self.process.tags);
+        for s in &mut self.spans {
+            s.unknown_fields.clear();
+            clear_tags(&mut s.tags);
+            for r in &mut s.references {
+                r.unknown_fields.clear();
+            }
+            for l in &mut s.logs {
+                l.unknown_fields.clear();
+                clear_tags(&mut l.fields);
+            }
+        }
+    }
+}
+
+/// The statistics of the spans which a client failed to report.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct ClientStats {
+    /// The number of the spans dropped because the queue of the client was full.
+    pub full_queue_dropped_spans: i64,
+
+    /// The number of the spans dropped because they were too large to be emitted.
+    pub too_large_dropped_spans: i64,
+
+    /// The number of the spans which the client failed to emit.
+    pub failed_to_emit_spans: i64,
+
+    /// The fields which are not defined in the schema.
+    ///
+    /// Note that these are not deserialized, because their Thrift types cannot be recovered
+    /// from JSON.
+    #[cfg_attr(
+        feature = "std",
+        serde(skip_serializing_if = "BTreeMap::is_empty", skip_deserializing)
+    )]
+    pub unknown_fields: UnknownFields,
+}
+
+/// A traced process.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Process {
+    /// The name of the service.
+    pub service_name: String,
+
+    /// The tags associated with the process.
+    #[cfg_attr(
+        feature = "std",
+        serde(default, serialize_with = "serialize_tags", deserialize_with = "deserialize_tags")
+    )]
+    pub tags: Vec<Tag>,
+
+    /// The fields which are not defined in the schema.
+    ///
+    /// Note that these are not deserialized, because their Thrift types cannot be recovered
+    /// from JSON.
+    #[cfg_attr(
+        feature = "std",
+        serde(skip_serializing_if = "BTreeMap::is_empty", skip_deserializing)
+    )]
+    pub unknown_fields: UnknownFields,
+}
+
+/// A span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// The identifier of the trace which the span belongs to.
+    pub trace_id: TraceId,
+
+    /// The identifier of the span.
+    pub span_id: SpanId,
+
+    /// The identifier of the parent span.
+    ///
+    /// `None` if the span is a root span.
+    pub parent_span_id: Option<SpanId>,
+
+    /// The name of the operation.
+    pub operation_name: String,
+
+    /// The references to other spans.
+    pub references: Vec<SpanRef>,
+
+    /// The flags of the span (e.g., sampled or debug).
+    pub flags: i32,
+
+    /// The start time of the span in microseconds since the UNIX epoch.
+    pub start_time: i64,
+
+    /// The duration of the span in microseconds.
+    pub duration: i64,
+
+    /// The tags of the span.
+    pub tags: Vec<Tag>,
+
+    /// The logs of the span.
+    pub logs: Vec<Log>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+#[cfg(feature = "std")]
+impl Serialize for Span {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SpanRepr {
+            trace_id: &self.trace_id,
+            span_id: &self.span_id,
+            parent_span_id: self.parent_span_id.as_ref(),
+            operation_name: &self.operation_name,
+            references: &self.references,
+            flags: self.flags,
+            start_datetime: Datetime(self.start_time),
+            start_unixtime: us_to_seconds(self.start_time),
+            duration: us_to_seconds(self.duration),
+            tags: &self.tags,
+            logs: &self.logs,
+            unknown_fields: &self.unknown_fields,
+        }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Span {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OwnedSpanRepr::deserialize(deserializer)?;
+        Ok(Span {
+            trace_id: repr.trace_id,
+            span_id: repr.span_id,
+            parent_span_id: repr.parent_span_id,
+            operation_name: repr.operation_name,
+            references: repr.references,
+            flags: repr.flags,
+            start_time: seconds_to_us(repr.start_unixtime),
+            duration: seconds_to_us(repr.duration),
+            tags: repr.tags,
+            logs: repr.logs,
+            unknown_fields: UnknownFields::new(),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Serialize)]
+struct SpanRepr<'a> {
+    trace_id: &'a TraceId,
+    span_id: &'a SpanId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent_span_id: Option<&'a SpanId>,
+    operation_name: &'a str,
+    #[serde(skip_serializing_if = "is_empty")]
+    references: &'a [SpanRef],
+    flags: i32,
+    start_datetime: Datetime,
+    start_unixtime: f64,
+    duration: f64, // seconds
+    #[serde(skip_serializing_if = "is_empty", serialize_with = "serialize_tags")]
+    tags: &'a [Tag],
+    #[serde(skip_serializing_if = "is_empty")]
+    logs: &'a [Log],
+    #[serde(skip_serializing_if = "is_empty_map")]
+    unknown_fields: &'a UnknownFields,
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+struct OwnedSpanRepr {
+    trace_id: TraceId,
+    span_id: SpanId,
+    #[serde(default)]
+    parent_span_id: Option<SpanId>,
+    operation_name: String,
+    #[serde(default)]
+    references: Vec<SpanRef>,
+    flags: i32,
+    start_unixtime: f64,
+    duration: f64,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    tags: Vec<Tag>,
+    #[serde(default)]
+    logs: Vec<Log>,
+}
+
+/// The identifier of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TraceId {
+    /// The higher 64 bits of the identifier.
+    pub high: i64,
+
+    /// The lower 64 bits of the identifier.
+    pub low: i64,
+}
+impl fmt::Display for TraceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.high == 0 {
+            write!(f, "0x{:x}", self.low)
+        } else {
+            write!(f, "0x{:x}{:016x}", self.high, self.low)
+        }
+    }
+}
+impl FromStr for TraceId {
+    type Err = ParseIntError;
+
+    /// Parses a hexadecimal string in the format of `Display` (the `0x` prefix is optional).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = trim_hex_prefix(s);
+        let (high, low) = if s.len() > 16 && s.is_char_boundary(s.len() - 16) {
+            s.split_at(s.len() - 16)
+        } else {
+            ("0", s)
+        };
+        Ok(TraceId {
+            high: u64::from_str_radix(high, 16)? as i64,
+            low: u64::from_str_radix(low, 16)? as i64,
+        })
+    }
+}
+#[cfg(feature = "std")]
+impl Serialize for TraceId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for TraceId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// The identifier of a span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpanId(pub i64);
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:x}", self.0)
+    }
+}
+impl FromStr for SpanId {
+    type Err = ParseIntError;
+
+    /// Parses a hexadecimal string in the format of `Display` (the `0x` prefix is optional).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(trim_hex_prefix(s), 16).map(|id| SpanId(id as i64))
+    }
+}
+#[cfg(feature = "std")]
+impl Serialize for SpanId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for SpanId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_from_str(deserializer)
+    }
+}
+
+/// An identifier formatted as Jaeger UI shows it, in zero-padded lowercase hexadecimal digits
+/// without the `0x` prefix.
+///
+/// Trace IDs are formatted in 32 digits (the higher 64 bits followed by the lower 64 bits),
+/// or in 16 digits if their higher 64 bits are zero, and span IDs are formatted in 16 digits.
+/// The `FromStr` implementations of the identifiers parse these too.
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::model::{Hex, SpanId, TraceId};
+///
+/// let id = TraceId { high: 0x154050ce43d48b61, low: 0x2ae64ad7cd070e8e };
+/// assert_eq!(Hex(id).to_string(), "154050ce43d48b612ae64ad7cd070e8e");
+/// assert_eq!(Hex(TraceId { high: 1, low: 2 }).to_string(), "00000000000000010000000000000002");
+/// assert_eq!(Hex(TraceId { high: 0, low: 2 }).to_string(), "0000000000000002");
+/// assert_eq!(Hex(SpanId(0x7a5fd207c065420)).to_string(), "07a5fd207c065420");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hex<T>(pub T);
+impl fmt::Display for Hex<TraceId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.high == 0 {
+            write!(f, "{:016x}", self.0.low)
+        } else {
+            write!(f, "{:016x}{:016x}", self.0.high, self.0.low)
+        }
+    }
+}
+impl fmt::Display for Hex<SpanId> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", (self.0).0)
+    }
+}
+
+/// A reference from a span to another span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanRef {
+    /// The type of the reference.
+    pub ref_type: SpanRefType,
+
+    /// The identifier of the trace which the referenced span belongs to.
+    pub trace_id: TraceId,
+
+    /// The identifier of the referenced span.
+    pub span_id: SpanId,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+#[cfg(feature = "std")]
+impl Serialize for SpanRef {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ids = SpanRefRepr {
+            trace_id: &self.trace_id,
+            span_id: &self.span_id,
+            unknown_fields: &self.unknown_fields,
+        };
+        match self.ref_type {
+            SpanRefType::ChildOf => {
+                serializer.serialize_newtype_variant("SpanRef", 0, "ChildOf", &ids)
+            }
+            SpanRefType::FollowsFrom => {
+                serializer.serialize_newtype_variant("SpanRef", 1, "FollowsFrom", &ids)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for SpanRef {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (ref_type, ids) = match OwnedSpanRefRepr::deserialize(deserializer)? {
+            OwnedSpanRefRepr::ChildOf(ids) => (SpanRefType::ChildOf, ids),
+            OwnedSpanRefRepr::FollowsFrom(ids) => (SpanRefType::FollowsFrom, ids),
+        };
+        Ok(SpanRef {
+            ref_type,
+            trace_id: ids.trace_id,
+            span_id: ids.span_id,
+            unknown_fields: UnknownFields::new(),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Serialize)]
+struct SpanRefRepr<'a> {
+    trace_id: &'a TraceId,
+    span_id: &'a SpanId,
+    #[serde(skip_serializing_if = "is_empty_map")]
+    unknown_fields: &'a UnknownFields,
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+enum OwnedSpanRefRepr {
+    ChildOf(OwnedSpanRefIds),
+    FollowsFrom(OwnedSpanRefIds),
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+struct OwnedSpanRefIds {
+    trace_id: TraceId,
+    span_id: SpanId,
+}
+
+/// The type of a span reference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub enum SpanRefType {
+    /// `CHILD_OF`.
+    ChildOf,
+
+    /// `FOLLOWS_FROM`.
+    FollowsFrom,
+}
+
+/// A log event of a span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Log {
+    /// The timestamp of the event in microseconds since the UNIX epoch.
+    pub timestamp: i64,
+
+    /// The fields of the event.
+    pub fields: Vec<Tag>,
+
+    /// The fields which are not defined in the schema.
+    pub unknown_fields: UnknownFields,
+}
+#[cfg(feature = "std")]
+impl Serialize for Log {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        LogRepr {
+            datetime: Datetime(self.timestamp),
+            unixtime: us_to_seconds(self.timestamp),
+            fields: &self.fields,
+            unknown_fields: &self.unknown_fields,
+        }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Log {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OwnedLogRepr::deserialize(deserializer)?;
+        Ok(Log {
+            timestamp: seconds_to_us(repr.unixtime),
+            fields: repr.fields,
+            unknown_fields: UnknownFields::new(),
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Serialize)]
+struct LogRepr<'a> {
+    datetime: Datetime,
+    unixtime: f64,
+    #[serde(serialize_with = "serialize_tags")]
+    fields: &'a [Tag],
+    #[serde(skip_serializing_if = "is_empty_map")]
+    unknown_fields: &'a UnknownFields,
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+struct OwnedLogRepr {
+    unixtime: f64,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    fields: Vec<Tag>,
+}
+
+/// A key-value pair.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+pub struct Tag {
+    /// The key of the tag.
+    pub key: String,
+
+    /// The value of the tag.
+    pub value: TagValue,
+
+    /// The fields which are not defined in the schema.
+    ///
+    /// Note that these are not serialized, because tags are serialized as key-value maps.
+    #[cfg_attr(feature = "std", serde(skip))]
+    pub unknown_fields: UnknownFields,
+}
+
+impl Tag {
+    /// Makes a new `Tag` instance.
+    pub fn new<K, V>(key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<TagValue>,
+    {
+        Tag {
+            key: key.into(),
+            value: value.into(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+}
+
+/// The value of a tag.
+///
+/// This is serialized as the plain value (e.g., `42`), where binary values are arrays of bytes.
+/// It is deserialized from a plain value, or from an object of its type and value
+/// (e.g., `{"type": "long", "value": 42}`, which `JsonWriter::typed_tags` writes).
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    /// `STRING`.
+    Str(String),
+
+    /// `BOOL`.
+    Bool(bool),
+
+    /// `LONG`.
+    Long(i64),
+
+    /// `DOUBLE`.
+    Double(f64),
+
+    /// `BINARY`.
+    Binary(Vec<u8>),
+}
+#[cfg(feature = "std")]
+impl Serialize for TagValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            TagValue::Str(ref v) => v.serialize(serializer),
+            TagValue::Bool(v) => v.serialize(serializer),
+            TagValue::Long(v) => v.serialize(serializer),
+            TagValue::Double(v) => v.serialize(serializer),
+            TagValue::Binary(ref v) => v.serialize(serializer),
+        }
+    }
+}
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for TagValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use self::OwnedTagValueRepr as Repr;
+        use self::OwnedTypedTagValueRepr as Typed;
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Str(v) | Repr::Typed(Typed::Str(v)) => TagValue::Str(v),
+            Repr::Bool(v) | Repr::Typed(Typed::Bool(v)) => TagValue::Bool(v),
+            Repr::Long(v) | Repr::Typed(Typed::Long(v)) => TagValue::Long(v),
+            Repr::Double(v) | Repr::Typed(Typed::Double(v)) => TagValue::Double(v),
+            Repr::Binary(v) | Repr::Typed(Typed::Binary(v)) => TagValue::Binary(v),
+        })
+    }
+}
+impl From<bool> for TagValue {
+    fn from(f: bool) -> Self {
+        TagValue::Bool(f)
+    }
+}
+impl From<i64> for TagValue {
+    fn from(f: i64) -> Self {
+        TagValue::Long(f)
+    }
+}
+impl From<i32> for TagValue {
+    fn from(f: i32) -> Self {
+        TagValue::Long(i64::from(f))
+    }
+}
+impl From<f64> for TagValue {
+    fn from(f: f64) -> Self {
+        TagValue::Double(f)
+    }
+}
+impl From<String> for TagValue {
+    fn from(f: String) -> Self {
+        TagValue::Str(f)
+    }
+}
+impl<'a> From<&'a str> for TagValue {
+    fn from(f: &'a str) -> Self {
+        TagValue::Str(f.to_owned())
+    }
+}
+impl From<Vec<u8>> for TagValue {
+    fn from(f: Vec<u8>) -> Self {
+        TagValue::Binary(f)
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OwnedTagValueRepr {
+    Str(String),
+    Bool(bool),
+    Long(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    Typed(OwnedTypedTagValueRepr),
+}
+
+#[cfg(feature = "std")]
+#[derive(Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+enum OwnedTypedTagValueRepr {
+    #[serde(rename = "string")]
+    Str(String),
+    Bool(bool),
+    Long(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+}
+
+/// Fields which are not defined in the schema, keyed by field ID.
+///
+/// `EmitBatchNotification::encode` encodes these fields along with the known ones.
+pub type UnknownFields = BTreeMap<i16, ThriftValue>;
+
+/// A Thrift value decoded without the knowledge of its schema.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize))]
+#[cfg_attr(feature = "std", serde(untagged))]
+pub enum ThriftValue {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Double(f64),
+    Binary(Vec<u8>),
+    Struct(BTreeMap<i16, ThriftValue>),
+    List(Vec<ThriftValue>),
+    Set(Vec<ThriftValue>),
+    Map(Vec<(ThriftValue, ThriftValue)>),
+}
+
+#[cfg(feature = "std")]
+/// Serializes tags as a map ordered by key.
+///
+/// If there are duplicate keys, the last one wins.
+fn serialize_tags<T, S>(tags: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: AsRef<[Tag]>,
+    S: Serializer,
+{
+    tags.as_ref()
+        .iter()
+        .map(|t| (&t.key, &t.value))
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+#[cfg(feature = "std")]
+/// Deserializes tags from a map.
+fn deserialize_tags<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Tag>, D::Error> {
+    let tags = BTreeMap::<String, TagValue>::deserialize(deserializer)?;
+    Ok(tags.into_iter()
+        .map(|(key, value)| Tag {
+            key,
+            value,
+            unknown_fields: UnknownFields::new(),
+        })
+        .collect())
+}
+
+#[cfg(feature = "std")]
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(D::Error::custom)
+}
+
+fn trim_hex_prefix(s: &str) -> &str {
+    s.strip_prefix("0x").unwrap_or(s)
+}
+
+#[cfg(feature = "std")]
+fn is_empty<T>(x: &&[T]) -> bool {
+    x.is_empty()
+}
+
+#[cfg(feature = "std")]
+fn is_empty_map(x: &&UnknownFields) -> bool {
+    x.is_empty()
+}
+
+#[cfg(feature = "std")]
+pub(crate) fn us_to_seconds(us: i64) -> f64 {
+    us as f64 / 1_000_000.0
+}
+
+#[cfg(feature = "std")]
+fn seconds_to_us(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0).round() as i64
+}
+
+/// Converts a timestamp in microseconds since the UNIX epoch to the local time,
+/// or returns `None` if it is out of the range of `chrono`.
+#[cfg(feature = "local-time")]
+fn local_datetime(us: i64) -> Option<DateTime<Local>> {
+    let secs = us.div_euclid(1_000_000);
+    let nsecs = (us.rem_euclid(1_000_000) * 1000) as u32;
+    let utc = NaiveDateTime::from_timestamp_opt(secs, nsecs)?;
+
+    // `chrono` panics if the offset of the local time zone moves the time out of the range
+    utc.checked_add_signed(Duration::days(1))?;
+    utc.checked_sub_signed(Duration::days(1))?;
+    Some(Local.from_utc_datetime(&utc))
+}
+
+/// A timestamp in microseconds since the UNIX epoch, formatted as `%Y-%m-%d %H:%M:%S`.
+///
+/// Timestamps out of the range of the calendar (e.g., sent by broken clients)
+/// are formatted as the number of microseconds.
+#[cfg(feature = "std")]
+pub(crate) struct Datetime(pub i64);
+#[cfg(feature = "local-time")]
+impl fmt::Display for Datetime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match local_datetime(self.0) {
+            Some(datetime) => write!(f, "{}", datetime.format("%Y-%m-%d %H:%M:%S")),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Formats the time in UTC, because the local time zone is unavailable without `chrono`.
+#[cfg(all(feature = "std", not(feature = "local-time")))]
+impl fmt::Display for Datetime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.0.div_euclid(1_000_000);
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            return write!(f, "{}", self.0);
+        }
+        let seconds_of_day = seconds.rem_euclid(86_400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day / 60 % 60,
+            seconds_of_day % 60
+        )
+    }
+}
+#[cfg(feature = "std")]
+impl Serialize for Datetime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A timestamp in microseconds since the UNIX epoch, formatted in RFC 3339
+/// with microseconds (e.g., `2018-01-31T14:24:18.654844+09:00`).
+///
+/// Like `Datetime`, timestamps out of range are formatted as the number of microseconds.
+#[cfg(feature = "server")]
+pub(crate) struct Rfc3339(pub i64);
+#[cfg(all(feature = "server", feature = "local-time"))]
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match local_datetime(self.0) {
+            Some(datetime) => write!(f, "{}", datetime.format("%Y-%m-%dT%H:%M:%S%.6f%:z")),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Formats the time in UTC, because the local time zone is unavailable without `chrono`.
+#[cfg(all(feature = "server", not(feature = "local-time")))]
+impl fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.0.div_euclid(1_000_000);
+        let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            return write!(f, "{}", self.0);
+        }
+        let seconds_of_day = seconds.rem_euclid(86_400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year,
+            month,
+            day,
+            seconds_of_day / 3600,
+            seconds_of_day / 60 % 60,
+            seconds_of_day % 60,
+            self.0.rem_euclid(1_000_000)
+        )
+    }
+}
+
+/// The range of the years formatted without `chrono`, which is the same as that of `chrono`.
+#[cfg(all(feature = "std", not(feature = "local-time")))]
+const MIN_YEAR: i64 = -262_143;
+#[cfg(all(feature = "std", not(feature = "local-time")))]
+const MAX_YEAR: i64 = 262_142;
+
+/// Converts days since the UNIX epoch to a date of the proleptic Gregorian calendar.
+///
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+#[cfg(all(feature = "std", not(feature = "local-time")))]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn datetime_works() {
+        // 1969-12-31 23:59:59 UTC (or the day before or after it in the local time zone)
+        assert_eq!(Datetime(-1).to_string().len(), 19);
+        assert_eq!(Datetime(-1_500_000).to_string().len(), 19);
+
+        // Out of range
+        assert_eq!(Datetime(i64::MIN).to_string(), i64::MIN.to_string());
+        assert_eq!(Datetime(i64::MAX).to_string(), i64::MAX.to_string());
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn rfc3339_works() {
+        assert!(Rfc3339(-1).to_string().starts_with("19"));
+        assert_eq!(Rfc3339(i64::MIN).to_string(), i64::MIN.to_string());
+    }
+}
@@ -0,0 +1,1379 @@
+//! Decoding of OpenTelemetry (OTLP) trace export requests into Jaeger batches,
+//! and encoding of Jaeger batches into the OTLP JSON encoding.
+//!
+//! `Otlp` decodes the bodies of `ExportTraceServiceRequest` messages sent to
+//! `POST /v1/traces` in both the protobuf and the JSON encodings,
+//! and maps the spans in the same way as the Jaeger collector does:
+//!
+//! - The `service.name` attribute of the resource becomes the service name of the process,
+//!   and the other attributes of the resource become the process tags.
+//! - Events become logs (with an `event` field holding the event name),
+//!   and links become `FOLLOWS_FROM` references.
+//! - The span kind, the status and the instrumentation scope become the `span.kind`,
+//!   `otel.status_code`, `otel.status_description`, `error`, `otel.scope.name` and
+//!   `otel.scope.version` tags.
+//! - Array and key-value list attributes become JSON strings.
+//!
+//! A batch holds the spans of a single process, so if a request contains multiple resources,
+//! the first one becomes the process, and the attributes of the others are added to
+//! the tags of their spans.
+//!
+//! `to_json` does the reverse mapping, and is used by `Format::OtlpJson`.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::otlp::Otlp;
+//! use jaegercat::protocol::WireProtocol;
+//! use jaegercat::thrift::{DecodeLimits, DecodeMode};
+//!
+//! let body = br#"{"resourceSpans": [{
+//!     "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "foo"}}]},
+//!     "scopeSpans": [{"spans": [{
+//!         "traceId": "5b8efff798038103d269b633813fc60c",
+//!         "spanId": "eee19b7ec3c1b174",
+//!         "name": "bar",
+//!         "startTimeUnixNano": "1544712660000000000",
+//!         "endTimeUnixNano": "1544712661000000000"
+//!     }]}]
+//! }]}"#;
+//! let (message, _) = Otlp
+//!     .decode_batch(body, DecodeMode::Normal, &DecodeLimits::default())
+//!     .unwrap();
+//! assert_eq!(message.batch.process.service_name, "foo");
+//! assert_eq!(message.batch.spans[0].operation_name, "bar");
+//! assert_eq!(message.batch.spans[0].duration, 1_000_000);
+//! ```
+use serde_json::{self, Map, Value};
+
+use borrowed::PartialDecodeError;
+use error::{DecodeError, DecodeResult};
+use model::{Batch, Log, Process, Span, SpanId, SpanRef, SpanRefType, Tag, TagValue, TraceId,
+            UnknownFields};
+use proto::{check_collection, check_string, Field, Reader};
+use protocol::WireProtocol;
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification};
+
+/// The service name of the resources which have no `service.name` attribute.
+pub const NO_SERVICE_NAME: &str = "OTLPResourceNoServiceName";
+
+/// The `WireProtocol` of OTLP trace export requests in the protobuf or the JSON encoding.
+///
+/// The encoding is detected from the first byte of the message,
+/// since a JSON object starts with `{` (or whitespace),
+/// which cannot start a protobuf `ExportTraceServiceRequest`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Otlp;
+impl WireProtocol for Otlp {
+    fn name(&self) -> &'static str {
+        "otlp"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let is_json = buf.iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|&b| b == b'{');
+        let resource_spans = if is_json {
+            decode_json(buf, limits)?
+        } else {
+            read_request(&Reader::new("ExportTraceServiceRequest", mode, limits), buf, 0)?
+        };
+        let batch = to_batch(resource_spans)?;
+        Ok((EmitBatchNotification { batch }, None))
+    }
+}
+
+#[derive(Debug, Default)]
+struct ResourceSpans {
+    attributes: Vec<(String, AnyValue)>,
+    scope_spans: Vec<ScopeSpans>,
+}
+
+#[derive(Debug, Default)]
+struct ScopeSpans {
+    name: String,
+    version: String,
+    spans: Vec<OtlpSpan>,
+}
+
+#[derive(Debug, Default)]
+struct OtlpSpan {
+    trace_id: Vec<u8>,
+    span_id: Vec<u8>,
+    trace_state: String,
+    parent_span_id: Vec<u8>,
+    name: String,
+    kind: i64,
+    start_time: u64,
+    end_time: u64,
+    attributes: Vec<(String, AnyValue)>,
+    events: Vec<Event>,
+    links: Vec<Link>,
+    status_code: i64,
+    status_message: String,
+}
+
+#[derive(Debug, Default)]
+struct Event {
+    time: u64,
+    name: String,
+    attributes: Vec<(String, AnyValue)>,
+}
+
+#[derive(Debug, Default)]
+struct Link {
+    trace_id: Vec<u8>,
+    span_id: Vec<u8>,
+}
+
+#[derive(Debug)]
+enum AnyValue {
+    Empty,
+    Str(String),
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Array(Vec<AnyValue>),
+    KvList(Vec<(String, AnyValue)>),
+}
+impl AnyValue {
+    fn into_tag_value(self) -> TagValue {
+        match self {
+            AnyValue::Str(v) => TagValue::Str(v),
+            AnyValue::Bool(v) => TagValue::Bool(v),
+            AnyValue::Int(v) => TagValue::Long(v),
+            AnyValue::Double(v) => TagValue::Double(v),
+            AnyValue::Bytes(v) => TagValue::Binary(v),
+            AnyValue::Empty => TagValue::Str(String::new()),
+            v @ AnyValue::Array(_) | v @ AnyValue::KvList(_) => {
+                TagValue::Str(v.to_json().to_string())
+            }
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        match *self {
+            AnyValue::Empty => Value::Null,
+            AnyValue::Str(ref v) => Value::from(v.as_str()),
+            AnyValue::Bool(v) => Value::from(v),
+            AnyValue::Int(v) => Value::from(v),
+            AnyValue::Double(v) => Value::from(v),
+            AnyValue::Bytes(ref v) => Value::from(encode_base64(v)),
+            AnyValue::Array(ref vs) => Value::Array(vs.iter().map(|v| v.to_json()).collect()),
+            AnyValue::KvList(ref kvs) => Value::Object(
+                kvs.iter()
+                    .map(|(k, v)| (k.clone(), v.to_json()))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+fn to_batch(resource_spans: Vec<ResourceSpans>) -> DecodeResult<Batch> {
+    let mut process = None;
+    let mut spans = Vec::new();
+    for resource in resource_spans {
+        let mut service_name = None;
+        let mut tags = Vec::new();
+        for (key, value) in resource.attributes {
+            match value {
+                AnyValue::Str(ref name) if key == "service.name" && service_name.is_none() => {
+                    service_name = Some(name.clone());
+                }
+                value => tags.push(Tag::new(key, value.into_tag_value())),
+            }
+        }
+        let service_name = service_name.unwrap_or_else(|| NO_SERVICE_NAME.to_owned());
+        let extra_tags = if process.is_none() {
+            process = Some(Process {
+                service_name,
+                tags,
+                unknown_fields: UnknownFields::new(),
+            });
+            Vec::new()
+        } else {
+            tags.insert(0, Tag::new("service.name", service_name));
+            tags
+        };
+        for scope in resource.scope_spans {
+            for span in scope.spans {
+                let mut span = to_span(span, &scope.name, &scope.version)?;
+                span.tags.extend(extra_tags.iter().cloned());
+                spans.push(span);
+            }
+        }
+    }
+    let process = process.unwrap_or_else(|| Process {
+        service_name: NO_SERVICE_NAME.to_owned(),
+        tags: Vec::new(),
+        unknown_fields: UnknownFields::new(),
+    });
+    Ok(Batch {
+        process,
+        spans,
+        seq_no: None,
+        stats: None,
+        unknown_fields: UnknownFields::new(),
+    })
+}
+
+fn to_span(span: OtlpSpan, scope_name: &str, scope_version: &str) -> DecodeResult<Span> {
+    let mut tags = span.attributes
+        .into_iter()
+        .map(|(k, v)| Tag::new(k, v.into_tag_value()))
+        .collect::<Vec<_>>();
+    if !scope_name.is_empty() {
+        tags.push(Tag::new("otel.scope.name", scope_name));
+    }
+    if !scope_version.is_empty() {
+        tags.push(Tag::new("otel.scope.version", scope_version));
+    }
+    let kind = match span.kind {
+        1 => Some("internal"),
+        2 => Some("server"),
+        3 => Some("client"),
+        4 => Some("producer"),
+        5 => Some("consumer"),
+        _ => None,
+    };
+    if let Some(kind) = kind {
+        tags.push(Tag::new("span.kind", kind));
+    }
+    match span.status_code {
+        1 => tags.push(Tag::new("otel.status_code", "OK")),
+        2 => {
+            tags.push(Tag::new("otel.status_code", "ERROR"));
+            tags.push(Tag::new("error", true));
+        }
+        _ => {}
+    }
+    if !span.status_message.is_empty() {
+        tags.push(Tag::new("otel.status_description", span.status_message));
+    }
+    if !span.trace_state.is_empty() {
+        tags.push(Tag::new("w3c.tracestate", span.trace_state));
+    }
+
+    let logs = span.events
+        .into_iter()
+        .map(|event| {
+            let mut fields = vec![Tag::new("event", event.name)];
+            fields.extend(
+                event
+                    .attributes
+                    .into_iter()
+                    .map(|(k, v)| Tag::new(k, v.into_tag_value())),
+            );
+            Log {
+                timestamp: nanos_to_micros(event.time),
+                fields,
+                unknown_fields: UnknownFields::new(),
+            }
+        })
+        .collect();
+    let mut references = Vec::with_capacity(span.links.len());
+    for link in span.links {
+        references.push(SpanRef {
+            ref_type: SpanRefType::FollowsFrom,
+            trace_id: to_trace_id(&link.trace_id, "Link.trace_id")?,
+            span_id: to_span_id(&link.span_id, "Link.span_id")?,
+            unknown_fields: UnknownFields::new(),
+        });
+    }
+    let parent_span_id = if span.parent_span_id.is_empty() {
+        None
+    } else {
+        Some(to_span_id(&span.parent_span_id, "Span.parent_span_id")?)
+    };
+    Ok(Span {
+        trace_id: to_trace_id(&span.trace_id, "Span.trace_id")?,
+        span_id: to_span_id(&span.span_id, "Span.span_id")?,
+        parent_span_id,
+        operation_name: span.name,
+        references,
+        flags: 1,
+        start_time: nanos_to_micros(span.start_time),
+        duration: nanos_to_micros(span.end_time.saturating_sub(span.start_time)),
+        tags,
+        logs,
+        unknown_fields: UnknownFields::new(),
+    })
+}
+
+fn to_trace_id(bytes: &[u8], field: &'static str) -> DecodeResult<TraceId> {
+    if bytes.len() != 16 {
+        return Err(invalid_id(bytes, field));
+    }
+    Ok(TraceId {
+        high: be_i64(&bytes[..8]),
+        low: be_i64(&bytes[8..]),
+    })
+}
+
+fn to_span_id(bytes: &[u8], field: &'static str) -> DecodeResult<SpanId> {
+    if bytes.len() != 8 {
+        return Err(invalid_id(bytes, field));
+    }
+    Ok(SpanId(be_i64(bytes)))
+}
+
+fn invalid_id(bytes: &[u8], field: &'static str) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("{} must not be {} bytes", field, bytes.len()),
+    }
+}
+
+fn be_i64(bytes: &[u8]) -> i64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(bytes);
+    i64::from_be_bytes(b)
+}
+
+fn nanos_to_micros(nanos: u64) -> i64 {
+    (nanos / 1000) as i64
+}
+
+/// Converts a batch into a `TracesData` message in the OTLP JSON encoding,
+/// which is what the file exporter and the file receiver of the OpenTelemetry Collector use.
+///
+/// This is the reverse of the mapping done by `Otlp`:
+/// the process becomes the resource, the spans are grouped by the `otel.scope.name` and
+/// `otel.scope.version` tags, and the `span.kind`, `otel.status_code`, `error`,
+/// `otel.status_description` and `w3c.tracestate` tags become the corresponding fields.
+/// The references other than the parent become links
+/// (with an `opentracing.ref_type` attribute, as the Jaeger translator of the Collector does).
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::model::{Batch, Process, Span, SpanId, TraceId, UnknownFields};
+/// use jaegercat::otlp;
+///
+/// let batch = Batch {
+///     process: Process {
+///         service_name: "foo".to_owned(),
+///         tags: Vec::new(),
+///         unknown_fields: UnknownFields::new(),
+///     },
+///     spans: vec![Span {
+///         trace_id: TraceId { high: 0, low: 1 },
+///         span_id: SpanId(2),
+///         parent_span_id: None,
+///         operation_name: "bar".to_owned(),
+///         references: Vec::new(),
+///         flags: 1,
+///         start_time: 1_544_712_660_000_000,
+///         duration: 1_000_000,
+///         tags: Vec::new(),
+///         logs: Vec::new(),
+///         unknown_fields: UnknownFields::new(),
+///     }],
+///     seq_no: None,
+///     stats: None,
+///     unknown_fields: UnknownFields::new(),
+/// };
+/// let data = otlp::to_json(&batch);
+/// let span = &data["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+/// assert_eq!(span["traceId"], "00000000000000000000000000000001");
+/// assert_eq!(span["endTimeUnixNano"], "1544712661000000000");
+/// ```
+pub fn to_json(batch: &Batch) -> Value {
+    let mut attributes = vec![json_attribute(
+        "service.name",
+        &TagValue::Str(batch.process.service_name.clone()),
+    )];
+    attributes.extend(
+        batch
+            .process
+            .tags
+            .iter()
+            .map(|tag| json_attribute(&tag.key, &tag.value)),
+    );
+
+    // Scopes are kept in the order of their first spans
+    let mut scopes: Vec<((&str, &str), Vec<Value>)> = Vec::new();
+    for span in &batch.spans {
+        let (scope, span) = span_to_json(span);
+        match scopes.iter_mut().find(|s| s.0 == scope) {
+            Some(s) => s.1.push(span),
+            None => scopes.push((scope, vec![span])),
+        }
+    }
+    let scope_spans = scopes
+        .into_iter()
+        .map(|((name, version), spans)| {
+            let mut scope = Map::new();
+            if !name.is_empty() {
+                scope.insert("name".to_owned(), Value::from(name));
+            }
+            if !version.is_empty() {
+                scope.insert("version".to_owned(), Value::from(version));
+            }
+            json_object(vec![
+                ("scope", Value::Object(scope)),
+                ("spans", Value::Array(spans)),
+            ])
+        })
+        .collect();
+    let resource = json_object(vec![("attributes", Value::Array(attributes))]);
+    let resource_spans = json_object(vec![
+        ("resource", resource),
+        ("scopeSpans", Value::Array(scope_spans)),
+    ]);
+    json_object(vec![("resourceSpans", Value::Array(vec![resource_spans]))])
+}
+
+/// Converts a span, and returns it with the name and the version of its scope.
+fn span_to_json(span: &Span) -> ((&str, &str), Value) {
+    let mut scope = ("", "");
+    let mut kind = 0;
+    let mut status_code = 0;
+    let mut error = false;
+    let mut status_message = None;
+    let mut trace_state = None;
+    let mut attributes = Vec::new();
+    for tag in &span.tags {
+        match (tag.key.as_str(), &tag.value) {
+            ("otel.scope.name", TagValue::Str(v)) => scope.0 = v,
+            ("otel.scope.version", TagValue::Str(v)) => scope.1 = v,
+            ("span.kind", TagValue::Str(v)) if kind_code(v) != 0 => kind = kind_code(v),
+            ("otel.status_code", TagValue::Str(v)) if v == "OK" => status_code = 1,
+            ("otel.status_code", TagValue::Str(v)) if v == "ERROR" => status_code = 2,
+            ("error", TagValue::Bool(true)) => error = true,
+            ("otel.status_description", TagValue::Str(v)) => status_message = Some(v),
+            ("w3c.tracestate", TagValue::Str(v)) => trace_state = Some(v),
+            _ => attributes.push(json_attribute(&tag.key, &tag.value)),
+        }
+    }
+    if error && status_code == 0 {
+        status_code = 2;
+    }
+
+    let parent = match span.parent_span_id {
+        Some(id) if id.0 != 0 => Some(id),
+        _ => span.references
+            .iter()
+            .find(|r| r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id)
+            .map(|r| r.span_id),
+    };
+    let links = span.references
+        .iter()
+        .filter(|r| {
+            !(r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id
+                && Some(r.span_id) == parent)
+        })
+        .map(|r| {
+            let ref_type = match r.ref_type {
+                SpanRefType::ChildOf => "child_of",
+                SpanRefType::FollowsFrom => "follows_from",
+            };
+            json_object(vec![
+                ("traceId", Value::from(hex_trace_id(r.trace_id))),
+                ("spanId", Value::from(hex_span_id(r.span_id))),
+                (
+                    "attributes",
+                    Value::Array(vec![json_attribute(
+                        "opentracing.ref_type",
+                        &TagValue::Str(ref_type.to_owned()),
+                    )]),
+                ),
+            ])
+        })
+        .collect();
+    let events = span.logs
+        .iter()
+        .map(|log| {
+            let mut name = "";
+            let mut attributes = Vec::new();
+            for field in &log.fields {
+                match (field.key.as_str(), &field.value) {
+                    ("event", TagValue::Str(v)) if name.is_empty() => name = v,
+                    _ => attributes.push(json_attribute(&field.key, &field.value)),
+                }
+            }
+            json_object(vec![
+                ("timeUnixNano", Value::from(micros_to_nanos(log.timestamp))),
+                ("name", Value::from(name)),
+                ("attributes", Value::Array(attributes)),
+            ])
+        })
+        .collect();
+
+    let mut status = Map::new();
+    if status_code != 0 {
+        status.insert("code".to_owned(), Value::from(status_code));
+    }
+    if let Some(message) = status_message {
+        status.insert("message".to_owned(), Value::from(message.as_str()));
+    }
+    let mut fields = vec![
+        ("traceId", Value::from(hex_trace_id(span.trace_id))),
+        ("spanId", Value::from(hex_span_id(span.span_id))),
+    ];
+    if let Some(state) = trace_state {
+        fields.push(("traceState", Value::from(state.as_str())));
+    }
+    if let Some(parent) = parent {
+        fields.push(("parentSpanId", Value::from(hex_span_id(parent))));
+    }
+    fields.extend(vec![
+        ("name", Value::from(span.operation_name.as_str())),
+        ("kind", Value::from(kind)),
+        ("startTimeUnixNano", Value::from(micros_to_nanos(span.start_time))),
+        (
+            "endTimeUnixNano",
+            Value::from(micros_to_nanos(span.start_time.saturating_add(span.duration))),
+        ),
+        ("attributes", Value::Array(attributes)),
+        ("events", Value::Array(events)),
+        ("links", Value::Array(links)),
+        ("status", Value::Object(status)),
+    ]);
+    (scope, json_object(fields))
+}
+
+fn kind_code(kind: &str) -> u8 {
+    match kind {
+        "internal" => 1,
+        "server" => 2,
+        "client" => 3,
+        "producer" => 4,
+        "consumer" => 5,
+        _ => 0,
+    }
+}
+
+fn json_object(fields: Vec<(&str, Value)>) -> Value {
+    Value::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+}
+
+/// Converts a tag into a `KeyValue` (64-bit integers are strings, as in the protobuf JSON mapping).
+fn json_attribute(key: &str, value: &TagValue) -> Value {
+    let value = match *value {
+        TagValue::Str(ref v) => ("stringValue", Value::from(v.as_str())),
+        TagValue::Bool(v) => ("boolValue", Value::from(v)),
+        TagValue::Long(v) => ("intValue", Value::from(v.to_string())),
+        TagValue::Double(v) if v.is_nan() => ("doubleValue", Value::from("NaN")),
+        TagValue::Double(v) if v.is_infinite() => {
+            let v = if v > 0.0 { "Infinity" } else { "-Infinity" };
+            ("doubleValue", Value::from(v))
+        }
+        TagValue::Double(v) => ("doubleValue", Value::from(v)),
+        TagValue::Binary(ref v) => ("bytesValue", Value::from(encode_base64(v))),
+    };
+    json_object(vec![
+        ("key", Value::from(key)),
+        ("value", json_object(vec![value])),
+    ])
+}
+
+fn hex_trace_id(id: TraceId) -> String {
+    format!("{:016x}{:016x}", id.high as u64, id.low as u64)
+}
+
+fn hex_span_id(id: SpanId) -> String {
+    format!("{:016x}", id.0 as u64)
+}
+
+/// Converts a timestamp into a string of nanoseconds (negative ones are clamped to zero).
+fn micros_to_nanos(micros: i64) -> String {
+    (micros.max(0) as u64).saturating_mul(1000).to_string()
+}
+
+fn read_request(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Vec<ResourceSpans>> {
+    let mut resource_spans = Vec::new();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => {
+                check_collection(&resource_spans, r.limits, field_offset)?;
+                resource_spans.push(read_resource_spans(r, b, o)?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(resource_spans)
+}
+
+fn read_resource_spans(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<ResourceSpans> {
+    let mut resource = ResourceSpans::default();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => {
+                r.fields(b, o, |r, number, field, field_offset| {
+                    match (number, field) {
+                        (1, Field::Bytes(b, o)) => {
+                            check_collection(&resource.attributes, r.limits, field_offset)?;
+                            resource.attributes.push(read_key_value(r, b, o)?);
+                        }
+                        (2, Field::Varint(_)) => {}
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+            }
+
+            // `instrumentation_library_spans` (1000) has been renamed to `scope_spans` (2)
+            (2, Field::Bytes(b, o)) | (1000, Field::Bytes(b, o)) => {
+                check_collection(&resource.scope_spans, r.limits, field_offset)?;
+                resource.scope_spans.push(read_scope_spans(r, b, o)?);
+            }
+            (3, Field::Bytes(..)) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(resource)
+}
+
+fn read_scope_spans(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<ScopeSpans> {
+    let mut scope = ScopeSpans::default();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => {
+                r.fields(b, o, |r, number, field, _| {
+                    match (number, field) {
+                        (1, Field::Bytes(b, o)) => scope.name = r.string(b, o)?,
+                        (2, Field::Bytes(b, o)) => scope.version = r.string(b, o)?,
+                        (3, Field::Bytes(..)) | (4, Field::Varint(_)) => {}
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+            }
+            (2, Field::Bytes(b, o)) => {
+                check_collection(&scope.spans, r.limits, field_offset)?;
+                scope.spans.push(read_span(r, b, o)?);
+            }
+            (3, Field::Bytes(..)) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(scope)
+}
+
+fn read_span(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<OtlpSpan> {
+    let mut span = OtlpSpan::default();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => span.trace_id = r.bytes(b, o)?,
+            (2, Field::Bytes(b, o)) => span.span_id = r.bytes(b, o)?,
+            (3, Field::Bytes(b, o)) => span.trace_state = r.string(b, o)?,
+            (4, Field::Bytes(b, o)) => span.parent_span_id = r.bytes(b, o)?,
+            (5, Field::Bytes(b, o)) => span.name = r.string(b, o)?,
+            (6, Field::Varint(v)) => span.kind = v as i64,
+            (7, Field::Fixed64(v)) => span.start_time = v,
+            (8, Field::Fixed64(v)) => span.end_time = v,
+            (9, Field::Bytes(b, o)) => {
+                check_collection(&span.attributes, r.limits, field_offset)?;
+                span.attributes.push(read_key_value(r, b, o)?);
+            }
+            (11, Field::Bytes(b, o)) => {
+                check_collection(&span.events, r.limits, field_offset)?;
+                span.events.push(read_event(r, b, o)?);
+            }
+            (13, Field::Bytes(b, o)) => {
+                check_collection(&span.links, r.limits, field_offset)?;
+                span.links.push(read_link(r, b, o)?);
+            }
+            (15, Field::Bytes(b, o)) => {
+                r.fields(b, o, |r, number, field, _| {
+                    match (number, field) {
+                        (2, Field::Bytes(b, o)) => span.status_message = r.string(b, o)?,
+                        (3, Field::Varint(v)) => span.status_code = v as i64,
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+            }
+            (10, Field::Varint(_))
+            | (12, Field::Varint(_))
+            | (14, Field::Varint(_))
+            | (16, Field::Fixed32) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(span)
+}
+
+fn read_event(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Event> {
+    let mut event = Event::default();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Fixed64(v)) => event.time = v,
+            (2, Field::Bytes(b, o)) => event.name = r.string(b, o)?,
+            (3, Field::Bytes(b, o)) => {
+                check_collection(&event.attributes, r.limits, field_offset)?;
+                event.attributes.push(read_key_value(r, b, o)?);
+            }
+            (4, Field::Varint(_)) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(event)
+}
+
+fn read_link(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Link> {
+    let mut link = Link::default();
+    reader.fields(buf, offset, |r, number, field, _| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => link.trace_id = r.bytes(b, o)?,
+            (2, Field::Bytes(b, o)) => link.span_id = r.bytes(b, o)?,
+            (3, Field::Bytes(..))
+            | (4, Field::Bytes(..))
+            | (5, Field::Varint(_))
+            | (6, Field::Fixed32) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(link)
+}
+
+fn read_key_value(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<(String, AnyValue)> {
+    let mut key = String::new();
+    let mut value = AnyValue::Empty;
+    reader.fields(buf, offset, |r, number, field, _| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => key = r.string(b, o)?,
+            (2, Field::Bytes(b, o)) => value = read_any_value(r, b, o)?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok((key, value))
+}
+
+fn read_any_value(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<AnyValue> {
+    let mut value = AnyValue::Empty;
+    reader.fields(buf, offset, |r, number, field, _| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => value = AnyValue::Str(r.string(b, o)?),
+            (2, Field::Varint(v)) => value = AnyValue::Bool(v != 0),
+            (3, Field::Varint(v)) => value = AnyValue::Int(v as i64),
+            (4, Field::Fixed64(v)) => value = AnyValue::Double(f64::from_bits(v)),
+            (5, Field::Bytes(b, o)) => {
+                let mut values = Vec::new();
+                r.fields(b, o, |r, number, field, field_offset| {
+                    match (number, field) {
+                        (1, Field::Bytes(b, o)) => {
+                            check_collection(&values, r.limits, field_offset)?;
+                            values.push(read_any_value(r, b, o)?);
+                        }
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                value = AnyValue::Array(values);
+            }
+            (6, Field::Bytes(b, o)) => {
+                let mut values = Vec::new();
+                r.fields(b, o, |r, number, field, field_offset| {
+                    match (number, field) {
+                        (1, Field::Bytes(b, o)) => {
+                            check_collection(&values, r.limits, field_offset)?;
+                            values.push(read_key_value(r, b, o)?);
+                        }
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                value = AnyValue::KvList(values);
+            }
+            (7, Field::Bytes(b, o)) => value = AnyValue::Bytes(r.bytes(b, o)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(value)
+}
+
+/// Decodes the JSON encoding, in which IDs are hex strings and 64-bit integers
+/// may be strings (see the OTLP specification).
+fn decode_json(buf: &[u8], limits: &DecodeLimits) -> DecodeResult<Vec<ResourceSpans>> {
+    let value: Value = serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Invalid JSON: {}", e),
+    })?;
+    let json = Json { limits };
+    json.array(&value, "resourceSpans", |v| json.resource_spans(v))
+}
+
+struct Json<'b> {
+    limits: &'b DecodeLimits,
+}
+impl<'b> Json<'b> {
+    fn array<T, F>(&self, object: &Value, key: &str, f: F) -> DecodeResult<Vec<T>>
+    where
+        F: Fn(&Value) -> DecodeResult<T>,
+    {
+        match object.get(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Array(values)) => {
+                let mut items = Vec::with_capacity(values.len());
+                for v in values {
+                    check_collection(&items, self.limits, 0)?;
+                    items.push(f(v)?);
+                }
+                Ok(items)
+            }
+            Some(v) => Err(json_error(key, v)),
+        }
+    }
+
+    fn resource_spans(&self, value: &Value) -> DecodeResult<ResourceSpans> {
+        let attributes = match value.get("resource") {
+            Some(resource) => self.attributes(resource, "attributes")?,
+            None => Vec::new(),
+        };
+        let mut scope_spans = self.array(value, "scopeSpans", |v| self.scope_spans(v))?;
+        scope_spans.extend(self.array(value, "instrumentationLibrarySpans", |v| {
+            self.scope_spans(v)
+        })?);
+        Ok(ResourceSpans {
+            attributes,
+            scope_spans,
+        })
+    }
+
+    fn scope_spans(&self, value: &Value) -> DecodeResult<ScopeSpans> {
+        let scope = value
+            .get("scope")
+            .or_else(|| value.get("instrumentationLibrary"));
+        let (name, version) = match scope {
+            Some(scope) => (self.string(scope, "name")?, self.string(scope, "version")?),
+            None => (String::new(), String::new()),
+        };
+        Ok(ScopeSpans {
+            name,
+            version,
+            spans: self.array(value, "spans", |v| self.span(v))?,
+        })
+    }
+
+    fn span(&self, value: &Value) -> DecodeResult<OtlpSpan> {
+        let status = value.get("status");
+        Ok(OtlpSpan {
+            trace_id: self.hex(value, "traceId")?,
+            span_id: self.hex(value, "spanId")?,
+            trace_state: self.string(value, "traceState")?,
+            parent_span_id: self.hex(value, "parentSpanId")?,
+            name: self.string(value, "name")?,
+            kind: self.int(value, "kind")?,
+            start_time: self.int(value, "startTimeUnixNano")? as u64,
+            end_time: self.int(value, "endTimeUnixNano")? as u64,
+            attributes: self.attributes(value, "attributes")?,
+            events: self.array(value, "events", |v| {
+                Ok(Event {
+                    time: self.int(v, "timeUnixNano")? as u64,
+                    name: self.string(v, "name")?,
+                    attributes: self.attributes(v, "attributes")?,
+                })
+            })?,
+            links: self.array(value, "links", |v| {
+                Ok(Link {
+                    trace_id: self.hex(v, "traceId")?,
+                    span_id: self.hex(v, "spanId")?,
+                })
+            })?,
+            status_code: match status {
+                Some(status) => self.int(status, "code")?,
+                None => 0,
+            },
+            status_message: match status {
+                Some(status) => self.string(status, "message")?,
+                None => String::new(),
+            },
+        })
+    }
+
+    fn attributes(&self, value: &Value, key: &str) -> DecodeResult<Vec<(String, AnyValue)>> {
+        self.array(value, key, |v| self.key_value(v, 1))
+    }
+
+    fn key_value(&self, value: &Value, depth: usize) -> DecodeResult<(String, AnyValue)> {
+        let key = self.string(value, "key")?;
+        let value = match value.get("value") {
+            Some(v) => self.any_value(v, depth)?,
+            None => AnyValue::Empty,
+        };
+        Ok((key, value))
+    }
+
+    fn any_value(&self, value: &Value, depth: usize) -> DecodeResult<AnyValue> {
+        if depth > self.limits.max_depth {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_depth",
+                offset: 0,
+                size: depth as u64,
+                max: self.limits.max_depth,
+            });
+        }
+        let object = match *value {
+            Value::Object(ref object) => object,
+            _ => return Err(json_error("value", value)),
+        };
+        let (key, v) = match object.iter().next() {
+            Some(entry) => entry,
+            None => return Ok(AnyValue::Empty),
+        };
+        Ok(match (key.as_str(), v) {
+            ("stringValue", Value::String(s)) => {
+                check_string(s.len(), self.limits, 0)?;
+                AnyValue::Str(s.clone())
+            }
+            ("boolValue", Value::Bool(b)) => AnyValue::Bool(*b),
+            ("intValue", _) => AnyValue::Int(self.int(value, "intValue")?),
+            ("doubleValue", Value::Number(n)) => AnyValue::Double(n.as_f64().unwrap_or(0.0)),
+
+            // `NaN`, `Infinity` and `-Infinity` are strings in the protobuf JSON mapping
+            ("doubleValue", Value::String(s)) => {
+                AnyValue::Double(s.parse().map_err(|_| json_error(key, v))?)
+            }
+            ("bytesValue", Value::String(s)) => {
+                check_string(s.len(), self.limits, 0)?;
+                AnyValue::Bytes(decode_base64(s).ok_or_else(|| json_error(key, v))?)
+            }
+            ("arrayValue", _) => {
+                AnyValue::Array(self.array(v, "values", |v| self.any_value(v, depth + 1))?)
+            }
+            ("kvlistValue", _) => {
+                AnyValue::KvList(self.array(v, "values", |v| self.key_value(v, depth + 1))?)
+            }
+            _ => return Err(json_error(key, v)),
+        })
+    }
+
+    fn string(&self, object: &Value, key: &str) -> DecodeResult<String> {
+        match object.get(key) {
+            None | Some(Value::Null) => Ok(String::new()),
+            Some(Value::String(s)) => {
+                check_string(s.len(), self.limits, 0)?;
+                Ok(s.clone())
+            }
+            Some(v) => Err(json_error(key, v)),
+        }
+    }
+
+    fn int(&self, object: &Value, key: &str) -> DecodeResult<i64> {
+        let v = match object.get(key) {
+            None | Some(Value::Null) => return Ok(0),
+            Some(v) => v,
+        };
+        let n = match *v {
+            Value::Number(ref n) => n.as_i64().or_else(|| n.as_u64().map(|n| n as i64)),
+            Value::String(ref s) => s.parse::<i64>()
+                .ok()
+                .or_else(|| s.parse::<u64>().ok().map(|n| n as i64)),
+            _ => None,
+        };
+        n.ok_or_else(|| json_error(key, v))
+    }
+
+    fn hex(&self, object: &Value, key: &str) -> DecodeResult<Vec<u8>> {
+        let s = self.string(object, key)?;
+        decode_hex(&s).ok_or_else(|| DecodeError::InvalidValue {
+            offset: 0,
+            reason: format!("{} is not a hex string: {:?}", key, s),
+        })
+    }
+}
+
+fn json_error(key: &str, value: &Value) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Unexpected value of {:?}: {}", key, value),
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64_CHARS[(n >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// Decodes the standard or the URL-safe base64 (with or without padding).
+pub(crate) fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in s.bytes().take_while(|&c| c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        n = n << 6 | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(buf: &[u8]) -> DecodeResult<Batch> {
+        let (message, _) = Otlp.decode_batch(buf, DecodeMode::Normal, &DecodeLimits::default())?;
+        Ok(message.batch)
+    }
+
+    fn json(s: &str) -> Value {
+        serde_json::from_str(s).unwrap()
+    }
+
+    fn span(trace_id: TraceId, span_id: i64) -> Span {
+        Span {
+            trace_id,
+            span_id: SpanId(span_id),
+            parent_span_id: None,
+            operation_name: "bar".to_owned(),
+            references: Vec::new(),
+            flags: 1,
+            start_time: 0,
+            duration: 0,
+            tags: Vec::new(),
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    const TRACE_ID: TraceId = TraceId {
+        high: 0x5b8e_fff7_9803_8103,
+        low: 0xd269_b633_813f_c60c_u64 as i64,
+    };
+
+    /// An `ExportTraceServiceRequest` with a resource (`service.name` and `host`),
+    /// a scope (`lib` 1.0) and a client span with the attributes of every type,
+    /// an event, a link and an error status.
+    const REQUEST: [u8; 299] = [
+        0x0a, 0xa8, 0x02, 0x0a, 0x26, 0x0a, 0x15, 0x0a, 0x0c, 0x73, 0x65, 0x72, 0x76, 0x69, 0x63,
+        0x65, 0x2e, 0x6e, 0x61, 0x6d, 0x65, 0x12, 0x05, 0x0a, 0x03, 0x66, 0x6f, 0x6f, 0x0a, 0x0d,
+        0x0a, 0x04, 0x68, 0x6f, 0x73, 0x74, 0x12, 0x05, 0x0a, 0x03, 0x62, 0x61, 0x72, 0x12, 0xfd,
+        0x01, 0x0a, 0x0a, 0x0a, 0x03, 0x6c, 0x69, 0x62, 0x12, 0x03, 0x31, 0x2e, 0x30, 0x12, 0xee,
+        0x01, 0x0a, 0x10, 0x5b, 0x8e, 0xff, 0xf7, 0x98, 0x03, 0x81, 0x03, 0xd2, 0x69, 0xb6, 0x33,
+        0x81, 0x3f, 0xc6, 0x0c, 0x12, 0x08, 0xee, 0xe1, 0x9b, 0x7e, 0xc3, 0xc1, 0xb1, 0x74, 0x1a,
+        0x03, 0x6b, 0x3d, 0x76, 0x22, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x2a,
+        0x03, 0x62, 0x61, 0x72, 0x30, 0x03, 0x39, 0x00, 0x48, 0x59, 0xe3, 0xfa, 0xeb, 0x6f, 0x15,
+        0x41, 0xe8, 0x15, 0xf4, 0x1e, 0xfb, 0xeb, 0x6f, 0x15, 0x4a, 0x08, 0x0a, 0x01, 0x73, 0x12,
+        0x03, 0x0a, 0x01, 0x78, 0x4a, 0x07, 0x0a, 0x01, 0x62, 0x12, 0x02, 0x10, 0x01, 0x4a, 0x10,
+        0x0a, 0x01, 0x69, 0x12, 0x0b, 0x18, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0x01, 0x4a, 0x0e, 0x0a, 0x01, 0x64, 0x12, 0x09, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xe0, 0x3f, 0x4a, 0x09, 0x0a, 0x01, 0x79, 0x12, 0x04, 0x3a, 0x02, 0x00, 0xff, 0x4a, 0x12,
+        0x0a, 0x01, 0x61, 0x12, 0x0d, 0x2a, 0x0b, 0x0a, 0x02, 0x18, 0x01, 0x0a, 0x05, 0x0a, 0x03,
+        0x74, 0x77, 0x6f, 0x4a, 0x11, 0x0a, 0x02, 0x6b, 0x76, 0x12, 0x0b, 0x32, 0x09, 0x0a, 0x07,
+        0x0a, 0x01, 0x6b, 0x12, 0x02, 0x18, 0x01, 0x5a, 0x19, 0x09, 0x00, 0xad, 0x26, 0x01, 0xfb,
+        0xeb, 0x6f, 0x15, 0x12, 0x05, 0x72, 0x65, 0x74, 0x72, 0x79, 0x1a, 0x07, 0x0a, 0x01, 0x6e,
+        0x12, 0x02, 0x18, 0x02, 0x6a, 0x1c, 0x0a, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x12, 0x08, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x02, 0x7a, 0x08, 0x12, 0x04, 0x62, 0x6f, 0x6f, 0x6d, 0x18, 0x02,
+    ];
+
+    #[test]
+    fn protobuf_request_is_decoded() {
+        let batch = decode(&REQUEST).unwrap();
+        assert_eq!(batch.process.service_name, "foo");
+        assert_eq!(batch.process.tags, vec![Tag::new("host", "bar")]);
+
+        let mut expected = span(TRACE_ID, 0xeee1_9b7e_c3c1_b174_u64 as i64);
+        expected.parent_span_id = Some(SpanId(0x0102_0304_0506_0708));
+        expected.start_time = 1_544_712_660_000_000;
+        expected.duration = 1_000_001;
+        expected.tags = vec![
+            Tag::new("s", "x"),
+            Tag::new("b", true),
+            Tag::new("i", -1i64),
+            Tag::new("d", 0.5),
+            Tag::new("y", vec![0, 255]),
+            Tag::new("a", r#"[1,"two"]"#),
+            Tag::new("kv", r#"{"k":1}"#),
+            Tag::new("otel.scope.name", "lib"),
+            Tag::new("otel.scope.version", "1.0"),
+            Tag::new("span.kind", "client"),
+            Tag::new("otel.status_code", "ERROR"),
+            Tag::new("error", true),
+            Tag::new("otel.status_description", "boom"),
+            Tag::new("w3c.tracestate", "k=v"),
+        ];
+        expected.logs = vec![Log {
+            timestamp: 1_544_712_660_500_000,
+            fields: vec![Tag::new("event", "retry"), Tag::new("n", 2i64)],
+            unknown_fields: UnknownFields::new(),
+        }];
+        expected.references = vec![SpanRef {
+            ref_type: SpanRefType::FollowsFrom,
+            trace_id: TraceId { high: 0, low: 1 },
+            span_id: SpanId(2),
+            unknown_fields: UnknownFields::new(),
+        }];
+        assert_eq!(batch.spans, vec![expected]);
+
+        // Every prefix is truncated
+        for len in 1..REQUEST.len() {
+            assert!(decode(&REQUEST[..len]).is_err(), "len={}", len);
+        }
+    }
+
+    #[test]
+    fn json_request_is_decoded() {
+        let body = br#"{"resourceSpans": [{
+            "scopeSpans": [{"spans": [
+                {"traceId": "5b8efff798038103d269b633813fc60c", "spanId": "0000000000000001",
+                 "startTimeUnixNano": "1544712660000000000", "endTimeUnixNano": 1544712660000001999,
+                 "status": {"code": 1}},
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "0000000000000002",
+                 "kind": 1,
+                 "attributes": [{"key": "i", "value": {"intValue": "-9223372036854775808"}}]},
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "0000000000000003",
+                 "kind": 2, "attributes": [{"key": "i", "value": {"intValue": 7}}]},
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "0000000000000004",
+                 "kind": 4, "attributes": [{"key": "y", "value": {"bytesValue": "AP8="}}]},
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "0000000000000005",
+                 "kind": 5, "attributes": [{"key": "e", "value": {}}]},
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "0000000000000006",
+                 "kind": 9, "attributes": [{"key": "d", "value": {"doubleValue": "-Infinity"}}]}
+            ]}],
+            "instrumentationLibrarySpans": [{"instrumentationLibrary": {"name": "old"}, "spans": [
+                {"traceId": "0000000000000000000000000000abcd", "spanId": "ffffffffffffffff"}
+            ]}]
+        }]}"#;
+        let batch = decode(body).unwrap();
+        assert_eq!(batch.process.service_name, NO_SERVICE_NAME);
+
+        let spans = &batch.spans;
+        assert_eq!(spans.len(), 7);
+        assert_eq!(spans[0].trace_id, TRACE_ID);
+        assert_eq!(spans[0].start_time, 1_544_712_660_000_000);
+        assert_eq!(spans[0].duration, 1);
+        assert_eq!(spans[0].tags, vec![Tag::new("otel.status_code", "OK")]);
+
+        // 64-bit trace IDs are 128-bit ones with zero high bits
+        assert_eq!(spans[1].trace_id, TraceId { high: 0, low: 0xabcd });
+        let kind = |span: &Span| {
+            span.tags
+                .iter()
+                .find(|t| t.key == "span.kind")
+                .map(|t| t.value.clone())
+        };
+        let kinds = spans.iter().map(kind).collect::<Vec<_>>();
+        let expected = vec![None, Some("internal"), Some("server"), Some("producer")];
+        let mut expected = expected
+            .into_iter()
+            .map(|k| k.map(TagValue::from))
+            .collect::<Vec<_>>();
+        expected.extend(vec![Some(TagValue::from("consumer")), None, None]);
+        assert_eq!(kinds, expected);
+
+        assert_eq!(spans[1].tags[0], Tag::new("i", i64::MIN));
+        assert_eq!(spans[2].tags[0], Tag::new("i", 7i64));
+        assert_eq!(spans[3].tags[0], Tag::new("y", vec![0, 255]));
+        assert_eq!(spans[4].tags[0], Tag::new("e", ""));
+        assert_eq!(spans[5].tags[0], Tag::new("d", f64::NEG_INFINITY));
+        assert_eq!(spans[6].span_id, SpanId(-1));
+        assert_eq!(spans[6].tags, vec![Tag::new("otel.scope.name", "old")]);
+    }
+
+    #[test]
+    fn invalid_ids_are_rejected() {
+        let request = |trace_id: &str, span_id: &str| {
+            let body = format!(
+                r#"{{"resourceSpans": [{{"scopeSpans": [{{"spans": [
+                    {{"traceId": "{}", "spanId": "{}"}}
+                ]}}]}}]}}"#,
+                trace_id, span_id
+            );
+            decode(body.as_bytes()).err().map(|e| e.to_string())
+        };
+        assert_eq!(request("0000000000000000000000000000abcd", "0000000000000001"), None);
+
+        // A trace ID is always 16 bytes (even if it is a 64-bit one in Jaeger)
+        let error = request("000000000000abcd", "0000000000000001").unwrap();
+        assert!(error.contains("Span.trace_id must not be 8 bytes"), "{}", error);
+        let error = request("", "0000000000000001").unwrap();
+        assert!(error.contains("Span.trace_id must not be 0 bytes"), "{}", error);
+        let error = request("0000000000000000000000000000abcd", "01").unwrap();
+        assert!(error.contains("Span.span_id must not be 1 bytes"), "{}", error);
+        let error = request("0000000000000000000000000000abcg", "0000000000000001").unwrap();
+        assert!(error.contains("traceId is not a hex string"), "{}", error);
+    }
+
+    #[test]
+    fn batch_is_encoded_into_json() {
+        let mut first = span(TraceId { high: 0, low: 0x1234 }, -1);
+        first.references = vec![
+            SpanRef {
+                ref_type: SpanRefType::ChildOf,
+                trace_id: first.trace_id,
+                span_id: SpanId(7),
+                unknown_fields: UnknownFields::new(),
+            },
+            SpanRef {
+                ref_type: SpanRefType::FollowsFrom,
+                trace_id: TRACE_ID,
+                span_id: SpanId(3),
+                unknown_fields: UnknownFields::new(),
+            },
+        ];
+        first.start_time = 1_544_712_660_000_000;
+        first.duration = 1500;
+        first.tags = vec![
+            Tag::new("span.kind", "server"),
+            Tag::new("error", true),
+            Tag::new("otel.status_description", "boom"),
+            Tag::new("otel.scope.name", "lib"),
+            Tag::new("otel.scope.version", "1.0"),
+            Tag::new("s", "x"),
+            Tag::new("l", -1i64),
+            Tag::new("d", 0.5),
+            Tag::new("n", f64::NAN),
+            Tag::new("y", vec![0, 255]),
+        ];
+        first.logs = vec![Log {
+            timestamp: 1_544_712_660_000_500,
+            fields: vec![Tag::new("event", "retry"), Tag::new("n", 2i64)],
+            unknown_fields: UnknownFields::new(),
+        }];
+        let mut second = span(TRACE_ID, 2);
+        second.parent_span_id = Some(SpanId(1));
+        second.start_time = -1;
+        second.tags = vec![Tag::new("span.kind", "unknown"), Tag::new("w3c.tracestate", "k=v")];
+
+        let batch = Batch {
+            process: Process {
+                service_name: "foo".to_owned(),
+                tags: vec![Tag::new("pid", 42i64)],
+                unknown_fields: UnknownFields::new(),
+            },
+            spans: vec![first, second],
+            seq_no: None,
+            stats: None,
+            unknown_fields: UnknownFields::new(),
+        };
+        let expected = json(
+            r#"{"resourceSpans": [{
+                "resource": {"attributes": [
+                    {"key": "service.name", "value": {"stringValue": "foo"}},
+                    {"key": "pid", "value": {"intValue": "42"}}
+                ]},
+                "scopeSpans": [
+                    {"scope": {"name": "lib", "version": "1.0"}, "spans": [{
+                        "traceId": "00000000000000000000000000001234",
+                        "spanId": "ffffffffffffffff",
+                        "parentSpanId": "0000000000000007",
+                        "name": "bar",
+                        "kind": 2,
+                        "startTimeUnixNano": "1544712660000000000",
+                        "endTimeUnixNano": "1544712660001500000",
+                        "attributes": [
+                            {"key": "s", "value": {"stringValue": "x"}},
+                            {"key": "l", "value": {"intValue": "-1"}},
+                            {"key": "d", "value": {"doubleValue": 0.5}},
+                            {"key": "n", "value": {"doubleValue": "NaN"}},
+                            {"key": "y", "value": {"bytesValue": "AP8="}}
+                        ],
+                        "events": [{
+                            "timeUnixNano": "1544712660000500000",
+                            "name": "retry",
+                            "attributes": [{"key": "n", "value": {"intValue": "2"}}]
+                        }],
+                        "links": [{
+                            "traceId": "5b8efff798038103d269b633813fc60c",
+                            "spanId": "0000000000000003",
+                            "attributes": [{
+                                "key": "opentracing.ref_type",
+                                "value": {"stringValue": "follows_from"}
+                            }]
+                        }],
+                        "status": {"code": 2, "message": "boom"}
+                    }]},
+                    {"scope": {}, "spans": [{
+                        "traceId": "5b8efff798038103d269b633813fc60c",
+                        "spanId": "0000000000000002",
+                        "traceState": "k=v",
+                        "parentSpanId": "0000000000000001",
+                        "name": "bar",
+                        "kind": 0,
+                        "startTimeUnixNano": "0",
+                        "endTimeUnixNano": "0",
+                        "attributes": [{"key": "span.kind", "value": {"stringValue": "unknown"}}],
+                        "events": [],
+                        "links": [],
+                        "status": {}
+                    }]}
+                ]
+            }]}"#,
+        );
+        assert_eq!(to_json(&batch), expected);
+
+        // The encoded batch is decoded into the same IDs and times
+        let decoded = decode(to_json(&batch).to_string().as_bytes()).unwrap();
+        for (decoded, span) in decoded.spans.iter().zip(&batch.spans) {
+            assert_eq!(decoded.trace_id, span.trace_id);
+            assert_eq!(decoded.span_id, span.span_id);
+            assert_eq!(decoded.start_time, span.start_time.max(0));
+            assert_eq!(decoded.duration, span.duration);
+        }
+    }
+
+    #[test]
+    fn base64_works() {
+        // The test vectors of RFC 4648
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for &(bytes, encoded) in &vectors {
+            assert_eq!(encode_base64(bytes.as_bytes()), encoded);
+            assert_eq!(decode_base64(encoded), Some(bytes.as_bytes().to_vec()));
+        }
+        assert_eq!(decode_base64("AP8"), Some(vec![0, 255]));
+        assert_eq!(decode_base64("-_8="), Some(vec![251, 255]));
+        assert_eq!(decode_base64("AP*="), None);
+    }
+}
@@ -0,0 +1,773 @@
+//! Output of spans into [Parquet] files, one row per span.
+//!
+//! The columns are chosen by the same `Column`s as the CSV output, with these types:
+//!
+//! - `start`: `INT64` timestamps in microseconds (`TIMESTAMP_MICROS`, in UTC).
+//! - `duration_us`: `INT64`, and `duration` (seconds) and `duration_ms`: `DOUBLE`.
+//! - `flags`: `INT32`, and `error`: `BOOLEAN`.
+//! - The others: `BYTE_ARRAY` strings (`UTF8`), which are null for the absent values of
+//!   `parent_span_id`, `kind` and `tag:KEY` columns.
+//!
+//! The pages are `PLAIN` encoded and uncompressed. The rows are written as a row group when
+//! the row group size is reached. Until then, the open row group and the footer are rewritten
+//! (at the end of the file) periodically and whenever the server becomes idle,
+//! so the file is always readable as long as the process is not killed in the middle of a write.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use jaegercat::parquet::ParquetHandler;
+//! use jaegercat::server::ServerBuilder;
+//!
+//! let mut handler = ParquetHandler::create("spans.parquet").unwrap();
+//! handler.row_group_size(10_000);
+//! let server = ServerBuilder::new().build(handler).unwrap();
+//! server.join().unwrap();
+//! ```
+//!
+//! [Parquet]: https://parquet.apache.org/docs/file-format/
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use trackable::error::Failure;
+
+use Result;
+use csv::Column;
+use handler::BatchHandler;
+use model::Span;
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+const MAGIC: &[u8] = b"PAR1";
+
+/// Parquet physical types.
+const BOOLEAN: i32 = 0;
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+
+/// Parquet encodings.
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+/// Returns the columns written by default
+/// (`trace_id,span_id,parent_span_id,service,operation,start,duration_us,error,kind`).
+pub fn default_columns() -> Vec<Column> {
+    vec![
+        Column::TraceId,
+        Column::SpanId,
+        Column::ParentSpanId,
+        Column::Service,
+        Column::Operation,
+        Column::Start,
+        Column::DurationUs,
+        Column::Error,
+        Column::Kind,
+    ]
+}
+
+/// A `BatchHandler` implementation which writes spans into a Parquet file.
+#[derive(Debug)]
+pub struct ParquetHandler {
+    file: File,
+    columns: Vec<Column>,
+    chunks: Vec<ColumnChunk>,
+    rows: usize,
+    unflushed: bool,
+    row_groups: Vec<RowGroup>,
+    data_end: u64,
+    row_group_size: usize,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+impl ParquetHandler {
+    /// Creates (or truncates) the file at `path`, and writes an empty Parquet file to it.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = track!(
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(Failure::from_error)
+        )?;
+        let columns = default_columns();
+        let mut handler = ParquetHandler {
+            file,
+            chunks: columns.iter().map(|_| ColumnChunk::default()).collect(),
+            columns,
+            rows: 0,
+            unflushed: false,
+            row_groups: Vec::new(),
+            data_end: MAGIC.len() as u64,
+            row_group_size: 100_000,
+            flush_interval: Duration::from_secs(1),
+            last_flush: Instant::now(),
+        };
+        track!(handler.file.write_all(MAGIC).map_err(Failure::from_error))?;
+        track!(handler.flush())?;
+        Ok(handler)
+    }
+
+    /// Sets the columns.
+    ///
+    /// This must be called before any span is written.
+    ///
+    /// The default value is `default_columns()`.
+    pub fn columns(&mut self, columns: Vec<Column>) -> &mut Self {
+        self.chunks = columns.iter().map(|_| ColumnChunk::default()).collect();
+        self.columns = columns;
+        self
+    }
+
+    /// Sets the number of the rows of each row group.
+    ///
+    /// The default value is `100000`.
+    pub fn row_group_size(&mut self, rows: usize) -> &mut Self {
+        self.row_group_size = rows.max(1);
+        self
+    }
+
+    /// Sets the maximum interval between rewrites of the open row group while spans keep arriving.
+    ///
+    /// The open row group is also rewritten whenever the server becomes idle.
+    ///
+    /// The default value is 1 second.
+    pub fn flush_interval(&mut self, interval: Duration) -> &mut Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Appends a row per span of `batch`.
+    ///
+    /// The rows are written when the row group becomes full, the flush interval has elapsed
+    /// or `flush` is called.
+    pub fn write(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) -> Result<()> {
+        let mut value = Vec::new();
+        for span in &batch.batch.spans {
+            for (column, chunk) in self.columns.iter().zip(&mut self.chunks) {
+                value.clear();
+                chunk.push(column, batch, span, meta, &mut value);
+            }
+            self.rows += 1;
+            self.unflushed = true;
+            if self.rows >= self.row_group_size {
+                track!(self.write_row_groups(true))?;
+                self.unflushed = false;
+            }
+        }
+        if self.unflushed && self.last_flush.elapsed() >= self.flush_interval {
+            track!(self.flush())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the open row group (if any) and the footer, which makes the file complete.
+    ///
+    /// The rows of the open row group are kept, and are written again with the subsequent rows.
+    pub fn flush(&mut self) -> Result<()> {
+        track!(self.write_row_groups(false))?;
+        self.unflushed = false;
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// Writes the open row group and the footer after the closed row groups.
+    /// If `close` is `true`, the open row group is closed.
+    fn write_row_groups(&mut self, close: bool) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut row_groups = self.row_groups.clone();
+        if self.rows > 0 {
+            row_groups.push(self.encode_row_group(&mut buf));
+        }
+        let row_groups_end = self.data_end + buf.len() as u64;
+
+        let num_rows = row_groups.iter().map(|g| g.num_rows).sum::<i64>();
+        let row_group_structs = row_groups
+            .iter()
+            .map(|g| g.to_thrift(&self.columns))
+            .collect::<Vec<_>>();
+        let metadata = Thrift::Struct(vec![
+            field(1, 1i32),
+            field(2, self.schema()),
+            field(3, num_rows),
+            field(4, row_group_structs),
+            field(6, concat!("jaegercat version ", env!("CARGO_PKG_VERSION")).to_owned()),
+        ]);
+        let metadata_start = buf.len();
+        metadata.encode(&mut buf);
+        let metadata_len = (buf.len() - metadata_start) as u32;
+        buf.extend_from_slice(&metadata_len.to_le_bytes());
+        buf.extend_from_slice(MAGIC);
+
+        track!(self.file.seek(SeekFrom::Start(self.data_end)).map_err(Failure::from_error))?;
+        track!(self.file.write_all(&buf).map_err(Failure::from_error))?;
+        let len = self.data_end + buf.len() as u64;
+        track!(self.file.set_len(len).map_err(Failure::from_error))?;
+
+        if close && self.rows > 0 {
+            self.row_groups = row_groups;
+            self.data_end = row_groups_end;
+            self.rows = 0;
+            for chunk in &mut self.chunks {
+                *chunk = ColumnChunk::default();
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the column chunks of the open row group to `buf`,
+    /// which is written at `self.data_end`.
+    fn encode_row_group(&self, buf: &mut Vec<u8>) -> RowGroup {
+        let mut row_group = RowGroup {
+            columns: Vec::with_capacity(self.columns.len()),
+            num_rows: self.rows as i64,
+        };
+        for (column, chunk) in self.columns.iter().zip(&self.chunks) {
+            let mut data = Vec::new();
+            if is_optional(column) {
+                let mut levels = Vec::new();
+                write_rle_levels(&mut levels, &chunk.defined);
+                data.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+                data.extend_from_slice(&levels);
+            }
+            if physical_type(column) == BOOLEAN {
+                write_bit_packed(&mut data, &chunk.bools);
+            } else {
+                data.extend_from_slice(&chunk.values);
+            }
+            let header = Thrift::Struct(vec![
+                field(1, 0i32), // DATA_PAGE
+                field(2, data.len() as i32),
+                field(3, data.len() as i32),
+                field(
+                    5,
+                    Thrift::Struct(vec![
+                        field(1, self.rows as i32),
+                        field(2, PLAIN),
+                        field(3, RLE),
+                        field(4, RLE),
+                    ]),
+                ),
+            ]);
+            let offset = self.data_end + buf.len() as u64;
+            header.encode(buf);
+            buf.extend_from_slice(&data);
+            row_group.columns.push(ColumnMeta {
+                offset: offset as i64,
+                size: (self.data_end + buf.len() as u64 - offset) as i64,
+            });
+        }
+        row_group
+    }
+
+    fn schema(&self) -> Vec<Thrift> {
+        let mut schema = vec![Thrift::Struct(vec![
+            field(4, "schema".to_owned()),
+            field(5, self.columns.len() as i32),
+        ])];
+        for column in &self.columns {
+            let mut fields = vec![
+                field(1, physical_type(column)),
+                field(3, if is_optional(column) { 1i32 } else { 0i32 }),
+                field(4, column.to_string()),
+            ];
+            match physical_type(column) {
+                BYTE_ARRAY => {
+                    // UTF8 and STRING
+                    fields.push(field(6, 0i32));
+                    fields.push(field(10, Thrift::Struct(vec![field(1, Thrift::Struct(vec![]))])));
+                }
+                INT64 if *column == Column::Start => {
+                    // TIMESTAMP_MICROS and TIMESTAMP(isAdjustedToUTC=true, unit=MICROS)
+                    let unit = Thrift::Struct(vec![field(2, Thrift::Struct(vec![]))]);
+                    let timestamp = Thrift::Struct(vec![field(1, true), field(2, unit)]);
+                    fields.push(field(6, 10i32));
+                    fields.push(field(10, Thrift::Struct(vec![field(8, timestamp)])));
+                }
+                _ => {}
+            }
+            schema.push(Thrift::Struct(fields));
+        }
+        schema
+    }
+}
+impl BatchHandler for ParquetHandler {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        track_try_unwrap!(self.write(batch, meta));
+    }
+
+    fn on_idle(&mut self) {
+        if self.unflushed {
+            track_try_unwrap!(self.flush());
+        }
+    }
+}
+
+/// The values of a column in the open row group.
+#[derive(Debug, Default)]
+struct ColumnChunk {
+    /// The `PLAIN` encoded values except booleans and nulls.
+    values: Vec<u8>,
+    bools: Vec<bool>,
+    /// Whether each value is not null (only for optional columns).
+    defined: Vec<bool>,
+}
+impl ColumnChunk {
+    fn push(
+        &mut self,
+        column: &Column,
+        batch: &EmitBatchNotification,
+        span: &Span,
+        meta: &ReceiveMeta,
+        scratch: &mut Vec<u8>,
+    ) {
+        match *column {
+            Column::Start => self.values.extend_from_slice(&span.start_time.to_le_bytes()),
+            Column::DurationUs => self.values.extend_from_slice(&span.duration.to_le_bytes()),
+            Column::Duration => {
+                let seconds = span.duration as f64 / 1_000_000.0;
+                self.values.extend_from_slice(&seconds.to_bits().to_le_bytes());
+            }
+            Column::DurationMs => {
+                let millis = span.duration as f64 / 1000.0;
+                self.values.extend_from_slice(&millis.to_bits().to_le_bytes());
+            }
+            Column::Flags => self.values.extend_from_slice(&span.flags.to_le_bytes()),
+            Column::Error => {
+                column.write_value(scratch, batch, span, meta);
+                self.bools.push(scratch == b"true");
+            }
+            _ => {
+                column.write_value(scratch, batch, span, meta);
+                if is_optional(column) {
+                    self.defined.push(!scratch.is_empty());
+                    if scratch.is_empty() {
+                        return;
+                    }
+                }
+                self.values.extend_from_slice(&(scratch.len() as u32).to_le_bytes());
+                self.values.extend_from_slice(scratch);
+            }
+        }
+    }
+}
+
+/// The location of a column chunk (which consists of a data page).
+#[derive(Debug, Clone)]
+struct ColumnMeta {
+    offset: i64,
+    size: i64,
+}
+
+#[derive(Debug, Clone)]
+struct RowGroup {
+    columns: Vec<ColumnMeta>,
+    num_rows: i64,
+}
+impl RowGroup {
+    fn to_thrift(&self, columns: &[Column]) -> Thrift {
+        let chunks = columns
+            .iter()
+            .zip(&self.columns)
+            .map(|(column, meta)| {
+                let metadata = Thrift::Struct(vec![
+                    field(1, physical_type(column)),
+                    field(2, vec![Thrift::I32(PLAIN), Thrift::I32(RLE)]),
+                    field(3, vec![Thrift::from(column.to_string())]),
+                    field(4, 0i32), // UNCOMPRESSED
+                    field(5, self.num_rows),
+                    field(6, meta.size),
+                    field(7, meta.size),
+                    field(9, meta.offset),
+                ]);
+                Thrift::Struct(vec![field(2, meta.offset), field(3, metadata)])
+            })
+            .collect::<Vec<_>>();
+        Thrift::Struct(vec![
+            field(1, chunks),
+            field(2, self.columns.iter().map(|c| c.size).sum::<i64>()),
+            field(3, self.num_rows),
+        ])
+    }
+}
+
+/// A value of the Thrift compact protocol, in which Parquet metadata are encoded.
+#[derive(Debug, PartialEq)]
+enum Thrift {
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    Binary(Vec<u8>),
+    List(Vec<Thrift>),
+    Struct(Vec<(i16, Thrift)>),
+}
+impl Thrift {
+    fn kind(&self) -> u8 {
+        match *self {
+            Thrift::Bool(true) => 1,
+            Thrift::Bool(false) => 2,
+            Thrift::I32(_) => 5,
+            Thrift::I64(_) => 6,
+            Thrift::Binary(_) => 8,
+            Thrift::List(_) => 9,
+            Thrift::Struct(_) => 12,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match *self {
+            Thrift::Bool(v) => out.push(v as u8),
+            Thrift::I32(v) => write_uleb128(out, ((v << 1) ^ (v >> 31)) as u32 as u64),
+            Thrift::I64(v) => write_uleb128(out, ((v << 1) ^ (v >> 63)) as u64),
+            Thrift::Binary(ref v) => {
+                write_uleb128(out, v.len() as u64);
+                out.extend_from_slice(v);
+            }
+            Thrift::List(ref elements) => {
+                let kind = elements.first().map_or(12, |e| e.kind());
+                if elements.len() < 15 {
+                    out.push((elements.len() as u8) << 4 | kind);
+                } else {
+                    out.push(0xF0 | kind);
+                    write_uleb128(out, elements.len() as u64);
+                }
+                for e in elements {
+                    e.encode(out);
+                }
+            }
+            Thrift::Struct(ref fields) => {
+                let mut last_id = 0;
+                for &(id, ref value) in fields {
+                    // The field IDs are always in ascending order
+                    let delta = id - last_id;
+                    if delta <= 15 {
+                        out.push((delta as u8) << 4 | value.kind());
+                    } else {
+                        out.push(value.kind());
+                        Thrift::I32(i32::from(id)).encode(out);
+                    }
+                    if let Thrift::Bool(_) = *value {
+                        // The value is in the field type
+                    } else {
+                        value.encode(out);
+                    }
+                    last_id = id;
+                }
+                out.push(0);
+            }
+        }
+    }
+}
+impl From<bool> for Thrift {
+    fn from(v: bool) -> Self {
+        Thrift::Bool(v)
+    }
+}
+impl From<i32> for Thrift {
+    fn from(v: i32) -> Self {
+        Thrift::I32(v)
+    }
+}
+impl From<i64> for Thrift {
+    fn from(v: i64) -> Self {
+        Thrift::I64(v)
+    }
+}
+impl From<String> for Thrift {
+    fn from(v: String) -> Self {
+        Thrift::Binary(v.into_bytes())
+    }
+}
+impl From<Vec<Thrift>> for Thrift {
+    fn from(v: Vec<Thrift>) -> Self {
+        Thrift::List(v)
+    }
+}
+
+fn field<T: Into<Thrift>>(id: i16, value: T) -> (i16, Thrift) {
+    (id, value.into())
+}
+
+fn physical_type(column: &Column) -> i32 {
+    match *column {
+        Column::Start | Column::DurationUs => INT64,
+        Column::Duration | Column::DurationMs => DOUBLE,
+        Column::Flags => INT32,
+        Column::Error => BOOLEAN,
+        _ => BYTE_ARRAY,
+    }
+}
+
+fn is_optional(column: &Column) -> bool {
+    matches!(*column, Column::ParentSpanId | Column::Kind | Column::Tag(_))
+}
+
+/// Writes definition levels (of bit width 1) as runs of the RLE/bit-packing hybrid encoding.
+fn write_rle_levels(out: &mut Vec<u8>, defined: &[bool]) {
+    let mut i = 0;
+    while i < defined.len() {
+        let run = defined[i..].iter().take_while(|&&d| d == defined[i]).count();
+        write_uleb128(out, (run as u64) << 1);
+        out.push(defined[i] as u8);
+        i += run;
+    }
+}
+
+/// Writes booleans in the `PLAIN` encoding (bit-packed, from the least significant bit).
+fn write_bit_packed(out: &mut Vec<u8>, bools: &[bool]) {
+    for chunk in bools.chunks(8) {
+        let byte = chunk
+            .iter()
+            .enumerate()
+            .fold(0u8, |byte, (i, &b)| byte | ((b as u8) << i));
+        out.push(byte);
+    }
+}
+
+fn write_uleb128(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::net::SocketAddr;
+
+    use super::*;
+    use model::{Batch, Process, SpanId, Tag, TraceId, UnknownFields};
+    use thrift::Protocol;
+    use wire::{Reader, Type};
+
+    fn span(span_id: i64, parent_span_id: Option<i64>, tags: Vec<Tag>) -> Span {
+        Span {
+            trace_id: TraceId { high: 0, low: 1 },
+            span_id: SpanId(span_id),
+            parent_span_id: parent_span_id.map(SpanId),
+            operation_name: "get".to_owned(),
+            references: Vec::new(),
+            flags: span_id as i32,
+            start_time: 1_544_712_660_000_000 + span_id,
+            duration: 1500,
+            tags,
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    /// Reads a value of the Thrift compact protocol (of the types used by Parquet metadata).
+    fn read_value(reader: &mut Reader, ty: Type) -> Thrift {
+        match ty {
+            Type::Bool => Thrift::Bool(reader.read_bool().unwrap()),
+            Type::I32 => Thrift::I32(reader.read_i32().unwrap()),
+            Type::I64 => Thrift::I64(reader.read_i64().unwrap()),
+            Type::Binary => Thrift::Binary(reader.read_binary().unwrap().to_vec()),
+            Type::List => {
+                let (ty, size) = reader.read_list_begin().unwrap();
+                Thrift::List((0..size).map(|_| read_value(reader, ty)).collect())
+            }
+            Type::Struct => {
+                let mut fields = Vec::new();
+                reader.read_struct_begin().unwrap();
+                while let Some(header) = reader.read_field_begin().unwrap() {
+                    fields.push((header.id, read_value(reader, header.ty)));
+                }
+                reader.read_struct_end();
+                Thrift::Struct(fields)
+            }
+            _ => panic!("Unexpected type: {:?}", ty),
+        }
+    }
+
+    fn get(value: &Thrift, id: i16) -> &Thrift {
+        match *value {
+            Thrift::Struct(ref fields) => match fields.iter().find(|f| f.0 == id) {
+                Some(f) => &f.1,
+                None => panic!("No field {} in {:?}", id, value),
+            },
+            _ => panic!("Not a struct: {:?}", value),
+        }
+    }
+
+    fn list(value: &Thrift) -> &[Thrift] {
+        match *value {
+            Thrift::List(ref elements) => elements,
+            _ => panic!("Not a list: {:?}", value),
+        }
+    }
+
+    fn i64_value(value: &Thrift) -> i64 {
+        match *value {
+            Thrift::I64(v) => v,
+            _ => panic!("Not an i64: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn footer_and_schema_are_decoded() {
+        let path = ::std::env::temp_dir().join(format!(
+            "jaegercat-parquet-test-{}.parquet",
+            ::std::process::id()
+        ));
+        let columns = vec![
+            Column::SpanId,
+            Column::ParentSpanId,
+            Column::Start,
+            Column::Duration,
+            Column::Flags,
+            Column::Error,
+            Column::Kind,
+        ];
+        let batch = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans: vec![
+                    span(1, None, vec![Tag::new("span.kind", "server")]),
+                    span(2, Some(1), vec![Tag::new("error", true)]),
+                    span(3, Some(1), Vec::new()),
+                ],
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            port: 6831,
+            protocol: &Protocol::Compact,
+            bytes: &[],
+        };
+        let mut handler = ParquetHandler::create(&path).unwrap();
+        handler.columns(columns).row_group_size(2);
+        handler.write(&batch, &meta).unwrap();
+        handler.flush().unwrap();
+        let file = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+        let mut len = [0; 4];
+        len.copy_from_slice(&file[file.len() - 8..file.len() - 4]);
+        let metadata_start = file.len() - 8 - u32::from_le_bytes(len) as usize;
+        let mut reader = Reader::new(&file[metadata_start..file.len() - 8], Protocol::Compact);
+        let metadata = read_value(&mut reader, Type::Struct);
+        assert_eq!(reader.position(), file.len() - 8 - metadata_start);
+
+        // FileMetaData
+        assert_eq!(*get(&metadata, 1), Thrift::I32(1));
+        assert_eq!(i64_value(get(&metadata, 3)), 3);
+        match *get(&metadata, 6) {
+            Thrift::Binary(ref v) => assert!(v.starts_with(b"jaegercat version ")),
+            ref v => panic!("Unexpected created_by: {:?}", v),
+        }
+
+        // SchemaElements (type, repetition_type, name, converted_type, logicalType)
+        let string = || field(10, Thrift::Struct(vec![field(1, Thrift::Struct(vec![]))]));
+        let micros = Thrift::Struct(vec![field(2, Thrift::Struct(vec![]))]);
+        let timestamp = Thrift::Struct(vec![field(1, true), field(2, micros)]);
+        let schema = [
+            Thrift::Struct(vec![field(4, "schema".to_owned()), field(5, 7i32)]),
+            Thrift::Struct(vec![
+                field(1, BYTE_ARRAY),
+                field(3, 0i32),
+                field(4, "span_id".to_owned()),
+                field(6, 0i32),
+                string(),
+            ]),
+            Thrift::Struct(vec![
+                field(1, BYTE_ARRAY),
+                field(3, 1i32),
+                field(4, "parent_span_id".to_owned()),
+                field(6, 0i32),
+                string(),
+            ]),
+            Thrift::Struct(vec![
+                field(1, INT64),
+                field(3, 0i32),
+                field(4, "start".to_owned()),
+                field(6, 10i32),
+                field(10, Thrift::Struct(vec![field(8, timestamp)])),
+            ]),
+            Thrift::Struct(vec![field(1, DOUBLE), field(3, 0i32), field(4, "duration".to_owned())]),
+            Thrift::Struct(vec![field(1, INT32), field(3, 0i32), field(4, "flags".to_owned())]),
+            Thrift::Struct(vec![field(1, BOOLEAN), field(3, 0i32), field(4, "error".to_owned())]),
+            Thrift::Struct(vec![
+                field(1, BYTE_ARRAY),
+                field(3, 1i32),
+                field(4, "kind".to_owned()),
+                field(6, 0i32),
+                string(),
+            ]),
+        ];
+        assert_eq!(list(get(&metadata, 2)), &schema[..]);
+
+        // RowGroups: the closed one of 2 rows, and the open one of the third row
+        let row_groups = list(get(&metadata, 4));
+        assert_eq!(row_groups.len(), 2);
+        let mut offset = MAGIC.len() as i64;
+        let mut pages = Vec::new();
+        for (row_group, &num_rows) in row_groups.iter().zip(&[2i64, 1]) {
+            assert_eq!(i64_value(get(row_group, 3)), num_rows);
+            let chunks = list(get(row_group, 1));
+            assert_eq!(chunks.len(), 7);
+            let mut total_size = 0;
+            for chunk in chunks {
+                let meta = get(chunk, 3);
+                assert_eq!(i64_value(get(chunk, 2)), offset);
+                assert_eq!(i64_value(get(meta, 9)), offset);
+                assert_eq!(i64_value(get(meta, 5)), num_rows);
+                assert_eq!(*get(meta, 4), Thrift::I32(0));
+                let size = i64_value(get(meta, 7));
+                assert_eq!(i64_value(get(meta, 6)), size);
+
+                // PageHeader (DATA_PAGE of the PLAIN values)
+                let page = &file[offset as usize..(offset + size) as usize];
+                let mut reader = Reader::new(page, Protocol::Compact);
+                let header = read_value(&mut reader, Type::Struct);
+                assert_eq!(*get(&header, 1), Thrift::I32(0));
+                assert_eq!(*get(&header, 2), Thrift::I32((page.len() - reader.position()) as i32));
+                assert_eq!(*get(get(&header, 5), 1), Thrift::I32(num_rows as i32));
+                assert_eq!(*get(get(&header, 5), 2), Thrift::I32(PLAIN));
+                pages.push(page[reader.position()..].to_vec());
+
+                offset += size;
+                total_size += size;
+            }
+            assert_eq!(i64_value(get(row_group, 2)), total_size);
+        }
+        assert_eq!(offset as usize, metadata_start);
+
+        // span_id: a length-prefixed string per row
+        assert_eq!(pages[0], b"\x03\x00\x00\x000x1\x03\x00\x00\x000x2");
+        assert_eq!(pages[7], b"\x03\x00\x00\x000x3");
+
+        // parent_span_id: definition levels (the RLE runs [0] and [1]) and the defined value
+        assert_eq!(pages[1], b"\x04\x00\x00\x00\x02\x00\x02\x01\x03\x00\x00\x000x1");
+
+        // start, duration and flags: little-endian values
+        let mut start = Vec::new();
+        start.extend_from_slice(&1_544_712_660_000_001i64.to_le_bytes());
+        start.extend_from_slice(&1_544_712_660_000_002i64.to_le_bytes());
+        assert_eq!(pages[2], start);
+        assert_eq!(pages[10], 0.0015f64.to_bits().to_le_bytes());
+        assert_eq!(pages[4], b"\x01\x00\x00\x00\x02\x00\x00\x00");
+
+        // error: bit-packed booleans
+        assert_eq!(pages[5], [0b10]);
+        assert_eq!(pages[12], [0]);
+
+        // kind: a defined value and a null, and a null only
+        assert_eq!(pages[6], b"\x04\x00\x00\x00\x02\x01\x02\x00\x06\x00\x00\x00server");
+        assert_eq!(pages[13], b"\x02\x00\x00\x00\x02\x00");
+    }
+}
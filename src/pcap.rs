@@ -0,0 +1,586 @@
+//! The `pcap` subcommand, which decodes the messages sent to the agent ports
+//! in a capture file written by tcpdump (or another libpcap based tool).
+//!
+//! Both the classic pcap format and the pcapng format are supported.
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use jaegercat::Result;
+use jaegercat::handler::Formatter;
+use jaegercat::protocol::WireProtocol;
+use jaegercat::server::ReceiveMeta;
+use trackable::error::{Failed, Failure};
+
+use decode::{self, Decode};
+
+/// Link-layer header types (<https://www.tcpdump.org/linktypes.html>).
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// pcapng block types.
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+
+/// Settings of a pcap run.
+pub struct Pcap {
+    pub decode: Decode,
+
+    /// The destination ports of the UDP datagrams to decode, and their protocols.
+    pub ports: Vec<(u16, Box<dyn WireProtocol>)>,
+}
+impl Pcap {
+    /// Decodes the UDP datagrams sent to `ports` in the capture file at `path`
+    /// (the standard input if it is `-`), and writes them to the standard output.
+    ///
+    /// The file is read as a stream, so the messages are written while it is being read
+    /// (and each of them is flushed if the file is the standard input).
+    /// IP fragments and datagrams truncated by the snapshot length are reported and skipped,
+    /// and so is the last packet if it is truncated (e.g., tcpdump was stopped while writing it).
+    /// Malformed messages are reported to the standard error, and make the run fail
+    /// after the whole capture is processed.
+    pub fn run(&self, path: &str, formatter: &Formatter) -> Result<()> {
+        let input = track!(decode::open_input(path))?;
+        let mut capture = track!(CaptureReader::new(BufReader::new(input)), "{}", path)?;
+
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        let mut packets = 0;
+        let mut messages = 0;
+        let mut errors = 0;
+        loop {
+            let packet = track!(capture.next_packet(), "{}: packet={}", path, packets + 1)?;
+            let packet = match packet {
+                Some(packet) => packet,
+                None => break,
+            };
+            packets += 1;
+            let location = format!("{}: packet={}", path, packets);
+            let written = track!(self.write_packet(
+                packet.link_type,
+                packet.data,
+                &location,
+                formatter,
+                &mut writer
+            ))?;
+            match written {
+                None => {}
+                Some(true) => messages += 1,
+                Some(false) => {
+                    messages += 1;
+                    errors += 1;
+                }
+            }
+            if path == "-" && written.is_some() {
+                // The standard input may be a live capture
+                track!(writer.flush().map_err(Failure::from_error))?;
+            }
+        }
+        track!(writer.flush().map_err(Failure::from_error))?;
+        if let Some(offset) = capture.truncated_at {
+            eprintln!("{}: offset={}: the last packet is truncated and skipped", path, offset);
+        }
+        eprintln!("{}: {} packets, {} messages", path, packets, messages);
+        track_assert_eq!(errors, 0, Failed, "{} messages could not be decoded", errors);
+        Ok(())
+    }
+
+    /// Decodes and writes the UDP datagram in a packet if it is sent to one of `ports`.
+    ///
+    /// Returns `None` if the packet contains no such datagram,
+    /// and `Some(false)` if the message in the datagram is malformed.
+    pub fn write_packet<W: Write>(
+        &self,
+        link_type: u32,
+        data: &[u8],
+        location: &str,
+        formatter: &Formatter,
+        writer: &mut W,
+    ) -> Result<Option<bool>> {
+        let datagram = match dissect(link_type, data) {
+            Ok(Some(datagram)) => datagram,
+            Ok(None) => return Ok(None),
+            Err(reason) => {
+                if self.protocol(reason.port()).is_some() {
+                    eprintln!("{}: skipped: {}", location, reason);
+                }
+                return Ok(None);
+            }
+        };
+        let protocol = match self.protocol(datagram.dst.port()) {
+            None => return Ok(None),
+            Some(protocol) => protocol,
+        };
+        let meta = ReceiveMeta {
+            peer: datagram.src,
+            port: datagram.dst.port(),
+            protocol,
+            bytes: datagram.payload,
+        };
+        let ok = track!(self.decode.write_message(&meta, location, formatter, writer))?;
+        Ok(Some(ok))
+    }
+
+    fn protocol(&self, port: u16) -> Option<&dyn WireProtocol> {
+        self.ports
+            .iter()
+            .find(|&&(p, _)| p == port)
+            .map(|(_, protocol)| &**protocol)
+    }
+}
+
+/// A captured packet.
+struct Packet<'a> {
+    link_type: u32,
+    data: &'a [u8],
+}
+
+/// A reader of the packets in a capture file in either of the pcap and pcapng formats.
+struct CaptureReader<R> {
+    reader: R,
+    pcapng: bool,
+    bytes: Bytes,
+
+    /// The link types of the interfaces (of the current section of pcapng),
+    /// or the link type of the packets of pcap.
+    link_types: Vec<u32>,
+
+    /// The current record (a packet record or a block), preceded by the previous one
+    /// (of `consumed` bytes) until the current one is read.
+    buf: Vec<u8>,
+    consumed: usize,
+    offset: u64,
+
+    /// The offset of the last record if it is truncated.
+    truncated_at: Option<u64>,
+}
+impl<R: Read> CaptureReader<R> {
+    /// Reads the header of a capture file.
+    fn new(reader: R) -> Result<Self> {
+        let mut capture = CaptureReader {
+            reader,
+            pcapng: true,
+            bytes: Bytes::Little,
+            link_types: Vec::new(),
+            buf: Vec::new(),
+            consumed: 0,
+            offset: 0,
+            truncated_at: None,
+        };
+        let len = track!(capture.fill(4).map_err(Failure::from_error))?;
+        track_assert_eq!(len, 4, Failed, "Too short capture file");
+        if let Some(bytes) = Bytes::magic(&capture.buf) {
+            let len = track!(capture.fill(24).map_err(Failure::from_error))?;
+            track_assert_eq!(len, 24, Failed, "Too short capture file");
+            capture.pcapng = false;
+            capture.bytes = bytes;
+            capture.link_types.push(bytes.u32(&capture.buf, 20) & 0x0FFF_FFFF);
+            capture.consume(24);
+        } else {
+            // The section header block is read as the first block
+            let block_type = Bytes::Little.u32(&capture.buf, 0);
+            track_assert_eq!(block_type, SECTION_HEADER_BLOCK, Failed, "Not a capture file");
+        }
+        Ok(capture)
+    }
+
+    /// Reads the next packet, and returns `None` at the end of the capture
+    /// (or at a truncated packet, whose offset is set to `truncated_at`).
+    fn next_packet(&mut self) -> Result<Option<Packet<'_>>> {
+        // The link type and the range of the data in the buffer
+        let packet = if self.pcapng {
+            track!(self.next_pcapng_packet())?
+        } else {
+            track!(self.next_pcap_packet())?
+        };
+        Ok(packet.map(move |(link_type, start, end)| Packet {
+            link_type,
+            data: &self.buf[start..end],
+        }))
+    }
+
+    fn next_pcap_packet(&mut self) -> Result<Option<(u32, usize, usize)>> {
+        if !track!(self.read_record(16))? {
+            return Ok(None);
+        }
+        let captured_len = self.bytes.u32(&self.buf, 8) as usize;
+        if !track!(self.read_record(16 + captured_len))? {
+            return Ok(None);
+        }
+        self.consume(16 + captured_len);
+        Ok(Some((self.link_types[0], 16, 16 + captured_len)))
+    }
+
+    fn next_pcapng_packet(&mut self) -> Result<Option<(u32, usize, usize)>> {
+        loop {
+            let offset = self.offset;
+            if !track!(self.read_record(12))? {
+                return Ok(None);
+            }
+            if Bytes::Little.u32(&self.buf, 0) == SECTION_HEADER_BLOCK {
+                // Each section may have its own byte order and interfaces
+                self.bytes = match Bytes::magic(&self.buf[8..12]) {
+                    Some(bytes) => bytes,
+                    None => track_panic!(Failed, "Unknown byte-order magic at {}", offset + 8),
+                };
+                self.link_types.clear();
+            }
+            let bytes = self.bytes;
+            let block_type = bytes.u32(&self.buf, 0);
+            let block_len = bytes.u32(&self.buf, 4) as usize;
+            track_assert!(
+                block_len >= 12 && block_len.is_multiple_of(4),
+                Failed,
+                "Invalid block length {} at {}",
+                block_len,
+                offset
+            );
+            if !track!(self.read_record(block_len))? {
+                return Ok(None);
+            }
+            self.consume(block_len);
+
+            let body = &self.buf[8..block_len - 4];
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => {
+                    track_assert!(body.len() >= 8, Failed, "Too short block at {}", offset);
+                    self.link_types.push(u32::from(bytes.u16(body, 0)));
+                }
+                ENHANCED_PACKET_BLOCK => {
+                    track_assert!(body.len() >= 20, Failed, "Too short block at {}", offset);
+                    let interface = bytes.u32(body, 0) as usize;
+                    let captured_len = bytes.u32(body, 12) as usize;
+                    track_assert!(
+                        body.len() - 20 >= captured_len,
+                        Failed,
+                        "Invalid captured length {} at {}",
+                        captured_len,
+                        offset
+                    );
+                    let link_type = track_assert_some!(
+                        self.link_types.get(interface),
+                        Failed,
+                        "Unknown interface {} at {}",
+                        interface,
+                        offset
+                    );
+                    return Ok(Some((*link_type, 28, 28 + captured_len)));
+                }
+                SIMPLE_PACKET_BLOCK => {
+                    track_assert!(body.len() >= 4, Failed, "Too short block at {}", offset);
+                    let original_len = bytes.u32(body, 0) as usize;
+                    let link_type = track_assert_some!(
+                        self.link_types.first(),
+                        Failed,
+                        "No interface for the packet at {}",
+                        offset
+                    );
+                    let captured_len = original_len.min(body.len() - 4);
+                    return Ok(Some((*link_type, 12, 12 + captured_len)));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the first `len` bytes of the current record (keeping the bytes already read),
+    /// and returns `false` at the end of the capture.
+    ///
+    /// If the capture ends in the middle of the record, its offset is set to `truncated_at`.
+    fn read_record(&mut self, len: usize) -> Result<bool> {
+        if self.consumed != 0 {
+            self.buf.drain(..self.consumed);
+            self.consumed = 0;
+        }
+        let read = track!(self.fill(len).map_err(Failure::from_error))?;
+        if read == len {
+            return Ok(true);
+        }
+        if read != 0 {
+            self.truncated_at = Some(self.offset);
+        }
+        Ok(false)
+    }
+
+    /// Marks the current record of `len` bytes as read, which is removed from the buffer
+    /// when the next one is read.
+    fn consume(&mut self, len: usize) {
+        self.consumed = len;
+        self.offset += len as u64;
+    }
+
+    /// Reads `self.buf` up to `len` bytes, and returns its length (less than `len` at the end).
+    fn fill(&mut self, len: usize) -> io::Result<usize> {
+        if self.buf.len() < len {
+            let remaining = (len - self.buf.len()) as u64;
+            (&mut self.reader).take(remaining).read_to_end(&mut self.buf)?;
+        }
+        Ok(self.buf.len())
+    }
+}
+
+/// The byte order of the headers in a capture file.
+#[derive(Clone, Copy)]
+enum Bytes {
+    Little,
+    Big,
+}
+impl Bytes {
+    /// Detects the byte order from the magic number of a pcap file header or
+    /// the byte-order magic of a pcapng section header.
+    fn magic(buf: &[u8]) -> Option<Self> {
+        let magic = [buf[0], buf[1], buf[2], buf[3]];
+        match u32::from_le_bytes(magic) {
+            0xA1B2_C3D4 | 0xA1B2_3C4D | 0x1A2B_3C4D => return Some(Bytes::Little),
+            _ => {}
+        }
+        match u32::from_be_bytes(magic) {
+            0xA1B2_C3D4 | 0xA1B2_3C4D | 0x1A2B_3C4D => Some(Bytes::Big),
+            _ => None,
+        }
+    }
+
+    fn u16(self, buf: &[u8], pos: usize) -> u16 {
+        let b = [buf[pos], buf[pos + 1]];
+        match self {
+            Bytes::Little => u16::from_le_bytes(b),
+            Bytes::Big => u16::from_be_bytes(b),
+        }
+    }
+
+    fn u32(self, buf: &[u8], pos: usize) -> u32 {
+        let b = [buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]];
+        match self {
+            Bytes::Little => u32::from_le_bytes(b),
+            Bytes::Big => u32::from_be_bytes(b),
+        }
+    }
+}
+
+/// A UDP datagram extracted from a packet.
+struct Datagram<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: &'a [u8],
+}
+
+/// The result of extracting the UDP datagram in a packet.
+type Dissected<'a> = ::std::result::Result<Option<Datagram<'a>>, Skipped>;
+
+/// The reason why a UDP datagram could not be extracted.
+enum Skipped {
+    Fragmented { port: u16 },
+    Truncated { port: u16, len: usize, captured: usize },
+}
+impl Skipped {
+    /// Returns the destination port of the datagram.
+    fn port(&self) -> u16 {
+        match *self {
+            Skipped::Fragmented { port } | Skipped::Truncated { port, .. } => port,
+        }
+    }
+}
+impl ::std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Skipped::Fragmented { .. } => {
+                write!(f, "Fragmented datagram (IP reassembly is not supported)")
+            }
+            Skipped::Truncated { len, captured, .. } => write!(
+                f,
+                "Datagram of {} bytes truncated to {} bytes by the snapshot length",
+                len, captured
+            ),
+        }
+    }
+}
+
+/// Extracts the UDP datagram in a packet, and returns `None` if it is not a UDP packet.
+fn dissect(link_type: u32, data: &[u8]) -> Dissected<'_> {
+    let (ethertype, ip) = match link_type {
+        LINKTYPE_ETHERNET if data.len() >= 14 => {
+            let mut ethertype = be16(data, 12);
+            let mut pos = 14;
+            while (ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ)
+                && data.len() >= pos + 4
+            {
+                ethertype = be16(data, pos + 2);
+                pos += 4;
+            }
+            (ethertype, &data[pos..])
+        }
+        LINKTYPE_LINUX_SLL if data.len() >= 16 => (be16(data, 14), &data[16..]),
+        LINKTYPE_LINUX_SLL2 if data.len() >= 20 => (be16(data, 0), &data[20..]),
+        LINKTYPE_NULL | LINKTYPE_LOOP if data.len() >= 4 => {
+            // The address family in the host byte order (NULL) or big-endian (LOOP)
+            let family = if data[0] == 0 { data[3] } else { data[0] };
+            let ethertype = match family {
+                2 => ETHERTYPE_IPV4,
+                10 | 24 | 28 | 30 => ETHERTYPE_IPV6,
+                _ => return Ok(None),
+            };
+            (ethertype, &data[4..])
+        }
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 if !data.is_empty() => match data[0] >> 4 {
+            4 => (ETHERTYPE_IPV4, data),
+            6 => (ETHERTYPE_IPV6, data),
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    match ethertype {
+        ETHERTYPE_IPV4 => dissect_ipv4(ip),
+        ETHERTYPE_IPV6 => dissect_ipv6(ip),
+        _ => Ok(None),
+    }
+}
+
+fn dissect_ipv4(ip: &[u8]) -> Dissected<'_> {
+    if ip.len() < 20 || ip[0] >> 4 != 4 || ip[9] != IPPROTO_UDP {
+        return Ok(None);
+    }
+    let header_len = usize::from(ip[0] & 0x0F) * 4;
+    if header_len < 20 || ip.len() < header_len {
+        return Ok(None);
+    }
+    let flags_and_offset = be16(ip, 6);
+    if flags_and_offset & 0x1FFF != 0 {
+        // Only the first fragment contains the UDP header
+        return Ok(None);
+    }
+    if flags_and_offset & 0x2000 != 0 {
+        return fragmented(&ip[header_len..]);
+    }
+    let src = IpAddr::V4(Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]));
+    let dst = IpAddr::V4(Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]));
+    dissect_udp(src, dst, &ip[header_len..])
+}
+
+fn dissect_ipv6(ip: &[u8]) -> Dissected<'_> {
+    if ip.len() < 40 || ip[0] >> 4 != 6 {
+        return Ok(None);
+    }
+    let mut src = [0; 16];
+    let mut dst = [0; 16];
+    src.copy_from_slice(&ip[8..24]);
+    dst.copy_from_slice(&ip[24..40]);
+    let mut next_header = ip[6];
+    let mut pos = 40;
+    loop {
+        match next_header {
+            IPPROTO_UDP => break,
+            // Hop-by-hop options, routing and destination options
+            0 | 43 | 60 if ip.len() >= pos + 8 => {
+                next_header = ip[pos];
+                pos += (usize::from(ip[pos + 1]) + 1) * 8;
+            }
+            44 if ip.len() >= pos + 8 => {
+                if be16(ip, pos + 2) & 0xFFF8 != 0 || ip[pos] != IPPROTO_UDP {
+                    return Ok(None);
+                }
+                return fragmented(&ip[pos + 8..]);
+            }
+            _ => return Ok(None),
+        }
+    }
+    if ip.len() < pos {
+        return Ok(None);
+    }
+    let src = IpAddr::V6(Ipv6Addr::from(src));
+    let dst = IpAddr::V6(Ipv6Addr::from(dst));
+    dissect_udp(src, dst, &ip[pos..])
+}
+
+fn dissect_udp(src: IpAddr, dst: IpAddr, udp: &[u8]) -> Dissected<'_> {
+    if udp.len() < 8 {
+        return Ok(None);
+    }
+    let port = be16(udp, 2);
+    let len = usize::from(be16(udp, 4));
+    if len < 8 {
+        return Ok(None);
+    }
+    if udp.len() < len {
+        let captured = udp.len() - 8;
+        return Err(Skipped::Truncated { port, len: len - 8, captured });
+    }
+    Ok(Some(Datagram {
+        src: SocketAddr::new(src, be16(udp, 0)),
+        dst: SocketAddr::new(dst, port),
+        payload: &udp[8..len],
+    }))
+}
+
+/// Reports the first fragment of a UDP datagram.
+fn fragmented(udp: &[u8]) -> Dissected<'_> {
+    if udp.len() < 8 {
+        return Ok(None);
+    }
+    Err(Skipped::Fragmented { port: be16(udp, 2) })
+}
+
+fn be16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([buf[pos], buf[pos + 1]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pcap_header() -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&0xA1B2_C3D4u32.to_le_bytes());
+        buf.extend_from_slice(&[2, 0, 4, 0]); // Version 2.4
+        buf.extend_from_slice(&[0; 12]);
+        buf.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        buf
+    }
+
+    fn pcap_record(data: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; 8];
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn capture_reader_works() {
+        let mut buf = pcap_header();
+        buf.extend(pcap_record(b"foo"));
+        buf.extend(pcap_record(b"barbaz"));
+
+        let mut capture = CaptureReader::new(&buf[..]).unwrap();
+        assert_eq!(capture.next_packet().unwrap().unwrap().data, b"foo");
+        let packet = capture.next_packet().unwrap().unwrap();
+        assert_eq!((packet.link_type, packet.data), (LINKTYPE_RAW, &b"barbaz"[..]));
+        assert!(capture.next_packet().unwrap().is_none());
+        assert_eq!(capture.truncated_at, None);
+    }
+
+    #[test]
+    fn capture_reader_skips_truncated_last_packet() {
+        let mut buf = pcap_header();
+        buf.extend(pcap_record(b"foo"));
+        buf.extend(pcap_record(b"barbaz"));
+        buf.truncate(buf.len() - 2);
+
+        let mut capture = CaptureReader::new(&buf[..]).unwrap();
+        assert_eq!(capture.next_packet().unwrap().unwrap().data, b"foo");
+        assert!(capture.next_packet().unwrap().is_none());
+        assert_eq!(capture.truncated_at, Some(24 + 16 + 3));
+    }
+}
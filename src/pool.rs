@@ -0,0 +1,148 @@
+//! Pools of reusable buffers used by `Server`.
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+/// A pool of fixed size buffers.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    inner: Arc<Inner>,
+}
+impl BufferPool {
+    /// Makes a new pool of `buffer_size` bytes buffers, which retains up to `max_pooled`
+    /// released buffers.
+    pub fn new(buffer_size: usize, max_pooled: usize) -> Self {
+        BufferPool {
+            inner: Arc::new(Inner {
+                buffer_size,
+                max_pooled,
+                free: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Allocates buffers in advance until the pool retains `count` buffers
+    /// (or `max_pooled` ones, whichever is smaller).
+    pub fn preallocate(&self, count: usize) {
+        let mut free = self.inner.free.lock().unwrap_or_else(|e| e.into_inner());
+        while free.len() < count.min(self.inner.max_pooled) {
+            free.push(vec![0; self.inner.buffer_size]);
+        }
+    }
+
+    /// Takes a buffer from the pool, or allocates a new one if the pool is empty.
+    ///
+    /// The buffer is returned to the pool when it is dropped.
+    pub fn acquire(&self) -> PooledBuffer {
+        let buf = self.inner
+            .free
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| vec![0; self.inner.buffer_size]);
+        PooledBuffer {
+            buf,
+            len: 0,
+            pool: Arc::clone(&self.inner),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    buffer_size: usize,
+    max_pooled: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+/// A buffer taken from `BufferPool`.
+///
+/// It dereferences to the first `len` bytes set by `set_len`.
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    len: usize,
+    pool: Arc<Inner>,
+}
+impl PooledBuffer {
+    /// Makes a buffer holding `buf`, which is not returned to any pool when dropped.
+    pub fn unpooled(buf: Vec<u8>) -> Self {
+        PooledBuffer {
+            len: buf.len(),
+            buf,
+            pool: Arc::new(Inner {
+                buffer_size: 0,
+                max_pooled: 0,
+                free: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the whole buffer to be filled.
+    pub fn as_mut_buf(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Sets the number of the filled bytes.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.buf.len());
+        self.len = len;
+    }
+}
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        let mut free = self.pool.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.pool.max_pooled {
+            free.push(::std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+/// A pool of growable buffers, which keep their capacities while being reused.
+#[derive(Debug, Clone)]
+pub struct VecPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_pooled: usize,
+}
+impl VecPool {
+    /// Makes a new pool which retains up to `max_pooled` released buffers.
+    pub fn new(max_pooled: usize) -> Self {
+        VecPool {
+            free: Arc::new(Mutex::new(Vec::new())),
+            max_pooled,
+        }
+    }
+
+    /// Allocates buffers of `capacity` bytes in advance until the pool retains `count` buffers
+    /// (or `max_pooled` ones, whichever is smaller).
+    pub fn preallocate(&self, count: usize, capacity: usize) {
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        while free.len() < count.min(self.max_pooled) {
+            free.push(Vec::with_capacity(capacity));
+        }
+    }
+
+    /// Takes an empty buffer from the pool, or allocates a new one if the pool is empty.
+    pub fn acquire(&self) -> Vec<u8> {
+        self.free
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_default()
+    }
+
+    /// Returns `buf` to the pool.
+    pub fn release(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < self.max_pooled {
+            free.push(buf);
+        }
+    }
+}
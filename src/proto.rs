@@ -0,0 +1,224 @@
+//! A minimal reader of the protobuf encoding, which is shared by the decoders of
+//! the OTLP and Zipkin messages, and the checks of `DecodeLimits` used by them.
+//! The `write_*` functions are the counterparts used by the encoder of `kafka`.
+use std::str;
+
+use error::{DecodeError, DecodeResult};
+use thrift::{DecodeLimits, DecodeMode};
+
+/// Protobuf wire types.
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+/// A field value read from a protobuf message.
+///
+/// `Bytes` holds the offset of the bytes in the whole message too.
+#[derive(Clone, Copy)]
+pub enum Field<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8], usize),
+    Fixed32,
+}
+impl<'a> Field<'a> {
+    fn wire_type_name(&self) -> &'static str {
+        match *self {
+            Field::Varint(_) => "varint",
+            Field::Fixed64(_) => "fixed64",
+            Field::Bytes(..) => "length-delimited",
+            Field::Fixed32 => "fixed32",
+        }
+    }
+}
+
+/// A decoder of protobuf messages.
+///
+/// `offset` arguments are the offsets of the given buffers in the whole message.
+pub struct Reader<'b> {
+    pub limits: &'b DecodeLimits,
+    message_name: &'static str,
+    mode: DecodeMode,
+    depth: usize,
+}
+impl<'b> Reader<'b> {
+    /// Makes a new `Reader` instance.
+    ///
+    /// `message_name` is the name of the top-level message, which is used in errors.
+    pub fn new(message_name: &'static str, mode: DecodeMode, limits: &'b DecodeLimits) -> Self {
+        Reader {
+            limits,
+            message_name,
+            mode,
+            depth: 0,
+        }
+    }
+
+    /// Invokes `f` for each field of the message in `buf`.
+    ///
+    /// `f` returns `false` if the field is unknown, which is an error in `DecodeMode::Strict`.
+    pub fn fields<'a, F>(&self, buf: &'a [u8], offset: usize, mut f: F) -> DecodeResult<()>
+    where
+        F: FnMut(&Self, u64, Field<'a>, usize) -> DecodeResult<bool>,
+    {
+        let depth = self.depth + 1;
+        if depth > self.limits.max_depth {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_depth",
+                offset,
+                size: depth as u64,
+                max: self.limits.max_depth,
+            });
+        }
+        let reader = Reader {
+            limits: self.limits,
+            message_name: self.message_name,
+            mode: self.mode,
+            depth,
+        };
+        let mut pos = 0;
+        while pos < buf.len() {
+            let field_offset = offset + pos;
+            let key = read_varint(buf, &mut pos, offset)?;
+            let field = match (key & 7) as u8 {
+                VARINT => Field::Varint(read_varint(buf, &mut pos, offset)?),
+                FIXED64 => Field::Fixed64(read_fixed(buf, &mut pos, offset, 8)?),
+                LENGTH_DELIMITED => {
+                    let len = read_varint(buf, &mut pos, offset)?;
+                    if len > buf.len().saturating_sub(pos) as u64 {
+                        return Err(DecodeError::Truncated {
+                            offset: offset + buf.len(),
+                        });
+                    }
+                    let start = pos;
+                    pos += len as usize;
+                    Field::Bytes(&buf[start..pos], offset + start)
+                }
+                FIXED32 => {
+                    read_fixed(buf, &mut pos, offset, 4)?;
+                    Field::Fixed32
+                }
+                wire_type => {
+                    return Err(DecodeError::UnknownTypeCode {
+                        offset: field_offset,
+                        code: wire_type,
+                    })
+                }
+            };
+            let number = key >> 3;
+            if !f(&reader, number, field, field_offset)? && self.mode == DecodeMode::Strict {
+                return Err(DecodeError::UnknownField {
+                    struct_name: self.message_name,
+                    field_id: number as i16,
+                    offset: field_offset,
+                    actual: field.wire_type_name(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn bytes(&self, buf: &[u8], offset: usize) -> DecodeResult<Vec<u8>> {
+        check_string(buf.len(), self.limits, offset)?;
+        Ok(buf.to_vec())
+    }
+
+    pub fn string(&self, buf: &[u8], offset: usize) -> DecodeResult<String> {
+        check_string(buf.len(), self.limits, offset)?;
+        match str::from_utf8(buf) {
+            Ok(s) => Ok(s.to_owned()),
+            Err(error) => Err(DecodeError::InvalidUtf8 { offset, error }),
+        }
+    }
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize, offset: usize) -> DecodeResult<u64> {
+    let mut value = 0u64;
+    for shift in 0..10 {
+        let b = *buf.get(*pos).ok_or(DecodeError::Truncated {
+            offset: offset + buf.len(),
+        })?;
+        *pos += 1;
+        value |= u64::from(b & 0x7F) << (shift * 7);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidValue {
+        offset: offset + *pos,
+        reason: "Too long varint".to_owned(),
+    })
+}
+
+fn read_fixed(buf: &[u8], pos: &mut usize, offset: usize, size: usize) -> DecodeResult<u64> {
+    if buf.len() - *pos < size {
+        return Err(DecodeError::Truncated {
+            offset: offset + buf.len(),
+        });
+    }
+    let mut value = 0u64;
+    for (i, &b) in buf[*pos..*pos + size].iter().enumerate() {
+        value |= u64::from(b) << (i * 8);
+    }
+    *pos += size;
+    Ok(value)
+}
+
+/// Appends a varint to `out`.
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Appends a varint field to `out` unless `value` is zero (the default value, which is omitted).
+pub fn write_varint_field(out: &mut Vec<u8>, number: u64, value: u64) {
+    if value != 0 {
+        write_varint(out, number << 3 | u64::from(VARINT));
+        write_varint(out, value);
+    }
+}
+
+/// Appends a fixed64 field to `out` unless `value` is zero.
+pub fn write_fixed64_field(out: &mut Vec<u8>, number: u64, value: u64) {
+    if value != 0 {
+        write_varint(out, number << 3 | u64::from(FIXED64));
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Appends a length-delimited field (bytes, a string or an encoded message) to `out`.
+pub fn write_bytes_field(out: &mut Vec<u8>, number: u64, bytes: &[u8]) {
+    write_varint(out, number << 3 | u64::from(LENGTH_DELIMITED));
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+/// Rejects a string (or binary) of `len` bytes if it exceeds `limits`.
+pub fn check_string(len: usize, limits: &DecodeLimits, offset: usize) -> DecodeResult<()> {
+    if len > limits.max_string_len {
+        return Err(DecodeError::LimitExceeded {
+            limit: "max_string_len",
+            offset,
+            size: len as u64,
+            max: limits.max_string_len,
+        });
+    }
+    Ok(())
+}
+
+/// Rejects adding an element to `items` if it would exceed `limits`.
+pub fn check_collection<T>(items: &[T], limits: &DecodeLimits, offset: usize) -> DecodeResult<()> {
+    if items.len() >= limits.max_collection_size {
+        return Err(DecodeError::LimitExceeded {
+            limit: "max_collection_size",
+            offset,
+            size: items.len() as u64 + 1,
+            max: limits.max_collection_size,
+        });
+    }
+    Ok(())
+}
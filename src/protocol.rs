@@ -0,0 +1,74 @@
+//! Wire protocols which carry batches of spans.
+//!
+//! `Server` decodes the received datagrams through the `WireProtocol` trait,
+//! so other wire formats can be supported by implementing it.
+use std::fmt;
+
+use error::DecodeResult;
+
+use borrowed::{self, PartialDecodeError};
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification, Protocol};
+
+/// This trait allows for decoding batches encoded in a specific wire format.
+pub trait WireProtocol: fmt::Debug + Send + Sync {
+    /// Returns the name of the protocol (e.g., `"compact"`).
+    ///
+    /// It is used in logs and in the `peer` object of outputs.
+    fn name(&self) -> &'static str;
+
+    /// Decodes a batch from `buf`.
+    ///
+    /// In `DecodeMode::Lenient`, implementations may return the spans decoded before a failure
+    /// along with the error.
+    /// Implementations which do not distinguish the modes can ignore `mode`.
+    ///
+    /// Values exceeding `limits` should be rejected with `DecodeError::LimitExceeded`.
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)>;
+}
+impl WireProtocol for Protocol {
+    fn name(&self) -> &'static str {
+        match *self {
+            Protocol::Compact => "compact",
+            Protocol::Binary => "binary",
+        }
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        EmitBatchNotification::decode_with_limits(buf, *self, mode, limits)
+    }
+}
+
+/// The wire format of the batches which Jaeger clients send to the collector over HTTP
+/// (`POST /api/traces` with `Content-Type: application/x-thrift`).
+///
+/// Unlike the agent protocols, the body is a bare `Batch` struct in the binary protocol,
+/// which is not wrapped in an `emitBatch` message.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CollectorThrift;
+impl WireProtocol for CollectorThrift {
+    fn name(&self) -> &'static str {
+        "collector-thrift"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let (batch, error) =
+            borrowed::decode_batch_with_limits(buf, Protocol::Binary, mode, limits)?;
+        let batch = batch.into_owned();
+        Ok((EmitBatchNotification { batch }, error))
+    }
+}
@@ -0,0 +1,223 @@
+//! Bounded multi-producer multi-consumer queue used between the stages of `Server`.
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// What a stage does when the queue to the next stage is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Waits until the next stage takes an item.
+    Block,
+
+    /// Drops the item being enqueued.
+    DropNew,
+
+    /// Drops the oldest item in the queue to make room.
+    DropOld,
+}
+
+/// The result of a successful `QueueSender::send`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sent {
+    Enqueued,
+    Dropped,
+}
+
+pub fn bounded<T>(capacity: usize, policy: OverflowPolicy) -> (QueueSender<T>, QueueReceiver<T>) {
+    let inner = Arc::new(Inner {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            senders: 1,
+            receivers: 1,
+            closed: false,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        capacity,
+        policy,
+        dropped: Arc::new(AtomicUsize::new(0)),
+    });
+    (
+        QueueSender {
+            inner: Arc::clone(&inner),
+        },
+        QueueReceiver { inner },
+    )
+}
+
+#[derive(Debug)]
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: Arc<AtomicUsize>,
+}
+impl<T> Inner<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receivers: usize,
+    closed: bool,
+}
+
+#[derive(Debug)]
+pub struct QueueSender<T> {
+    inner: Arc<Inner<T>>,
+}
+impl<T> QueueSender<T> {
+    /// Allocates the memory for the capacity of the queue in advance.
+    pub fn preallocate(&self) {
+        let mut state = self.inner.lock();
+        let additional = self.inner.capacity.saturating_sub(state.items.len());
+        state.items.reserve(additional);
+    }
+
+    /// Enqueues `item` according to the overflow policy.
+    ///
+    /// `Err(item)` means that all the receivers have been dropped or the queue has been closed.
+    pub fn send(&self, item: T) -> Result<Sent, T> {
+        let mut state = self.inner.lock();
+        let mut sent = Sent::Enqueued;
+        while state.items.len() >= self.inner.capacity && state.receivers != 0 && !state.closed {
+            match self.inner.policy {
+                OverflowPolicy::Block => {
+                    state = self.inner
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                OverflowPolicy::DropNew => {
+                    self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(Sent::Dropped);
+                }
+                OverflowPolicy::DropOld => {
+                    state.items.pop_front();
+                    self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                    sent = Sent::Dropped;
+                }
+            }
+        }
+        if state.receivers == 0 || state.closed {
+            return Err(item);
+        }
+        state.items.push_back(item);
+        self.inner.not_empty.notify_one();
+        Ok(sent)
+    }
+
+    /// Returns `true` if the queue has reached its capacity.
+    pub fn is_full(&self) -> bool {
+        self.inner.lock().items.len() >= self.inner.capacity
+    }
+
+    /// Returns the counter of the items dropped by the overflow policy.
+    pub fn dropped(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.inner.dropped)
+    }
+}
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.inner.lock().senders += 1;
+        QueueSender {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.inner.not_empty.notify_all();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueReceiver<T> {
+    inner: Arc<Inner<T>>,
+}
+impl<T> QueueReceiver<T> {
+    /// Dequeues the next item, waiting for it if the queue is empty.
+    ///
+    /// `None` means that all the senders have been dropped (or the queue has been closed)
+    /// and the queue is empty.
+    pub fn recv(&self) -> Option<T> {
+        let mut state = self.inner.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.inner.not_full.notify_one();
+                return Some(item);
+            }
+            if state.senders == 0 || state.closed {
+                return None;
+            }
+            state = self.inner
+                .not_empty
+                .wait(state)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Dequeues the next item if the queue is not empty.
+    pub fn try_recv(&self) -> Option<T> {
+        let item = self.inner.lock().items.pop_front();
+        if item.is_some() {
+            self.inner.not_full.notify_one();
+        }
+        item
+    }
+
+    /// Returns a `QueueCloser` of this queue.
+    pub fn closer(&self) -> QueueCloser<T> {
+        QueueCloser {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+impl<T> Clone for QueueReceiver<T> {
+    fn clone(&self) -> Self {
+        self.inner.lock().receivers += 1;
+        QueueReceiver {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.inner.lock();
+        state.receivers -= 1;
+        if state.receivers == 0 {
+            self.inner.not_full.notify_all();
+        }
+    }
+}
+
+/// A handle to close a queue, which does not count as a sender or a receiver.
+pub struct QueueCloser<T> {
+    inner: Arc<Inner<T>>,
+}
+impl<T> QueueCloser<T> {
+    /// Closes the queue.
+    ///
+    /// The items in the queue are still received, but no more items can be sent.
+    pub fn close(&self) {
+        self.inner.lock().closed = true;
+        self.inner.not_empty.notify_all();
+        self.inner.not_full.notify_all();
+    }
+}
+impl<T> fmt::Debug for QueueCloser<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "QueueCloser {{ .. }}")
+    }
+}
@@ -0,0 +1,41 @@
+//! Rate limiting of the spans output by `Server`.
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// A token bucket which lets through up to `rate` spans per second on average,
+/// and bursts of up to a second's worth of spans.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+impl RateLimiter {
+    /// Makes a new `RateLimiter` instance, whose bucket is full.
+    pub fn new(rate: u64) -> Self {
+        RateLimiter {
+            rate: rate as f64,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes up to `n` tokens from the bucket, and returns the number of the taken ones.
+    pub fn acquire(&self, n: usize) -> usize {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.updated_at = now;
+        let taken = (bucket.tokens as usize).min(n);
+        bucket.tokens -= taken as f64;
+        taken
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
@@ -0,0 +1,97 @@
+//! The `replay` subcommand, which sends captured messages to an agent over UDP.
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+use jaegercat::Result;
+use jaegercat::server::MAX_UDP_PAYLOAD_SIZE;
+use trackable::error::{Failed, Failure};
+
+use decode::{self, Decode};
+
+/// Settings of a replay run.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    /// Settings used to split the inputs into messages.
+    pub decode: Decode,
+
+    /// The address of the agent (e.g., `localhost:6831`).
+    pub target: String,
+
+    /// The number of the messages sent per second, which is unlimited if `None`.
+    pub rate: Option<f64>,
+}
+impl Replay {
+    /// Sends the messages in `inputs` (the standard input if it is empty or `-`) to `target`
+    /// in order, one datagram per message.
+    ///
+    /// The inputs are split into messages in the same way as the `decode` subcommand does
+    /// (the length prefixes are not sent).
+    /// The messages which cannot be split or sent are reported to the standard error,
+    /// and make the run fail after all the inputs are processed.
+    pub fn run(&self, inputs: &[&str]) -> Result<()> {
+        if let Some(rate) = self.rate {
+            track_assert!(rate > 0.0, Failed, "Non-positive rate: {}", rate);
+        }
+        let target = track!(self.target_addr())?;
+        let local_addr = if target.is_ipv4() {
+            SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            SocketAddr::from(([0u16; 8], 0))
+        };
+        let socket = track!(UdpSocket::bind(local_addr).map_err(Failure::from_error))?;
+        let interval = self.rate.map(|rate| Duration::from_secs_f64(1.0 / rate));
+        let started_at = Instant::now();
+        let mut sent = 0u32;
+        let mut errors = 0;
+        let inputs = if inputs.is_empty() { &["-"][..] } else { inputs };
+        for &input in inputs {
+            let buf = track!(decode::read_input(input))?;
+            let (messages, error) = self.decode.split(&buf);
+            for (offset, message) in messages {
+                if message.len() > MAX_UDP_PAYLOAD_SIZE {
+                    eprintln!(
+                        "{}: offset={}: Too large message for a datagram ({} bytes)",
+                        input,
+                        offset,
+                        message.len()
+                    );
+                    errors += 1;
+                    continue;
+                }
+                if let Some(interval) = interval {
+                    let due = started_at + interval * sent;
+                    let now = Instant::now();
+                    if due > now {
+                        thread::sleep(due - now);
+                    }
+                }
+                if let Err(e) = socket.send_to(message, target) {
+                    eprintln!("{}: offset={}: {}", input, offset, e);
+                    errors += 1;
+                    continue;
+                }
+                sent += 1;
+            }
+            if let Some((offset, reason)) = error {
+                eprintln!("{}: offset={}: {}", input, offset, reason);
+                errors += 1;
+            }
+        }
+        let elapsed = started_at.elapsed().as_secs_f64();
+        eprintln!("Sent {} messages to {} in {:.3} seconds", sent, target, elapsed);
+        track_assert_eq!(errors, 0, Failed, "{} messages could not be sent", errors);
+        Ok(())
+    }
+
+    fn target_addr(&self) -> Result<SocketAddr> {
+        let mut addrs = track!(
+            self.target.to_socket_addrs().map_err(Failure::from_error),
+            "{}",
+            self.target
+        )?;
+        match addrs.next() {
+            Some(addr) => Ok(addr),
+            None => track_panic!(Failed, "No address for {}", self.target),
+        }
+    }
+}
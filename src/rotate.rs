@@ -0,0 +1,169 @@
+//! Output file rotated by size and age.
+//!
+//! `RotatingFile` appends to the file at its path until the file reaches the maximum size
+//! or gets older than the rotation interval. Then the file is renamed to `PATH.1`
+//! (the older files being shifted to `PATH.2`, `PATH.3`, ..., up to the number of kept files)
+//! and a new file is created at the path.
+//!
+//! The rotation happens before a write, so that each call of `Write::write` or
+//! `Write::write_vectored` (e.g., the formatted records of `Server`) is written to a single file,
+//! and the files may exceed the maximum size by the last write.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use jaegercat::handler::{Format, Formatter};
+//! use jaegercat::rotate::RotatingFile;
+//! use jaegercat::server::ServerBuilder;
+//!
+//! let mut file = RotatingFile::open("spans.jsonl").unwrap();
+//! file.max_size(100 * 1024 * 1024)
+//!     .interval(Duration::from_secs(3600))
+//!     .keep(24);
+//! let formatter = Formatter::new(Format::Json);
+//! let server = ServerBuilder::new().build_with_formatter(formatter, file).unwrap();
+//! server.join().unwrap();
+//! ```
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use trackable::error::Failure;
+
+use Result;
+
+/// A `Write` implementation which rotates the file at its path.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_at: Instant,
+    max_size: Option<u64>,
+    interval: Option<Duration>,
+    keep: usize,
+    header: Vec<u8>,
+}
+impl RotatingFile {
+    /// Opens (or creates) the file at `path` for appending.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = track!(open_append(&path).map_err(Failure::from_error), "path={:?}", path)?;
+        let size = track!(file.metadata().map_err(Failure::from_error))?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            size,
+            opened_at: Instant::now(),
+            max_size: None,
+            interval: None,
+            keep: 10,
+            header: Vec::new(),
+        })
+    }
+
+    /// Sets the size at which the file is rotated.
+    ///
+    /// By default, the file is not rotated by size.
+    pub fn max_size(&mut self, size: u64) -> &mut Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Sets the age at which the file is rotated (counted from when it has been opened).
+    ///
+    /// By default, the file is not rotated by age.
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Sets the number of the rotated files which are kept (the older ones are removed).
+    ///
+    /// The default value is `10`.
+    pub fn keep(&mut self, keep: usize) -> &mut Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Sets the bytes written at the beginning of each file (e.g., `Formatter::write_header`).
+    ///
+    /// By default, nothing is written.
+    pub fn header(&mut self, header: Vec<u8>) -> &mut Self {
+        self.header = header;
+        self
+    }
+
+    /// Renames the current file to `PATH.1` (shifting the older ones) and creates a new file.
+    pub fn rotate(&mut self) -> Result<()> {
+        track!(self.file.flush().map_err(Failure::from_error))?;
+        for n in (1..self.keep).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                let to = self.rotated_path(n + 1);
+                track!(fs::rename(&from, &to).map_err(Failure::from_error), "from={:?}", from)?;
+            }
+        }
+        if self.keep == 0 {
+            track!(fs::remove_file(&self.path).map_err(Failure::from_error))?;
+        } else {
+            let to = self.rotated_path(1);
+            track!(fs::rename(&self.path, &to).map_err(Failure::from_error), "to={:?}", to)?;
+        }
+        self.file = track!(open_append(&self.path).map_err(Failure::from_error))?;
+        self.size = 0;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn needs_rotation(&self) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        self.max_size.is_some_and(|size| self.size >= size)
+            || self.interval.is_some_and(|i| self.opened_at.elapsed() >= i)
+    }
+
+    /// Writes all of `bufs` to the same file.
+    fn write_all_bufs(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        if self.needs_rotation() {
+            self.rotate().map_err(|e| io::Error::other(e.to_string()))?;
+        }
+        if self.size == 0 && !self.header.is_empty() {
+            self.file.write_all(&self.header)?;
+            self.size += self.header.len() as u64;
+        }
+        let mut written = 0;
+        for buf in bufs {
+            self.file.write_all(buf)?;
+            written += buf.len();
+        }
+        self.size += written as u64;
+        Ok(written)
+    }
+}
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all_bufs(&[IoSlice::new(buf)])
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        self.write_all_bufs(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,462 @@
+//! HTTP server which serves sampling strategies, baggage restrictions and throttling credits
+//! to Jaeger clients, like the agent does on port 5778.
+//!
+//! This is mainly intended for embedding a fake agent control plane in tests of Jaeger clients.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use jaegercat::sampling::{SamplingServerBuilder, SamplingStrategy};
+//!
+//! let server = SamplingServerBuilder::new()
+//!     .port(0)
+//!     .strategy("foo", SamplingStrategy::RateLimiting { max_traces_per_second: 10 })
+//!     .baggage_restriction("foo", "user-id", 64)
+//!     .build()
+//!     .unwrap();
+//! println!("Listening on {}", server.local_addr());
+//! ```
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use serdeconv;
+use slog::{Discard, Logger};
+use trackable::error::{Failed, Failure};
+
+use Result;
+
+const MAX_REQUEST_HEADER_SIZE: usize = 8192;
+
+/// A sampling strategy of a service.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SamplingStrategy {
+    /// Samples traces with the given probability.
+    Probabilistic {
+        /// The probability in the range `[0.0, 1.0]`.
+        sampling_rate: f64,
+    },
+
+    /// Samples up to the given number of traces per second.
+    RateLimiting {
+        /// The maximum number of traces sampled per second.
+        max_traces_per_second: i16,
+    },
+
+    /// Samples traces with probabilities which differ by operation.
+    PerOperation {
+        /// The probability for the operations which are not listed in `operations`.
+        default_sampling_probability: f64,
+
+        /// The number of traces per second sampled for each operation regardless of probabilities.
+        default_lower_bound_traces_per_second: f64,
+
+        /// Pairs of an operation name and its probability.
+        operations: Vec<(String, f64)>,
+    },
+}
+
+/// A restriction on a baggage item.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BaggageRestriction {
+    /// The key of the baggage item.
+    pub key: String,
+
+    /// The maximum length of the value of the baggage item.
+    pub max_value_length: i32,
+}
+
+/// A builder for `SamplingServer`.
+#[derive(Debug, Clone)]
+pub struct SamplingServerBuilder {
+    bind_address: IpAddr,
+    port: u16,
+    default_strategy: SamplingStrategy,
+    strategies: HashMap<String, SamplingStrategy>,
+    baggage_restrictions: HashMap<String, Vec<BaggageRestriction>>,
+    throttling_credits: f64,
+    logger: Logger,
+}
+impl SamplingServerBuilder {
+    /// Makes a new `SamplingServerBuilder` instance.
+    ///
+    /// By default, it listens on port 5778, and serves the probabilistic strategy
+    /// with the rate `0.001` to every service.
+    pub fn new() -> Self {
+        SamplingServerBuilder {
+            bind_address: IpAddr::from([0, 0, 0, 0]),
+            port: 5778,
+            default_strategy: SamplingStrategy::Probabilistic {
+                sampling_rate: 0.001,
+            },
+            strategies: HashMap::new(),
+            baggage_restrictions: HashMap::new(),
+            throttling_credits: 0.0,
+            logger: Logger::root(Discard, o!()),
+        }
+    }
+
+    /// Sets the local address which the HTTP listener is bound to.
+    ///
+    /// The default value is `0.0.0.0`.
+    pub fn bind_address(&mut self, addr: IpAddr) -> &mut Self {
+        self.bind_address = addr;
+        self
+    }
+
+    /// Sets the port of the HTTP listener.
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets the strategy served to the services which have no specific strategy.
+    pub fn default_strategy(&mut self, strategy: SamplingStrategy) -> &mut Self {
+        self.default_strategy = strategy;
+        self
+    }
+
+    /// Sets the strategy served to the given service.
+    pub fn strategy<S>(&mut self, service: S, strategy: SamplingStrategy) -> &mut Self
+    where
+        S: Into<String>,
+    {
+        self.strategies.insert(service.into(), strategy);
+        self
+    }
+
+    /// Adds a baggage restriction served to the given service.
+    ///
+    /// Services without restrictions are served an empty list.
+    pub fn baggage_restriction<S, K>(
+        &mut self,
+        service: S,
+        key: K,
+        max_value_length: i32,
+    ) -> &mut Self
+    where
+        S: Into<String>,
+        K: Into<String>,
+    {
+        self.baggage_restrictions
+            .entry(service.into())
+            .or_default()
+            .push(BaggageRestriction {
+                key: key.into(),
+                max_value_length,
+            });
+        self
+    }
+
+    /// Sets the throttling credits granted to each operation on every request.
+    ///
+    /// The default value is `0.0`.
+    pub fn throttling_credits(&mut self, credits: f64) -> &mut Self {
+        self.throttling_credits = credits;
+        self
+    }
+
+    /// Sets the logger of the server.
+    pub fn logger(&mut self, logger: Logger) -> &mut Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Binds the listener and starts a thread serving requests.
+    pub fn build(&self) -> Result<SamplingServer> {
+        let addr = SocketAddr::new(self.bind_address, self.port);
+        let listener = track!(TcpListener::bind(addr).map_err(Failure::from_error))?;
+        let local_addr = track!(listener.local_addr().map_err(Failure::from_error))?;
+        let logger = self.logger.new(o!("port" => local_addr.port()));
+        info!(logger, "Sampling server started");
+
+        let config = self.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!(logger, "Cannot accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                if let Err(e) = track!(config.handle_connection(stream, &logger)) {
+                    warn!(logger, "Cannot handle a request: {}", e);
+                }
+            }
+            Ok(())
+        });
+        Ok(SamplingServer { local_addr, thread })
+    }
+
+    fn handle_connection(&self, mut stream: TcpStream, logger: &Logger) -> Result<()> {
+        let timeout = Some(Duration::from_secs(5));
+        track!(stream.set_read_timeout(timeout).map_err(Failure::from_error))?;
+        track!(stream.set_write_timeout(timeout).map_err(Failure::from_error))?;
+
+        let head = track!(read_request_head(&mut stream))?;
+        let request_line = head.lines().next().unwrap_or("");
+        debug!(logger, "Request: {}", request_line);
+        let (status, body) = self.respond(request_line);
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        track!(stream.write_all(response.as_bytes()).map_err(Failure::from_error))?;
+        Ok(())
+    }
+
+    fn respond(&self, request_line: &str) -> (&'static str, String) {
+        let mut parts = request_line.split(' ');
+        let method = parts.next().unwrap_or("");
+        let target = parts.next().unwrap_or("");
+        if method != "GET" {
+            return ("405 Method Not Allowed", error_body("Method not allowed"));
+        }
+        let (path, query) = match target.find('?') {
+            Some(i) => (&target[..i], parse_query(&target[i + 1..])),
+            None => (target, Vec::new()),
+        };
+        let service = match query.iter().find(|q| q.0 == "service") {
+            Some(q) if !q.1.is_empty() => q.1.clone(),
+            _ => return ("400 Bad Request", error_body("'service' parameter is empty")),
+        };
+        let body = match path {
+            "/" | "/sampling" => {
+                let strategy = self.strategies
+                    .get(&service)
+                    .unwrap_or(&self.default_strategy);
+                serdeconv::to_json_string(&StrategyResponse::new(strategy))
+            }
+            "/baggageRestrictions" => {
+                let restrictions = self.baggage_restrictions
+                    .get(&service)
+                    .map_or(&[][..], |r| &r[..])
+                    .iter()
+                    .map(|r| BaggageRestrictionResponse {
+                        baggage_key: &r.key,
+                        max_value_length: r.max_value_length,
+                    })
+                    .collect::<Vec<_>>();
+                serdeconv::to_json_string(&restrictions)
+            }
+            "/credits" => {
+                let balances = query
+                    .iter()
+                    .filter(|q| q.0 == "operations")
+                    .map(|q| Balance {
+                        operation: &q.1,
+                        balance: self.throttling_credits,
+                    })
+                    .collect();
+                serdeconv::to_json_string(&CreditsResponse { balances })
+            }
+            _ => return ("404 Not Found", error_body("Not found")),
+        };
+        match body {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("500 Internal Server Error", error_body(&e.to_string())),
+        }
+    }
+}
+impl Default for SamplingServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A running HTTP server which serves sampling strategies to Jaeger clients.
+#[derive(Debug)]
+pub struct SamplingServer {
+    local_addr: SocketAddr,
+    thread: JoinHandle<Result<()>>,
+}
+impl SamplingServer {
+    /// Returns the address bound by the listener.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Blocks until the listener thread terminates.
+    pub fn join(self) -> Result<()> {
+        match self.thread.join() {
+            Ok(r) => r,
+            Err(_) => Err(track!(Failure::from_error("listener thread panicked"))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StrategyResponse<'a> {
+    strategy_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    probabilistic_sampling: Option<ProbabilisticSampling>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limiting_sampling: Option<RateLimitingSampling>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation_sampling: Option<OperationSampling<'a>>,
+}
+impl<'a> StrategyResponse<'a> {
+    fn new(strategy: &'a SamplingStrategy) -> Self {
+        let mut response = StrategyResponse {
+            strategy_type: "PROBABILISTIC",
+            probabilistic_sampling: None,
+            rate_limiting_sampling: None,
+            operation_sampling: None,
+        };
+        match *strategy {
+            SamplingStrategy::Probabilistic { sampling_rate } => {
+                response.probabilistic_sampling = Some(ProbabilisticSampling { sampling_rate });
+            }
+            SamplingStrategy::RateLimiting {
+                max_traces_per_second,
+            } => {
+                response.strategy_type = "RATE_LIMITING";
+                response.rate_limiting_sampling = Some(RateLimitingSampling {
+                    max_traces_per_second,
+                });
+            }
+            SamplingStrategy::PerOperation {
+                default_sampling_probability,
+                default_lower_bound_traces_per_second,
+                ref operations,
+            } => {
+                response.probabilistic_sampling = Some(ProbabilisticSampling {
+                    sampling_rate: default_sampling_probability,
+                });
+                response.operation_sampling = Some(OperationSampling {
+                    default_sampling_probability,
+                    default_lower_bound_traces_per_second,
+                    per_operation_strategies: operations
+                        .iter()
+                        .map(|&(ref operation, sampling_rate)| OperationStrategy {
+                            operation,
+                            probabilistic_sampling: ProbabilisticSampling { sampling_rate },
+                        })
+                        .collect(),
+                });
+            }
+        }
+        response
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProbabilisticSampling {
+    sampling_rate: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RateLimitingSampling {
+    max_traces_per_second: i16,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OperationSampling<'a> {
+    default_sampling_probability: f64,
+    default_lower_bound_traces_per_second: f64,
+    per_operation_strategies: Vec<OperationStrategy<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OperationStrategy<'a> {
+    operation: &'a str,
+    probabilistic_sampling: ProbabilisticSampling,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BaggageRestrictionResponse<'a> {
+    baggage_key: &'a str,
+    max_value_length: i32,
+}
+
+#[derive(Serialize)]
+struct CreditsResponse<'a> {
+    balances: Vec<Balance<'a>>,
+}
+
+#[derive(Serialize)]
+struct Balance<'a> {
+    operation: &'a str,
+    balance: f64,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    error: &'a str,
+}
+
+fn error_body(message: &str) -> String {
+    serdeconv::to_json_string(&ErrorResponse { error: message }).unwrap_or_default()
+}
+
+/// Reads the request line and the headers (the body is ignored).
+fn read_request_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        track_assert!(
+            head.len() < MAX_REQUEST_HEADER_SIZE,
+            Failed,
+            "Too large request header"
+        );
+        let size = track!(stream.read(&mut buf).map_err(Failure::from_error))?;
+        track_assert_ne!(size, 0, Failed, "Unexpected end of stream");
+        head.extend_from_slice(&buf[..size]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Parses a query string into decoded key-value pairs.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            let key = percent_decode(kv.next().unwrap_or(""));
+            let value = percent_decode(kv.next().unwrap_or(""));
+            (key, value)
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() && is_hex_digits(&bytes[i + 1..i + 3]) => {
+                decoded.push(hex_value(bytes[i + 1]) << 4 | hex_value(bytes[i + 2]));
+                i += 2;
+            }
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn is_hex_digits(bytes: &[u8]) -> bool {
+    bytes.iter().all(|b| b.is_ascii_hexdigit())
+}
+
+fn hex_value(b: u8) -> u8 {
+    match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10,
+    }
+}
@@ -0,0 +1,1954 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::io::{self, BufReader, IoSlice, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use slog::{Discard, Logger};
+use trackable::error::{Failed, Failure};
+
+use Result;
+use affinity;
+use dump::DumpDir;
+use emit::SpanBuilder;
+use error::DecodeError;
+use filter::SpanFilter;
+use handler::{BatchFormatter, BatchHandler};
+use hook::{DecodeHook, StageHook};
+use http::{self, BodyError, RequestError};
+use model::{Batch, Process, Tag, UnknownFields};
+use pool::{BufferPool, PooledBuffer, VecPool};
+use protocol::WireProtocol;
+use queue::{self, QueueCloser, QueueReceiver, QueueSender, Sent};
+use ratelimit::RateLimiter;
+use socket;
+use spill::SpillFile;
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification, Protocol};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use uring;
+
+pub use queue::OverflowPolicy;
+
+/// The maximum size of the payload of a UDP datagram over IPv4.
+pub const MAX_UDP_PAYLOAD_SIZE: usize = 65_507;
+
+/// The maximum size of the body of an HTTP request received by an HTTP endpoint.
+pub const MAX_HTTP_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The maximum size of a frame received by a TCP listener.
+pub const MAX_TCP_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// The maximum number of the connections open at the same time on a TCP listener
+/// (or on the port of HTTP endpoints).
+///
+/// Further connections are closed as soon as they are accepted.
+pub const MAX_TCP_CONNECTIONS: usize = 256;
+
+/// Information about a received datagram.
+#[derive(Debug, Clone)]
+pub struct ReceiveMeta<'a> {
+    /// The address of the sender.
+    pub peer: SocketAddr,
+
+    /// The local port which received the datagram (or the HTTP request).
+    pub port: u16,
+
+    /// The wire protocol used to decode the datagram.
+    pub protocol: &'a dyn WireProtocol,
+
+    /// The raw bytes of the datagram.
+    pub bytes: &'a [u8],
+}
+
+/// A builder for `Server`.
+#[derive(Debug, Clone)]
+pub struct ServerBuilder {
+    bind_address: IpAddr,
+    listeners: Vec<(u16, Arc<dyn WireProtocol>)>,
+    http_endpoints: Vec<(u16, String, Arc<dyn WireProtocol>)>,
+    unix_sockets: Vec<(PathBuf, Arc<dyn WireProtocol>)>,
+    tcp_listeners: Vec<(u16, Arc<dyn WireProtocol>)>,
+    http_timeout: Duration,
+    udp_buffer_size: usize,
+    socket_recv_buffer_size: Option<usize>,
+    decode_mode: DecodeMode,
+    decode_limits: DecodeLimits,
+    decode_hook: Option<Arc<dyn DecodeHook>>,
+    stage_hook: Option<Arc<dyn StageHook>>,
+    span_filter: Option<Arc<SpanFilter>>,
+    max_spans_per_sec: Option<u64>,
+    max_spans: Option<u64>,
+    max_batches: Option<u64>,
+    decode_workers: usize,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    flush_interval: Duration,
+    flush_lines: usize,
+    spill_file: Option<(PathBuf, u64)>,
+    dump_dir: Option<PathBuf>,
+    pin_cores: Vec<usize>,
+    recv_threads: usize,
+    sockets_per_port: usize,
+    io_uring_depth: usize,
+    preallocate: bool,
+    warmup: bool,
+    logger: Logger,
+}
+impl ServerBuilder {
+    /// Makes a new `ServerBuilder` instance.
+    ///
+    /// By default, it listens the compact protocol on port 6831 and the binary protocol on port 6832.
+    pub fn new() -> Self {
+        ServerBuilder {
+            bind_address: IpAddr::from([0, 0, 0, 0]),
+            listeners: vec![
+                (6831, Arc::new(Protocol::Compact)),
+                (6832, Arc::new(Protocol::Binary)),
+            ],
+            http_endpoints: Vec::new(),
+            unix_sockets: Vec::new(),
+            tcp_listeners: Vec::new(),
+            http_timeout: Duration::from_secs(30),
+            udp_buffer_size: MAX_UDP_PAYLOAD_SIZE,
+            socket_recv_buffer_size: None,
+            decode_mode: DecodeMode::Normal,
+            decode_limits: DecodeLimits::default(),
+            decode_hook: None,
+            stage_hook: None,
+            span_filter: None,
+            max_spans_per_sec: None,
+            max_spans: None,
+            max_batches: None,
+            decode_workers: 1,
+            queue_capacity: 1024,
+            overflow_policy: OverflowPolicy::Block,
+            flush_interval: Duration::from_millis(100),
+            flush_lines: 0,
+            spill_file: None,
+            dump_dir: None,
+            pin_cores: Vec::new(),
+            recv_threads: 1,
+            sockets_per_port: 1,
+            io_uring_depth: 0,
+            preallocate: false,
+            warmup: false,
+            logger: Logger::root(Discard, o!()),
+        }
+    }
+
+    /// Sets the local address which the UDP, TCP and HTTP listeners are bound to.
+    ///
+    /// The default value is `0.0.0.0` (all the IPv4 interfaces).
+    /// `::` accepts both IPv6 and IPv4 on most platforms.
+    pub fn bind_address(&mut self, addr: IpAddr) -> &mut Self {
+        self.bind_address = addr;
+        self
+    }
+
+    /// Sets the port of the compact protocol listener.
+    pub fn compact_thrift_port(&mut self, port: u16) -> &mut Self {
+        self.set_port(&Protocol::Compact, port);
+        self
+    }
+
+    /// Sets the port of the binary protocol listener.
+    pub fn binary_thrift_port(&mut self, port: u16) -> &mut Self {
+        self.set_port(&Protocol::Binary, port);
+        self
+    }
+
+    /// Adds a listener which decodes the datagrams received on `port` by `protocol`.
+    ///
+    /// If a listener of the same protocol already exists, its port is changed instead.
+    pub fn listener<P>(&mut self, port: u16, protocol: P) -> &mut Self
+    where
+        P: WireProtocol + 'static,
+    {
+        if self.listeners.iter().any(|l| l.1.name() == protocol.name()) {
+            self.set_port(&protocol, port);
+        } else {
+            self.listeners.push((port, Arc::new(protocol)));
+        }
+        self
+    }
+
+    /// Removes the listeners of `protocol` (including the default ones).
+    pub fn remove_listeners(&mut self, protocol: &dyn WireProtocol) -> &mut Self {
+        self.listeners.retain(|l| l.1.name() != protocol.name());
+        self
+    }
+
+    /// Changes the port of the first listener of `protocol`, and does nothing if there is none.
+    pub fn listener_port(&mut self, protocol: &dyn WireProtocol, port: u16) -> &mut Self {
+        self.set_port(protocol, port);
+        self
+    }
+
+    /// Adds another listener which decodes the datagrams received on `port` by `protocol`,
+    /// even if a listener of the same protocol already exists.
+    ///
+    /// `compact_thrift_port`, `binary_thrift_port` and `listener` change the port of the first
+    /// listener of each protocol, so this should be called after them.
+    pub fn extra_listener<P>(&mut self, port: u16, protocol: P) -> &mut Self
+    where
+        P: WireProtocol + 'static,
+    {
+        self.listeners.push((port, Arc::new(protocol)));
+        self
+    }
+
+    /// Adds an HTTP endpoint which decodes the bodies of the `POST` requests to `path`
+    /// on `port` by `protocol`.
+    ///
+    /// The bodies are processed in the same way as the datagrams received by the listeners,
+    /// and the requests are answered as soon as their bodies are queued.
+    /// Endpoints of the same port share a single TCP listener.
+    ///
+    /// Bodies compressed with `gzip`, `deflate` or `zstd` (as indicated by `Content-Encoding`)
+    /// are decompressed before they are queued.
+    /// Bodies larger than `MAX_HTTP_BODY_SIZE` (before or after decompression) are rejected,
+    /// and so are the ones compressed with other codings.
+    ///
+    /// At most `MAX_TCP_CONNECTIONS` connections are served at the same time by each port,
+    /// and further ones are answered by `503 Service Unavailable`.
+    pub fn http_endpoint<P>(&mut self, port: u16, path: &str, protocol: P) -> &mut Self
+    where
+        P: WireProtocol + 'static,
+    {
+        self.http_endpoints
+            .push((port, path.to_owned(), Arc::new(protocol)));
+        self
+    }
+
+    /// Sets the read and write timeouts of the connections to the HTTP endpoints.
+    ///
+    /// A connection on which no data is received (or can be sent) for `timeout` is closed.
+    ///
+    /// The default value is 30 seconds.
+    pub fn http_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.http_timeout = timeout;
+        self
+    }
+
+    /// Adds a listener which decodes the datagrams received on a Unix domain datagram socket
+    /// created at `path` by `protocol`.
+    ///
+    /// The datagrams are processed in the same way as the ones received by the UDP listeners
+    /// (with `0.0.0.0:0` as the sender address and `0` as the local port),
+    /// except that `socket_recv_buffer_size` does not apply to it.
+    /// A socket file left at `path` (e.g., by a previous run) is replaced.
+    /// This is only supported on Unix.
+    pub fn unix_socket<P, Q>(&mut self, path: Q, protocol: P) -> &mut Self
+    where
+        P: WireProtocol + 'static,
+        Q: Into<PathBuf>,
+    {
+        self.unix_sockets.push((path.into(), Arc::new(protocol)));
+        self
+    }
+
+    /// Adds a TCP listener which decodes the messages received on `port` by `protocol`.
+    ///
+    /// Each message is preceded by its 4 bytes big-endian length (as in the framed transport
+    /// of Thrift), and a connection may carry any number of messages.
+    /// The messages are processed in the same way as the datagrams received by the UDP listeners.
+    ///
+    /// A connection sending a frame larger than `MAX_TCP_FRAME_SIZE` is closed,
+    /// and at most `MAX_TCP_CONNECTIONS` connections are served at the same time.
+    pub fn tcp_listener<P>(&mut self, port: u16, protocol: P) -> &mut Self
+    where
+        P: WireProtocol + 'static,
+    {
+        self.tcp_listeners.push((port, Arc::new(protocol)));
+        self
+    }
+
+    /// Sets the size of the buffer used to receive each UDP datagram.
+    ///
+    /// Larger datagrams are dropped with a warning,
+    /// and their number is available through `Server::truncated_datagrams`.
+    ///
+    /// The default value is `MAX_UDP_PAYLOAD_SIZE`, which fits any datagram.
+    pub fn udp_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.udp_buffer_size = size;
+        self
+    }
+
+    /// Sets the size of the kernel receive buffer (`SO_RCVBUF`) of each socket.
+    ///
+    /// A larger buffer absorbs longer bursts of datagrams.
+    /// A warning is logged if the kernel caps the size (e.g., by `net.core.rmem_max` on Linux).
+    /// This is only supported on Unix.
+    ///
+    /// By default, the size is left to the kernel.
+    pub fn socket_recv_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.socket_recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets how strictly the received messages are decoded.
+    ///
+    /// The default value is `DecodeMode::Normal`.
+    pub fn decode_mode(&mut self, mode: DecodeMode) -> &mut Self {
+        self.decode_mode = mode;
+        self
+    }
+
+    /// Sets the limits on the sizes of the values in the received messages.
+    ///
+    /// The default value is `DecodeLimits::default()`.
+    pub fn decode_limits(&mut self, limits: DecodeLimits) -> &mut Self {
+        self.decode_limits = limits;
+        self
+    }
+
+    /// Sets the hook which observes the decoded messages and the decoding failures.
+    ///
+    /// The hook is shared by all the listeners.
+    pub fn decode_hook<H: DecodeHook + 'static>(&mut self, hook: H) -> &mut Self {
+        self.decode_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the hook which observes the latencies of the stages of the server.
+    pub fn stage_hook<H: StageHook + 'static>(&mut self, hook: H) -> &mut Self {
+        self.stage_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets the logger of the server.
+    pub fn logger(&mut self, logger: Logger) -> &mut Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Sets the number of the threads which receive datagrams from each socket.
+    ///
+    /// The default value is `1`.
+    pub fn recv_threads(&mut self, count: usize) -> &mut Self {
+        self.recv_threads = count;
+        self
+    }
+
+    /// Sets the number of the sockets bound to each port.
+    ///
+    /// If it is greater than `1`, the sockets are bound with `SO_REUSEPORT`
+    /// and the kernel distributes the datagrams among them.
+    /// This is only supported on Linux.
+    ///
+    /// The default value is `1`.
+    pub fn sockets_per_port(&mut self, count: usize) -> &mut Self {
+        self.sockets_per_port = count;
+        self
+    }
+
+    /// If it is not `0`, the receiving threads receive datagrams with io_uring,
+    /// keeping `depth` receive operations in flight and reaping their completions in batches.
+    ///
+    /// This requires the `io-uring` feature and Linux 5.1 or later.
+    /// Otherwise, a warning is logged and datagrams are received one by one as usual.
+    ///
+    /// The default value is `0`.
+    pub fn io_uring_depth(&mut self, depth: usize) -> &mut Self {
+        self.io_uring_depth = depth;
+        self
+    }
+
+    /// Sets the number of the worker threads which decode the received datagrams and invoke
+    /// the handler.
+    ///
+    /// The listener threads only receive datagrams and pass them to the workers,
+    /// so that a slow handler does not make the kernel drop datagrams.
+    /// Note that the handler itself is invoked by one worker at a time.
+    ///
+    /// The default value is `1`.
+    pub fn decode_workers(&mut self, count: usize) -> &mut Self {
+        self.decode_workers = count;
+        self
+    }
+
+    /// Sets the capacity of the queues between the stages of the server
+    /// (i.e., the listeners, the decode workers and the handler).
+    ///
+    /// The default value is `1024`.
+    pub fn queue_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Sets what a stage does when the queue to the next stage is full.
+    ///
+    /// The numbers of the dropped items are available through `Server::dropped_datagrams`
+    /// and `Server::dropped_batches`.
+    ///
+    /// The default value is `OverflowPolicy::Block`.
+    pub fn overflow_policy(&mut self, policy: OverflowPolicy) -> &mut Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets the maximum interval between flushes of the output of `build_with_formatter`
+    /// while batches keep arriving.
+    ///
+    /// The default value is 100 milliseconds.
+    pub fn flush_interval(&mut self, interval: Duration) -> &mut Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Sets the number of the lines of the output of `build_with_formatter` after which
+    /// the output is flushed, even before the flush interval elapses.
+    ///
+    /// The default value is `0`, which means that the output is flushed only when the server
+    /// becomes idle or by the flush interval.
+    pub fn flush_lines(&mut self, lines: usize) -> &mut Self {
+        self.flush_lines = lines;
+        self
+    }
+
+    /// Sets the file into which the formatted batches of `build_with_formatter` are spilled
+    /// while the queue to the writer is full.
+    ///
+    /// The spilled batches are written when the writer catches up,
+    /// so short stalls of the output do not make the server drop batches.
+    /// The file holds up to `max_size` bytes, and the overflow policy applies beyond that.
+    ///
+    /// By default, no spill file is used.
+    pub fn spill_file<P: Into<PathBuf>>(&mut self, path: P, max_size: u64) -> &mut Self {
+        self.spill_file = Some((path.into(), max_size));
+        self
+    }
+
+    /// Sets the filter applied to each decoded batch by the decode workers.
+    ///
+    /// The spans filtered out are neither formatted nor passed to the handler,
+    /// and neither are the batches whose spans are all filtered out.
+    /// Note that `Format::Raw` writes the whole messages which contain any spans passing
+    /// the filter.
+    ///
+    /// By default, all the spans are output.
+    pub fn span_filter(&mut self, filter: SpanFilter) -> &mut Self {
+        self.span_filter = if filter.is_empty() {
+            None
+        } else {
+            Some(Arc::new(filter))
+        };
+        self
+    }
+
+    /// Sets the maximum number of the spans output per second.
+    ///
+    /// The decode workers drop the spans beyond the rate (after the span filter),
+    /// allowing bursts of up to a second's worth of spans, and their number is available
+    /// through `Server::throttled_spans`.
+    /// Note that `Format::Raw` writes the whole messages which contain any spans within the rate.
+    ///
+    /// By default, the output is not rate limited.
+    pub fn max_spans_per_sec(&mut self, rate: u64) -> &mut Self {
+        self.max_spans_per_sec = Some(rate);
+        self
+    }
+
+    /// Sets the number of the spans after which the server stops its output.
+    ///
+    /// The spans beyond the number are removed from the last batch (after the span filter and
+    /// the rate limit). Once the last batch is output, the output is flushed and the handler
+    /// (or the writer) is dropped as by `ShutdownHandle::shutdown`,
+    /// which `Server::join_output` waits for.
+    ///
+    /// By default, the output does not stop.
+    pub fn max_spans(&mut self, spans: u64) -> &mut Self {
+        self.max_spans = Some(spans);
+        self
+    }
+
+    /// Sets the number of the batches after which the server stops its output.
+    ///
+    /// This works like `max_spans`, and the output stops when either number is reached.
+    ///
+    /// By default, the output does not stop.
+    pub fn max_batches(&mut self, batches: u64) -> &mut Self {
+        self.max_batches = Some(batches);
+        self
+    }
+
+    /// Sets the directory into which each received datagram (or HTTP, TCP or Unix socket message)
+    /// is written as a file by the decode workers, before it is decoded.
+    ///
+    /// The files can be decoded again with the `decode` subcommand of `jaegercat`
+    /// (e.g., for reproducing a decoding failure). The directory is created if it does not exist.
+    ///
+    /// By default, no datagrams are written.
+    pub fn dump_dir<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.dump_dir = Some(path.into());
+        self
+    }
+
+    /// Sets the cores to which the listener and the decode worker threads are pinned.
+    ///
+    /// Each of the threads is pinned to one of `cores` in turn,
+    /// and the thread which invokes the handler (or the writer) is kept off `cores`
+    /// as long as there are other online cores.
+    /// This is only supported on Linux; elsewhere, and if pinning fails, a warning is logged.
+    ///
+    /// By default, no threads are pinned.
+    pub fn pin_cores(&mut self, cores: Vec<usize>) -> &mut Self {
+        self.pin_cores = cores;
+        self
+    }
+
+    /// If `true`, the queues and the buffers needed to absorb a burst filling the queues
+    /// are allocated at startup, instead of when the first burst arrives.
+    ///
+    /// Note that this allocates about `queue_capacity * udp_buffer_size` bytes up front.
+    ///
+    /// The default value is `false`.
+    pub fn preallocate(&mut self, enabled: bool) -> &mut Self {
+        self.preallocate = enabled;
+        self
+    }
+
+    /// If `true`, each decode worker decodes and formats a sample batch once at startup,
+    /// so that lazy initializations (e.g., of the local time zone) do not delay
+    /// the first datagrams.
+    ///
+    /// The sample batch is neither passed to the handler nor written.
+    ///
+    /// The default value is `false`.
+    pub fn warmup(&mut self, enabled: bool) -> &mut Self {
+        self.warmup = enabled;
+        self
+    }
+
+    /// Binds the listeners and starts a thread for each of them, the decode workers
+    /// and a thread which invokes the handler.
+    ///
+    /// `handler` is invoked for every successfully decoded batch.
+    pub fn build<H>(&self, handler: H) -> Result<Server>
+    where
+        H: BatchHandler + Send + 'static,
+    {
+        track!(self.start(None, HandlerStage(handler)))
+    }
+
+    /// Binds the listeners and starts a thread for each of them, the decode workers
+    /// and a thread which writes the formatted batches to `writer`.
+    ///
+    /// Unlike `build`, the batches are formatted by the decode workers in parallel.
+    /// Each formatted batch is written as a whole by the single writer thread,
+    /// so the outputs of the workers are never interleaved.
+    ///
+    /// The output is buffered, and is flushed when the server becomes idle
+    /// or when the flush interval has elapsed since the last flush.
+    pub fn build_with_formatter<F, W>(&self, formatter: F, writer: W) -> Result<Server>
+    where
+        F: BatchFormatter + 'static,
+        W: Write + Send + 'static,
+    {
+        // Enough buffers for all the records which can be in flight at once
+        let records =
+            VecPool::new(self.queue_capacity + self.decode_workers + MAX_PENDING_RECORDS + 1);
+        if self.preallocate {
+            records.preallocate(
+                self.queue_capacity + self.decode_workers,
+                PREALLOCATED_RECORD_SIZE,
+            );
+        }
+        let spill = if let Some((ref path, max_size)) = self.spill_file {
+            Some(Arc::new(track!(SpillFile::create(path, max_size), "path={:?}", path)?))
+        } else {
+            None
+        };
+        let formatting = Formatting {
+            formatter: Arc::new(formatter),
+            records: records.clone(),
+            spill: spill.clone(),
+        };
+        let stage = WriterStage {
+            writer,
+            pending: Vec::with_capacity(MAX_PENDING_RECORDS),
+            pending_bytes: 0,
+            records,
+            spill,
+            flush_interval: self.flush_interval,
+            flush_lines: self.flush_lines,
+            unflushed_lines: 0,
+            last_flush: Instant::now(),
+        };
+        track!(self.start(Some(formatting), stage))
+    }
+
+    fn start<S>(&self, formatting: Option<Formatting>, stage: S) -> Result<Server>
+    where
+        S: OutputStage + Send + 'static,
+    {
+        track_assert_ne!(self.decode_workers, 0, Failed, "No decode workers");
+        track_assert_ne!(self.queue_capacity, 0, Failed, "Zero queue capacity");
+        track_assert_ne!(self.recv_threads, 0, Failed, "No receiving threads");
+        track_assert_ne!(self.sockets_per_port, 0, Failed, "No sockets");
+        let (datagram_tx, datagram_rx) = queue::bounded(self.queue_capacity, self.overflow_policy);
+        let (batch_tx, batch_rx) = queue::bounded(self.queue_capacity, self.overflow_policy);
+        let dropped_datagrams = datagram_tx.dropped();
+        let dropped_batches = batch_tx.dropped();
+        let count = if self.max_spans.is_some() || self.max_batches.is_some() {
+            let count = OutputCount::new(self.max_spans, self.max_batches, batch_rx.closer());
+            Some(Arc::new(count))
+        } else {
+            None
+        };
+
+        // Enough buffers for all the datagrams which can be in flight at once
+        let recv_threads = (self.listeners.len() * self.sockets_per_port + self.unix_sockets.len())
+            * self.recv_threads;
+        let max_in_flight = self.queue_capacity * 2
+            + self.decode_workers
+            + recv_threads * self.io_uring_depth.max(1)
+            + 1;
+        // The extra byte is used to detect datagrams larger than `udp_buffer_size`
+        let pool = BufferPool::new(self.udp_buffer_size + 1, max_in_flight);
+        if self.preallocate {
+            pool.preallocate(self.queue_capacity + recv_threads);
+            datagram_tx.preallocate();
+            batch_tx.preallocate();
+        }
+        let truncated_datagrams = Arc::new(AtomicUsize::new(0));
+        let throttled_spans = Arc::new(AtomicUsize::new(0));
+        let rate_limiter = self.max_spans_per_sec.map(|rate| Arc::new(RateLimiter::new(rate)));
+        let dump = if let Some(ref path) = self.dump_dir {
+            Some(Arc::new(track!(DumpDir::create(path))?))
+        } else {
+            None
+        };
+
+        let mut cores = self.pin_cores.iter().cloned().cycle();
+        let mut local_addrs = Vec::new();
+        let mut threads = Vec::new();
+        for &(port, ref protocol) in &self.listeners {
+            let mut port = port;
+            for i in 0..self.sockets_per_port {
+                let addr = SocketAddr::new(self.bind_address, port);
+                let reuse_port = self.sockets_per_port > 1;
+                let socket = track!(socket::bind(addr, reuse_port), "addr={}", addr)?;
+                let local_addr = track!(socket.local_addr().map_err(Failure::from_error))?;
+
+                // The rest of the sockets are bound to the port assigned to the first one
+                port = local_addr.port();
+                let logger = self.logger
+                    .new(o!("port" => port, "protocol" => protocol.name()));
+                if let Some(size) = self.socket_recv_buffer_size {
+                    set_recv_buffer_size(&logger, &socket, size);
+                }
+                if i == 0 {
+                    match socket::recv_buffer_size(&socket) {
+                        Ok(size) => info!(logger, "UDP server started"; "recv_buffer_size" => size),
+                        Err(_) => info!(logger, "UDP server started"),
+                    }
+                    local_addrs.push(local_addr);
+                }
+
+                for _ in 0..self.recv_threads {
+                    let socket = track!(socket.try_clone().map_err(Failure::from_error))?;
+                    let listener = Listener {
+                        socket: RecvSocket::Udp(socket),
+                        tx: datagram_tx.clone(),
+                        pool: pool.clone(),
+                        max_size: self.udp_buffer_size,
+                        truncated: Arc::clone(&truncated_datagrams),
+                        port,
+                        protocol: Arc::clone(protocol),
+                        logger: logger.clone(),
+                    };
+                    let core: Vec<_> = cores.next().into_iter().collect();
+                    let io_uring_depth = self.io_uring_depth;
+                    threads.push(thread::spawn(move || {
+                        pin_current_thread(&listener.logger, &core);
+                        if io_uring_depth == 0 {
+                            listener.run()
+                        } else {
+                            listener.run_io_uring(io_uring_depth)
+                        }
+                    }));
+                }
+            }
+        }
+
+        for (path, protocol) in &self.unix_sockets {
+            let socket = track!(bind_unix_socket(path), "path={:?}", path)?;
+            let logger = self.logger.new(o!(
+                "path" => path.display().to_string(),
+                "protocol" => protocol.name()
+            ));
+            info!(logger, "Unix domain socket server started");
+            for _ in 0..self.recv_threads {
+                let listener = Listener {
+                    socket: track!(socket.try_clone().map_err(Failure::from_error))?,
+                    tx: datagram_tx.clone(),
+                    pool: pool.clone(),
+                    max_size: self.udp_buffer_size,
+                    truncated: Arc::clone(&truncated_datagrams),
+                    port: 0,
+                    protocol: Arc::clone(protocol),
+                    logger: logger.clone(),
+                };
+                let core: Vec<_> = cores.next().into_iter().collect();
+                threads.push(thread::spawn(move || {
+                    pin_current_thread(&listener.logger, &core);
+                    listener.run()
+                }));
+            }
+        }
+
+        for (port, protocol) in &self.tcp_listeners {
+            let addr = SocketAddr::new(self.bind_address, *port);
+            let socket = track!(
+                TcpListener::bind(addr).map_err(Failure::from_error),
+                "addr={}",
+                addr
+            )?;
+            let local_addr = track!(socket.local_addr().map_err(Failure::from_error))?;
+            let logger = self.logger
+                .new(o!("port" => local_addr.port(), "protocol" => protocol.name()));
+            info!(logger, "TCP server started");
+            local_addrs.push(local_addr);
+            let listener = FramedListener {
+                socket,
+                tx: datagram_tx.clone(),
+                port: local_addr.port(),
+                protocol: Arc::clone(protocol),
+                connections: Connections::default(),
+                logger,
+            };
+            threads.push(thread::spawn(move || listener.run()));
+        }
+
+        let mut http_ports = BTreeMap::new();
+        for &(port, ref path, ref protocol) in &self.http_endpoints {
+            http_ports
+                .entry(port)
+                .or_insert_with(Vec::new)
+                .push((path.clone(), Arc::clone(protocol)));
+        }
+        for (port, endpoints) in http_ports {
+            let addr = SocketAddr::new(self.bind_address, port);
+            let socket = track!(
+                TcpListener::bind(addr).map_err(Failure::from_error),
+                "addr={}",
+                addr
+            )?;
+            let local_addr = track!(socket.local_addr().map_err(Failure::from_error))?;
+            let logger = self.logger.new(o!("port" => local_addr.port()));
+            info!(logger, "HTTP server started");
+            local_addrs.push(local_addr);
+            let listener = HttpListener {
+                socket,
+                tx: datagram_tx.clone(),
+                endpoints: Arc::new(endpoints),
+                port: local_addr.port(),
+                timeout: self.http_timeout,
+                connections: Connections::default(),
+                logger,
+            };
+            threads.push(thread::spawn(move || listener.run()));
+        }
+        drop(datagram_tx);
+
+        for _ in 0..self.decode_workers {
+            let worker = DecodeWorker {
+                rx: datagram_rx.clone(),
+                tx: batch_tx.clone(),
+                decode_mode: self.decode_mode,
+                decode_limits: self.decode_limits,
+                decode_hook: self.decode_hook.clone(),
+                stage_hook: self.stage_hook.clone(),
+                span_filter: self.span_filter.clone(),
+                rate_limiter: rate_limiter.clone(),
+                throttled_spans: Arc::clone(&throttled_spans),
+                count: count.clone(),
+                formatting: formatting.clone(),
+                dump: dump.clone(),
+                warmup: self.warmup,
+            };
+            let logger = self.logger.clone();
+            let core: Vec<_> = cores.next().into_iter().collect();
+            threads.push(thread::spawn(move || {
+                pin_current_thread(&logger, &core);
+                worker.run()
+            }));
+        }
+        drop((datagram_rx, batch_tx));
+
+        let shutdown = ShutdownHandle {
+            queue: Arc::new(batch_rx.closer()),
+            stopped: Arc::new(Stopped::default()),
+        };
+        let stopped = Arc::clone(&shutdown.stopped);
+        let stage_hook = self.stage_hook.clone();
+        let logger = self.logger.clone();
+        let other_cores: Vec<_> = if self.pin_cores.is_empty() {
+            Vec::new()
+        } else {
+            (0..affinity::online_cores())
+                .filter(|c| !self.pin_cores.contains(c))
+                .collect()
+        };
+        threads.push(thread::spawn(move || {
+            // Set after the stage is dropped, even if it fails
+            let _stopped = StoppedOnDrop(stopped);
+            let mut stage = stage;
+            pin_current_thread(&logger, &other_cores);
+            loop {
+                let output = match batch_rx.try_recv() {
+                    Some(output) => output,
+                    None => {
+                        track!(stage.on_idle())?;
+                        match batch_rx.recv() {
+                            Some(output) => output,
+                            None => break,
+                        }
+                    }
+                };
+                if let Some(ref hook) = stage_hook {
+                    hook.on_output(output.received_at().elapsed());
+                }
+                track!(stage.on_output(output))?;
+            }
+            track!(stage.on_idle())
+        }));
+        Ok(Server {
+            local_addrs,
+            threads,
+            dropped_datagrams,
+            dropped_batches,
+            truncated_datagrams,
+            throttled_spans,
+            shutdown,
+        })
+    }
+
+    fn set_port(&mut self, protocol: &dyn WireProtocol, port: u16) {
+        // Only the first listener of the protocol is changed (the others are extra ones)
+        if let Some(listener) = self
+            .listeners
+            .iter_mut()
+            .find(|l| l.1.name() == protocol.name())
+        {
+            listener.0 = port;
+        }
+    }
+}
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A thread which receives datagrams from a socket and passes them to the decode workers.
+struct Listener {
+    socket: RecvSocket,
+    tx: QueueSender<Datagram>,
+    pool: BufferPool,
+    max_size: usize,
+    truncated: Arc<AtomicUsize>,
+    port: u16,
+    protocol: Arc<dyn WireProtocol>,
+    logger: Logger,
+}
+impl Listener {
+    fn run(self) -> Result<()> {
+        loop {
+            let mut buf = self.pool.acquire();
+            let (recv_size, peer) = track!(
+                self.socket
+                    .recv_from(buf.as_mut_buf())
+                    .map_err(Failure::from_error)
+            )?;
+            if !self.on_received(buf, recv_size, peer) {
+                return Ok(()); // All the decode workers have terminated
+            }
+        }
+    }
+
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn run_io_uring(self, depth: usize) -> Result<()> {
+        let socket = match self.socket {
+            RecvSocket::Udp(ref socket) => socket,
+            RecvSocket::Unix(_) => return track!(self.run()),
+        };
+        let mut receiver = match uring::Receiver::new(socket, depth) {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                warn!(self.logger, "Cannot use io_uring (falling back to recv_from): {}", e);
+                return track!(self.run());
+            }
+        };
+        let mut completed = Vec::with_capacity(depth);
+        loop {
+            while receiver.has_free_slot() {
+                track!(receiver.post(self.pool.acquire()))?;
+            }
+            track!(receiver.wait(&mut completed))?;
+            for (buf, result) in completed.drain(..) {
+                let (recv_size, peer) = track!(result.map_err(Failure::from_error))?;
+                if !self.on_received(buf, recv_size, peer) {
+                    return Ok(()); // All the decode workers have terminated
+                }
+            }
+        }
+    }
+
+    #[cfg(not(all(feature = "io-uring", target_os = "linux")))]
+    fn run_io_uring(self, _depth: usize) -> Result<()> {
+        warn!(
+            self.logger,
+            "io_uring is not available in this build (falling back to recv_from)"
+        );
+        track!(self.run())
+    }
+
+    /// Passes a received datagram to the decode workers.
+    ///
+    /// `false` means that all the decode workers have terminated.
+    fn on_received(&self, mut buf: PooledBuffer, recv_size: usize, peer: SocketAddr) -> bool {
+        if recv_size > self.max_size {
+            let truncated = self.truncated.fetch_add(1, Ordering::Relaxed) + 1;
+            if truncated.is_power_of_two() {
+                warn!(
+                    self.logger,
+                    "Datagram from {} is larger than the UDP buffer size ({} bytes): \
+                     {} datagrams have been dropped so far",
+                    peer,
+                    self.max_size,
+                    truncated
+                );
+            }
+            return true;
+        }
+        buf.set_len(recv_size);
+        debug!(self.logger, "Received {} bytes from {}", recv_size, peer);
+        let datagram = Datagram {
+            received_at: Instant::now(),
+            bytes: buf,
+            peer,
+            port: self.port,
+            protocol: Arc::clone(&self.protocol),
+            logger: self.logger.clone(),
+        };
+        match self.tx.send(datagram) {
+            Ok(Sent::Enqueued) => {}
+            Ok(Sent::Dropped) => warn_dropped(&self.logger, "datagrams", &self.tx.dropped()),
+            Err(_) => return false,
+        }
+        true
+    }
+}
+
+/// A socket from which a `Listener` receives datagrams.
+enum RecvSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+impl RecvSocket {
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match *self {
+            RecvSocket::Udp(ref socket) => socket.recv_from(buf),
+            #[cfg(unix)]
+            RecvSocket::Unix(ref socket) => {
+                // The senders have no IP addresses
+                let size = socket.recv(buf)?;
+                Ok((size, SocketAddr::from(([0, 0, 0, 0], 0))))
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        match *self {
+            RecvSocket::Udp(ref socket) => socket.try_clone().map(RecvSocket::Udp),
+            #[cfg(unix)]
+            RecvSocket::Unix(ref socket) => socket.try_clone().map(RecvSocket::Unix),
+        }
+    }
+}
+
+/// Creates a Unix domain datagram socket at `path`, replacing a socket file left there.
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path) -> Result<RecvSocket> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        track_assert!(
+            metadata.file_type().is_socket(),
+            Failed,
+            "Not a socket file: {:?}",
+            path
+        );
+        track!(fs::remove_file(path).map_err(Failure::from_error))?;
+    }
+    let socket = track!(UnixDatagram::bind(path).map_err(Failure::from_error))?;
+    Ok(RecvSocket::Unix(socket))
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket(path: &Path) -> Result<RecvSocket> {
+    track_panic!(Failed, "Unix domain sockets are only supported on Unix: {:?}", path)
+}
+
+/// The count of the open connections of a listener.
+#[derive(Debug, Default)]
+struct Connections(Arc<AtomicUsize>);
+impl Connections {
+    /// Counts a new connection, unless `MAX_TCP_CONNECTIONS` connections are already open.
+    ///
+    /// The connection is uncounted when the returned guard is dropped.
+    fn open(&self) -> Option<ConnectionGuard> {
+        let opened = self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            if n < MAX_TCP_CONNECTIONS {
+                Some(n + 1)
+            } else {
+                None
+            }
+        });
+        opened.ok().map(|_| ConnectionGuard(Arc::clone(&self.0)))
+    }
+}
+
+/// A connection counted by `Connections`.
+#[derive(Debug)]
+struct ConnectionGuard(Arc<AtomicUsize>);
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A thread which accepts TCP connections carrying framed messages.
+struct FramedListener {
+    socket: TcpListener,
+    tx: QueueSender<Datagram>,
+    port: u16,
+    protocol: Arc<dyn WireProtocol>,
+    connections: Connections,
+    logger: Logger,
+}
+impl FramedListener {
+    fn run(self) -> Result<()> {
+        loop {
+            let (stream, peer) = track!(self.socket.accept().map_err(Failure::from_error))?;
+            let guard = match self.connections.open() {
+                Some(guard) => guard,
+                None => {
+                    warn!(
+                        self.logger,
+                        "Already {} connections are open (closing the connection from {})",
+                        MAX_TCP_CONNECTIONS,
+                        peer
+                    );
+                    continue;
+                }
+            };
+            let connection = FramedConnection {
+                tx: self.tx.clone(),
+                port: self.port,
+                protocol: Arc::clone(&self.protocol),
+                peer,
+                logger: self.logger.new(o!("peer" => peer.to_string())),
+            };
+            thread::spawn(move || {
+                let _guard = guard;
+                if let Err(e) = connection.run(stream) {
+                    debug!(connection.logger, "Connection closed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// A connection accepted by `FramedListener`, which is served by its own thread.
+struct FramedConnection {
+    tx: QueueSender<Datagram>,
+    port: u16,
+    protocol: Arc<dyn WireProtocol>,
+    peer: SocketAddr,
+    logger: Logger,
+}
+impl FramedConnection {
+    fn run(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut header = [0; 4];
+        loop {
+            // The connection may be closed between frames
+            if reader.read(&mut header[..1])? == 0 {
+                return Ok(());
+            }
+            reader.read_exact(&mut header[1..])?;
+            let size = u32::from_be_bytes(header) as usize;
+            if size > MAX_TCP_FRAME_SIZE {
+                warn!(
+                    self.logger,
+                    "Frame of {} bytes is larger than {} bytes (closing the connection)",
+                    size,
+                    MAX_TCP_FRAME_SIZE
+                );
+                return Ok(());
+            }
+            // The buffer grows as the payload arrives (rather than by the size in the header)
+            let mut frame = Vec::new();
+            (&mut reader).take(size as u64).read_to_end(&mut frame)?;
+            if frame.len() < size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            debug!(self.logger, "Received {} bytes", size);
+            let datagram = Datagram {
+                received_at: Instant::now(),
+                bytes: PooledBuffer::unpooled(frame),
+                peer: self.peer,
+                port: self.port,
+                protocol: Arc::clone(&self.protocol),
+                logger: self.logger.clone(),
+            };
+            match self.tx.send(datagram) {
+                Ok(Sent::Enqueued) => {}
+                Ok(Sent::Dropped) => warn_dropped(&self.logger, "datagrams", &self.tx.dropped()),
+                Err(_) => return Ok(()), // All the decode workers have terminated
+            }
+        }
+    }
+}
+
+/// A thread which accepts HTTP connections and passes the bodies of the requests to
+/// the decode workers.
+struct HttpListener {
+    socket: TcpListener,
+    tx: QueueSender<Datagram>,
+    endpoints: Arc<Vec<(String, Arc<dyn WireProtocol>)>>,
+    port: u16,
+    timeout: Duration,
+    connections: Connections,
+    logger: Logger,
+}
+impl HttpListener {
+    fn run(self) -> Result<()> {
+        loop {
+            let (mut stream, peer) = track!(self.socket.accept().map_err(Failure::from_error))?;
+            let timeouts = stream
+                .set_read_timeout(Some(self.timeout))
+                .and_then(|()| stream.set_write_timeout(Some(self.timeout)));
+            if let Err(e) = timeouts {
+                warn!(
+                    self.logger,
+                    "Cannot set the timeouts of the connection from {}: {}",
+                    peer,
+                    e
+                );
+                continue;
+            }
+            let guard = match self.connections.open() {
+                Some(guard) => guard,
+                None => {
+                    warn!(
+                        self.logger,
+                        "Already {} connections are open (rejecting the connection from {})",
+                        MAX_TCP_CONNECTIONS,
+                        peer
+                    );
+                    let _ = http::write_response(
+                        &mut stream,
+                        503,
+                        "Service Unavailable",
+                        None,
+                        b"",
+                        false,
+                    );
+                    continue;
+                }
+            };
+            let connection = HttpConnection {
+                tx: self.tx.clone(),
+                endpoints: Arc::clone(&self.endpoints),
+                port: self.port,
+                peer,
+                logger: self.logger.new(o!("peer" => peer.to_string())),
+            };
+            thread::spawn(move || {
+                let _guard = guard;
+                if let Err(e) = connection.run(stream) {
+                    debug!(connection.logger, "Connection closed: {}", e);
+                }
+            });
+        }
+    }
+}
+
+/// A connection accepted by `HttpListener`, which is served by its own thread.
+struct HttpConnection {
+    tx: QueueSender<Datagram>,
+    endpoints: Arc<Vec<(String, Arc<dyn WireProtocol>)>>,
+    port: u16,
+    peer: SocketAddr,
+    logger: Logger,
+}
+impl HttpConnection {
+    fn run(&self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let request = match http::read_request(&mut reader, MAX_HTTP_BODY_SIZE) {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(()),
+                Err(RequestError::Status(status, reason)) => {
+                    debug!(self.logger, "Invalid request: {} {}", status, reason);
+                    return http::write_response(&mut writer, status, reason, None, b"", false);
+                }
+                Err(RequestError::Io(e)) => return Err(e),
+            };
+            let keep_alive = request.keep_alive;
+            let (status, reason) = self.handle(request, &mut writer)?;
+            if status >= 400 {
+                http::write_response(&mut writer, status, reason, None, b"", keep_alive)?;
+            }
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Queues the body of `request`, and writes the response if it is accepted.
+    fn handle(
+        &self,
+        mut request: http::Request,
+        writer: &mut TcpStream,
+    ) -> io::Result<(u16, &'static str)> {
+        let protocol = match self.endpoints.iter().find(|e| e.0 == request.path) {
+            Some(endpoint) => Arc::clone(&endpoint.1),
+            None => return Ok((404, "Not Found")),
+        };
+        if request.method != "POST" {
+            return Ok((405, "Method Not Allowed"));
+        }
+        let received = request.body.len();
+        match http::decode_body(&mut request, MAX_HTTP_BODY_SIZE) {
+            Ok(()) => {}
+            Err(BodyError::UnsupportedEncoding(encoding)) => {
+                debug!(self.logger, "Unsupported content coding: {}", encoding);
+                return Ok((415, "Unsupported Media Type"));
+            }
+            Err(BodyError::Decode(e @ DecodeError::LimitExceeded { .. })) => {
+                debug!(self.logger, "Cannot decompress the body: {}", e);
+                return Ok((413, "Payload Too Large"));
+            }
+            Err(BodyError::Decode(e)) => {
+                debug!(self.logger, "Cannot decompress the body: {}", e);
+                return Ok((400, "Bad Request"));
+            }
+        }
+        debug!(self.logger, "Received {} bytes", received);
+        let datagram = Datagram {
+            received_at: Instant::now(),
+            bytes: PooledBuffer::unpooled(request.body),
+            peer: self.peer,
+            port: self.port,
+            protocol,
+            logger: self.logger.clone(),
+        };
+        match self.tx.send(datagram) {
+            Ok(Sent::Enqueued) => {}
+            Ok(Sent::Dropped) => warn_dropped(&self.logger, "datagrams", &self.tx.dropped()),
+            Err(_) => return Ok((503, "Service Unavailable")),
+        }
+
+        // The response is an empty message in the encoding of the request
+        // (as OTLP/HTTP clients expect)
+        let content_type = request.content_type.as_deref();
+        let (content_type, body): (_, &[u8]) = if content_type.is_some_and(|t| t.contains("json")) {
+            (Some("application/json"), b"{}")
+        } else {
+            (content_type, b"")
+        };
+        http::write_response(writer, 200, "OK", content_type, body, request.keep_alive)?;
+        Ok((200, "OK"))
+    }
+}
+
+/// A datagram passed from a listener to the decode workers.
+struct Datagram {
+    received_at: Instant,
+    bytes: PooledBuffer,
+    peer: SocketAddr,
+    port: u16,
+    protocol: Arc<dyn WireProtocol>,
+    logger: Logger,
+}
+impl Datagram {
+    fn meta(&self) -> ReceiveMeta<'_> {
+        ReceiveMeta {
+            peer: self.peer,
+            port: self.port,
+            protocol: &*self.protocol,
+            bytes: &self.bytes,
+        }
+    }
+}
+
+/// A batch decoded by a worker, which is passed to the handler.
+struct Decoded {
+    datagram: Datagram,
+    message: EmitBatchNotification,
+}
+
+/// An output of a decode worker.
+enum Output {
+    Decoded(Box<Decoded>),
+
+    /// A batch formatted by the worker, which is written as is,
+    /// and the time when its datagram was received.
+    Formatted(Vec<u8>, Instant),
+}
+impl Output {
+    fn received_at(&self) -> Instant {
+        match *self {
+            Output::Decoded(ref decoded) => decoded.datagram.received_at,
+            Output::Formatted(_, received_at) => received_at,
+        }
+    }
+}
+
+/// The last stage of the server, which consumes the outputs of the decode workers.
+trait OutputStage {
+    fn on_output(&mut self, output: Output) -> Result<()>;
+    fn on_idle(&mut self) -> Result<()>;
+}
+
+struct HandlerStage<H>(H);
+impl<H: BatchHandler> OutputStage for HandlerStage<H> {
+    fn on_output(&mut self, output: Output) -> Result<()> {
+        if let Output::Decoded(decoded) = output {
+            let meta = decoded.datagram.meta();
+            self.0.on_batch(&decoded.message, &meta);
+        }
+        Ok(())
+    }
+
+    fn on_idle(&mut self) -> Result<()> {
+        self.0.on_idle();
+        Ok(())
+    }
+}
+
+/// The maximum number of the records written by a `writev(2)` call.
+const MAX_PENDING_RECORDS: usize = 256;
+
+/// The number of the bytes of pending records which triggers a `writev(2)` call.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// The initial capacity of the record buffers allocated by `ServerBuilder::preallocate`.
+const PREALLOCATED_RECORD_SIZE: usize = 16 * 1024;
+
+/// The output stage of `build_with_formatter`.
+///
+/// Records are kept as they are until a number of them have been accumulated,
+/// and are then written at once with `Write::write_vectored`.
+struct WriterStage<W: Write> {
+    writer: W,
+    pending: Vec<Vec<u8>>,
+    pending_bytes: usize,
+    records: VecPool,
+    spill: Option<Arc<SpillFile>>,
+    flush_interval: Duration,
+    flush_lines: usize,
+    unflushed_lines: usize,
+    last_flush: Instant,
+}
+impl<W: Write> WriterStage<W> {
+    fn push(&mut self, record: Vec<u8>) -> Result<()> {
+        if record.is_empty() {
+            self.records.release(record);
+            return Ok(());
+        }
+        if self.flush_lines != 0 {
+            self.unflushed_lines += record.iter().filter(|&&b| b == b'\n').count();
+        }
+        self.pending_bytes += record.len();
+        self.pending.push(record);
+        if self.pending.len() >= MAX_PENDING_RECORDS || self.pending_bytes >= MAX_PENDING_BYTES {
+            track!(self.write_pending())?;
+        }
+        Ok(())
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        {
+            let mut slices = self.pending
+                .iter()
+                .map(|r| IoSlice::new(r))
+                .collect::<Vec<_>>();
+            let mut slices = &mut slices[..];
+            while !slices.is_empty() {
+                match self.writer.write_vectored(slices) {
+                    Ok(0) => track_panic!(Failed, "Cannot write the output"),
+                    Ok(n) => IoSlice::advance_slices(&mut slices, n),
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(track!(Failure::from_error(e))),
+                }
+            }
+        }
+        for record in self.pending.drain(..) {
+            self.records.release(record);
+        }
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
+impl<W: Write> OutputStage for WriterStage<W> {
+    fn on_output(&mut self, output: Output) -> Result<()> {
+        if let Output::Formatted(bytes, _) = output {
+            track!(self.push(bytes))?;
+            let lines_reached = self.flush_lines != 0 && self.unflushed_lines >= self.flush_lines;
+            if lines_reached || self.last_flush.elapsed() >= self.flush_interval {
+                track!(self.on_idle())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn on_idle(&mut self) -> Result<()> {
+        if let Some(spill) = self.spill.clone() {
+            // The decode workers keep spilling while the file is not empty,
+            // so it must be drained before waiting for the queue
+            loop {
+                let mut record = self.records.acquire();
+                if !track!(spill.lock().pop(&mut record))? {
+                    self.records.release(record);
+                    break;
+                }
+                track!(self.push(record))?;
+            }
+        }
+        track!(self.write_pending())?;
+        track!(self.writer.flush().map_err(Failure::from_error))?;
+        self.last_flush = Instant::now();
+        self.unflushed_lines = 0;
+        Ok(())
+    }
+}
+
+/// A formatter shared by the decode workers, and the pool of the buffers
+/// into which they format batches.
+#[derive(Clone)]
+struct Formatting {
+    formatter: Arc<dyn BatchFormatter>,
+    records: VecPool,
+    spill: Option<Arc<SpillFile>>,
+}
+
+/// A worker which decodes (and optionally formats) datagrams and passes the batches
+/// to the output stage.
+struct DecodeWorker {
+    rx: QueueReceiver<Datagram>,
+    tx: QueueSender<Output>,
+    decode_mode: DecodeMode,
+    decode_limits: DecodeLimits,
+    decode_hook: Option<Arc<dyn DecodeHook>>,
+    stage_hook: Option<Arc<dyn StageHook>>,
+    span_filter: Option<Arc<SpanFilter>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    throttled_spans: Arc<AtomicUsize>,
+    count: Option<Arc<OutputCount>>,
+    formatting: Option<Formatting>,
+    dump: Option<Arc<DumpDir>>,
+    warmup: bool,
+}
+impl DecodeWorker {
+    fn run(self) -> Result<()> {
+        if self.warmup {
+            self.warm_up();
+        }
+
+        // `None` means that all the listeners have terminated
+        while let Some(datagram) = self.rx.recv() {
+            let logger = datagram.logger.clone();
+
+            // A datagram triggering a bug must not stop the worker (and with it the output)
+            match panic::catch_unwind(AssertUnwindSafe(|| self.process(datagram))) {
+                Ok(true) => {}
+                Ok(false) => break, // The handler thread has terminated
+                Err(e) => error!(
+                    logger,
+                    "Dropped a datagram whose processing panicked: {}",
+                    panic_message(&*e)
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes (and optionally formats) `datagram`, and passes the result to the output stage.
+    ///
+    /// `false` means that the output stage has terminated.
+    fn process(&self, datagram: Datagram) -> bool {
+        let logger = datagram.logger.clone();
+        let started_at = Instant::now();
+        if let Some(ref hook) = self.stage_hook {
+            hook.on_dequeued(started_at - datagram.received_at);
+        }
+        if let Some(ref dump) = self.dump {
+            if let Err(e) = dump.write(&datagram.meta()) {
+                error!(logger, "Cannot dump a datagram: {}", e);
+            }
+        }
+        // Dropped after the batch is enqueued (or discarded)
+        let mut counted = None;
+        let result = if let Some(ref f) = self.formatting {
+            let mut bytes = f.records.acquire();
+            let result = self.format(f, &datagram, &mut bytes, &mut counted);
+            if let Some(ref hook) = self.stage_hook {
+                hook.on_processed(started_at.elapsed());
+            }
+            match result {
+                Ok(true) => {}
+                Ok(false) => {
+                    f.records.release(bytes);
+                    return true;
+                }
+                Err(e) => {
+                    error!(logger, "Cannot format a batch: {}", e);
+                    f.records.release(bytes);
+                    return true;
+                }
+            }
+
+            // Records are not enqueued while there are spilled ones, to keep their order.
+            // The lock is held until the record is enqueued,
+            // so that the other workers do not fill the queue in the meantime.
+            let mut spill = f.spill.as_ref().map(|s| s.lock());
+            if let Some(ref mut spill) = spill {
+                if !spill.is_empty() || self.tx.is_full() {
+                    match spill.push(&bytes) {
+                        Ok(true) => {
+                            f.records.release(bytes);
+                            return true;
+                        }
+                        Ok(false) => {}
+                        Err(e) => error!(logger, "Cannot spill a batch: {}", e),
+                    }
+                }
+            }
+            self.tx.send(Output::Formatted(bytes, datagram.received_at))
+        } else {
+            let message = self.decode(&datagram, &mut counted);
+            if let Some(ref hook) = self.stage_hook {
+                hook.on_processed(started_at.elapsed());
+            }
+            match message {
+                Some(message) => {
+                    self.tx.send(Output::Decoded(Box::new(Decoded { datagram, message })))
+                }
+                None => return true,
+            }
+        };
+        match result {
+            Ok(Sent::Enqueued) => true,
+            Ok(Sent::Dropped) => {
+                warn_dropped(&logger, "batches", &self.tx.dropped());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Formats `datagram` into `out`, decoding it only if the formatter needs the batch.
+    ///
+    /// `Ok(false)` means that the datagram cannot be decoded (or is filtered out or throttled).
+    fn format(
+        &self,
+        f: &Formatting,
+        datagram: &Datagram,
+        out: &mut Vec<u8>,
+        counted: &mut Option<Counted>,
+    ) -> Result<bool> {
+        let meta = datagram.meta();
+
+        // The decode hook observes every message, and the filter, the rate limiter and
+        // the count need the spans, so decoding cannot be skipped
+        let needs_decoding = self.decode_hook.is_some()
+            || self.span_filter.is_some()
+            || self.rate_limiter.is_some()
+            || self.count.is_some();
+        if !needs_decoding && track!(f.formatter.format_undecoded(&meta, out))? {
+            return Ok(true);
+        }
+        match self.decode(datagram, counted) {
+            Some(message) => {
+                track!(f.formatter.format(&message, &meta, out))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Decodes and formats a sample batch in each protocol, and discards the results.
+    fn warm_up(&self) {
+        let message = EmitBatchNotification {
+            batch: sample_batch(),
+        };
+        for protocol in &[Protocol::Compact, Protocol::Binary] {
+            let bytes = message.encode(*protocol);
+            let decoded = protocol.decode_batch(&bytes, self.decode_mode, &self.decode_limits);
+            if let (Ok((message, _)), Some(f)) = (decoded, self.formatting.as_ref()) {
+                let meta = ReceiveMeta {
+                    peer: ([127, 0, 0, 1], 0).into(),
+                    port: 0,
+                    protocol,
+                    bytes: &bytes,
+                };
+                let mut out = f.records.acquire();
+                let _ = f.formatter.format(&message, &meta, &mut out);
+                f.records.release(out);
+            }
+        }
+    }
+
+    /// Decodes `datagram`, and returns `None` if it cannot be decoded
+    /// (or is filtered out, throttled or beyond the count).
+    ///
+    /// If the server stops its output after a number of spans or batches,
+    /// the returned batch is counted in `counted`.
+    fn decode(
+        &self,
+        datagram: &Datagram,
+        counted: &mut Option<Counted>,
+    ) -> Option<EmitBatchNotification> {
+        let logger = &datagram.logger;
+        let bytes = &datagram.bytes[..];
+        let result = datagram
+            .protocol
+            .decode_batch(bytes, self.decode_mode, &self.decode_limits);
+        match result {
+            Err(e) => {
+                if let Some(ref hook) = self.decode_hook {
+                    hook.on_error(bytes.len(), &e);
+                }
+                error!(logger, "Received malformed or unknown message: {}", e);
+                debug!(logger, "Bytes: {:?}", bytes);
+                None
+            }
+            Ok((mut message, error)) => {
+                if let Some(ref hook) = self.decode_hook {
+                    hook.on_decoded(bytes.len(), message.batch.spans.len());
+                }
+                if let Some(e) = error {
+                    if let Some(ref hook) = self.decode_hook {
+                        hook.on_error(bytes.len(), &e.cause);
+                    }
+                    warn!(
+                        logger,
+                        "Salvaged {} spans from a malformed message: {}",
+                        message.batch.spans.len(),
+                        e
+                    );
+                }
+                if let Some(ref filter) = self.span_filter {
+                    if !filter.apply(&mut message.batch) {
+                        return None;
+                    }
+                }
+                if let Some(ref limiter) = self.rate_limiter {
+                    let spans = message.batch.spans.len();
+                    let allowed = limiter.acquire(spans);
+                    if allowed < spans {
+                        message.batch.spans.truncate(allowed);
+                        self.throttle(logger, spans - allowed);
+                        if allowed == 0 {
+                            return None;
+                        }
+                    }
+                }
+                if let Some(ref count) = self.count {
+                    *counted = Some(count.take(&mut message.batch)?);
+                }
+                Some(message)
+            }
+        }
+    }
+
+    /// Counts spans dropped by the rate limiter.
+    ///
+    /// To avoid flooding the log, only every power of two of the total number is logged.
+    fn throttle(&self, logger: &Logger, spans: usize) {
+        let before = self.throttled_spans.fetch_add(spans, Ordering::Relaxed);
+        let after = before + spans;
+        // Logged if the total reaches (or passes) a power of two
+        if before == 0 || after.leading_zeros() < before.leading_zeros() {
+            warn!(logger, "Rate limit exceeded: {} spans have been dropped so far", after);
+        }
+    }
+}
+
+/// Makes a batch which contains a value of each type, used for warming up the decode workers.
+fn sample_batch() -> Batch {
+    let parent = SpanBuilder::new("warmup").duration(1).build();
+    let child = SpanBuilder::new("warmup")
+        .child_of(&parent)
+        .tag("string", "value")
+        .tag("bool", true)
+        .tag("long", 1)
+        .tag("double", 1.0)
+        .tag("binary", vec![0u8])
+        .log(parent.start_time, vec![Tag::new("event", "warmup")])
+        .build();
+    Batch {
+        process: Process {
+            service_name: "jaegercat".to_owned(),
+            tags: vec![Tag::new("hostname", "localhost")],
+            unknown_fields: UnknownFields::new(),
+        },
+        spans: vec![parent, child],
+        seq_no: Some(0),
+        stats: None,
+        unknown_fields: UnknownFields::new(),
+    }
+}
+
+fn pin_current_thread(logger: &Logger, cores: &[usize]) {
+    if cores.is_empty() {
+        return;
+    }
+    if let Err(e) = affinity::pin_current_thread(cores) {
+        warn!(logger, "Cannot pin a thread to cores {:?}: {}", cores, e);
+    } else {
+        debug!(logger, "Pinned a thread to cores {:?}", cores);
+    }
+}
+
+fn set_recv_buffer_size(logger: &Logger, socket: &UdpSocket, size: usize) {
+    if let Err(e) = socket::set_recv_buffer_size(socket, size) {
+        warn!(logger, "Cannot set the receive buffer size: {}", e);
+        return;
+    }
+    match socket::recv_buffer_size(socket) {
+        // Linux doubles the given size for its bookkeeping overhead
+        Ok(actual) if cfg!(target_os = "linux") && actual / 2 < size || actual < size => warn!(
+            logger,
+            "The receive buffer size is capped by the kernel (e.g., net.core.rmem_max): \
+             requested={}, actual={}",
+            size,
+            actual
+        ),
+        Ok(_) => {}
+        Err(e) => warn!(logger, "Cannot get the receive buffer size: {}", e),
+    }
+}
+
+/// Returns the message of a panic caught by `panic::catch_unwind`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Logs a dropped item.
+///
+/// To avoid flooding the log, only every power of two of the total number is logged.
+fn warn_dropped(logger: &Logger, items: &str, dropped: &AtomicUsize) {
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped.is_power_of_two() {
+        warn!(logger, "Queue is full: {} {} have been dropped so far", dropped, items);
+    }
+}
+
+/// A running server which receives batches emitted by Jaeger clients.
+#[derive(Debug)]
+pub struct Server {
+    local_addrs: Vec<SocketAddr>,
+    threads: Vec<JoinHandle<Result<()>>>,
+    dropped_datagrams: Arc<AtomicUsize>,
+    dropped_batches: Arc<AtomicUsize>,
+    truncated_datagrams: Arc<AtomicUsize>,
+    throttled_spans: Arc<AtomicUsize>,
+    shutdown: ShutdownHandle,
+}
+impl Server {
+    /// Returns the addresses bound by the listeners.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns the number of the datagrams dropped because the decode workers were busy.
+    pub fn dropped_datagrams(&self) -> u64 {
+        self.dropped_datagrams.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the number of the decoded batches dropped because the handler was busy.
+    pub fn dropped_batches(&self) -> u64 {
+        self.dropped_batches.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the number of the datagrams dropped because they were larger than
+    /// the UDP buffer size.
+    pub fn truncated_datagrams(&self) -> u64 {
+        self.truncated_datagrams.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the number of the spans dropped because the output exceeded
+    /// `ServerBuilder::max_spans_per_sec`.
+    pub fn throttled_spans(&self) -> u64 {
+        self.throttled_spans.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns a handle to stop the output of the server (e.g., before exiting on a signal).
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Blocks until the output thread of the server terminates (e.g., after
+    /// `ServerBuilder::max_spans`), and returns its result.
+    ///
+    /// The listeners and the decode workers keep running,
+    /// so this is meant to be followed by the exit of the process.
+    pub fn join_output(mut self) -> Result<()> {
+        // The output thread is spawned last
+        let thread = track_assert_some!(self.threads.pop(), Failed, "No threads");
+        match thread.join() {
+            Ok(r) => r,
+            Err(_) => Err(track!(Failure::from_error("server thread panicked"))),
+        }
+    }
+
+    /// Blocks until all the threads of the server terminate.
+    ///
+    /// If any of them failed, the first error is returned.
+    pub fn join(self) -> Result<()> {
+        let mut result = Ok(());
+        for t in self.threads {
+            let r = match t.join() {
+                Ok(r) => r,
+                Err(_) => Err(track!(Failure::from_error("server thread panicked"))),
+            };
+            if result.is_ok() {
+                result = r;
+            }
+        }
+        result
+    }
+}
+
+/// A handle to stop the output of a `Server`, which is returned by `Server::shutdown_handle`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    queue: Arc<QueueCloser<Output>>,
+    stopped: Arc<Stopped>,
+}
+impl ShutdownHandle {
+    /// Stops the output of the server, and waits until it has stopped.
+    ///
+    /// The batches already decoded are written, the output is flushed and the handler
+    /// (or the writer) is dropped, while the batches decoded later are discarded.
+    /// The listeners and the decode workers keep running,
+    /// so this is meant to be followed by the exit of the process.
+    pub fn shutdown(&self) {
+        self.queue.close();
+        self.wait();
+    }
+
+    /// Waits until the output of the server has stopped
+    /// (by `shutdown`, or after `ServerBuilder::max_spans` or `ServerBuilder::max_batches`).
+    pub fn wait(&self) {
+        let mut stopped = self.stopped.0.lock().unwrap_or_else(|e| e.into_inner());
+        while !*stopped {
+            stopped = self.stopped.1.wait(stopped).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// The numbers of the spans and the batches passed to the output stage by the decode workers,
+/// which close the queue to the output stage once either reaches its maximum.
+struct OutputCount {
+    max_spans: Option<u64>,
+    max_batches: Option<u64>,
+    state: Mutex<CountState>,
+    queue: QueueCloser<Output>,
+}
+impl OutputCount {
+    fn new(max_spans: Option<u64>, max_batches: Option<u64>, queue: QueueCloser<Output>) -> Self {
+        let count = OutputCount {
+            max_spans,
+            max_batches,
+            state: Mutex::new(CountState::default()),
+            queue,
+        };
+        if count.is_reached(&CountState::default()) {
+            // Nothing is output
+            count.queue.close();
+        }
+        count
+    }
+
+    /// Counts `batch`, removing the spans beyond the maximum from it,
+    /// and returns `None` if the maximum has already been reached.
+    fn take(self: &Arc<Self>, batch: &mut Batch) -> Option<Counted> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if self.is_reached(&state) {
+            return None;
+        }
+        if let Some(max) = self.max_spans {
+            let remaining = max - state.spans;
+            if (batch.spans.len() as u64) > remaining {
+                batch.spans.truncate(remaining as usize);
+            }
+        }
+        state.spans += batch.spans.len() as u64;
+        state.batches += 1;
+        state.in_flight += 1;
+        Some(Counted(Arc::clone(self)))
+    }
+
+    fn is_reached(&self, state: &CountState) -> bool {
+        self.max_spans.is_some_and(|max| state.spans >= max)
+            || self.max_batches.is_some_and(|max| state.batches >= max)
+    }
+}
+
+#[derive(Debug, Default)]
+struct CountState {
+    spans: u64,
+    batches: u64,
+    in_flight: usize,
+}
+
+/// A batch counted by `OutputCount`.
+///
+/// When the last of the counted batches has been enqueued (or discarded) and this is dropped,
+/// the queue is closed, so the output stage stops after writing the batches in the queue.
+struct Counted(Arc<OutputCount>);
+impl Drop for Counted {
+    fn drop(&mut self) {
+        let mut state = (self.0).state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_flight -= 1;
+        if state.in_flight == 0 && self.0.is_reached(&state) {
+            (self.0).queue.close();
+        }
+    }
+}
+
+/// Whether the output thread has terminated.
+#[derive(Debug, Default)]
+struct Stopped(Mutex<bool>, Condvar);
+
+struct StoppedOnDrop(Arc<Stopped>);
+impl Drop for StoppedOnDrop {
+    fn drop(&mut self) {
+        *(self.0).0.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        (self.0).1.notify_all();
+    }
+}
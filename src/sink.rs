@@ -0,0 +1,221 @@
+//! Output of batches to multiple sinks at once.
+//!
+//! A sink is a destination of the output with its own format, written as `DESTINATION:FORMAT`
+//! (see `Sink`). `Tee` is a `BatchHandler` which passes each batch to the handlers of
+//! the sinks in order, so that, for example, JSON can be watched on the standard output
+//! while the raw messages are saved to a file and forwarded to an agent.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use std::io;
+//! use jaegercat::handler::{Format, Formatter};
+//! use jaegercat::server::ServerBuilder;
+//! use jaegercat::sink::{Sink, Tee, UdpWriter, WriterHandler};
+//!
+//! let sink: Sink = "file:/tmp/capture.raw:raw".parse().unwrap();
+//! assert_eq!(sink.format, Format::Raw);
+//!
+//! let file = File::create("/tmp/capture.raw").unwrap();
+//! let agent = UdpWriter::new("127.0.0.1:6831").unwrap();
+//! let mut tee = Tee::new();
+//! tee.push(WriterHandler::new(Formatter::new(Format::Json), io::stdout()))
+//!     .push(WriterHandler::new(Formatter::new(Format::Raw), file))
+//!     .push(WriterHandler::new(Formatter::new(Format::Raw), agent));
+//! let server = ServerBuilder::new().build(tee).unwrap();
+//! server.join().unwrap();
+//! ```
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::str::FromStr;
+use trackable::error::{Failed, Failure};
+
+use Result;
+use handler::{BatchFormatter, BatchHandler, Format, Formatter};
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// A destination of the output with its format.
+///
+/// The string representation is `stdout:FORMAT`, `stderr:FORMAT`, `file:PATH:FORMAT` or
+/// `udp:HOST:PORT:FORMAT`, where `FORMAT` is a value of the `--format` option
+/// (e.g., `stdout:json` or `file:/tmp/capture.raw:raw`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sink {
+    /// The destination of the output.
+    pub destination: Destination,
+
+    /// The format of the output.
+    pub format: Format,
+}
+impl FromStr for Sink {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("");
+        let rest = track_assert_some!(parts.next(), Failed, "No format: {:?}", s);
+        let (target, format) = match rest.rfind(':') {
+            Some(i) => (Some(&rest[..i]), &rest[i + 1..]),
+            None => (None, rest),
+        };
+        let destination = match (kind, target) {
+            ("stdout", None) => Destination::Stdout,
+            ("stderr", None) => Destination::Stderr,
+            ("file", Some(path)) if !path.is_empty() => Destination::File(PathBuf::from(path)),
+            ("udp", Some(addr)) if !addr.is_empty() => Destination::Udp(addr.to_owned()),
+            _ => track_panic!(Failed, "Malformed sink: {:?}", s),
+        };
+        let format = track!(format.parse(), "sink={:?}", s)?;
+        Ok(Sink {
+            destination,
+            format,
+        })
+    }
+}
+
+/// A destination of the output of a `Sink`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// The standard output.
+    Stdout,
+
+    /// The standard error.
+    Stderr,
+
+    /// The file at the path (which is appended to).
+    File(PathBuf),
+
+    /// The UDP address (`HOST:PORT`) to which the output of each batch is sent as a datagram.
+    Udp(String),
+}
+
+/// A `BatchHandler` implementation which passes each batch to multiple handlers in order.
+#[derive(Default)]
+pub struct Tee {
+    handlers: Vec<Box<dyn BatchHandler + Send>>,
+}
+impl Tee {
+    /// Makes a new `Tee` without handlers.
+    pub fn new() -> Self {
+        Tee::default()
+    }
+
+    /// Adds a handler to which the batches are passed.
+    pub fn push<H: BatchHandler + Send + 'static>(&mut self, handler: H) -> &mut Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+}
+impl BatchHandler for Tee {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        for handler in &mut self.handlers {
+            handler.on_batch(batch, meta);
+        }
+    }
+
+    fn on_idle(&mut self) {
+        for handler in &mut self.handlers {
+            handler.on_idle();
+        }
+    }
+}
+impl fmt::Debug for Tee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tee {{ handlers: {} }}", self.handlers.len())
+    }
+}
+
+/// A `BatchHandler` implementation which writes the batches formatted by a `Formatter`
+/// to a writer.
+///
+/// The output of each batch is written by a single `Write::write_all` call,
+/// and the writer is flushed when the server becomes idle.
+#[derive(Debug)]
+pub struct WriterHandler<W> {
+    formatter: Formatter,
+    writer: W,
+    buf: Vec<u8>,
+}
+impl<W: Write> WriterHandler<W> {
+    /// Makes a new `WriterHandler` instance.
+    pub fn new(formatter: Formatter, writer: W) -> Self {
+        WriterHandler {
+            formatter,
+            writer,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Writes the given batch.
+    pub fn write(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) -> Result<()> {
+        self.buf.clear();
+        track!(self.formatter.format(batch, meta, &mut self.buf))?;
+        if !self.buf.is_empty() {
+            track!(self.writer.write_all(&self.buf).map_err(Failure::from_error))?;
+        }
+        Ok(())
+    }
+}
+impl<W: Write> BatchHandler for WriterHandler<W> {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        track_try_unwrap!(self.write(batch, meta));
+    }
+
+    fn on_idle(&mut self) {
+        track_try_unwrap!(self.writer.flush().map_err(Failure::from_error));
+    }
+}
+
+/// A `Write` implementation which sends each write as a UDP datagram.
+///
+/// Like UDP itself, this does not fail when a datagram cannot be sent
+/// (e.g., it is too large); such datagrams are counted by `dropped`.
+#[derive(Debug)]
+pub struct UdpWriter {
+    socket: UdpSocket,
+    target: SocketAddr,
+    dropped: u64,
+}
+impl UdpWriter {
+    /// Makes a new `UdpWriter` sending datagrams to `target` (`HOST:PORT`).
+    pub fn new(target: &str) -> Result<Self> {
+        let mut addrs = track!(
+            target.to_socket_addrs().map_err(Failure::from_error),
+            "target={:?}",
+            target
+        )?;
+        let target = track_assert_some!(addrs.next(), Failed, "No address: {:?}", target);
+        let local_addr = if target.is_ipv4() {
+            SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            SocketAddr::from(([0u16; 8], 0))
+        };
+        let socket = track!(UdpSocket::bind(local_addr).map_err(Failure::from_error))?;
+        Ok(UdpWriter {
+            socket,
+            target,
+            dropped: 0,
+        })
+    }
+
+    /// Returns the number of the datagrams which could not be sent.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+impl Write for UdpWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.socket.send_to(buf, self.target).is_err() {
+            self.dropped += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+//! The `soak` subcommand, which runs the configured pipeline with generated traffic
+//! and reports its throughput, drops and latencies.
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use jaegercat::Result;
+use jaegercat::emit::SpanBuilder;
+use jaegercat::handler::Formatter;
+use jaegercat::hook::StageHook;
+use jaegercat::model::{Batch, Process, UnknownFields};
+use jaegercat::server::ServerBuilder;
+use jaegercat::thrift::{EmitBatchNotification, Protocol};
+use jaegercat::zipkin::ZipkinThrift;
+use trackable::error::{Failed, Failure};
+
+/// The number of the distinct datagrams sent repeatedly.
+const DATAGRAMS: usize = 64;
+
+/// Settings of a soak run.
+#[derive(Debug, Clone)]
+pub struct Soak {
+    pub spans_per_sec: u64,
+    pub spans_per_batch: usize,
+    pub duration: Duration,
+}
+impl Soak {
+    /// Starts a server built by `builder` on ephemeral ports, sends generated batches to it
+    /// for the duration, and prints the report to the standard output.
+    ///
+    /// The formatted output is discarded.
+    pub fn run(&self, builder: &mut ServerBuilder, formatter: Formatter) -> Result<()> {
+        track_assert_ne!(self.spans_per_sec, 0, Failed);
+        track_assert_ne!(self.spans_per_batch, 0, Failed);
+        let stages = Arc::new(Stages::default());
+        let output_bytes = Arc::new(AtomicU64::new(0));
+        let server = track!(
+            builder
+                .compact_thrift_port(0)
+                .binary_thrift_port(0)
+                .listener_port(&ZipkinThrift, 0)
+                .stage_hook(Arc::clone(&stages))
+                .build_with_formatter(formatter, Discard(Arc::clone(&output_bytes)))
+        )?;
+        // The loopback address is used unless the server is bound to a specific address
+        let mut agent_addr = server.local_addrs()[0];
+        let local_addr = if agent_addr.is_ipv4() {
+            if agent_addr.ip().is_unspecified() {
+                agent_addr.set_ip(Ipv4Addr::LOCALHOST.into());
+            }
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            if agent_addr.ip().is_unspecified() {
+                agent_addr.set_ip(Ipv6Addr::LOCALHOST.into());
+            }
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+        let socket = track!(UdpSocket::bind(local_addr).map_err(Failure::from_error))?;
+        let datagrams = (0..DATAGRAMS)
+            .map(|_| self.encode_batch())
+            .collect::<Vec<_>>();
+
+        // Sends datagrams evenly over the duration, catching up every millisecond
+        let batches_per_sec = self.spans_per_sec as f64 / self.spans_per_batch as f64;
+        let mut sent = 0u64;
+        let mut send_errors = 0u64;
+        let started_at = Instant::now();
+        while started_at.elapsed() < self.duration {
+            let due = (started_at.elapsed().as_secs_f64() * batches_per_sec) as u64;
+            while sent < due {
+                let datagram = &datagrams[sent as usize % datagrams.len()];
+                if socket.send_to(datagram, agent_addr).is_err() {
+                    send_errors += 1;
+                }
+                sent += 1;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        let elapsed = started_at.elapsed();
+
+        // Waits for the pipeline to drain
+        let mut outputs = stages.output.count();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(200));
+            let n = stages.output.count();
+            if n == outputs {
+                break;
+            }
+            outputs = n;
+        }
+
+        let spans = |batches: u64| batches * self.spans_per_batch as u64;
+        let secs = elapsed.as_secs_f64();
+        let bytes = output_bytes.load(Ordering::Relaxed);
+        let dropped = server.dropped_datagrams() + server.dropped_batches();
+        let lost = sent.saturating_sub(outputs + dropped + server.truncated_datagrams());
+        println!("duration:   {:.2}s", secs);
+        println!(
+            "sent:       {} batches ({} spans), {} send errors",
+            sent,
+            spans(sent),
+            send_errors
+        );
+        println!("output:     {} batches ({} spans), {} bytes", outputs, spans(outputs), bytes);
+        println!(
+            "throughput: {:.0} spans/s, {:.2} MiB/s",
+            spans(outputs) as f64 / secs,
+            bytes as f64 / secs / (1024.0 * 1024.0)
+        );
+        println!(
+            "dropped:    {} datagrams and {} batches by full queues, {} truncated datagrams, \
+             {} lost (e.g., dropped by the kernel or malformed)",
+            server.dropped_datagrams(),
+            server.dropped_batches(),
+            server.truncated_datagrams(),
+            lost
+        );
+        println!("latency:    mean / p50 / p99 / max (percentiles are bucket upper bounds)");
+        println!("  queue:    {}", stages.dequeued);
+        println!("  process:  {}", stages.processed);
+        println!("  output:   {}", stages.output);
+        Ok(())
+    }
+
+    fn encode_batch(&self) -> Vec<u8> {
+        let spans = (0..self.spans_per_batch)
+            .map(|i| {
+                SpanBuilder::new(format!("operation-{}", i % 8))
+                    .duration(1000)
+                    .tag("http.status_code", 200)
+                    .tag("component", "soak")
+                    .build()
+            })
+            .collect();
+        let message = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "jaegercat-soak".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans,
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        message.encode(Protocol::Compact)
+    }
+}
+
+/// A writer which discards the output and counts its bytes.
+struct Discard(Arc<AtomicU64>);
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stages {
+    dequeued: Latencies,
+    processed: Latencies,
+    output: Latencies,
+}
+impl StageHook for Stages {
+    fn on_dequeued(&self, elapsed: Duration) {
+        self.dequeued.record(elapsed);
+    }
+
+    fn on_processed(&self, elapsed: Duration) {
+        self.processed.record(elapsed);
+    }
+
+    fn on_output(&self, elapsed: Duration) {
+        self.output.record(elapsed);
+    }
+}
+
+/// A histogram of latencies in microseconds, whose buckets are bounded by powers of two.
+#[derive(Debug, Default)]
+struct Latencies {
+    count: AtomicU64,
+    sum_us: AtomicU64,
+    max_us: AtomicU64,
+    buckets: [AtomicU64; 32],
+}
+impl Latencies {
+    fn record(&self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+        let bucket = 64 - us.leading_zeros() as usize;
+        self.buckets[bucket.min(31)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the upper bound of the bucket containing the given percentile.
+    fn percentile_us(&self, percentile: f64) -> u64 {
+        let rank = (self.count() as f64 * percentile / 100.0).ceil() as u64;
+        let mut seen = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank.max(1) {
+                return (1u64 << i) - 1;
+            }
+        }
+        self.max_us.load(Ordering::Relaxed)
+    }
+}
+impl fmt::Display for Latencies {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let count = self.count();
+        if count == 0 {
+            return write!(f, "-");
+        }
+        write!(
+            f,
+            "{}us / {}us / {}us / {}us",
+            self.sum_us.load(Ordering::Relaxed) / count,
+            self.percentile_us(50.0),
+            self.percentile_us(99.0),
+            self.max_us.load(Ordering::Relaxed)
+        )
+    }
+}
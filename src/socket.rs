@@ -0,0 +1,149 @@
+//! UDP sockets used by `Server`.
+use std::net::{SocketAddr, UdpSocket};
+#[cfg(target_os = "linux")]
+use std::net::IpAddr;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::{io, mem};
+#[cfg(unix)]
+use libc;
+#[cfg(not(target_os = "linux"))]
+use trackable::error::Failed;
+use trackable::error::Failure;
+
+use Result;
+
+/// Binds a UDP socket to `addr`.
+///
+/// If `reuse_port` is `true`, `SO_REUSEPORT` is set on the socket,
+/// so that multiple sockets can be bound to the same port.
+pub fn bind(addr: SocketAddr, reuse_port: bool) -> Result<UdpSocket> {
+    if reuse_port {
+        track!(bind_reuse_port(addr))
+    } else {
+        track!(UdpSocket::bind(addr).map_err(Failure::from_error))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_reuse_port(addr: SocketAddr) -> Result<UdpSocket> {
+    let family = if addr.is_ipv4() {
+        libc::AF_INET
+    } else {
+        libc::AF_INET6
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(track!(Failure::from_error(io::Error::last_os_error())));
+    }
+
+    // The socket is closed when `socket` is dropped
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    let on: libc::c_int = 1;
+    let result = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &on as *const _ as *const libc::c_void,
+            mem::size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(track!(Failure::from_error(io::Error::last_os_error())));
+    }
+
+    let result = match addr.ip() {
+        IpAddr::V4(ip) => {
+            let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr.s_addr = u32::from(ip).to_be();
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const _ as *const libc::sockaddr,
+                    mem::size_of_val(&sockaddr) as libc::socklen_t,
+                )
+            }
+        }
+        IpAddr::V6(ip) => {
+            let mut sockaddr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_addr.s6_addr = ip.octets();
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const _ as *const libc::sockaddr,
+                    mem::size_of_val(&sockaddr) as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if result != 0 {
+        return Err(track!(Failure::from_error(io::Error::last_os_error())));
+    }
+    Ok(socket)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_reuse_port(addr: SocketAddr) -> Result<UdpSocket> {
+    track_panic!(Failed, "SO_REUSEPORT is only supported on Linux: {}", addr)
+}
+
+/// Sets the size of the kernel receive buffer (`SO_RCVBUF`) of `socket`.
+///
+/// Note that the kernel may cap the size (e.g., by `net.core.rmem_max` on Linux).
+#[cfg(unix)]
+pub fn set_recv_buffer_size(socket: &UdpSocket, size: usize) -> Result<()> {
+    let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const _ as *const libc::c_void,
+            mem::size_of_val(&size) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(track!(Failure::from_error(io::Error::last_os_error())));
+    }
+    Ok(())
+}
+
+/// Sets the size of the kernel receive buffer (`SO_RCVBUF`) of `socket`.
+#[cfg(not(unix))]
+pub fn set_recv_buffer_size(_socket: &UdpSocket, size: usize) -> Result<()> {
+    track_panic!(Failed, "SO_RCVBUF is only supported on Unix: size={}", size)
+}
+
+/// Returns the size of the kernel receive buffer (`SO_RCVBUF`) of `socket`.
+#[cfg(unix)]
+pub fn recv_buffer_size(socket: &UdpSocket) -> Result<usize> {
+    let mut size: libc::c_int = 0;
+    let mut len = mem::size_of_val(&size) as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &mut size as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(track!(Failure::from_error(io::Error::last_os_error())));
+    }
+    Ok(size as usize)
+}
+
+/// Returns the size of the kernel receive buffer (`SO_RCVBUF`) of `socket`.
+#[cfg(not(unix))]
+pub fn recv_buffer_size(_socket: &UdpSocket) -> Result<usize> {
+    track_panic!(Failed, "SO_RCVBUF is only supported on Unix")
+}
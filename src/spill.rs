@@ -0,0 +1,106 @@
+//! File-backed queue of formatted records used by `Server` when the output is slower than
+//! the decode workers.
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use trackable::error::Failure;
+
+use Result;
+
+/// A FIFO of records stored in a file.
+///
+/// Each record is stored with a 4 bytes big-endian length prefix.
+/// The file is truncated whenever all the records have been read.
+#[derive(Debug)]
+pub struct SpillFile {
+    state: Mutex<SpillState>,
+}
+impl SpillFile {
+    /// Creates (or truncates) the file at `path`, which holds up to `max_size` bytes of records.
+    pub fn create<P: AsRef<Path>>(path: P, max_size: u64) -> Result<Self> {
+        let file = track!(
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)
+                .map_err(Failure::from_error)
+        )?;
+        Ok(SpillFile {
+            state: Mutex::new(SpillState {
+                file,
+                max_size,
+                read_pos: 0,
+                write_pos: 0,
+            }),
+        })
+    }
+
+    /// Locks the file.
+    pub fn lock(&self) -> MutexGuard<'_, SpillState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+pub struct SpillState {
+    file: File,
+    max_size: u64,
+    read_pos: u64,
+    write_pos: u64,
+}
+impl SpillState {
+    /// Returns `true` if there are no records to be read.
+    pub fn is_empty(&self) -> bool {
+        self.read_pos == self.write_pos
+    }
+
+    /// Appends `record` to the file.
+    ///
+    /// `Ok(false)` means that the file has reached its maximum size.
+    pub fn push(&mut self, record: &[u8]) -> Result<bool> {
+        let size = 4 + record.len() as u64;
+        if self.write_pos + size > self.max_size || record.len() > u32::MAX as usize {
+            return Ok(false);
+        }
+        let len = record.len() as u32;
+        track!(
+            self.file
+                .seek(SeekFrom::Start(self.write_pos))
+                .map_err(Failure::from_error)
+        )?;
+        track!(self.file.write_all(&len.to_be_bytes()).map_err(Failure::from_error))?;
+        track!(self.file.write_all(record).map_err(Failure::from_error))?;
+        self.write_pos += size;
+        Ok(true)
+    }
+
+    /// Reads the oldest record into `buf`.
+    ///
+    /// `Ok(false)` means that there are no records to be read.
+    pub fn pop(&mut self, buf: &mut Vec<u8>) -> Result<bool> {
+        if self.is_empty() {
+            return Ok(false);
+        }
+        track!(
+            self.file
+                .seek(SeekFrom::Start(self.read_pos))
+                .map_err(Failure::from_error)
+        )?;
+        let mut len = [0; 4];
+        track!(self.file.read_exact(&mut len).map_err(Failure::from_error))?;
+        let len = u32::from_be_bytes(len) as usize;
+        buf.clear();
+        buf.resize(len, 0);
+        track!(self.file.read_exact(buf).map_err(Failure::from_error))?;
+        self.read_pos += 4 + len as u64;
+        if self.is_empty() {
+            track!(self.file.set_len(0).map_err(Failure::from_error))?;
+            self.read_pos = 0;
+            self.write_pos = 0;
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,575 @@
+//! Output of spans into [SQLite] databases.
+//!
+//! This requires the `sqlite` feature, which links the system `libsqlite3`.
+//!
+//! The tables of `SCHEMA` are created if they do not exist yet, and then each span is
+//! inserted into `spans`, with its tags in `tags` and the fields of its logs in `logs`.
+//! The inserts are committed in transactions of a number of spans,
+//! and whenever the server becomes idle.
+//! The database is in the WAL mode, so it can be queried while the spans are being inserted.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use jaegercat::server::ServerBuilder;
+//! use jaegercat::sqlite::SqliteHandler;
+//!
+//! let handler = SqliteHandler::open("spans.db").unwrap();
+//! let server = ServerBuilder::new().build(handler).unwrap();
+//! server.join().unwrap();
+//! ```
+//!
+//! [SQLite]: https://www.sqlite.org/
+use std::ffi::{CStr, CString};
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::time::{Duration, Instant};
+use libc::{c_char, c_int, c_void};
+use trackable::error::{Failed, Failure};
+
+use Result;
+use handler::BatchHandler;
+use model::{Span, TagValue};
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// The tables (and indices) into which spans are inserted.
+///
+/// The IDs are in the format of the JSON output (e.g., `0x2ae64ad7cd070e8e`),
+/// and the timestamps and durations are in microseconds.
+/// The values of tags and log fields are stored as `TEXT`, `INTEGER` (including booleans),
+/// `REAL` or `BLOB` according to their types.
+pub const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS spans (
+    id INTEGER PRIMARY KEY,
+    trace_id TEXT NOT NULL,
+    span_id TEXT NOT NULL,
+    parent_span_id TEXT,
+    service TEXT NOT NULL,
+    operation TEXT NOT NULL,
+    start_time INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    flags INTEGER NOT NULL,
+    peer TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tags (
+    span INTEGER NOT NULL REFERENCES spans (id),
+    key TEXT NOT NULL,
+    value
+);
+CREATE TABLE IF NOT EXISTS logs (
+    span INTEGER NOT NULL REFERENCES spans (id),
+    timestamp INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value
+);
+CREATE INDEX IF NOT EXISTS spans_trace_id ON spans (trace_id);
+CREATE INDEX IF NOT EXISTS tags_span ON tags (span);
+CREATE INDEX IF NOT EXISTS logs_span ON logs (span);
+";
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_DONE: c_int = 101;
+const SQLITE_OPEN_READWRITE: c_int = 0x02;
+const SQLITE_OPEN_CREATE: c_int = 0x04;
+
+/// `SQLITE_TRANSIENT`, which makes SQLite copy bound values.
+const SQLITE_TRANSIENT: isize = -1;
+
+enum Sqlite3 {}
+enum Sqlite3Stmt {}
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open_v2(
+        filename: *const c_char,
+        db: *mut *mut Sqlite3,
+        flags: c_int,
+        vfs: *const c_char,
+    ) -> c_int;
+    fn sqlite3_close(db: *mut Sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut Sqlite3) -> *const c_char;
+    fn sqlite3_exec(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        callback: *const c_void,
+        arg: *mut c_void,
+        errmsg: *mut *mut c_char,
+    ) -> c_int;
+    fn sqlite3_prepare_v2(
+        db: *mut Sqlite3,
+        sql: *const c_char,
+        len: c_int,
+        stmt: *mut *mut Sqlite3Stmt,
+        tail: *mut *const c_char,
+    ) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut Sqlite3Stmt, i: c_int, value: i64) -> c_int;
+    fn sqlite3_bind_double(stmt: *mut Sqlite3Stmt, i: c_int, value: f64) -> c_int;
+    fn sqlite3_bind_text(
+        stmt: *mut Sqlite3Stmt,
+        i: c_int,
+        value: *const c_char,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_bind_blob(
+        stmt: *mut Sqlite3Stmt,
+        i: c_int,
+        value: *const c_void,
+        len: c_int,
+        destructor: isize,
+    ) -> c_int;
+    fn sqlite3_bind_null(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+    fn sqlite3_step(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_finalize(stmt: *mut Sqlite3Stmt) -> c_int;
+    fn sqlite3_last_insert_rowid(db: *mut Sqlite3) -> i64;
+}
+
+/// A `BatchHandler` implementation which inserts spans into a SQLite database.
+#[derive(Debug)]
+pub struct SqliteHandler {
+    // The statements are declared (and so dropped) before the database
+    insert_span: Statement,
+    insert_tag: Statement,
+    insert_log: Statement,
+    db: Database,
+    pending: usize,
+    transaction_size: usize,
+    flush_interval: Duration,
+    last_commit: Instant,
+}
+impl SqliteHandler {
+    /// Opens (or creates) the database at `path`, and creates the tables of `SCHEMA` in it.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let db = track!(Database::open(path.as_ref()))?;
+        track!(db.execute("PRAGMA journal_mode = WAL"))?;
+        track!(db.execute(SCHEMA))?;
+        Ok(SqliteHandler {
+            insert_span: track!(db.prepare(
+                "INSERT INTO spans (trace_id, span_id, parent_span_id, service, operation, \
+                 start_time, duration, flags, peer) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+            ))?,
+            insert_tag: track!(db.prepare("INSERT INTO tags (span, key, value) VALUES (?, ?, ?)"))?,
+            insert_log: track!(db.prepare(
+                "INSERT INTO logs (span, timestamp, key, value) VALUES (?, ?, ?, ?)"
+            ))?,
+            db,
+            pending: 0,
+            transaction_size: 1000,
+            flush_interval: Duration::from_secs(1),
+            last_commit: Instant::now(),
+        })
+    }
+
+    /// Sets the maximum number of the spans inserted in a transaction.
+    ///
+    /// The default value is `1000`.
+    pub fn transaction_size(&mut self, spans: usize) -> &mut Self {
+        self.transaction_size = spans.max(1);
+        self
+    }
+
+    /// Sets the maximum interval between commits while spans keep arriving.
+    ///
+    /// The transaction is also committed whenever the server becomes idle.
+    ///
+    /// The default value is 1 second.
+    pub fn flush_interval(&mut self, interval: Duration) -> &mut Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Inserts the spans of `batch`.
+    ///
+    /// The inserts are committed when the transaction becomes full, the flush interval has
+    /// elapsed or `commit` is called.
+    pub fn insert(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) -> Result<()> {
+        // IPv4 senders to a dual-stack socket appear as IPv4-mapped addresses
+        let peer = SocketAddr::new(meta.peer.ip().to_canonical(), meta.peer.port()).to_string();
+        for span in &batch.batch.spans {
+            if self.pending == 0 {
+                track!(self.db.execute("BEGIN"))?;
+            }
+            track!(self.insert_span(span, &batch.batch.process.service_name, &peer))?;
+            self.pending += 1;
+            if self.pending >= self.transaction_size {
+                track!(self.commit())?;
+            }
+        }
+        if self.last_commit.elapsed() >= self.flush_interval {
+            track!(self.commit())?;
+        }
+        Ok(())
+    }
+
+    /// Commits the current transaction (if any).
+    pub fn commit(&mut self) -> Result<()> {
+        if self.pending > 0 {
+            track!(self.db.execute("COMMIT"))?;
+            self.pending = 0;
+        }
+        self.last_commit = Instant::now();
+        Ok(())
+    }
+
+    fn insert_span(&mut self, span: &Span, service: &str, peer: &str) -> Result<()> {
+        let stmt = &mut self.insert_span;
+        track!(stmt.bind_text(1, &span.trace_id.to_string()))?;
+        track!(stmt.bind_text(2, &span.span_id.to_string()))?;
+        match span.parent_span_id {
+            Some(id) if id.0 != 0 => track!(stmt.bind_text(3, &id.to_string()))?,
+            _ => track!(stmt.bind_null(3))?,
+        }
+        track!(stmt.bind_text(4, service))?;
+        track!(stmt.bind_text(5, &span.operation_name))?;
+        track!(stmt.bind_i64(6, span.start_time))?;
+        track!(stmt.bind_i64(7, span.duration))?;
+        track!(stmt.bind_i64(8, i64::from(span.flags)))?;
+        track!(stmt.bind_text(9, peer))?;
+        track!(stmt.execute())?;
+
+        let id = unsafe { sqlite3_last_insert_rowid(self.db.0) };
+        for tag in &span.tags {
+            let stmt = &mut self.insert_tag;
+            track!(stmt.bind_i64(1, id))?;
+            track!(stmt.bind_text(2, &tag.key))?;
+            track!(stmt.bind_tag_value(3, &tag.value))?;
+            track!(stmt.execute())?;
+        }
+        for log in &span.logs {
+            for field in &log.fields {
+                let stmt = &mut self.insert_log;
+                track!(stmt.bind_i64(1, id))?;
+                track!(stmt.bind_i64(2, log.timestamp))?;
+                track!(stmt.bind_text(3, &field.key))?;
+                track!(stmt.bind_tag_value(4, &field.value))?;
+                track!(stmt.execute())?;
+            }
+        }
+        Ok(())
+    }
+}
+impl BatchHandler for SqliteHandler {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, meta: &ReceiveMeta) {
+        track_try_unwrap!(self.insert(batch, meta));
+    }
+
+    fn on_idle(&mut self) {
+        track_try_unwrap!(self.commit());
+    }
+}
+impl Drop for SqliteHandler {
+    fn drop(&mut self) {
+        let _ = self.commit();
+    }
+}
+
+#[derive(Debug)]
+struct Database(*mut Sqlite3);
+impl Database {
+    fn open(path: &Path) -> Result<Self> {
+        let path = track!(CString::new(path.as_os_str().as_bytes()).map_err(Failure::from_error))?;
+        let mut db = ptr::null_mut();
+        let flags = SQLITE_OPEN_READWRITE | SQLITE_OPEN_CREATE;
+        let code = unsafe { sqlite3_open_v2(path.as_ptr(), &mut db, flags, ptr::null()) };
+        // A handle is allocated even on failure (unless out of memory)
+        let db = Database(db);
+        track_assert!(!db.0.is_null(), Failed, "Cannot allocate a SQLite connection");
+        track!(check(db.0, code))?;
+        Ok(db)
+    }
+
+    fn execute(&self, sql: &str) -> Result<()> {
+        let sql = track!(CString::new(sql).map_err(Failure::from_error))?;
+        let code = unsafe {
+            sqlite3_exec(self.0, sql.as_ptr(), ptr::null(), ptr::null_mut(), ptr::null_mut())
+        };
+        track!(check(self.0, code))
+    }
+
+    fn prepare(&self, sql: &str) -> Result<Statement> {
+        let sql = track!(CString::new(sql).map_err(Failure::from_error))?;
+        let mut stmt = ptr::null_mut();
+        let code =
+            unsafe { sqlite3_prepare_v2(self.0, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) };
+        track!(check(self.0, code))?;
+        Ok(Statement { stmt, db: self.0 })
+    }
+}
+// A connection is usable from any thread (but by one thread at a time) unless SQLite is
+// built with `SQLITE_THREADSAFE=0`
+unsafe impl Send for Database {}
+impl Drop for Database {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_close(self.0);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Statement {
+    stmt: *mut Sqlite3Stmt,
+    db: *mut Sqlite3,
+}
+impl Statement {
+    fn bind<F>(&mut self, i: c_int, f: F) -> Result<()>
+    where
+        F: FnOnce(*mut Sqlite3Stmt, c_int) -> c_int,
+    {
+        let code = f(self.stmt, i);
+        track!(check(self.db, code))
+    }
+
+    fn bind_i64(&mut self, i: c_int, value: i64) -> Result<()> {
+        track!(self.bind(i, |s, i| unsafe { sqlite3_bind_int64(s, i, value) }))
+    }
+
+    fn bind_null(&mut self, i: c_int) -> Result<()> {
+        track!(self.bind(i, |s, i| unsafe { sqlite3_bind_null(s, i) }))
+    }
+
+    fn bind_text(&mut self, i: c_int, value: &str) -> Result<()> {
+        let (ptr, len) = (value.as_ptr() as *const c_char, value.len() as c_int);
+        track!(self.bind(i, |s, i| unsafe {
+            sqlite3_bind_text(s, i, ptr, len, SQLITE_TRANSIENT)
+        }))
+    }
+
+    fn bind_tag_value(&mut self, i: c_int, value: &TagValue) -> Result<()> {
+        match *value {
+            TagValue::Str(ref v) => track!(self.bind_text(i, v)),
+            TagValue::Bool(v) => track!(self.bind_i64(i, i64::from(v))),
+            TagValue::Long(v) => track!(self.bind_i64(i, v)),
+            TagValue::Double(v) => {
+                track!(self.bind(i, |s, i| unsafe { sqlite3_bind_double(s, i, v) }))
+            }
+            TagValue::Binary(ref v) => {
+                let (ptr, len) = (v.as_ptr() as *const c_void, v.len() as c_int);
+                track!(self.bind(i, |s, i| unsafe {
+                    sqlite3_bind_blob(s, i, ptr, len, SQLITE_TRANSIENT)
+                }))
+            }
+        }
+    }
+
+    /// Executes the statement, and resets it for the next execution.
+    fn execute(&mut self) -> Result<()> {
+        let code = unsafe { sqlite3_step(self.stmt) };
+        unsafe {
+            sqlite3_reset(self.stmt);
+        }
+        track_assert_eq!(code, SQLITE_DONE, Failed, "SQLite error: {}", errmsg(self.db));
+        Ok(())
+    }
+}
+unsafe impl Send for Statement {}
+impl Drop for Statement {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_finalize(self.stmt);
+        }
+    }
+}
+
+fn check(db: *mut Sqlite3, code: c_int) -> Result<()> {
+    track_assert_eq!(code, SQLITE_OK, Failed, "SQLite error: {}", errmsg(db));
+    Ok(())
+}
+
+fn errmsg(db: *mut Sqlite3) -> String {
+    unsafe { CStr::from_ptr(sqlite3_errmsg(db)) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::Ipv4Addr;
+    use std::slice;
+
+    use super::*;
+    use model::{Batch, Log, Process, SpanId, Tag, TraceId, UnknownFields};
+    use thrift::Protocol;
+
+    const SQLITE_ROW: c_int = 100;
+    const SQLITE_INTEGER: c_int = 1;
+    const SQLITE_FLOAT: c_int = 2;
+    const SQLITE_TEXT: c_int = 3;
+    const SQLITE_BLOB: c_int = 4;
+
+    #[link(name = "sqlite3")]
+    extern "C" {
+        fn sqlite3_column_count(stmt: *mut Sqlite3Stmt) -> c_int;
+        fn sqlite3_column_type(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+        fn sqlite3_column_int64(stmt: *mut Sqlite3Stmt, i: c_int) -> i64;
+        fn sqlite3_column_double(stmt: *mut Sqlite3Stmt, i: c_int) -> f64;
+        fn sqlite3_column_blob(stmt: *mut Sqlite3Stmt, i: c_int) -> *const c_void;
+        fn sqlite3_column_bytes(stmt: *mut Sqlite3Stmt, i: c_int) -> c_int;
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Null,
+        Integer(i64),
+        Real(f64),
+        Text(String),
+        Blob(Vec<u8>),
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    /// Returns the rows of a query.
+    fn select(db: &Database, sql: &str) -> Vec<Vec<Value>> {
+        let stmt = db.prepare(sql).unwrap();
+        let mut rows = Vec::new();
+        loop {
+            let code = unsafe { sqlite3_step(stmt.stmt) };
+            if code == SQLITE_DONE {
+                return rows;
+            }
+            assert_eq!(code, SQLITE_ROW, "{}", errmsg(db.0));
+            let columns = unsafe { sqlite3_column_count(stmt.stmt) };
+            let row = (0..columns)
+                .map(|i| unsafe {
+                    match sqlite3_column_type(stmt.stmt, i) {
+                        SQLITE_INTEGER => Value::Integer(sqlite3_column_int64(stmt.stmt, i)),
+                        SQLITE_FLOAT => Value::Real(sqlite3_column_double(stmt.stmt, i)),
+                        ty @ SQLITE_TEXT | ty @ SQLITE_BLOB => {
+                            // `sqlite3_column_blob` returns the bytes of texts too
+                            let ptr = sqlite3_column_blob(stmt.stmt, i) as *const u8;
+                            let len = sqlite3_column_bytes(stmt.stmt, i) as usize;
+                            let bytes = if len == 0 {
+                                Vec::new()
+                            } else {
+                                slice::from_raw_parts(ptr, len).to_vec()
+                            };
+                            if ty == SQLITE_TEXT {
+                                Value::Text(String::from_utf8(bytes).unwrap())
+                            } else {
+                                Value::Blob(bytes)
+                            }
+                        }
+                        _ => Value::Null,
+                    }
+                })
+                .collect();
+            rows.push(row);
+        }
+    }
+
+    fn span(span_id: i64, parent_span_id: Option<i64>) -> Span {
+        Span {
+            trace_id: TraceId { high: 1, low: 2 },
+            span_id: SpanId(span_id),
+            parent_span_id: parent_span_id.map(SpanId),
+            operation_name: format!("op{}", span_id),
+            references: Vec::new(),
+            flags: 1,
+            start_time: 1_544_712_660_000_000 + span_id,
+            duration: 1500,
+            tags: Vec::new(),
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    #[test]
+    fn spans_tags_and_logs_are_inserted() {
+        let mut root = span(1, Some(0));
+        root.tags = vec![
+            Tag::new("s", "x"),
+            Tag::new("b", true),
+            Tag::new("l", -1i64),
+            Tag::new("d", 0.5),
+            Tag::new("y", vec![0, 255]),
+        ];
+        root.logs = vec![Log {
+            timestamp: 1_544_712_660_000_001,
+            fields: vec![Tag::new("event", "retry"), Tag::new("attempt", 2i64)],
+            unknown_fields: UnknownFields::new(),
+        }];
+        let batch = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans: vec![root, span(2, Some(1)), span(3, None)],
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        // An IPv4 sender to a dual-stack socket
+        let peer = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped().into(), 1234);
+        let meta = ReceiveMeta {
+            peer,
+            port: 6831,
+            protocol: &Protocol::Compact,
+            bytes: &[],
+        };
+
+        let mut handler = SqliteHandler::open(":memory:").unwrap();
+        handler.transaction_size(2);
+        handler.insert(&batch, &meta).unwrap();
+        assert_eq!(handler.pending, 1);
+        handler.commit().unwrap();
+        assert_eq!(handler.pending, 0);
+
+        let spans = select(&handler.db, "SELECT * FROM spans ORDER BY id");
+        let row = |id, span_id: &str, parent: Value, operation: &str, start| {
+            vec![
+                Value::Integer(id),
+                text("0x10000000000000002"),
+                text(span_id),
+                parent,
+                text("foo"),
+                text(operation),
+                Value::Integer(start),
+                Value::Integer(1500),
+                Value::Integer(1),
+                text("127.0.0.1:1234"),
+            ]
+        };
+        assert_eq!(
+            spans,
+            vec![
+                // A zero parent ID means no parent
+                row(1, "0x1", Value::Null, "op1", 1_544_712_660_000_001),
+                row(2, "0x2", text("0x1"), "op2", 1_544_712_660_000_002),
+                row(3, "0x3", Value::Null, "op3", 1_544_712_660_000_003),
+            ]
+        );
+
+        let tags = select(&handler.db, "SELECT * FROM tags ORDER BY rowid");
+        assert_eq!(
+            tags,
+            vec![
+                vec![Value::Integer(1), text("s"), text("x")],
+                vec![Value::Integer(1), text("b"), Value::Integer(1)],
+                vec![Value::Integer(1), text("l"), Value::Integer(-1)],
+                vec![Value::Integer(1), text("d"), Value::Real(0.5)],
+                vec![Value::Integer(1), text("y"), Value::Blob(vec![0, 255])],
+            ]
+        );
+
+        let logs = select(&handler.db, "SELECT * FROM logs ORDER BY rowid");
+        let field = |key: &str, value| {
+            vec![Value::Integer(1), Value::Integer(1_544_712_660_000_001), text(key), value]
+        };
+        assert_eq!(logs, vec![field("event", text("retry")), field("attempt", Value::Integer(2))]);
+    }
+
+    #[test]
+    fn invalid_databases_are_rejected() {
+        let path = ::std::env::temp_dir().join("jaegercat-no-such-dir").join("spans.db");
+        assert!(SqliteHandler::open(&path).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! The `tail` subcommand, which reads the JSON lines written by `--format json`
+//! (e.g., in an earlier run) and outputs them again in the format given by the options.
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::SocketAddr;
+use std::str;
+use std::thread;
+use std::time::Duration;
+use jaegercat::Result;
+use jaegercat::filter::SpanFilter;
+use jaegercat::handler::{BatchFormatter, Formatter};
+use jaegercat::server::ReceiveMeta;
+use jaegercat::thrift::{EmitBatchNotification, Protocol};
+use trackable::error::{Failed, Failure};
+
+/// The interval between the checks for the lines appended to a followed file.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Settings of a tail run.
+#[derive(Debug, Clone)]
+pub struct Tail {
+    /// If `true`, waits for the lines appended to the input after reaching its end
+    /// (as `tail -f` does).
+    pub follow: bool,
+
+    /// The filter applied to each batch.
+    pub filter: SpanFilter,
+}
+impl Tail {
+    /// Reads the batches in `input` (the standard input if `-`), one JSON object per line,
+    /// and writes them to the standard output.
+    ///
+    /// Each batch is written as if it were received as a compact Thrift message
+    /// (which is what `--format raw` writes) from `0.0.0.0:0`;
+    /// the `peer` objects in the input are not restored.
+    /// When following a file, it is read again from the start if it gets truncated.
+    /// Malformed lines are reported to the standard error, and make the run fail
+    /// after the whole input is processed (unless following it).
+    pub fn run(&self, input: &str, formatter: &Formatter) -> Result<()> {
+        let stdout = io::stdout();
+        let mut writer = BufWriter::new(stdout.lock());
+        let mut reader = track!(open(input), "{}", input)?;
+        let mut line = Vec::new();
+        let mut line_number = 0;
+        let mut position = 0;
+        let mut errors = 0;
+        loop {
+            let read = track!(
+                reader
+                    .read_until(b'\n', &mut line)
+                    .map_err(Failure::from_error),
+                "{}",
+                input
+            )?;
+            position += read as u64;
+            if line.ends_with(b"\n") {
+                line_number += 1;
+                if !track!(self.write_line(input, line_number, &line, formatter, &mut writer))? {
+                    errors += 1;
+                }
+                line.clear();
+                continue;
+            }
+
+            // At the end of the input
+            if !self.follow || input == "-" {
+                if !line.is_empty() {
+                    line_number += 1;
+                    if !track!(self.write_line(input, line_number, &line, formatter, &mut writer))?
+                    {
+                        errors += 1;
+                    }
+                }
+                break;
+            }
+            track!(writer.flush().map_err(Failure::from_error))?;
+            thread::sleep(POLL_INTERVAL);
+            let len = track!(fs::metadata(input).map_err(Failure::from_error), "{}", input)?.len();
+            if len < position {
+                eprintln!("{}: file truncated", input);
+                reader = track!(open(input), "{}", input)?;
+                line.clear();
+                line_number = 0;
+                position = 0;
+            }
+        }
+        track!(writer.flush().map_err(Failure::from_error))?;
+        track_assert_eq!(errors, 0, Failed, "{} lines could not be parsed", errors);
+        Ok(())
+    }
+
+    /// Parses and writes a line, and returns `false` if it is malformed.
+    fn write_line<W: Write>(
+        &self,
+        input: &str,
+        line_number: usize,
+        line: &[u8],
+        formatter: &Formatter,
+        writer: &mut W,
+    ) -> Result<bool> {
+        let parsed = str::from_utf8(line)
+            .map_err(Failure::from_error)
+            .and_then(|line| {
+                if line.trim().is_empty() {
+                    Ok(None)
+                } else {
+                    EmitBatchNotification::from_json_str(line).map(Some)
+                }
+            });
+        let mut batch = match parsed {
+            Ok(Some(batch)) => batch,
+            Ok(None) => return Ok(true),
+            Err(e) => {
+                eprintln!("{}: line={}: {}", input, line_number, e);
+                return Ok(false);
+            }
+        };
+        if !self.filter.apply(&mut batch.batch) {
+            return Ok(true);
+        }
+        let bytes = batch.encode(Protocol::Compact);
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([0, 0, 0, 0], 0)),
+            port: 0,
+            protocol: &Protocol::Compact,
+            bytes: &bytes,
+        };
+        let mut out = Vec::new();
+        track!(formatter.format(&batch, &meta, &mut out))?;
+        track!(writer.write_all(&out).map_err(Failure::from_error))?;
+        Ok(true)
+    }
+}
+
+/// Opens an input (the standard input if `input` is `-`).
+fn open(input: &str) -> Result<Box<dyn BufRead>> {
+    if input == "-" {
+        Ok(Box::new(BufReader::new(io::stdin())))
+    } else {
+        let file = track!(File::open(input).map_err(Failure::from_error))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
@@ -0,0 +1,176 @@
+//! Output of spans shaped by templates, one line per span.
+//!
+//! A template is a text with placeholders like `{{service}}`, which are replaced by
+//! the values of the `Column`s of the same names (e.g., `{{duration_ms}}` or
+//! `{{tag:http.method}}`; whitespace around the names is ignored).
+//! The values are written as they are (without quoting or escaping),
+//! and those of absent tags are empty.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::template::Template;
+//!
+//! let template: Template = "{{service}} {{operation}} {{duration_ms}}ms".parse().unwrap();
+//! assert!("{{service} {{operation}}".parse::<Template>().is_err());
+//! assert!("{{no_such_column}}".parse::<Template>().is_err());
+//! ```
+use std::str::FromStr;
+use trackable::error::{Failed, Failure};
+
+use csv::Column;
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// A parsed template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+impl Template {
+    /// Appends a line per span of `batch` to `out`.
+    pub fn write_lines(
+        &self,
+        out: &mut Vec<u8>,
+        batch: &EmitBatchNotification,
+        meta: &ReceiveMeta,
+    ) {
+        for span in &batch.batch.spans {
+            for part in &self.parts {
+                match *part {
+                    Part::Text(ref text) => out.extend_from_slice(text.as_bytes()),
+                    Part::Column(ref column) => column.write_value(out, batch, span, meta),
+                }
+            }
+            out.push(b'\n');
+        }
+    }
+}
+impl Default for Template {
+    /// Returns a template writing the values of `Column::defaults()` separated by spaces.
+    fn default() -> Self {
+        let mut parts = Vec::new();
+        for column in Column::defaults() {
+            if !parts.is_empty() {
+                parts.push(Part::Text(" ".to_owned()));
+            }
+            parts.push(Part::Column(column));
+        }
+        Template { parts }
+    }
+}
+impl FromStr for Template {
+    type Err = Failure;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = track_assert_some!(
+                rest[start..].find("}}"),
+                Failed,
+                "Unclosed placeholder: {:?}",
+                &rest[start..]
+            );
+            let name = rest[start + 2..start + end].trim();
+            parts.push(Part::Column(track!(name.parse())?));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Template { parts })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Column(Column),
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use model::{Batch, Process, Span, SpanId, Tag, TraceId, UnknownFields};
+    use thrift::Protocol;
+
+    fn render(template: &str) -> String {
+        let template: Template = template.parse().unwrap();
+        let batch = EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: "foo".to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans: vec![Span {
+                    trace_id: TraceId { high: 0, low: 1 },
+                    span_id: SpanId(2),
+                    parent_span_id: None,
+                    operation_name: "GET /api".to_owned(),
+                    references: Vec::new(),
+                    flags: 1,
+                    start_time: 1_000_000,
+                    duration: 1500,
+                    tags: vec![Tag::new("http.url", "/api?a=\"b c\"")],
+                    logs: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                }],
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        };
+        let meta = ReceiveMeta {
+            peer: SocketAddr::from(([127, 0, 0, 1], 1234)),
+            port: 6831,
+            protocol: &Protocol::Compact,
+            bytes: &[],
+        };
+        let mut out = Vec::new();
+        template.write_lines(&mut out, &batch, &meta);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn placeholders_are_replaced() {
+        assert_eq!(
+            render("{{service}} {{ operation }} {{duration_ms}}ms"),
+            "foo GET /api 1.500ms\n"
+        );
+        assert_eq!(render("{{tag:http.url}}|{{tag:absent}}|"), "/api?a=\"b c\"||\n");
+        assert_eq!(render("{{span_id}}{{parent_span_id}}{{trace_id}}"), "0x20x1\n");
+        assert_eq!(render("no placeholders"), "no placeholders\n");
+        assert_eq!(render(""), "\n");
+
+        // Single braces are written as they are
+        assert_eq!(render("{service} }} {"), "{service} }} {\n");
+    }
+
+    #[test]
+    fn default_template_has_default_columns() {
+        let names = Column::defaults()
+            .iter()
+            .map(|c| format!("{{{{{}}}}}", c))
+            .collect::<Vec<_>>();
+        assert_eq!(Template::default(), names.join(" ").parse().unwrap());
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        for template in &["{{service", "{{service}", "a {{service} b", "{{service}} {{"] {
+            let e = template.parse::<Template>().unwrap_err();
+            assert!(e.to_string().contains("Unclosed placeholder"), "{}: {}", template, e);
+        }
+        for template in &["{{}}", "{{ }}", "{{no_such_column}}", "{{tag:}}", "{{{service}}}"] {
+            let e = template.parse::<Template>().unwrap_err();
+            assert!(e.to_string().contains("Unknown column"), "{}: {}", template, e);
+        }
+    }
+}
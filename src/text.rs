@@ -0,0 +1,74 @@
+//! Human-readable output of spans, one line per span.
+//!
+//! Each line consists of the start time in RFC 3339 (in the local time zone with
+//! the `local-time` feature, and in UTC otherwise), the duration in milliseconds,
+//! the service name, the operation name, the status (`error` if the span has the `error` tag
+//! set to `true`, otherwise `ok`) and the trace ID:
+//!
+//! ```text
+//! 2018-01-31T05:24:18.665418Z      10.196ms  example  sub   ok     0x2ae64ad7cd070e8e
+//! 2018-01-31T05:24:18.654844Z      20.779ms  example  main  error  0x2ae64ad7cd070e8e
+//! ```
+//!
+//! The operation names are padded to the longest one in each batch,
+//! so that the columns of the spans of a batch are aligned.
+//!
+//! With `Colors`, the operation names and the statuses of error spans, the service names and
+//! slow durations are colored.
+use std::io::Write;
+
+use color::Colors;
+use csv::{is_error, write_milliseconds};
+use model::Rfc3339;
+use thrift::EmitBatchNotification;
+
+/// Appends a line per span of `batch` to `out`.
+pub fn write_lines(out: &mut Vec<u8>, batch: &EmitBatchNotification, colors: Option<&Colors>) {
+    let service = &batch.batch.process.service_name;
+    let operation_width = batch
+        .batch
+        .spans
+        .iter()
+        .map(|s| s.operation_name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut value = Vec::new();
+    for span in &batch.batch.spans {
+        let error = is_error(span);
+
+        // Writing to a `Vec` never fails
+        let _ = write!(out, "{}  ", Rfc3339(span.start_time));
+        value.clear();
+        let _ = write_milliseconds(&mut value, span.duration);
+        value.extend_from_slice(b"ms");
+        pad(out, 12, value.len());
+        match colors {
+            Some(colors) => colors.duration(out, span.duration, &value),
+            None => out.extend_from_slice(&value),
+        }
+        out.extend_from_slice(b"  ");
+
+        match colors {
+            Some(colors) => colors.service(out, service),
+            None => out.extend_from_slice(service.as_bytes()),
+        }
+        out.extend_from_slice(b"  ");
+
+        value.clear();
+        value.extend_from_slice(span.operation_name.as_bytes());
+        pad(&mut value, operation_width, span.operation_name.chars().count());
+        value.extend_from_slice(if error { b"  error  " } else { b"  ok     " });
+        match colors {
+            Some(colors) if error => colors.error(out, &value),
+            _ => out.extend_from_slice(&value),
+        }
+        let _ = writeln!(out, "{}", span.trace_id);
+    }
+}
+
+/// Appends spaces to `out` to make a text of `len` characters `width` characters wide.
+fn pad(out: &mut Vec<u8>, width: usize, len: usize) {
+    for _ in len..width {
+        out.push(b' ');
+    }
+}
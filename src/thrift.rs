@@ -1,392 +1,545 @@
-use std::collections::BTreeMap;
-use chrono::{Local, NaiveDateTime, TimeZone};
-use thrift_codec::{BinaryDecode, CompactDecode};
-use thrift_codec::data::{Data, DataRef, List, Struct};
-use thrift_codec::message::{Message, MessageKind};
-use trackable::error::{ErrorKindExt, Failed, Failure};
+#[cfg(feature = "std")]
+use serdeconv;
+#[cfg(feature = "std")]
+use trackable::error::{ErrorKindExt, Failed};
 
-use Result;
+use error::DecodeResult as Result;
 
-#[derive(Debug, Clone, Copy)]
+use borrowed::{decode_borrowed, decode_lenient, decode_strict, decode_with_limits,
+               PartialDecodeError};
+use model::Batch;
+#[cfg(feature = "std")]
+use model::{ClientStats, Log, Process, Span, SpanRef, SpanRefType, Tag, TagValue, ThriftValue,
+            UnknownFields};
+#[cfg(feature = "std")]
+use wire::{Type, Writer, MESSAGE_KIND_ONEWAY};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Protocol {
     Compact,
     Binary,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeMode {
+    /// Skips unknown fields and rejects malformed messages.
+    Normal,
+
+    /// Salvages the spans decoded before a failure.
+    Lenient,
+
+    /// Rejects unknown fields, fields of unexpected types and trailing bytes.
+    Strict,
+}
+
+/// Limits on the sizes of the values in a message, which protect the decoder from
+/// malicious or corrupt inputs.
+///
+/// Messages exceeding any of the limits are rejected with `DecodeError::LimitExceeded`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeLimits {
+    /// The maximum length of a string or a binary in bytes.
+    ///
+    /// The default value is `1048576` (1 MiB).
+    pub max_string_len: usize,
+
+    /// The maximum number of the elements of a list, a set or a map.
+    ///
+    /// The default value is `1048576`.
+    pub max_collection_size: usize,
+
+    /// The maximum nesting depth of structs and collections.
+    ///
+    /// The top-level argument struct of a message is at depth `1`.
+    /// The default value is `64`.
+    pub max_depth: usize,
+}
+impl DecodeLimits {
+    /// Returns limits which accept any message.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_string_len: usize::MAX,
+            max_collection_size: usize::MAX,
+            max_depth: usize::MAX,
+        }
+    }
+}
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_string_len: 1024 * 1024,
+            max_collection_size: 1024 * 1024,
+            max_depth: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct EmitBatchNotification {
-    #[serde(rename = "emit_batch")]
+    #[cfg_attr(feature = "std", serde(rename = "emit_batch"))]
     pub batch: Batch,
 }
 impl EmitBatchNotification {
-    pub fn decode(mut buf: &[u8], protocol: Protocol) -> Result<Self> {
-        let message = match protocol {
-            Protocol::Compact => {
-                track!(Message::compact_decode(&mut buf).map_err(|e| Failed.takes_over(e)))?
-            }
-            Protocol::Binary => {
-                track!(Message::binary_decode(&mut buf).map_err(|e| Failed.takes_over(e)))?
-            }
-        };
-        track_assert_eq!(message.method_name(), "emitBatch", Failed);
-        track_assert_eq!(message.kind(), MessageKind::Oneway, Failed);
-        let batch = track!(Batch::try_from(message.body()))?;
+    pub fn decode(buf: &[u8], protocol: Protocol) -> Result<Self> {
+        let batch = decode_borrowed(buf, protocol)?.into_owned();
         Ok(EmitBatchNotification { batch })
     }
-}
 
-#[derive(Debug, Serialize)]
-pub struct Batch {
-    pub process: Process,
-    pub spans: Vec<Span>,
-}
-impl Batch {
-    fn try_from(f: &Struct) -> Result<Self> {
-        let s0 = track!(f.struct_field(1))?;
-        let s1 = track!(s0.struct_field(1))?;
-        let process = track!(Process::try_from(&s1))?;
-        let spans = track!(
-            track!(s0.list_field(2))?
-                .iter()
-                .map(|x| Span::try_from_data(&x))
-                .collect::<Result<Vec<_>>>()
-        )?;
-        Ok(Batch { process, spans })
+    pub fn decode_strict(buf: &[u8], protocol: Protocol) -> Result<Self> {
+        let batch = decode_strict(buf, protocol)?.into_owned();
+        Ok(EmitBatchNotification { batch })
     }
-}
 
-#[derive(Debug, Serialize)]
-pub struct Process {
-    pub service_name: String,
-    pub tags: Tags,
-}
-impl Process {
-    fn try_from(f: &Struct) -> Result<Self> {
-        let service_name = track!(f.string_field(1))?;
-        let tags = track!(f.list_field(2).and_then(|x| Tags::try_from_list(&x)))?;
-        Ok(Process { service_name, tags })
+    pub fn decode_lenient(
+        buf: &[u8],
+        protocol: Protocol,
+    ) -> Result<(Self, Option<PartialDecodeError>)> {
+        let (batch, error) = decode_lenient(buf, protocol)?;
+        let batch = batch.into_owned();
+        Ok((EmitBatchNotification { batch }, error))
     }
-}
 
-#[derive(Debug, Serialize)]
-pub struct Tags(pub BTreeMap<String, TagValue>);
-impl Tags {
-    fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    /// Decodes a message in the given mode, rejecting values which exceed `limits`.
+    ///
+    /// The other `decode*` methods use `DecodeLimits::default()`.
+    /// An error is returned along with the message only in `DecodeMode::Lenient`.
+    pub fn decode_with_limits(
+        buf: &[u8],
+        protocol: Protocol,
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> Result<(Self, Option<PartialDecodeError>)> {
+        let (batch, error) = decode_with_limits(buf, protocol, mode, limits)?;
+        let batch = batch.into_owned();
+        Ok((EmitBatchNotification { batch }, error))
     }
-    fn try_from_list(f: &List) -> Result<Self> {
-        let mut map = BTreeMap::new();
-        for data in f.iter() {
-            let tag = track!(Tag::try_from_data(&data))?;
-            let (key, value) = match tag {
-                Tag::Bool { key, value } => (key, TagValue::Bool(value)),
-                Tag::Long { key, value } => (key, TagValue::I64(value)),
-                Tag::Double { key, value } => (key, TagValue::F64(value)),
-                Tag::String { key, value } => (key, TagValue::String(value)),
-                Tag::Binary { key, value } => (key, TagValue::Binary(value)),
-            };
-            map.insert(key, value);
-        }
-        Ok(Tags(map))
+
+    /// Parses a notification in the JSON format of `jaegercat --format json`.
+    ///
+    /// Because `start_datetime` and `datetime` are ignored, the times are restored from
+    /// the UNIX times in seconds, which are rounded to microseconds.
+    /// Unknown fields and the `peer` object are ignored too.
+    #[cfg(feature = "std")]
+    pub fn from_json_str(json: &str) -> ::Result<Self> {
+        serdeconv::from_json_str(json).map_err(|e| Failed.takes_over(e).into())
     }
-}
 
-#[derive(Debug, Serialize)]
-#[serde(untagged)]
-pub enum TagValue {
-    Bool(bool),
-    I64(i64),
-    F64(f64),
-    String(String),
-    Binary(Vec<u8>),
-}
+    /// Encodes the notification as a one-way `emitBatch` message.
+    ///
+    /// The unknown fields are encoded along with the known ones.
+    #[cfg(feature = "std")]
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        let mut w = Writer::new(protocol);
+        w.write_message_begin("emitBatch", MESSAGE_KIND_ONEWAY);
+        w.write_struct_begin();
+        w.write_field_begin(1, Type::Struct);
+        encode_batch(&mut w, &self.batch);
+        w.write_struct_end();
+        w.into_bytes()
+    }
 
-enum Tag {
-    String { key: String, value: String },
-    Double { key: String, value: f64 },
-    Bool { key: String, value: bool },
-    Long { key: String, value: i64 },
-    Binary { key: String, value: Vec<u8> },
+    /// Encodes the batch as a bare `Batch` struct, which is not wrapped in a message
+    /// (e.g., the body which clients `POST` to the collector in the binary protocol,
+    /// as decoded by `protocol::CollectorThrift`).
+    #[cfg(feature = "std")]
+    pub fn encode_batch(&self, protocol: Protocol) -> Vec<u8> {
+        let mut w = Writer::new(protocol);
+        encode_batch(&mut w, &self.batch);
+        w.into_bytes()
+    }
 }
-impl Tag {
-    fn try_from_data(f: &DataRef) -> Result<Self> {
-        let s = if let DataRef::Struct(s) = *f {
-            s
-        } else {
-            track_panic!(Failed, "Not a struct: {:?}", f);
-        };
 
-        let key = track!(s.string_field(1))?;
-        let kind = track!(s.i32_field(2))?;
-        Ok(match kind {
-            0 => Tag::String {
-                key,
-                value: track!(s.string_field(3))?,
-            },
-            1 => Tag::Double {
-                key,
-                value: track!(s.f64_field(4))?,
-            },
-            2 => Tag::Bool {
-                key,
-                value: track!(s.bool_field(5))?,
-            },
-            3 => Tag::Long {
-                key,
-                value: track!(s.i64_field(6))?,
-            },
-            4 => Tag::Binary {
-                key,
-                value: track!(s.binary_field(7))?,
-            },
-            _ => track_panic!(Failed, "Unknown tag kind: {}", kind),
-        })
+#[cfg(feature = "std")]
+fn encode_batch(w: &mut Writer, batch: &Batch) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::Struct);
+    encode_process(w, &batch.process);
+    w.write_field_begin(2, Type::List);
+    w.write_list_begin(Type::Struct, batch.spans.len());
+    for span in &batch.spans {
+        encode_span(w, span);
+    }
+    if let Some(seq_no) = batch.seq_no {
+        w.write_field_begin(3, Type::I64);
+        w.write_i64(seq_no);
+    }
+    if let Some(ref stats) = batch.stats {
+        w.write_field_begin(4, Type::Struct);
+        encode_client_stats(w, stats);
     }
+    encode_unknown_fields(w, &batch.unknown_fields, &[1, 2, 3, 4]);
+    w.write_struct_end();
 }
 
-fn trace_id(high: i64, low: i64) -> String {
-    if high == 0 {
-        format!("0x{:x}", low)
-    } else {
-        format!("0x{:x}{:016x}", high, low)
+#[cfg(feature = "std")]
+fn encode_process(w: &mut Writer, process: &Process) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::Binary);
+    w.write_binary(process.service_name.as_bytes());
+    if !process.tags.is_empty() {
+        w.write_field_begin(2, Type::List);
+        encode_tags(w, &process.tags);
     }
+    encode_unknown_fields(w, &process.unknown_fields, &[1, 2]);
+    w.write_struct_end();
 }
 
-fn span_id(id: i64) -> String {
-    if id == 0 {
-        "".to_owned()
-    } else {
-        format!("0x{:x}", id)
+#[cfg(feature = "std")]
+fn encode_client_stats(w: &mut Writer, stats: &ClientStats) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::I64);
+    w.write_i64(stats.full_queue_dropped_spans);
+    w.write_field_begin(2, Type::I64);
+    w.write_i64(stats.too_large_dropped_spans);
+    w.write_field_begin(3, Type::I64);
+    w.write_i64(stats.failed_to_emit_spans);
+    encode_unknown_fields(w, &stats.unknown_fields, &[1, 2, 3]);
+    w.write_struct_end();
+}
+
+#[cfg(feature = "std")]
+fn encode_tags(w: &mut Writer, tags: &[Tag]) {
+    w.write_list_begin(Type::Struct, tags.len());
+    for tag in tags {
+        encode_tag(w, tag);
     }
 }
 
-#[derive(Debug, Serialize)]
-pub struct Span {
-    pub trace_id: String,
-    pub span_id: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
-    pub parent_span_id: String,
-    pub operation_name: String,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub references: Vec<SpanRef>,
-    pub flags: i32,
-    pub start_datetime: String,
-    pub start_unixtime: f64,
-    pub duration: f64, // seconds
-    #[serde(skip_serializing_if = "Tags::is_empty")]
-    pub tags: Tags,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub logs: Vec<Log>,
+#[cfg(feature = "std")]
+fn encode_tag(w: &mut Writer, tag: &Tag) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::Binary);
+    w.write_binary(tag.key.as_bytes());
+    w.write_field_begin(2, Type::I32);
+    match tag.value {
+        TagValue::Str(ref v) => {
+            w.write_i32(0);
+            w.write_field_begin(3, Type::Binary);
+            w.write_binary(v.as_bytes());
+        }
+        TagValue::Double(v) => {
+            w.write_i32(1);
+            w.write_field_begin(4, Type::Double);
+            w.write_double(v);
+        }
+        TagValue::Bool(v) => {
+            w.write_i32(2);
+            w.write_field_begin(5, Type::Bool);
+            w.write_bool(v);
+        }
+        TagValue::Long(v) => {
+            w.write_i32(3);
+            w.write_field_begin(6, Type::I64);
+            w.write_i64(v);
+        }
+        TagValue::Binary(ref v) => {
+            w.write_i32(4);
+            w.write_field_begin(7, Type::Binary);
+            w.write_binary(v);
+        }
+    }
+    encode_unknown_fields(w, &tag.unknown_fields, &[1, 2, 3, 4, 5, 6, 7]);
+    w.write_struct_end();
 }
-impl Span {
-    fn try_from_data(f: &DataRef) -> Result<Self> {
-        let s = if let DataRef::Struct(s) = *f {
-            s
-        } else {
-            track_panic!(Failed, "Not a struct: {:?}", f);
-        };
-        let start_time_us = track!(s.i64_field(8))?;
-        let duration_us = track!(s.i64_field(9))?;
-        Ok(Span {
-            trace_id: trace_id(track!(s.i64_field(2))?, track!(s.i64_field(1))?),
-            span_id: span_id(track!(s.i64_field(3))?),
-            parent_span_id: span_id(track!(s.i64_field(4))?),
-            operation_name: track!(s.string_field(5))?,
-            references: track!(s.list_field(6).and_then(|x| SpanRef::try_from_list(&x)))?,
-            flags: track!(s.i32_field(7))?,
-            start_unixtime: start_time_us as f64 / 1000_000.0,
-            start_datetime: unixtime_to_datetime(start_time_us),
-            duration: duration_us as f64 / 1000_000.0,
-            tags: track!(s.list_field(10).and_then(|x| Tags::try_from_list(&x)))?,
-            logs: track!(s.list_field(11).and_then(|x| Log::try_from_list(&x)))?,
-        })
+
+#[cfg(feature = "std")]
+fn encode_span(w: &mut Writer, span: &Span) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::I64);
+    w.write_i64(span.trace_id.low);
+    w.write_field_begin(2, Type::I64);
+    w.write_i64(span.trace_id.high);
+    w.write_field_begin(3, Type::I64);
+    w.write_i64(span.span_id.0);
+    w.write_field_begin(4, Type::I64);
+    w.write_i64(span.parent_span_id.map_or(0, |id| id.0));
+    w.write_field_begin(5, Type::Binary);
+    w.write_binary(span.operation_name.as_bytes());
+    if !span.references.is_empty() {
+        w.write_field_begin(6, Type::List);
+        w.write_list_begin(Type::Struct, span.references.len());
+        for r in &span.references {
+            encode_span_ref(w, r);
+        }
+    }
+    w.write_field_begin(7, Type::I32);
+    w.write_i32(span.flags);
+    w.write_field_begin(8, Type::I64);
+    w.write_i64(span.start_time);
+    w.write_field_begin(9, Type::I64);
+    w.write_i64(span.duration);
+    if !span.tags.is_empty() {
+        w.write_field_begin(10, Type::List);
+        encode_tags(w, &span.tags);
     }
+    if !span.logs.is_empty() {
+        w.write_field_begin(11, Type::List);
+        w.write_list_begin(Type::Struct, span.logs.len());
+        for log in &span.logs {
+            encode_log(w, log);
+        }
+    }
+    let known = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+    encode_unknown_fields(w, &span.unknown_fields, &known);
+    w.write_struct_end();
 }
 
-fn unixtime_to_datetime(unixtime_us: i64) -> String {
-    Local
-        .from_utc_datetime(&NaiveDateTime::from_timestamp(
-            unixtime_us / 1_000_000,
-            (unixtime_us % 1_000_000 * 1000) as u32,
-        ))
-        .format("%Y-%m-%d %H:%M:%S")
-        .to_string()
+#[cfg(feature = "std")]
+fn encode_span_ref(w: &mut Writer, r: &SpanRef) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::I32);
+    w.write_i32(match r.ref_type {
+        SpanRefType::ChildOf => 0,
+        SpanRefType::FollowsFrom => 1,
+    });
+    w.write_field_begin(2, Type::I64);
+    w.write_i64(r.trace_id.low);
+    w.write_field_begin(3, Type::I64);
+    w.write_i64(r.trace_id.high);
+    w.write_field_begin(4, Type::I64);
+    w.write_i64(r.span_id.0);
+    encode_unknown_fields(w, &r.unknown_fields, &[1, 2, 3, 4]);
+    w.write_struct_end();
 }
 
-#[derive(Debug, Serialize)]
-pub enum SpanRef {
-    ChildOf { trace_id: String, span_id: String },
-    FollowsFrom { trace_id: String, span_id: String },
+#[cfg(feature = "std")]
+fn encode_log(w: &mut Writer, log: &Log) {
+    w.write_struct_begin();
+    w.write_field_begin(1, Type::I64);
+    w.write_i64(log.timestamp);
+    w.write_field_begin(2, Type::List);
+    encode_tags(w, &log.fields);
+    encode_unknown_fields(w, &log.unknown_fields, &[1, 2]);
+    w.write_struct_end();
 }
-impl SpanRef {
-    fn try_from_list(f: &List) -> Result<Vec<Self>> {
-        track!(
-            f.iter()
-                .map(|x| Self::try_from_data(&x))
-                .collect::<Result<Vec<_>>>()
-        )
-    }
-    fn try_from_data(f: &DataRef) -> Result<Self> {
-        let s = if let DataRef::Struct(s) = *f {
-            s
-        } else {
-            track_panic!(Failed, "Not a struct: {:?}", f);
-        };
 
-        let kind = track!(s.i32_field(1))?;
-        let trace_id = trace_id(track!(s.i64_field(3))?, track!(s.i64_field(2))?);
-        let span_id = span_id(track!(s.i64_field(4))?);
-        Ok(match kind {
-            0 => SpanRef::ChildOf { trace_id, span_id },
-            1 => SpanRef::FollowsFrom { trace_id, span_id },
-            _ => track_panic!(Failed, "Unknown span reference kind: {}", kind),
-        })
+/// Writes the unknown fields of a struct, except those whose IDs are taken by the known ones.
+#[cfg(feature = "std")]
+fn encode_unknown_fields(w: &mut Writer, fields: &UnknownFields, known_ids: &[i16]) {
+    for (&id, value) in fields {
+        if !known_ids.contains(&id) {
+            w.write_field_begin(id, value_type(value));
+            encode_value(w, value);
+        }
     }
 }
 
-#[derive(Debug, Serialize)]
-pub struct Log {
-    pub datetime: String,
-    pub unixtime: f64,
-    pub fields: Tags,
-}
-impl Log {
-    fn try_from_list(f: &List) -> Result<Vec<Self>> {
-        track!(
-            f.iter()
-                .map(|x| Self::try_from_data(&x))
-                .collect::<Result<Vec<_>>>()
-        )
+/// Writes an unknown value.
+///
+/// Because the element types of collections are not recorded, the elements of
+/// an empty collection are written as `bool`s, and the elements of a collection whose types
+/// differ from the first one are dropped.
+#[cfg(feature = "std")]
+fn encode_value(w: &mut Writer, value: &ThriftValue) {
+    match *value {
+        ThriftValue::Bool(v) => w.write_bool(v),
+        ThriftValue::I8(v) => w.write_i8(v),
+        ThriftValue::I16(v) => w.write_i16(v),
+        ThriftValue::I32(v) => w.write_i32(v),
+        ThriftValue::I64(v) => w.write_i64(v),
+        ThriftValue::Double(v) => w.write_double(v),
+        ThriftValue::Binary(ref v) => w.write_binary(v),
+        ThriftValue::Struct(ref fields) => {
+            w.write_struct_begin();
+            encode_unknown_fields(w, fields, &[]);
+            w.write_struct_end();
+        }
+        ThriftValue::List(ref elements) | ThriftValue::Set(ref elements) => {
+            let ty = elements.first().map_or(Type::Bool, value_type);
+            let elements = elements.iter().filter(|e| value_type(e) == ty).collect::<Vec<_>>();
+            w.write_list_begin(ty, elements.len());
+            for e in elements {
+                encode_value(w, e);
+            }
+        }
+        ThriftValue::Map(ref entries) => {
+            let types = |(k, v): &&(ThriftValue, ThriftValue)| (value_type(k), value_type(v));
+            let (key, value) = entries.first().map_or((Type::Bool, Type::Bool), |e| types(&e));
+            let entries = entries
+                .iter()
+                .filter(|e| types(e) == (key, value))
+                .collect::<Vec<_>>();
+            w.write_map_begin(key, value, entries.len());
+            for (k, v) in entries {
+                encode_value(w, k);
+                encode_value(w, v);
+            }
+        }
     }
-    fn try_from_data(f: &DataRef) -> Result<Self> {
-        let s = if let DataRef::Struct(s) = *f {
-            s
-        } else {
-            track_panic!(Failed, "Not a struct: {:?}", f);
-        };
+}
 
-        let timestamp_us = track!(s.i64_field(1))?;
-        let fields = track!(s.list_field(2).and_then(|x| Tags::try_from_list(&x)))?;
-        Ok(Log {
-            unixtime: timestamp_us as f64 / 1000_000.0,
-            datetime: unixtime_to_datetime(timestamp_us),
-            fields,
-        })
+#[cfg(feature = "std")]
+fn value_type(value: &ThriftValue) -> Type {
+    match *value {
+        ThriftValue::Bool(_) => Type::Bool,
+        ThriftValue::I8(_) => Type::I8,
+        ThriftValue::I16(_) => Type::I16,
+        ThriftValue::I32(_) => Type::I32,
+        ThriftValue::I64(_) => Type::I64,
+        ThriftValue::Double(_) => Type::Double,
+        ThriftValue::Binary(_) => Type::Binary,
+        ThriftValue::Struct(_) => Type::Struct,
+        ThriftValue::Map(_) => Type::Map,
+        ThriftValue::Set(_) => Type::Set,
+        ThriftValue::List(_) => Type::List,
     }
 }
 
-trait StructExt {
-    fn bool_field(&self, id: i16) -> Result<bool>;
-    fn i32_field(&self, id: i16) -> Result<i32>;
-    fn i64_field(&self, id: i16) -> Result<i64>;
-    fn f64_field(&self, id: i16) -> Result<f64>;
-    fn binary_field(&self, id: i16) -> Result<Vec<u8>>;
-    fn string_field(&self, id: i16) -> Result<String>;
-    fn list_field(&self, id: i16) -> Result<List>;
-    fn struct_field(&self, id: i16) -> Result<Struct>;
-}
-impl StructExt for Struct {
-    fn struct_field(&self, id: i16) -> Result<Struct> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::Struct(ref f) = *field.data() {
-            Ok(f.clone())
-        } else {
-            track_panic!(Failed, "not a struct field: {:?}", field)
-        }
-    }
-    fn string_field(&self, id: i16) -> Result<String> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::Binary(ref f) = *field.data() {
-            track!(String::from_utf8(f.to_owned()).map_err(Failure::from_error))
-        } else {
-            track_panic!(Failed, "not a string field: {:?}", field)
-        }
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use std::collections::BTreeMap;
+
+    use model::{Process, SpanId, TraceId};
+    use super::*;
+
+    fn tags(prefix: &str) -> Vec<Tag> {
+        vec![
+            Tag::new(format!("{}.str", prefix), "foo"),
+            Tag::new(format!("{}.double", prefix), 1.5),
+            Tag::new(format!("{}.bool", prefix), true),
+            Tag::new(format!("{}.long", prefix), -42i64),
+            Tag::new(format!("{}.binary", prefix), vec![0u8, 1, 255]),
+        ]
     }
-    fn binary_field(&self, id: i16) -> Result<Vec<u8>> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
+
+    /// Returns unknown fields of every Thrift type, including empty collections.
+    fn unknown_fields() -> UnknownFields {
+        let mut nested = BTreeMap::new();
+        nested.insert(1, ThriftValue::Binary(b"nested".to_vec()));
+        nested.insert(2, ThriftValue::List(vec![ThriftValue::I32(1), ThriftValue::I32(2)]));
+
+        let mut fields = UnknownFields::new();
+        fields.insert(100, ThriftValue::I8(-8));
+        fields.insert(101, ThriftValue::I16(16));
+        fields.insert(102, ThriftValue::I32(-32));
+        fields.insert(103, ThriftValue::I64(1 << 40));
+        fields.insert(104, ThriftValue::Double(-0.25));
+        fields.insert(105, ThriftValue::Binary(b"bar".to_vec()));
+        fields.insert(106, ThriftValue::Struct(nested.clone()));
+        fields.insert(107, ThriftValue::List(vec![ThriftValue::Struct(nested)]));
+        fields.insert(108, ThriftValue::Set(vec![ThriftValue::I64(3), ThriftValue::I64(4)]));
+        fields.insert(
+            109,
+            ThriftValue::Map(vec![(ThriftValue::Binary(b"k".to_vec()), ThriftValue::Bool(true))]),
         );
-        if let Data::Binary(ref f) = *field.data() {
-            Ok(f.clone())
-        } else {
-            track_panic!(Failed, "not a binary field: {:?}", field)
-        }
+        fields.insert(110, ThriftValue::List(Vec::new()));
+        fields.insert(111, ThriftValue::Map(Vec::new()));
+        let bools = vec![ThriftValue::Bool(true), ThriftValue::Bool(false)];
+        fields.insert(112, ThriftValue::Set(bools));
+        fields.insert(i16::MIN, ThriftValue::Bool(true));
+        fields.insert(i16::MAX, ThriftValue::I64(-1));
+        fields
     }
-    fn bool_field(&self, id: i16) -> Result<bool> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::Bool(ref f) = *field.data() {
-            Ok(*f)
-        } else {
-            track_panic!(Failed, "not a bool field: {:?}", field)
+
+    fn span(trace_id: TraceId, span_id: i64) -> Span {
+        Span {
+            trace_id,
+            span_id: SpanId(span_id),
+            parent_span_id: None,
+            operation_name: format!("op{}", span_id),
+            references: Vec::new(),
+            flags: 1,
+            start_time: 1_544_712_660_500_000,
+            duration: 1_000_001,
+            tags: Vec::new(),
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
         }
     }
-    fn i32_field(&self, id: i16) -> Result<i32> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::I32(ref f) = *field.data() {
-            Ok(*f)
-        } else {
-            track_panic!(Failed, "not an i32 field: {:?}", field)
+
+    fn batch() -> Batch {
+        let trace_id = TraceId {
+            high: 0x5b8e_fff7_9803_8103,
+            low: -0x2c97_1b4c_f7c0_e6b3,
+        };
+        let root = span(trace_id, 1);
+
+        let mut child = span(trace_id, -2);
+        child.parent_span_id = Some(SpanId(1));
+        child.references = vec![
+            SpanRef {
+                ref_type: SpanRefType::ChildOf,
+                trace_id,
+                span_id: SpanId(1),
+                unknown_fields: UnknownFields::new(),
+            },
+            SpanRef {
+                ref_type: SpanRefType::FollowsFrom,
+                trace_id: TraceId { high: 0, low: 7 },
+                span_id: SpanId(i64::MAX),
+                unknown_fields: unknown_fields(),
+            },
+        ];
+        child.tags = tags("span");
+        child.tags[0].unknown_fields = unknown_fields();
+        child.logs = vec![
+            Log {
+                timestamp: 1_544_712_660_600_000,
+                fields: tags("log"),
+                unknown_fields: UnknownFields::new(),
+            },
+            Log {
+                timestamp: -1,
+                fields: Vec::new(),
+                unknown_fields: unknown_fields(),
+            },
+        ];
+        child.unknown_fields = unknown_fields();
+
+        Batch {
+            process: Process {
+                service_name: "foo".to_owned(),
+                tags: tags("process"),
+                unknown_fields: unknown_fields(),
+            },
+            spans: vec![root, child],
+            seq_no: Some(3),
+            stats: Some(ClientStats {
+                full_queue_dropped_spans: 1,
+                too_large_dropped_spans: 2,
+                failed_to_emit_spans: 3,
+                unknown_fields: unknown_fields(),
+            }),
+            unknown_fields: unknown_fields(),
         }
     }
-    fn i64_field(&self, id: i16) -> Result<i64> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::I64(ref f) = *field.data() {
-            Ok(*f)
-        } else {
-            track_panic!(Failed, "not an i64 field: {:?}", field)
+
+    #[test]
+    fn encode_round_trip_works() {
+        let message = EmitBatchNotification { batch: batch() };
+        for &protocol in &[Protocol::Compact, Protocol::Binary] {
+            let buf = message.encode(protocol);
+            let decoded = EmitBatchNotification::decode(&buf, protocol).unwrap();
+            assert_eq!(decoded, message, "{:?}", protocol);
         }
     }
-    fn f64_field(&self, id: i16) -> Result<f64> {
-        let field = track_assert_some!(
-            self.fields().iter().find(|f| f.id() == id),
-            Failed,
-            "missing field: id={}",
-            id
-        );
-        if let Data::Double(ref f) = *field.data() {
-            Ok(*f)
-        } else {
-            track_panic!(Failed, "not a f64 field: {:?}", field)
+
+    #[test]
+    fn encode_conforms_to_schema() {
+        let mut batch = batch();
+        batch.clear_unknown_fields();
+        let message = EmitBatchNotification { batch };
+        for &protocol in &[Protocol::Compact, Protocol::Binary] {
+            let buf = message.encode(protocol);
+            let decoded = EmitBatchNotification::decode_strict(&buf, protocol).unwrap();
+            assert_eq!(decoded, message, "{:?}", protocol);
         }
     }
-    fn list_field(&self, id: i16) -> Result<List> {
-        if let Some(field) = self.fields().iter().find(|f| f.id() == id) {
-            if let Data::List(ref f) = *field.data() {
-                Ok(f.clone())
-            } else {
-                track_panic!(Failed, "not a list field: {:?}", field)
-            }
-        } else {
-            // dummy
-            Ok(List::from(Vec::<bool>::new()))
-        }
+
+    #[test]
+    fn encode_drops_unencodable_unknown_fields() {
+        let mut batch = batch();
+        batch.clear_unknown_fields();
+        let mixed = vec![ThriftValue::I32(1), ThriftValue::Bool(true), ThriftValue::I32(2)];
+        batch.unknown_fields.insert(100, ThriftValue::List(mixed));
+        batch.unknown_fields.insert(1, ThriftValue::Bool(true)); // Taken by `process`
+
+        let message = EmitBatchNotification { batch };
+        let buf = message.encode(Protocol::Compact);
+        let decoded = EmitBatchNotification::decode(&buf, Protocol::Compact).unwrap();
+        let list = vec![ThriftValue::I32(1), ThriftValue::I32(2)];
+        assert_eq!(decoded.batch.unknown_fields.len(), 1);
+        assert_eq!(decoded.batch.unknown_fields[&100], ThriftValue::List(list));
     }
 }
@@ -0,0 +1,563 @@
+//! Output of traces as trees of their spans.
+//!
+//! `TreeHandler` buffers the spans of each trace until no more spans of it have been received
+//! for a while, and then writes the trace with each span indented under its parent
+//! (found by the parent span ID or the first reference), in the order of the start times:
+//!
+//! ```text
+//! 0x2ae64ad7cd070e8e (3 spans, 20.779ms)
+//! └── main (example) 20.779ms
+//!     ├── sub (example) 10.196ms [error]
+//!     └── query (db) 3.012ms
+//! ```
+//!
+//! The spans whose parents have not been received are written as roots, with the IDs of
+//! the missing parents.
+//!
+//! With `Colors`, the operation names of error spans, the service names and slow durations
+//! are colored.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use std::io;
+//! use std::time::Duration;
+//! use jaegercat::server::ServerBuilder;
+//! use jaegercat::tree::TreeHandler;
+//!
+//! let mut handler = TreeHandler::new(io::stdout());
+//! handler.wait(Duration::from_secs(3));
+//! let server = ServerBuilder::new().build(handler).unwrap();
+//! server.join().unwrap();
+//! ```
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use trackable::error::Failure;
+
+use Result;
+use color::Colors;
+use csv::{is_error, write_milliseconds};
+use folded;
+use handler::BatchHandler;
+use model::{Span, SpanId, TraceId};
+use server::ReceiveMeta;
+use thrift::EmitBatchNotification;
+
+/// A `BatchHandler` implementation which writes each trace as a tree of its spans.
+///
+/// The traces are written by a background thread, which terminates when the handler is dropped
+/// (after the remaining traces are written).
+pub struct TreeHandler {
+    traces: Arc<Mutex<Traces>>,
+}
+impl TreeHandler {
+    /// Makes a new `TreeHandler` writing the trees to `writer`.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        let traces = Arc::new(Mutex::new(Traces {
+            pending: HashMap::new(),
+            wait: Duration::from_secs(1),
+            writer: Box::new(writer),
+            colors: None,
+            folded: false,
+            error: None,
+        }));
+        let weak = Arc::downgrade(&traces);
+        thread::spawn(move || write_expired_traces(&weak));
+        TreeHandler { traces }
+    }
+
+    /// Sets how long the spans of a trace are buffered after the last one of them is received.
+    ///
+    /// The default value is 1 second.
+    pub fn wait(&mut self, wait: Duration) -> &mut Self {
+        lock(&self.traces).wait = wait;
+        self
+    }
+
+    /// Sets the colors of the trees.
+    ///
+    /// By default, the trees are not colored.
+    pub fn colors(&mut self, colors: Colors) -> &mut Self {
+        lock(&self.traces).colors = Some(colors);
+        self
+    }
+
+    /// Sets whether the traces are written as folded stacks (see `folded`) instead of trees.
+    ///
+    /// The default value is `false`.
+    pub fn folded(&mut self, folded: bool) -> &mut Self {
+        lock(&self.traces).folded = folded;
+        self
+    }
+
+    /// Buffers the spans of `batch`.
+    ///
+    /// This fails if the background thread has failed to write traces.
+    pub fn push(&mut self, batch: &EmitBatchNotification) -> Result<()> {
+        let mut traces = lock(&self.traces);
+        if let Some(e) = traces.error.take() {
+            return Err(track!(e));
+        }
+        let now = Instant::now();
+        for span in &batch.batch.spans {
+            let trace = traces.pending.entry(span.trace_id).or_insert_with(|| Trace {
+                spans: Vec::new(),
+                last_received: now,
+            });
+            trace.spans.push((span.clone(), batch.batch.process.service_name.clone()));
+            trace.last_received = now;
+        }
+        Ok(())
+    }
+}
+impl BatchHandler for TreeHandler {
+    fn on_batch(&mut self, batch: &EmitBatchNotification, _meta: &ReceiveMeta) {
+        track_try_unwrap!(self.push(batch));
+    }
+}
+impl Drop for TreeHandler {
+    fn drop(&mut self) {
+        let _ = lock(&self.traces).write(None);
+    }
+}
+
+struct Traces {
+    pending: HashMap<TraceId, Trace>,
+    wait: Duration,
+    writer: Box<dyn Write + Send>,
+    colors: Option<Colors>,
+    folded: bool,
+    error: Option<Failure>,
+}
+impl Traces {
+    /// Writes the traces which have received no spans for `wait` (or all if `None`).
+    fn write(&mut self, wait: Option<Duration>) -> Result<()> {
+        let mut expired = self.pending
+            .iter()
+            .filter(|&(_, t)| wait.is_none_or(|w| t.last_received.elapsed() >= w))
+            .map(|(&id, t)| (t.spans.iter().map(|s| s.0.start_time).min(), id))
+            .collect::<Vec<_>>();
+        if expired.is_empty() {
+            return Ok(());
+        }
+        expired.sort();
+        let mut out = Vec::new();
+        for (_, id) in expired {
+            if let Some(trace) = self.pending.remove(&id) {
+                let mut spans = trace.spans.iter().map(|s| (&s.0, &s.1[..])).collect::<Vec<_>>();
+                if self.folded {
+                    folded::write_trace(&mut out, &mut spans);
+                } else {
+                    write_trace(&mut out, id, &mut spans, self.colors.as_ref());
+                }
+            }
+        }
+        track!(self.writer.write_all(&out).map_err(Failure::from_error))?;
+        track!(self.writer.flush().map_err(Failure::from_error))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Trace {
+    spans: Vec<(Span, String)>,
+    last_received: Instant,
+}
+
+/// Appends a tree per trace of the spans of `batch` to `out`.
+///
+/// This is used by `Format::Tree`, which cannot join the spans of a trace
+/// received in different batches (unlike `TreeHandler`).
+pub fn write_trees(out: &mut Vec<u8>, batch: &EmitBatchNotification, colors: Option<&Colors>) {
+    for (id, mut spans) in group_by_trace(batch) {
+        write_trace(out, id, &mut spans, colors);
+    }
+}
+
+/// Groups the pairs of the spans of `batch` and their service names by trace,
+/// in the order of the start times of the traces.
+pub(crate) fn group_by_trace(
+    batch: &EmitBatchNotification,
+) -> Vec<(TraceId, Vec<(&Span, &str)>)> {
+    let service = &batch.batch.process.service_name[..];
+    let mut traces: Vec<(TraceId, Vec<(&Span, &str)>)> = Vec::new();
+    for span in &batch.batch.spans {
+        match traces.iter().position(|t| t.0 == span.trace_id) {
+            Some(i) => traces[i].1.push((span, service)),
+            None => traces.push((span.trace_id, vec![(span, service)])),
+        }
+    }
+    traces.sort_by_key(|t| t.1.iter().map(|s| s.0.start_time).min());
+    traces
+}
+
+/// The parent-child relations of the spans of a trace.
+pub(crate) struct Relations {
+    /// The IDs of the spans.
+    pub received: HashSet<SpanId>,
+
+    /// The indices of the children of each span.
+    pub children: HashMap<SpanId, Vec<usize>>,
+
+    /// The indices of the spans whose parents have not been received.
+    pub roots: Vec<usize>,
+}
+impl Relations {
+    pub fn new(spans: &[(&Span, &str)]) -> Self {
+        let received = spans.iter().map(|s| s.0.span_id).collect::<HashSet<_>>();
+        let mut children: HashMap<SpanId, Vec<usize>> = HashMap::new();
+        let mut roots = Vec::new();
+        for (i, &(span, _)) in spans.iter().enumerate() {
+            match parent_of(span) {
+                Some(parent) if received.contains(&parent) => {
+                    children.entry(parent).or_default().push(i);
+                }
+                _ => roots.push(i),
+            }
+        }
+        Relations {
+            received,
+            children,
+            roots,
+        }
+    }
+}
+
+/// Appends a trace (the pairs of its spans and their service names) to `out`.
+fn write_trace(
+    out: &mut Vec<u8>,
+    id: TraceId,
+    spans: &mut [(&Span, &str)],
+    colors: Option<&Colors>,
+) {
+    spans.sort_by_key(|s| s.0.start_time);
+    let start = spans.iter().map(|s| s.0.start_time).min().unwrap_or(0);
+    let end = spans
+        .iter()
+        .map(|s| s.0.start_time.saturating_add(s.0.duration))
+        .max()
+        .unwrap_or(0);
+    let plural = if spans.len() == 1 { "" } else { "s" };
+    // Writing to a `Vec` never fails
+    let _ = write!(out, "{} ({} span{}, ", id, spans.len(), plural);
+    // The times may be arbitrary values sent by broken clients
+    let _ = write_milliseconds(out, end.saturating_sub(start));
+    out.extend_from_slice(b"ms)\n");
+
+    let relations = Relations::new(spans);
+    let mut tree = Tree {
+        spans,
+        relations: &relations,
+        colors,
+        visited: vec![false; spans.len()],
+        prefix: String::new(),
+    };
+    for (n, &i) in relations.roots.iter().enumerate() {
+        tree.write(out, i, n + 1 == relations.roots.len());
+    }
+    // The spans in reference cycles (which are never reached from the roots)
+    for i in 0..spans.len() {
+        if !tree.visited[i] {
+            tree.write(out, i, true);
+        }
+    }
+    out.push(b'\n');
+}
+
+struct Tree<'a> {
+    spans: &'a [(&'a Span, &'a str)],
+    relations: &'a Relations,
+    colors: Option<&'a Colors>,
+    visited: Vec<bool>,
+    prefix: String,
+}
+impl<'a> Tree<'a> {
+    fn write(&mut self, out: &mut Vec<u8>, i: usize, last: bool) {
+        self.visited[i] = true;
+        let (span, service) = self.spans[i];
+        let error = is_error(span);
+        let branch = if last { "└── " } else { "├── " };
+        let _ = write!(out, "{}{}", self.prefix, branch);
+        match self.colors {
+            Some(colors) if error => colors.error(out, span.operation_name.as_bytes()),
+            _ => out.extend_from_slice(span.operation_name.as_bytes()),
+        }
+        out.extend_from_slice(b" (");
+        match self.colors {
+            Some(colors) => colors.service(out, service),
+            None => out.extend_from_slice(service.as_bytes()),
+        }
+        out.extend_from_slice(b") ");
+        let mut duration = Vec::new();
+        let _ = write_milliseconds(&mut duration, span.duration);
+        duration.extend_from_slice(b"ms");
+        match self.colors {
+            Some(colors) => colors.duration(out, span.duration, &duration),
+            None => out.extend_from_slice(&duration),
+        }
+        match self.colors {
+            Some(colors) if error => colors.error(out, b" [error]"),
+            _ if error => out.extend_from_slice(b" [error]"),
+            _ => {}
+        }
+        match parent_of(span) {
+            Some(parent) if !self.relations.received.contains(&parent) => {
+                let _ = write!(out, " [parent {} not received]", parent);
+            }
+            _ => {}
+        }
+        out.push(b'\n');
+
+        let children = self.relations
+            .children
+            .get(&span.span_id)
+            .map(|c| c.iter().filter(|&&c| !self.visited[c]).cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let prefix_len = self.prefix.len();
+        self.prefix.push_str(if last { "    " } else { "│   " });
+        for (n, &c) in children.iter().enumerate() {
+            if !self.visited[c] {
+                self.write(out, c, n + 1 == children.len());
+            }
+        }
+        self.prefix.truncate(prefix_len);
+    }
+}
+
+/// Returns the ID of the parent of `span` (the parent span ID or the first reference).
+pub(crate) fn parent_of(span: &Span) -> Option<SpanId> {
+    match span.parent_span_id {
+        Some(id) if id.0 != 0 => Some(id),
+        _ => span.references.first().map(|r| r.span_id),
+    }
+}
+
+fn lock(traces: &Mutex<Traces>) -> ::std::sync::MutexGuard<'_, Traces> {
+    // A panic while holding the lock leaves the traces consistent enough to be written
+    traces.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write_expired_traces(traces: &Weak<Mutex<Traces>>) {
+    loop {
+        let wait = match traces.upgrade() {
+            None => return,
+            Some(traces) => {
+                let mut traces = lock(&traces);
+                if traces.error.is_none() {
+                    let wait = traces.wait;
+                    if let Err(e) = track!(traces.write(Some(wait))) {
+                        traces.error = Some(e);
+                    }
+                }
+                traces.wait
+            }
+        };
+        thread::sleep(wait.min(Duration::from_millis(100)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::*;
+    use model::{Batch, Process, SpanRef, SpanRefType, Tag, UnknownFields};
+
+    fn span(trace_id: i64, span_id: i64, parent_span_id: i64, start_time: i64) -> Span {
+        Span {
+            trace_id: TraceId {
+                high: 0,
+                low: trace_id,
+            },
+            span_id: SpanId(span_id),
+            parent_span_id: Some(SpanId(parent_span_id)),
+            operation_name: format!("op{}", span_id),
+            references: Vec::new(),
+            flags: 1,
+            start_time,
+            duration: 1000,
+            tags: Vec::new(),
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    fn batch(service: &str, spans: Vec<Span>) -> EmitBatchNotification {
+        EmitBatchNotification {
+            batch: Batch {
+                process: Process {
+                    service_name: service.to_owned(),
+                    tags: Vec::new(),
+                    unknown_fields: UnknownFields::new(),
+                },
+                spans,
+                seq_no: None,
+                stats: None,
+                unknown_fields: UnknownFields::new(),
+            },
+        }
+    }
+
+    fn trees(spans: Vec<Span>) -> String {
+        let mut out = Vec::new();
+        write_trees(&mut out, &batch("foo", spans), None);
+        String::from_utf8(out).unwrap()
+    }
+
+    /// A writer shared with the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn children_are_written_in_start_order() {
+        let mut error = span(1, 2, 1, 30);
+        error.tags.push(Tag::new("error", true));
+        let mut follower = span(1, 5, 0, 40);
+        follower.references.push(SpanRef {
+            ref_type: SpanRefType::FollowsFrom,
+            trace_id: follower.trace_id,
+            span_id: SpanId(1),
+            unknown_fields: UnknownFields::new(),
+        });
+        let spans = vec![error, span(1, 4, 3, 20), span(1, 1, 0, 0), follower, span(1, 3, 1, 10)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (5 spans, 1.040ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             \x20   ├── op3 (foo) 1.000ms\n\
+             \x20   │   └── op4 (foo) 1.000ms\n\
+             \x20   ├── op2 (foo) 1.000ms [error]\n\
+             \x20   └── op5 (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn orphans_are_written_as_roots() {
+        let spans = vec![span(1, 2, 9, 10), span(1, 3, 2, 20), span(1, 1, 0, 0)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (3 spans, 1.020ms)\n\
+             ├── op1 (foo) 1.000ms\n\
+             └── op2 (foo) 1.000ms [parent 0x9 not received]\n\
+             \x20   └── op3 (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn spans_in_cycles_are_written_once() {
+        // A span of its own parent
+        assert_eq!(
+            trees(vec![span(1, 1, 1, 0)]),
+            "0x1 (1 span, 1.000ms)\n└── op1 (foo) 1.000ms\n\n"
+        );
+
+        // Spans of each other's parents, one of which has a child
+        let spans = vec![span(1, 2, 1, 10), span(1, 1, 2, 0), span(1, 3, 2, 20)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (3 spans, 1.020ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             \x20   └── op2 (foo) 1.000ms\n\
+             \x20       └── op3 (foo) 1.000ms\n\
+             \n"
+        );
+
+        // A cycle beside a root, which is written after the tree of the root
+        let spans = vec![span(1, 1, 0, 0), span(1, 2, 3, 10), span(1, 3, 2, 20)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (3 spans, 1.020ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             └── op2 (foo) 1.000ms\n\
+             \x20   └── op3 (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn spans_of_duplicate_ids_are_written() {
+        let mut duplicate = span(1, 2, 1, 20);
+        duplicate.operation_name = "retry".to_owned();
+        let spans = vec![span(1, 1, 0, 0), span(1, 2, 1, 10), duplicate, span(1, 3, 2, 30)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (4 spans, 1.030ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             \x20   ├── op2 (foo) 1.000ms\n\
+             \x20   │   └── op3 (foo) 1.000ms\n\
+             \x20   └── retry (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn traces_are_written_in_start_order() {
+        let spans = vec![span(2, 3, 0, 10), span(1, 2, 1, 20), span(1, 1, 0, 0)];
+        assert_eq!(
+            trees(spans),
+            "0x1 (2 spans, 1.020ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             \x20   └── op2 (foo) 1.000ms\n\
+             \n\
+             0x2 (1 span, 1.000ms)\n\
+             └── op3 (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn traces_split_across_batches_are_joined() {
+        let output = Output::default();
+        {
+            let mut handler = TreeHandler::new(output.clone());
+            handler.wait(Duration::from_secs(3600));
+            handler.push(&batch("bar", vec![span(1, 2, 1, 10)])).unwrap();
+            handler.push(&batch("foo", vec![span(2, 3, 0, 0), span(1, 1, 0, 0)])).unwrap();
+            handler.push(&batch("baz", vec![span(1, 3, 2, 20)])).unwrap();
+            assert!(output.0.lock().unwrap().is_empty());
+        }
+        // The traces are written when the handler is dropped
+        assert_eq!(
+            String::from_utf8(output.0.lock().unwrap().clone()).unwrap(),
+            "0x1 (3 spans, 1.020ms)\n\
+             └── op1 (foo) 1.000ms\n\
+             \x20   └── op2 (bar) 1.000ms\n\
+             \x20       └── op3 (baz) 1.000ms\n\
+             \n\
+             0x2 (1 span, 1.000ms)\n\
+             └── op3 (foo) 1.000ms\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn expired_traces_are_written() {
+        let output = Output::default();
+        let mut handler = TreeHandler::new(output.clone());
+        handler.wait(Duration::from_millis(0));
+        handler.push(&batch("foo", vec![span(1, 1, 0, 0)])).unwrap();
+        let start = Instant::now();
+        while output.0.lock().unwrap().is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            String::from_utf8(output.0.lock().unwrap().clone()).unwrap(),
+            "0x1 (1 span, 1.000ms)\n└── op1 (foo) 1.000ms\n\n"
+        );
+    }
+}
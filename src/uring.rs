@@ -0,0 +1,409 @@
+//! UDP receiver backed by Linux io_uring, used by `Server` when `ServerBuilder::io_uring_depth`
+//! is set.
+//!
+//! The system calls are invoked directly, since `libc` does not define io_uring yet.
+use std::io;
+use std::mem;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use libc;
+use trackable::error::{Failed, Failure};
+
+use Result;
+use pool::PooledBuffer;
+
+const SYS_IO_URING_SETUP: libc::c_long = 425;
+const SYS_IO_URING_ENTER: libc::c_long = 426;
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_RECVMSG: u8 = 10;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// A submission queue entry.
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    msg_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+/// A completion queue entry.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A memory mapped region of the ring.
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(track!(Failure::from_error(io::Error::last_os_error())));
+        }
+        Ok(Mmap {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize) as *mut T
+    }
+}
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// An io_uring instance.
+struct Ring {
+    fd: RawFd,
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+    sq_entries: u32,
+    sq_mask: u32,
+    cq_mask: u32,
+    params: Params,
+    unsubmitted: u32,
+}
+impl Ring {
+    fn new(entries: u32) -> Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(track!(Failure::from_error(io::Error::last_os_error())));
+        }
+        let fd = fd as RawFd;
+        let mmaps = (|| {
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len =
+                params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>();
+            let sqes_len = params.sq_entries as usize * mem::size_of::<Sqe>();
+            let sq = track!(Mmap::new(fd, sq_len, IORING_OFF_SQ_RING))?;
+            let cq = track!(Mmap::new(fd, cq_len, IORING_OFF_CQ_RING))?;
+            let sqes = track!(Mmap::new(fd, sqes_len, IORING_OFF_SQES))?;
+            Ok((sq, cq, sqes))
+        })();
+        let (sq, cq, sqes) = match mmaps {
+            Ok(mmaps) => mmaps,
+            Err(e) => {
+                unsafe {
+                    libc::close(fd);
+                }
+                return Err(e);
+            }
+        };
+        let (sq_mask, cq_mask) = unsafe {
+            (
+                *sq.at::<u32>(params.sq_off.ring_mask),
+                *cq.at::<u32>(params.cq_off.ring_mask),
+            )
+        };
+        Ok(Ring {
+            fd,
+            sq,
+            cq,
+            sqes,
+            sq_entries: params.sq_entries,
+            sq_mask,
+            cq_mask,
+            params,
+            unsubmitted: 0,
+        })
+    }
+
+    fn atomic(&self, mmap: &Mmap, offset: u32) -> &AtomicU32 {
+        unsafe { &*mmap.at::<AtomicU32>(offset) }
+    }
+
+    /// Queues a `recvmsg(2)` of `msg` on `fd`.
+    ///
+    /// `msg` and the memory it points to must be valid until the operation completes.
+    fn push_recvmsg(&mut self, fd: RawFd, msg: *mut libc::msghdr, user_data: u64) -> Result<()> {
+        let head = self.atomic(&self.sq, self.params.sq_off.head).load(Ordering::Acquire);
+        let tail = self.atomic(&self.sq, self.params.sq_off.tail).load(Ordering::Relaxed);
+        track_assert!(
+            tail.wrapping_sub(head) < self.sq_entries,
+            Failed,
+            "io_uring submission queue is full"
+        );
+        let index = tail & self.sq_mask;
+        unsafe {
+            let sqe = self.sqes.at::<Sqe>(index * mem::size_of::<Sqe>() as u32);
+            ptr::write(
+                sqe,
+                Sqe {
+                    opcode: IORING_OP_RECVMSG,
+                    flags: 0,
+                    ioprio: 0,
+                    fd,
+                    off: 0,
+                    addr: msg as u64,
+                    len: 1,
+                    msg_flags: 0,
+                    user_data,
+                    pad: [0; 3],
+                },
+            );
+            *self.sq.at::<u32>(self.params.sq_off.array).add(index as usize) = index;
+        }
+        self.atomic(&self.sq, self.params.sq_off.tail)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        self.unsubmitted += 1;
+        Ok(())
+    }
+
+    /// Submits the queued operations and waits until at least one of them completes.
+    fn submit_and_wait(&mut self) -> Result<()> {
+        loop {
+            let result = unsafe {
+                libc::syscall(
+                    SYS_IO_URING_ENTER,
+                    self.fd,
+                    self.unsubmitted,
+                    1u32,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if result >= 0 {
+                self.unsubmitted -= result as u32;
+                return Ok(());
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(track!(Failure::from_error(e)));
+            }
+        }
+    }
+
+    /// Takes a completed operation, if any.
+    fn pop_completion(&mut self) -> Option<(u64, i32)> {
+        let head = self.atomic(&self.cq, self.params.cq_off.head).load(Ordering::Relaxed);
+        let tail = self.atomic(&self.cq, self.params.cq_off.tail).load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let completion = unsafe {
+            let cqes = self.cq.at::<Cqe>(self.params.cq_off.cqes);
+            let cqe = &*cqes.add((head & self.cq_mask) as usize);
+            (cqe.user_data, cqe.res)
+        };
+        self.atomic(&self.cq, self.params.cq_off.head)
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some(completion)
+    }
+}
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// A receive operation, whose header must stay at the same address while it is in flight.
+struct Slot {
+    buf: Option<PooledBuffer>,
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+    msg: libc::msghdr,
+}
+
+/// A receiver which keeps a number of receive operations in flight on a socket,
+/// and reaps their completions in batches.
+pub struct Receiver {
+    ring: Ring,
+    slots: Box<[Slot]>,
+    free_slots: Vec<usize>,
+    fd: RawFd,
+}
+impl Receiver {
+    /// Makes a new `Receiver` instance which keeps up to `depth` receives in flight on `socket`.
+    ///
+    /// `socket` must outlive the receiver.
+    pub fn new(socket: &UdpSocket, depth: usize) -> Result<Self> {
+        track_assert_ne!(depth, 0, Failed);
+        let ring = track!(Ring::new(depth as u32))?;
+        let slots = (0..depth)
+            .map(|_| Slot {
+                buf: None,
+                iov: libc::iovec {
+                    iov_base: ptr::null_mut(),
+                    iov_len: 0,
+                },
+                addr: unsafe { mem::zeroed() },
+                msg: unsafe { mem::zeroed() },
+            })
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+        Ok(Receiver {
+            ring,
+            slots,
+            free_slots: (0..depth).rev().collect(),
+            fd: socket.as_raw_fd(),
+        })
+    }
+
+    /// Returns `true` if more receives can be posted.
+    pub fn has_free_slot(&self) -> bool {
+        !self.free_slots.is_empty()
+    }
+
+    /// Posts a receive into `buf`.
+    ///
+    /// The receive is submitted by the next call of `wait`.
+    pub fn post(&mut self, mut buf: PooledBuffer) -> Result<()> {
+        let index = track_assert_some!(self.free_slots.pop(), Failed, "No free slots");
+        let slot = &mut self.slots[index];
+        {
+            let data = buf.as_mut_buf();
+            slot.iov.iov_base = data.as_mut_ptr() as *mut libc::c_void;
+            slot.iov.iov_len = data.len();
+        }
+        slot.msg = unsafe { mem::zeroed() };
+        slot.msg.msg_name = &mut slot.addr as *mut _ as *mut libc::c_void;
+        slot.msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        slot.msg.msg_iov = &mut slot.iov;
+        slot.msg.msg_iovlen = 1;
+        slot.buf = Some(buf);
+        let msg: *mut libc::msghdr = &mut slot.msg;
+        if let Err(e) = self.ring.push_recvmsg(self.fd, msg, index as u64) {
+            slot.buf = None;
+            self.free_slots.push(index);
+            return Err(track!(e));
+        }
+        Ok(())
+    }
+
+    /// Submits the posted receives, waits until at least one receive completes,
+    /// and appends the completed ones to `completed`.
+    ///
+    /// Each completion consists of the buffer and either the received size and the sender,
+    /// or the error of the receive.
+    pub fn wait(&mut self, completed: &mut Vec<Completion>) -> Result<()> {
+        track!(self.ring.submit_and_wait())?;
+        while let Some((user_data, res)) = self.ring.pop_completion() {
+            let index = user_data as usize;
+            let slot = &mut self.slots[index];
+            let buf = track_assert_some!(slot.buf.take(), Failed, "Unknown completion");
+            self.free_slots.push(index);
+            let result = if res < 0 {
+                Err(io::Error::from_raw_os_error(-res))
+            } else {
+                match to_socket_addr(&slot.addr) {
+                    Some(peer) => Ok((res as usize, peer)),
+                    None => Err(io::Error::other("Unknown address family")),
+                }
+            };
+            completed.push((buf, result));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        // The kernel may still write into the slots of the receives in flight
+        // after the ring is closed, so they are leaked
+        if self.free_slots.len() != self.slots.len() {
+            mem::forget(mem::replace(&mut self.slots, Box::new([])));
+        }
+    }
+}
+
+// The raw pointers in the slots point into the slots themselves and the owned buffers
+unsafe impl Send for Receiver {}
+
+/// A completed receive (see `Receiver::wait`).
+pub type Completion = (PooledBuffer, io::Result<(usize, SocketAddr)>);
+
+fn to_socket_addr(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match i32::from(addr.ss_family) {
+        libc::AF_INET => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            Some(SocketAddrV4::new(ip, u16::from_be(addr.sin_port)).into())
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            let port = u16::from_be(addr.sin6_port);
+            Some(SocketAddrV6::new(ip, port, addr.sin6_flowinfo, addr.sin6_scope_id).into())
+        }
+        _ => None,
+    }
+}
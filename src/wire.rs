@@ -0,0 +1,826 @@
+//! Low-level reader and writer of the Thrift compact and binary protocols.
+use std::str;
+#[cfg(not(feature = "std"))]
+use std::prelude::*;
+
+use error::{DecodeError, DecodeResult as Result};
+use thrift::{DecodeLimits, Protocol};
+
+const COMPACT_PROTOCOL_ID: u8 = 0x82;
+const COMPACT_PROTOCOL_VERSION: u8 = 1;
+const BINARY_PROTOCOL_VERSION: u16 = 1;
+
+pub const MESSAGE_KIND_ONEWAY: u8 = 4;
+
+/// The type of a Thrift value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    Double,
+    Binary,
+    Struct,
+    Map,
+    Set,
+    List,
+}
+impl Type {
+    fn from_compact(code: u8) -> Option<Self> {
+        Some(match code {
+            1 | 2 => Type::Bool,
+            3 => Type::I8,
+            4 => Type::I16,
+            5 => Type::I32,
+            6 => Type::I64,
+            7 => Type::Double,
+            8 => Type::Binary,
+            9 => Type::List,
+            10 => Type::Set,
+            11 => Type::Map,
+            12 => Type::Struct,
+            _ => return None,
+        })
+    }
+
+    fn from_binary(code: u8) -> Option<Self> {
+        Some(match code {
+            2 => Type::Bool,
+            3 => Type::I8,
+            4 => Type::Double,
+            6 => Type::I16,
+            8 => Type::I32,
+            10 => Type::I64,
+            11 => Type::Binary,
+            12 => Type::Struct,
+            13 => Type::Map,
+            14 => Type::Set,
+            15 => Type::List,
+            _ => return None,
+        })
+    }
+
+    #[cfg(feature = "std")]
+    fn compact_code(self) -> u8 {
+        match self {
+            Type::Bool => 1,
+            Type::I8 => 3,
+            Type::I16 => 4,
+            Type::I32 => 5,
+            Type::I64 => 6,
+            Type::Double => 7,
+            Type::Binary => 8,
+            Type::List => 9,
+            Type::Set => 10,
+            Type::Map => 11,
+            Type::Struct => 12,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn binary_code(self) -> u8 {
+        match self {
+            Type::Bool => 2,
+            Type::I8 => 3,
+            Type::Double => 4,
+            Type::I16 => 6,
+            Type::I32 => 8,
+            Type::I64 => 10,
+            Type::Binary => 11,
+            Type::Struct => 12,
+            Type::Map => 13,
+            Type::Set => 14,
+            Type::List => 15,
+        }
+    }
+
+    /// Returns the name of the type.
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Bool => "bool",
+            Type::I8 => "i8",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::Double => "double",
+            Type::Binary => "binary",
+            Type::Struct => "struct",
+            Type::Map => "map",
+            Type::Set => "set",
+            Type::List => "list",
+        }
+    }
+}
+
+/// The header of a struct field.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldHeader {
+    pub id: i16,
+    pub ty: Type,
+}
+
+/// The header of a message.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageHeader<'a> {
+    pub name: &'a str,
+    pub kind: u8,
+}
+
+/// A reader which decodes Thrift values from a byte slice without copying.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    protocol: Protocol,
+    last_field_ids: Vec<i16>,
+    last_field_id: i16,
+    pending_bool: Option<bool>,
+    truncated: bool,
+    strict: bool,
+    limits: DecodeLimits,
+    depth: usize,
+}
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8], protocol: Protocol) -> Self {
+        Reader {
+            buf,
+            pos: 0,
+            protocol,
+            last_field_ids: Vec::new(),
+            last_field_id: 0,
+            pending_bool: None,
+            truncated: false,
+            strict: false,
+            limits: DecodeLimits::default(),
+            depth: 0,
+        }
+    }
+
+    /// Returns the current offset from the beginning of the buffer.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// If `true`, the callers should reject unknown fields.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn set_limits(&mut self, limits: DecodeLimits) {
+        self.limits = limits;
+    }
+
+    /// Returns `true` if the reader has tried to read beyond the end of the buffer.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn read_message_begin(&mut self) -> Result<MessageHeader<'a>> {
+        match self.protocol {
+            Protocol::Compact => {
+                let protocol_id = self.read_u8()?;
+                if protocol_id != COMPACT_PROTOCOL_ID {
+                    return Err(DecodeError::InvalidHeader {
+                        offset: 0,
+                        reason: format!("Unknown protocol ID: {:#x}", protocol_id),
+                    });
+                }
+                let kind_and_version = self.read_u8()?;
+                if kind_and_version & 0b1_1111 != COMPACT_PROTOCOL_VERSION {
+                    return Err(DecodeError::InvalidHeader {
+                        offset: 1,
+                        reason: format!("Unsupported version: {}", kind_and_version & 0b1_1111),
+                    });
+                }
+                let _sequence_id = self.read_varint()?;
+                let name = self.read_str()?;
+                Ok(MessageHeader {
+                    name,
+                    kind: kind_and_version >> 5,
+                })
+            }
+            Protocol::Binary => {
+                let version = self.read_fixed(2)?;
+                let version = u16::from(version[0]) << 8 | u16::from(version[1]);
+                if version >> 15 != 1 {
+                    return Err(DecodeError::InvalidHeader {
+                        offset: 0,
+                        reason: "Old format is unsupported".to_owned(),
+                    });
+                }
+                if version & 0x7FFF != BINARY_PROTOCOL_VERSION {
+                    return Err(DecodeError::InvalidHeader {
+                        offset: 0,
+                        reason: format!("Unsupported version: {}", version & 0x7FFF),
+                    });
+                }
+                let _unused = self.read_u8()?;
+                let kind = self.read_u8()? & 0b111;
+                let name = self.read_str()?;
+                let _sequence_id = self.read_i32()?;
+                Ok(MessageHeader { name, kind })
+            }
+        }
+    }
+
+    pub fn read_struct_begin(&mut self) -> Result<()> {
+        self.enter()?;
+        self.last_field_ids.push(self.last_field_id);
+        self.last_field_id = 0;
+        Ok(())
+    }
+
+    pub fn read_struct_end(&mut self) {
+        self.last_field_id = self.last_field_ids.pop().unwrap_or(0);
+        self.leave();
+    }
+
+    /// Increments the nesting depth before reading the contents of a struct or a collection.
+    pub fn enter(&mut self) -> Result<()> {
+        if self.depth >= self.limits.max_depth {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_depth",
+                offset: self.pos,
+                size: self.depth as u64 + 1,
+                max: self.limits.max_depth,
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Decrements the nesting depth incremented by `enter`.
+    pub fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    /// Reads the header of the next field.
+    ///
+    /// `None` means the end of the current struct.
+    pub fn read_field_begin(&mut self) -> Result<Option<FieldHeader>> {
+        let offset = self.pos;
+        let b = self.read_u8()?;
+        if b == 0 {
+            return Ok(None);
+        }
+        match self.protocol {
+            Protocol::Compact => {
+                let delta = b >> 4;
+                let code = b & 0b1111;
+                let ty = type_from_compact(code, offset)?;
+                let id = if delta != 0 {
+                    self.last_field_id.wrapping_add(i16::from(delta))
+                } else {
+                    self.read_i16()?
+                };
+                self.last_field_id = id;
+                if ty == Type::Bool {
+                    self.pending_bool = Some(code == 1);
+                }
+                Ok(Some(FieldHeader { id, ty }))
+            }
+            Protocol::Binary => {
+                let ty = type_from_binary(b, offset)?;
+                let id = self.read_i16()?;
+                Ok(Some(FieldHeader { id, ty }))
+            }
+        }
+    }
+
+    /// Reads the header of a list (or a set), and returns the element type and the size.
+    pub fn read_list_begin(&mut self) -> Result<(Type, usize)> {
+        let offset = self.pos;
+        match self.protocol {
+            Protocol::Compact => {
+                let b = self.read_u8()?;
+                let code = b & 0b1111;
+                let ty = type_from_compact(code, offset)?;
+                let size = if b >> 4 == 0b1111 {
+                    self.read_varint()?
+                } else {
+                    u64::from(b >> 4)
+                };
+                let size = self.check_collection_size(offset, size)?;
+                Ok((ty, size))
+            }
+            Protocol::Binary => {
+                let code = self.read_u8()?;
+                let ty = type_from_binary(code, offset)?;
+                let size = self.read_i32()?;
+                if size < 0 {
+                    return Err(invalid_size(offset, i64::from(size)));
+                }
+                let size = self.check_collection_size(offset, size as u64)?;
+                Ok((ty, size))
+            }
+        }
+    }
+
+    /// Reads the header of a map, and returns the key type, the value type and the size.
+    pub fn read_map_begin(&mut self) -> Result<(Type, Type, usize)> {
+        let offset = self.pos;
+        match self.protocol {
+            Protocol::Compact => {
+                let size = self.read_varint()?;
+                let size = self.check_collection_size(offset, size)?;
+                if size == 0 {
+                    return Ok((Type::Bool, Type::Bool, 0));
+                }
+                let b = self.read_u8()?;
+                let key = type_from_compact(b >> 4, offset)?;
+                let value = type_from_compact(b & 0b1111, offset)?;
+                Ok((key, value, size))
+            }
+            Protocol::Binary => {
+                let k = self.read_u8()?;
+                let v = self.read_u8()?;
+                let key = type_from_binary(k, offset)?;
+                let value = type_from_binary(v, offset)?;
+                let size = self.read_i32()?;
+                if size < 0 {
+                    return Err(invalid_size(offset, i64::from(size)));
+                }
+                let size = self.check_collection_size(offset, size as u64)?;
+                Ok((key, value, size))
+            }
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        if let Some(b) = self.pending_bool.take() {
+            return Ok(b);
+        }
+        let offset = self.pos;
+        let b = self.read_u8()?;
+        match (self.protocol, b) {
+            (_, 1) => Ok(true),
+            (Protocol::Binary, 0) | (Protocol::Compact, 0) | (Protocol::Compact, 2) => Ok(false),
+            _ => Err(DecodeError::InvalidValue {
+                offset,
+                reason: format!("Invalid bool: {}", b),
+            }),
+        }
+    }
+
+    pub fn read_i8(&mut self) -> Result<i8> {
+        self.read_u8().map(|b| b as i8)
+    }
+
+    pub fn read_i16(&mut self) -> Result<i16> {
+        match self.protocol {
+            Protocol::Compact => {
+                let offset = self.pos;
+                let v = self.read_i32()?;
+                if v != i32::from(v as i16) {
+                    return Err(DecodeError::InvalidValue {
+                        offset,
+                        reason: "i16 overflow".to_owned(),
+                    });
+                }
+                Ok(v as i16)
+            }
+            Protocol::Binary => {
+                let b = self.read_fixed(2)?;
+                Ok((u16::from(b[0]) << 8 | u16::from(b[1])) as i16)
+            }
+        }
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32> {
+        match self.protocol {
+            Protocol::Compact => {
+                let offset = self.pos;
+                let n = self.read_varint()?;
+                if n > 0xFFFF_FFFF {
+                    return Err(DecodeError::InvalidValue {
+                        offset,
+                        reason: "i32 overflow".to_owned(),
+                    });
+                }
+                Ok(zigzag_decode_i32(n as u32))
+            }
+            Protocol::Binary => {
+                let b = self.read_fixed(4)?;
+                let mut n = 0u32;
+                for &x in b {
+                    n = n << 8 | u32::from(x);
+                }
+                Ok(n as i32)
+            }
+        }
+    }
+
+    pub fn read_i64(&mut self) -> Result<i64> {
+        match self.protocol {
+            Protocol::Compact => {
+                let n = self.read_varint()?;
+                Ok(zigzag_decode_i64(n))
+            }
+            Protocol::Binary => {
+                let b = self.read_fixed(8)?;
+                let mut n = 0u64;
+                for &x in b {
+                    n = n << 8 | u64::from(x);
+                }
+                Ok(n as i64)
+            }
+        }
+    }
+
+    pub fn read_double(&mut self) -> Result<f64> {
+        let b = self.read_fixed(8)?;
+        let mut n = 0u64;
+        match self.protocol {
+            // NOTE: Implementations of the compact protocol are using little-endian
+            Protocol::Compact => {
+                for &x in b.iter().rev() {
+                    n = n << 8 | u64::from(x);
+                }
+            }
+            Protocol::Binary => {
+                for &x in b {
+                    n = n << 8 | u64::from(x);
+                }
+            }
+        }
+        Ok(f64::from_bits(n))
+    }
+
+    pub fn read_binary(&mut self) -> Result<&'a [u8]> {
+        let offset = self.pos;
+        let size = match self.protocol {
+            Protocol::Compact => self.read_varint()?,
+            Protocol::Binary => {
+                let size = self.read_i32()?;
+                if size < 0 {
+                    return Err(invalid_size(offset, i64::from(size)));
+                }
+                size as u64
+            }
+        };
+        if size > 0x7FFF_FFFF {
+            return Err(invalid_size(offset, size as i64));
+        }
+        if size > self.limits.max_string_len as u64 {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_string_len",
+                offset,
+                size,
+                max: self.limits.max_string_len,
+            });
+        }
+        self.read_fixed(size as usize)
+    }
+
+    pub fn read_str(&mut self) -> Result<&'a str> {
+        let offset = self.pos;
+        let bytes = self.read_binary()?;
+        str::from_utf8(bytes).map_err(|error| DecodeError::InvalidUtf8 { offset, error })
+    }
+
+    /// Skips a value of the given type.
+    pub fn skip(&mut self, ty: Type) -> Result<()> {
+        match ty {
+            Type::Bool => {
+                self.read_bool()?;
+            }
+            Type::I8 => {
+                self.read_i8()?;
+            }
+            Type::I16 => {
+                self.read_i16()?;
+            }
+            Type::I32 => {
+                self.read_i32()?;
+            }
+            Type::I64 => {
+                self.read_i64()?;
+            }
+            Type::Double => {
+                self.read_double()?;
+            }
+            Type::Binary => {
+                self.read_binary()?;
+            }
+            Type::Struct => {
+                self.read_struct_begin()?;
+                while let Some(field) = self.read_field_begin()? {
+                    self.skip(field.ty)?;
+                }
+                self.read_struct_end();
+            }
+            Type::Map => {
+                let (key, value, size) = self.read_map_begin()?;
+                self.enter()?;
+                for _ in 0..size {
+                    self.skip(key)?;
+                    self.skip(value)?;
+                }
+                self.leave();
+            }
+            Type::Set | Type::List => {
+                let (elem, size) = self.read_list_begin()?;
+                self.enter()?;
+                for _ in 0..size {
+                    self.skip(elem)?;
+                }
+                self.leave();
+            }
+        }
+        Ok(())
+    }
+
+    fn check_collection_size(&self, offset: usize, size: u64) -> Result<usize> {
+        if size > self.limits.max_collection_size as u64 {
+            return Err(DecodeError::LimitExceeded {
+                limit: "max_collection_size",
+                offset,
+                size,
+                max: self.limits.max_collection_size,
+            });
+        }
+        Ok(size as usize)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        let b = self.read_fixed(1)?;
+        Ok(b[0])
+    }
+
+    /// Reads an unsigned LEB128 varint directly from the remaining bytes.
+    ///
+    /// If at least ten bytes (the maximum length of a varint) remain, the bytes are read without
+    /// bounds checks; otherwise, the slow path detects truncated varints.
+    fn read_varint(&mut self) -> Result<u64> {
+        let rest = &self.buf[self.pos..];
+        if rest.len() >= 10 {
+            let mut n = 0;
+            for (i, &b) in rest[..10].iter().enumerate() {
+                n |= u64::from(b & 0b0111_1111) << (i * 7);
+                if b < 0b1000_0000 {
+                    self.pos += i + 1;
+                    return Ok(n);
+                }
+            }
+            let offset = self.pos;
+            self.pos += 10;
+            return Err(DecodeError::InvalidValue {
+                offset,
+                reason: "Too long varint".to_owned(),
+            });
+        }
+        self.read_varint_slow()
+    }
+
+    #[cold]
+    fn read_varint_slow(&mut self) -> Result<u64> {
+        let offset = self.pos;
+        let mut n = 0;
+        for i in 0..10 {
+            let b = self.read_u8()?;
+            n |= u64::from(b & 0b0111_1111) << (i * 7);
+            if (b & 0b1000_0000) == 0 {
+                return Ok(n);
+            }
+        }
+        Err(DecodeError::InvalidValue {
+            offset,
+            reason: "Too long varint".to_owned(),
+        })
+    }
+
+    fn read_fixed(&mut self, size: usize) -> Result<&'a [u8]> {
+        if size > self.buf.len() - self.pos {
+            self.truncated = true;
+            return Err(DecodeError::Truncated { offset: self.pos });
+        }
+        let bytes = &self.buf[self.pos..self.pos + size];
+        self.pos += size;
+        Ok(bytes)
+    }
+}
+
+/// A writer which encodes Thrift values into a byte vector.
+///
+/// As in `Reader`, the contents of a struct are written between `write_struct_begin` and
+/// `write_struct_end`, and each field is its header followed by its value.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Writer {
+    buf: Vec<u8>,
+    protocol: Protocol,
+    last_field_ids: Vec<i16>,
+    last_field_id: i16,
+    pending_bool_field: Option<i16>,
+}
+#[cfg(feature = "std")]
+impl Writer {
+    pub fn new(protocol: Protocol) -> Self {
+        Writer {
+            buf: Vec::new(),
+            protocol,
+            last_field_ids: Vec::new(),
+            last_field_id: 0,
+            pending_bool_field: None,
+        }
+    }
+
+    /// Returns the written bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Writes the header of a message whose sequence ID is zero.
+    pub fn write_message_begin(&mut self, name: &str, kind: u8) {
+        match self.protocol {
+            Protocol::Compact => {
+                self.buf.push(COMPACT_PROTOCOL_ID);
+                self.buf.push(kind << 5 | COMPACT_PROTOCOL_VERSION);
+                self.write_varint(0);
+                self.write_binary(name.as_bytes());
+            }
+            Protocol::Binary => {
+                let version = 0x8000 | BINARY_PROTOCOL_VERSION;
+                self.buf.extend_from_slice(&version.to_be_bytes());
+                self.buf.extend_from_slice(&[0, kind]);
+                self.write_binary(name.as_bytes());
+                self.write_i32(0);
+            }
+        }
+    }
+
+    pub fn write_struct_begin(&mut self) {
+        self.last_field_ids.push(self.last_field_id);
+        self.last_field_id = 0;
+    }
+
+    /// Writes the stop field which ends the current struct.
+    pub fn write_struct_end(&mut self) {
+        self.buf.push(0);
+        self.last_field_id = self.last_field_ids.pop().unwrap_or(0);
+    }
+
+    /// Writes the header of a field.
+    ///
+    /// In the compact protocol, the header of a `bool` field is written by the following
+    /// `write_bool`, since it contains the value.
+    pub fn write_field_begin(&mut self, id: i16, ty: Type) {
+        match self.protocol {
+            Protocol::Compact if ty == Type::Bool => self.pending_bool_field = Some(id),
+            Protocol::Compact => self.write_compact_field_header(id, ty.compact_code()),
+            Protocol::Binary => {
+                self.buf.push(ty.binary_code());
+                self.buf.extend_from_slice(&id.to_be_bytes());
+            }
+        }
+    }
+
+    /// Writes the header of a list (or a set).
+    pub fn write_list_begin(&mut self, elem: Type, size: usize) {
+        match self.protocol {
+            Protocol::Compact => {
+                if size < 15 {
+                    self.buf.push((size as u8) << 4 | elem.compact_code());
+                } else {
+                    self.buf.push(0b1111_0000 | elem.compact_code());
+                    self.write_varint(size as u64);
+                }
+            }
+            Protocol::Binary => {
+                self.buf.push(elem.binary_code());
+                self.write_i32(size as i32);
+            }
+        }
+    }
+
+    /// Writes the header of a map.
+    pub fn write_map_begin(&mut self, key: Type, value: Type, size: usize) {
+        match self.protocol {
+            Protocol::Compact => {
+                self.write_varint(size as u64);
+                if size > 0 {
+                    self.buf.push(key.compact_code() << 4 | value.compact_code());
+                }
+            }
+            Protocol::Binary => {
+                self.buf.push(key.binary_code());
+                self.buf.push(value.binary_code());
+                self.write_i32(size as i32);
+            }
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        let compact_code = if value { 1 } else { 2 };
+        if let Some(id) = self.pending_bool_field.take() {
+            self.write_compact_field_header(id, compact_code);
+            return;
+        }
+        match self.protocol {
+            Protocol::Compact => self.buf.push(compact_code),
+            Protocol::Binary => self.buf.push(value as u8),
+        }
+    }
+
+    pub fn write_i8(&mut self, value: i8) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn write_i16(&mut self, value: i16) {
+        match self.protocol {
+            Protocol::Compact => self.write_i32(i32::from(value)),
+            Protocol::Binary => self.buf.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        match self.protocol {
+            Protocol::Compact => self.write_varint(u64::from(zigzag_encode_i32(value))),
+            Protocol::Binary => self.buf.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_i64(&mut self, value: i64) {
+        match self.protocol {
+            Protocol::Compact => self.write_varint(zigzag_encode_i64(value)),
+            Protocol::Binary => self.buf.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_double(&mut self, value: f64) {
+        match self.protocol {
+            Protocol::Compact => self.buf.extend_from_slice(&value.to_le_bytes()),
+            Protocol::Binary => self.buf.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn write_binary(&mut self, value: &[u8]) {
+        match self.protocol {
+            Protocol::Compact => self.write_varint(value.len() as u64),
+            Protocol::Binary => self.write_i32(value.len() as i32),
+        }
+        self.buf.extend_from_slice(value);
+    }
+
+    fn write_compact_field_header(&mut self, id: i16, code: u8) {
+        let delta = i32::from(id) - i32::from(self.last_field_id);
+        if 0 < delta && delta <= 15 {
+            self.buf.push((delta as u8) << 4 | code);
+        } else {
+            self.buf.push(code);
+            self.write_i16(id);
+        }
+        self.last_field_id = id;
+    }
+
+    fn write_varint(&mut self, mut n: u64) {
+        while n >= 0b1000_0000 {
+            self.buf.push(n as u8 | 0b1000_0000);
+            n >>= 7;
+        }
+        self.buf.push(n as u8);
+    }
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn zigzag_encode_i32(n: i32) -> u32 {
+    ((n << 1) ^ (n >> 31)) as u32
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn zigzag_encode_i64(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+#[inline]
+fn zigzag_decode_i32(n: u32) -> i32 {
+    (n >> 1) as i32 ^ -((n & 1) as i32)
+}
+
+#[inline]
+fn zigzag_decode_i64(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn type_from_compact(code: u8, offset: usize) -> Result<Type> {
+    Type::from_compact(code).ok_or(DecodeError::UnknownTypeCode { offset, code })
+}
+
+fn type_from_binary(code: u8, offset: usize) -> Result<Type> {
+    Type::from_binary(code).ok_or(DecodeError::UnknownTypeCode { offset, code })
+}
+
+fn invalid_size(offset: usize, size: i64) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset,
+        reason: format!("Invalid size: {}", size),
+    }
+}
@@ -0,0 +1,1314 @@
+//! Decoding of Zipkin spans into Jaeger batches, and encoding of Jaeger batches into
+//! the JSON encoding of the Zipkin v2 API.
+//!
+//! `ZipkinV2` decodes the bodies of the requests sent to `POST /api/v2/spans`
+//! (lists of spans in the JSON or the proto3 encoding of the Zipkin v2 API),
+//! and `ZipkinThrift` decodes the `emitZipkinBatch` messages which legacy Zipkin clients send
+//! to the agent (spans of [zipkincore.thrift] in the compact protocol).
+//! The spans are mapped in the same way as the Jaeger collector does:
+//!
+//! - The service name of the local endpoint becomes the service name of the process,
+//!   and its address becomes the `ip` tag of the process.
+//! - The remote endpoint becomes the `peer.service`, `peer.ipv4`, `peer.ipv6` and `peer.port`
+//!   tags, and the kind of the span becomes the `span.kind` tag.
+//! - Annotations become logs with an `event` field holding the annotation value.
+//! - Debug spans have the debug flag (`2`) as well as the sampled flag (`1`).
+//! - In `ZipkinThrift`, the core annotations (e.g., `cs` and `sr`) determine the kind and
+//!   the local endpoint of the span, the address annotations (e.g., `sa`) determine
+//!   the remote endpoint, and the other binary annotations become tags.
+//!
+//! A batch holds the spans of a single process, so if a request contains spans of
+//! multiple services, the service of the first span becomes the process,
+//! and the other spans have a `service.name` tag holding their service names.
+//!
+//! `to_json` does the reverse mapping, and is used by `Format::ZipkinJson`.
+//!
+//! # Examples
+//!
+//! ```
+//! use jaegercat::protocol::WireProtocol;
+//! use jaegercat::thrift::{DecodeLimits, DecodeMode};
+//! use jaegercat::zipkin::ZipkinV2;
+//!
+//! let body = br#"[{
+//!     "traceId": "5b8efff798038103d269b633813fc60c",
+//!     "id": "eee19b7ec3c1b174",
+//!     "kind": "SERVER",
+//!     "name": "get /api",
+//!     "timestamp": 1544712660000000,
+//!     "duration": 1000,
+//!     "localEndpoint": {"serviceName": "foo", "ipv4": "192.168.99.1"},
+//!     "tags": {"http.method": "GET"}
+//! }]"#;
+//! let (message, _) = ZipkinV2
+//!     .decode_batch(body, DecodeMode::Normal, &DecodeLimits::default())
+//!     .unwrap();
+//! assert_eq!(message.batch.process.service_name, "foo");
+//! assert_eq!(message.batch.spans[0].operation_name, "get /api");
+//! assert_eq!(message.batch.spans[0].tags.len(), 2);
+//! ```
+//!
+//! [zipkincore.thrift]: https://github.com/jaegertracing/jaeger-idl/blob/master/thrift/zipkincore.thrift
+use std::net::{Ipv4Addr, Ipv6Addr};
+use serde_json::{self, Map, Value};
+
+use borrowed::PartialDecodeError;
+use error::{DecodeError, DecodeResult};
+use model::{Batch, Log, Process, Span, SpanId, SpanRefType, Tag, TagValue, TraceId,
+            UnknownFields};
+use otlp::encode_base64;
+use proto::{check_collection, check_string, Field, Reader};
+use protocol::WireProtocol;
+use thrift::{DecodeLimits, DecodeMode, EmitBatchNotification, Protocol};
+use wire::{self, Type, MESSAGE_KIND_ONEWAY};
+
+/// The service name of the spans which have no local endpoint.
+pub const NO_SERVICE_NAME: &str = "unknown";
+
+/// The `WireProtocol` of lists of spans in the JSON or the proto3 encoding of
+/// the Zipkin v2 API.
+///
+/// The encoding is detected from the first byte of the message,
+/// since a JSON array starts with `[` (or whitespace),
+/// which cannot start a protobuf `ListOfSpans`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZipkinV2;
+impl WireProtocol for ZipkinV2 {
+    fn name(&self) -> &'static str {
+        "zipkin-v2"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let is_json = buf.iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|&b| b == b'[');
+        let spans = if is_json {
+            decode_json(buf, limits)?
+        } else {
+            read_list_of_spans(&Reader::new("ListOfSpans", mode, limits), buf, 0)?
+        };
+        let batch = to_batch(spans);
+        Ok((EmitBatchNotification { batch }, None))
+    }
+}
+
+/// The `WireProtocol` of the `emitZipkinBatch` messages in the compact protocol,
+/// which the agent accepts on port 5775.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZipkinThrift;
+impl WireProtocol for ZipkinThrift {
+    fn name(&self) -> &'static str {
+        "zipkin-thrift"
+    }
+
+    fn decode_batch(
+        &self,
+        buf: &[u8],
+        mode: DecodeMode,
+        limits: &DecodeLimits,
+    ) -> DecodeResult<(EmitBatchNotification, Option<PartialDecodeError>)> {
+        let mut reader = wire::Reader::new(buf, Protocol::Compact);
+        reader.set_limits(*limits);
+        reader.set_strict(mode == DecodeMode::Strict);
+        let spans = thrift_message(&mut reader)?;
+        if mode == DecodeMode::Strict && reader.position() != buf.len() {
+            return Err(DecodeError::TrailingBytes {
+                offset: reader.position(),
+                size: buf.len() - reader.position(),
+            });
+        }
+        let batch = to_batch(spans);
+        Ok((EmitBatchNotification { batch }, None))
+    }
+}
+
+/// A span in the Zipkin v2 model.
+#[derive(Debug)]
+struct ZipkinSpan {
+    trace_id: TraceId,
+    id: SpanId,
+    parent_id: Option<SpanId>,
+    kind: Option<&'static str>,
+    name: String,
+    timestamp: i64,
+    duration: i64,
+    local_endpoint: Endpoint,
+    remote_endpoint: Option<Endpoint>,
+    annotations: Vec<(i64, String)>,
+    tags: Vec<(String, TagValue)>,
+    debug: bool,
+}
+impl Default for ZipkinSpan {
+    fn default() -> Self {
+        ZipkinSpan {
+            trace_id: TraceId { high: 0, low: 0 },
+            id: SpanId(0),
+            parent_id: None,
+            kind: None,
+            name: String::new(),
+            timestamp: 0,
+            duration: 0,
+            local_endpoint: Endpoint::default(),
+            remote_endpoint: None,
+            annotations: Vec::new(),
+            tags: Vec::new(),
+            debug: false,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Endpoint {
+    service_name: String,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+    port: Option<i64>,
+}
+
+fn to_batch(spans: Vec<ZipkinSpan>) -> Batch {
+    let local_endpoint = spans
+        .first()
+        .map(|s| s.local_endpoint.clone())
+        .unwrap_or_default();
+    let service_name = endpoint_service_name(&local_endpoint);
+    let mut process_tags = Vec::new();
+    if let Some(ip) = local_endpoint.ipv4 {
+        process_tags.push(Tag::new("ip", ip.to_string()));
+    } else if let Some(ip) = local_endpoint.ipv6 {
+        process_tags.push(Tag::new("ip", ip.to_string()));
+    }
+    let spans = spans
+        .into_iter()
+        .map(|span| {
+            let other_service = endpoint_service_name(&span.local_endpoint);
+            let mut span = to_span(span);
+            if other_service != service_name {
+                span.tags.push(Tag::new("service.name", other_service));
+            }
+            span
+        })
+        .collect();
+    Batch {
+        process: Process {
+            service_name,
+            tags: process_tags,
+            unknown_fields: UnknownFields::new(),
+        },
+        spans,
+        seq_no: None,
+        stats: None,
+        unknown_fields: UnknownFields::new(),
+    }
+}
+
+fn endpoint_service_name(endpoint: &Endpoint) -> String {
+    if endpoint.service_name.is_empty() {
+        NO_SERVICE_NAME.to_owned()
+    } else {
+        endpoint.service_name.clone()
+    }
+}
+
+fn to_span(span: ZipkinSpan) -> Span {
+    let mut tags = span.tags
+        .into_iter()
+        .map(|(k, v)| Tag::new(k, v))
+        .collect::<Vec<_>>();
+    if let Some(kind) = span.kind {
+        tags.push(Tag::new("span.kind", kind));
+    }
+    if let Some(remote) = span.remote_endpoint {
+        if !remote.service_name.is_empty() {
+            tags.push(Tag::new("peer.service", remote.service_name));
+        }
+        if let Some(ip) = remote.ipv4 {
+            tags.push(Tag::new("peer.ipv4", ip.to_string()));
+        }
+        if let Some(ip) = remote.ipv6 {
+            tags.push(Tag::new("peer.ipv6", ip.to_string()));
+        }
+        if let Some(port) = remote.port {
+            tags.push(Tag::new("peer.port", port));
+        }
+    }
+    let logs = span.annotations
+        .into_iter()
+        .map(|(timestamp, value)| Log {
+            timestamp,
+            fields: vec![Tag::new("event", value)],
+            unknown_fields: UnknownFields::new(),
+        })
+        .collect();
+    Span {
+        trace_id: span.trace_id,
+        span_id: span.id,
+        parent_span_id: span.parent_id,
+        operation_name: span.name,
+        references: Vec::new(),
+        flags: if span.debug { 3 } else { 1 },
+        start_time: span.timestamp,
+        duration: span.duration,
+        tags,
+        logs,
+        unknown_fields: UnknownFields::new(),
+    }
+}
+
+fn kind_name(kind: &str) -> Option<&'static str> {
+    match kind {
+        "CLIENT" => Some("client"),
+        "SERVER" => Some("server"),
+        "PRODUCER" => Some("producer"),
+        "CONSUMER" => Some("consumer"),
+        _ => None,
+    }
+}
+
+/// Converts a batch into a list of spans in the JSON encoding of the Zipkin v2 API,
+/// as accepted by `POST /api/v2/spans`.
+///
+/// This is the reverse of the mapping done by `ZipkinV2`:
+///
+/// - The process becomes the local endpoint of each span (its address from the `ip` tag),
+///   and the other process tags are added to the tags of each span.
+///   The `service.name` tag of a span overrides the service name of the process.
+/// - The `span.kind` tag becomes the kind, and the `peer.service`, `peer.ipv4`, `peer.ipv6`
+///   and `peer.port` tags become the remote endpoint.
+/// - The parent span ID (or the first `CHILD_OF` reference in the same trace) becomes
+///   the parent ID. Zipkin has no other references, so they are dropped.
+/// - Logs become annotations. The value of a log with only an `event` field is the event,
+///   and that of the others is a JSON object of all the fields.
+/// - Tag values become strings (binary ones are base64 encoded).
+///
+/// # Examples
+///
+/// ```
+/// use jaegercat::model::{Batch, Process, Span, SpanId, Tag, TraceId, UnknownFields};
+/// use jaegercat::zipkin;
+///
+/// let batch = Batch {
+///     process: Process {
+///         service_name: "foo".to_owned(),
+///         tags: vec![Tag::new("ip", "192.168.99.1")],
+///         unknown_fields: UnknownFields::new(),
+///     },
+///     spans: vec![Span {
+///         trace_id: TraceId { high: 0, low: 1 },
+///         span_id: SpanId(2),
+///         parent_span_id: None,
+///         operation_name: "get /api".to_owned(),
+///         references: Vec::new(),
+///         flags: 1,
+///         start_time: 1_544_712_660_000_000,
+///         duration: 1000,
+///         tags: vec![Tag::new("span.kind", "server"), Tag::new("http.status_code", 200)],
+///         logs: Vec::new(),
+///         unknown_fields: UnknownFields::new(),
+///     }],
+///     seq_no: None,
+///     stats: None,
+///     unknown_fields: UnknownFields::new(),
+/// };
+/// let spans = zipkin::to_json(&batch);
+/// assert_eq!(spans[0]["traceId"], "0000000000000001");
+/// assert_eq!(spans[0]["kind"], "SERVER");
+/// assert_eq!(spans[0]["localEndpoint"]["ipv4"], "192.168.99.1");
+/// assert_eq!(spans[0]["tags"]["http.status_code"], "200");
+/// ```
+pub fn to_json(batch: &Batch) -> Value {
+    let mut ip = None;
+    let mut process_tags = Map::new();
+    for tag in &batch.process.tags {
+        match (tag.key.as_str(), &tag.value) {
+            ("ip", TagValue::Str(v)) if ip.is_none() => ip = Some(v.as_str()),
+            _ => {
+                process_tags.insert(tag.key.clone(), json_tag_value(&tag.value));
+            }
+        }
+    }
+    let spans = batch
+        .spans
+        .iter()
+        .map(|span| span_to_json(span, &batch.process.service_name, ip, &process_tags))
+        .collect();
+    Value::Array(spans)
+}
+
+fn span_to_json(
+    span: &Span,
+    service_name: &str,
+    ip: Option<&str>,
+    process_tags: &Map<String, Value>,
+) -> Value {
+    let mut service_name = service_name;
+    let mut kind = None;
+    let mut remote = Map::new();
+    let mut tags = process_tags.clone();
+    for tag in &span.tags {
+        match (tag.key.as_str(), &tag.value) {
+            ("service.name", TagValue::Str(v)) => service_name = v,
+            ("span.kind", TagValue::Str(v)) if v == "internal" => {}
+            ("span.kind", TagValue::Str(v)) if kind_name(&v.to_uppercase()).is_some() => {
+                kind = Some(v.to_uppercase());
+            }
+            ("peer.service", TagValue::Str(v)) => {
+                remote.insert("serviceName".to_owned(), Value::from(v.as_str()));
+            }
+            ("peer.ipv4", TagValue::Str(v)) if v.parse::<Ipv4Addr>().is_ok() => {
+                remote.insert("ipv4".to_owned(), Value::from(v.as_str()));
+            }
+            // Jaeger clients send IPv4 addresses as integers
+            ("peer.ipv4", &TagValue::Long(v)) if v >= 0 && v <= i64::from(u32::MAX) => {
+                let ip = Ipv4Addr::from(v as u32).to_string();
+                remote.insert("ipv4".to_owned(), Value::from(ip));
+            }
+            ("peer.ipv6", TagValue::Str(v)) if v.parse::<Ipv6Addr>().is_ok() => {
+                remote.insert("ipv6".to_owned(), Value::from(v.as_str()));
+            }
+            ("peer.port", &TagValue::Long(v)) if v > 0 && v <= i64::from(u16::MAX) => {
+                remote.insert("port".to_owned(), Value::from(v));
+            }
+            _ => {
+                tags.insert(tag.key.clone(), json_tag_value(&tag.value));
+            }
+        }
+    }
+
+    let mut local = Map::new();
+    local.insert("serviceName".to_owned(), Value::from(service_name));
+    match ip {
+        Some(ip) if ip.parse::<Ipv4Addr>().is_ok() => {
+            local.insert("ipv4".to_owned(), Value::from(ip));
+        }
+        Some(ip) if ip.parse::<Ipv6Addr>().is_ok() => {
+            local.insert("ipv6".to_owned(), Value::from(ip));
+        }
+        _ => {}
+    }
+
+    let trace_id = if span.trace_id.high == 0 {
+        format!("{:016x}", span.trace_id.low as u64)
+    } else {
+        format!("{:016x}{:016x}", span.trace_id.high as u64, span.trace_id.low as u64)
+    };
+    let parent_id = match span.parent_span_id {
+        Some(id) if id.0 != 0 => Some(id),
+        _ => span.references
+            .iter()
+            .find(|r| r.ref_type == SpanRefType::ChildOf && r.trace_id == span.trace_id)
+            .map(|r| r.span_id),
+    };
+    let annotations = span.logs
+        .iter()
+        .map(|log| {
+            let value = match log.fields.as_slice() {
+                [Tag {
+                    key,
+                    value: TagValue::Str(event),
+                    ..
+                }] if key == "event" => event.clone(),
+                fields => Value::Object(
+                    fields
+                        .iter()
+                        .map(|f| (f.key.clone(), json_tag_value(&f.value)))
+                        .collect(),
+                ).to_string(),
+            };
+            let mut annotation = Map::new();
+            annotation.insert("timestamp".to_owned(), Value::from(log.timestamp));
+            annotation.insert("value".to_owned(), Value::from(value));
+            Value::Object(annotation)
+        })
+        .collect();
+
+    let mut object = Map::new();
+    object.insert("traceId".to_owned(), Value::from(trace_id));
+    object.insert("id".to_owned(), Value::from(format!("{:016x}", span.span_id.0 as u64)));
+    if let Some(parent_id) = parent_id {
+        object.insert("parentId".to_owned(), Value::from(format!("{:016x}", parent_id.0 as u64)));
+    }
+    if let Some(kind) = kind {
+        object.insert("kind".to_owned(), Value::from(kind));
+    }
+    object.insert("name".to_owned(), Value::from(span.operation_name.as_str()));
+    object.insert("timestamp".to_owned(), Value::from(span.start_time));
+    object.insert("duration".to_owned(), Value::from(span.duration));
+    if span.flags & 2 != 0 {
+        object.insert("debug".to_owned(), Value::from(true));
+    }
+    object.insert("localEndpoint".to_owned(), Value::Object(local));
+    if !remote.is_empty() {
+        object.insert("remoteEndpoint".to_owned(), Value::Object(remote));
+    }
+    object.insert("annotations".to_owned(), Value::Array(annotations));
+    object.insert("tags".to_owned(), Value::Object(tags));
+    Value::Object(object)
+}
+
+fn json_tag_value(value: &TagValue) -> Value {
+    let s = match *value {
+        TagValue::Str(ref v) => v.clone(),
+        TagValue::Bool(v) => v.to_string(),
+        TagValue::Long(v) => v.to_string(),
+        TagValue::Double(v) => v.to_string(),
+        TagValue::Binary(ref v) => encode_base64(v),
+    };
+    Value::from(s)
+}
+
+fn invalid_id(field: &'static str, offset: usize) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset,
+        reason: format!("Invalid {}", field),
+    }
+}
+
+fn be_i64(bytes: &[u8]) -> i64 {
+    let mut b = [0; 8];
+    b.copy_from_slice(bytes);
+    i64::from_be_bytes(b)
+}
+
+fn read_list_of_spans(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Vec<ZipkinSpan>> {
+    let mut spans = Vec::new();
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => {
+                check_collection(&spans, r.limits, field_offset)?;
+                spans.push(read_span(r, b, o)?);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(spans)
+}
+
+fn read_span(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<ZipkinSpan> {
+    let mut span = ZipkinSpan::default();
+    let mut trace_id = None;
+    let mut id = None;
+    reader.fields(buf, offset, |r, number, field, field_offset| {
+        match (number, field) {
+            (1, Field::Bytes(b, _)) => {
+                trace_id = Some(match b.len() {
+                    8 => TraceId {
+                        high: 0,
+                        low: be_i64(b),
+                    },
+                    16 => TraceId {
+                        high: be_i64(&b[..8]),
+                        low: be_i64(&b[8..]),
+                    },
+                    _ => return Err(invalid_id("Span.trace_id", field_offset)),
+                });
+            }
+            (2, Field::Bytes(b, _)) if b.len() == 8 => span.parent_id = Some(SpanId(be_i64(b))),
+            (2, Field::Bytes(&[], _)) => {}
+            (3, Field::Bytes(b, _)) if b.len() == 8 => id = Some(SpanId(be_i64(b))),
+            (2, Field::Bytes(..)) => return Err(invalid_id("Span.parent_id", field_offset)),
+            (3, Field::Bytes(..)) => return Err(invalid_id("Span.id", field_offset)),
+            (4, Field::Varint(v)) => {
+                span.kind = match v {
+                    1 => Some("client"),
+                    2 => Some("server"),
+                    3 => Some("producer"),
+                    4 => Some("consumer"),
+                    _ => None,
+                };
+            }
+            (5, Field::Bytes(b, o)) => span.name = r.string(b, o)?,
+            (6, Field::Fixed64(v)) => span.timestamp = v as i64,
+            (7, Field::Varint(v)) => span.duration = v as i64,
+            (8, Field::Bytes(b, o)) => span.local_endpoint = read_endpoint(r, b, o)?,
+            (9, Field::Bytes(b, o)) => span.remote_endpoint = Some(read_endpoint(r, b, o)?),
+            (10, Field::Bytes(b, o)) => {
+                check_collection(&span.annotations, r.limits, field_offset)?;
+                let mut annotation = (0, String::new());
+                r.fields(b, o, |r, number, field, _| {
+                    match (number, field) {
+                        (1, Field::Fixed64(v)) => annotation.0 = v as i64,
+                        (2, Field::Bytes(b, o)) => annotation.1 = r.string(b, o)?,
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                span.annotations.push(annotation);
+            }
+            (11, Field::Bytes(b, o)) => {
+                check_collection(&span.tags, r.limits, field_offset)?;
+                let mut tag = (String::new(), TagValue::Str(String::new()));
+                r.fields(b, o, |r, number, field, _| {
+                    match (number, field) {
+                        (1, Field::Bytes(b, o)) => tag.0 = r.string(b, o)?,
+                        (2, Field::Bytes(b, o)) => tag.1 = TagValue::Str(r.string(b, o)?),
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+                span.tags.push(tag);
+            }
+            (12, Field::Varint(v)) => span.debug = v != 0,
+            (13, Field::Varint(_)) => {}
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    span.trace_id = trace_id.ok_or(DecodeError::MissingField {
+        field: "Span.trace_id",
+    })?;
+    span.id = id.ok_or(DecodeError::MissingField { field: "Span.id" })?;
+    Ok(span)
+}
+
+fn read_endpoint(reader: &Reader, buf: &[u8], offset: usize) -> DecodeResult<Endpoint> {
+    let mut endpoint = Endpoint::default();
+    reader.fields(buf, offset, |r, number, field, _| {
+        match (number, field) {
+            (1, Field::Bytes(b, o)) => endpoint.service_name = r.string(b, o)?,
+            (2, Field::Bytes(b, _)) if b.len() == 4 => {
+                endpoint.ipv4 = Some(Ipv4Addr::new(b[0], b[1], b[2], b[3]));
+            }
+            (3, Field::Bytes(b, _)) if b.len() == 16 => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(b);
+                endpoint.ipv6 = Some(Ipv6Addr::from(octets));
+            }
+            (2, Field::Bytes(..)) | (3, Field::Bytes(..)) => {}
+            (4, Field::Varint(v)) => endpoint.port = Some(i64::from(v as i32)).filter(|&p| p != 0),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(endpoint)
+}
+
+fn decode_json(buf: &[u8], limits: &DecodeLimits) -> DecodeResult<Vec<ZipkinSpan>> {
+    let value: Value = serde_json::from_slice(buf).map_err(|e| DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Invalid JSON: {}", e),
+    })?;
+    let values = match value {
+        Value::Array(values) => values,
+        v => return Err(json_error("spans", &v)),
+    };
+    let mut spans = Vec::with_capacity(values.len());
+    for v in &values {
+        check_collection(&spans, limits, 0)?;
+        spans.push(json_span(v, limits)?);
+    }
+    Ok(spans)
+}
+
+fn json_span(value: &Value, limits: &DecodeLimits) -> DecodeResult<ZipkinSpan> {
+    let trace_id = json_string(value, "traceId", limits)?;
+    let (high, low) = parse_hex_id(&trace_id, 32).ok_or_else(|| json_error("traceId", value))?;
+    let id = json_string(value, "id", limits)?;
+    let id = parse_hex_id(&id, 16).ok_or_else(|| json_error("id", value))?.1;
+    let parent_id = match json_string(value, "parentId", limits)?.as_str() {
+        "" => None,
+        s => Some(SpanId(
+            parse_hex_id(s, 16).ok_or_else(|| json_error("parentId", value))?.1,
+        )),
+    };
+    let mut annotations = Vec::new();
+    if let Some(Value::Array(values)) = value.get("annotations") {
+        for v in values {
+            check_collection(&annotations, limits, 0)?;
+            annotations.push((json_int(v, "timestamp")?, json_string(v, "value", limits)?));
+        }
+    }
+    let mut tags = Vec::new();
+    if let Some(Value::Object(values)) = value.get("tags") {
+        for (k, v) in values {
+            check_collection(&tags, limits, 0)?;
+            check_string(k.len(), limits, 0)?;
+            let v = match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            check_string(v.len(), limits, 0)?;
+            tags.push((k.clone(), TagValue::Str(v)));
+        }
+    }
+    Ok(ZipkinSpan {
+        trace_id: TraceId { high, low },
+        id: SpanId(id),
+        parent_id,
+        kind: kind_name(&json_string(value, "kind", limits)?),
+        name: json_string(value, "name", limits)?,
+        timestamp: json_int(value, "timestamp")?,
+        duration: json_int(value, "duration")?,
+        local_endpoint: match value.get("localEndpoint") {
+            Some(v) => json_endpoint(v, limits)?,
+            None => Endpoint::default(),
+        },
+        remote_endpoint: match value.get("remoteEndpoint") {
+            Some(v) => Some(json_endpoint(v, limits)?),
+            None => None,
+        },
+        annotations,
+        tags,
+        debug: value.get("debug").and_then(|v| v.as_bool()).unwrap_or(false),
+    })
+}
+
+fn json_endpoint(value: &Value, limits: &DecodeLimits) -> DecodeResult<Endpoint> {
+    Ok(Endpoint {
+        service_name: json_string(value, "serviceName", limits)?,
+        ipv4: json_string(value, "ipv4", limits)?.parse().ok(),
+        ipv6: json_string(value, "ipv6", limits)?.parse().ok(),
+        port: Some(json_int(value, "port")?).filter(|&p| p != 0),
+    })
+}
+
+fn json_string(object: &Value, key: &str, limits: &DecodeLimits) -> DecodeResult<String> {
+    match object.get(key) {
+        None | Some(Value::Null) => Ok(String::new()),
+        Some(Value::String(s)) => {
+            check_string(s.len(), limits, 0)?;
+            Ok(s.clone())
+        }
+        Some(v) => Err(json_error(key, v)),
+    }
+}
+
+fn json_int(object: &Value, key: &str) -> DecodeResult<i64> {
+    match object.get(key) {
+        None | Some(Value::Null) => Ok(0),
+        Some(v) => v.as_i64().ok_or_else(|| json_error(key, v)),
+    }
+}
+
+fn json_error(key: &str, value: &Value) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset: 0,
+        reason: format!("Unexpected value of {:?}: {}", key, value),
+    }
+}
+
+/// Parses a lower-hex ID of up to `max_len` digits into its high and low 64 bits.
+fn parse_hex_id(s: &str, max_len: usize) -> Option<(i64, i64)> {
+    if s.is_empty() || s.len() > max_len || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let split = s.len().saturating_sub(16);
+    let high = if split == 0 {
+        0
+    } else {
+        u64::from_str_radix(&s[..split], 16).ok()?
+    };
+    let low = u64::from_str_radix(&s[split..], 16).ok()?;
+    Some((high as i64, low as i64))
+}
+
+fn thrift_message(reader: &mut wire::Reader) -> DecodeResult<Vec<ZipkinSpan>> {
+    let header = reader.read_message_begin()?;
+    if header.name != "emitZipkinBatch" || header.kind != MESSAGE_KIND_ONEWAY {
+        return Err(DecodeError::UnexpectedMessage {
+            name: header.name.to_owned(),
+            kind: header.kind,
+        });
+    }
+    let mut spans = None;
+    thrift_struct(reader, "emitZipkinBatch_args", |reader, id, ty| {
+        match (id, ty) {
+            (1, Type::List) => {
+                spans = Some(thrift_list(reader, "emitZipkinBatch_args.spans", thrift_span)?)
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    spans.ok_or(DecodeError::MissingField {
+        field: "emitZipkinBatch_args.spans",
+    })
+}
+
+/// Invokes `f` for each field of a struct.
+///
+/// `f` returns `false` if the field is unknown, which is skipped (or rejected in
+/// the strict mode).
+fn thrift_struct<'a, F>(
+    reader: &mut wire::Reader<'a>,
+    name: &'static str,
+    mut f: F,
+) -> DecodeResult<()>
+where
+    F: FnMut(&mut wire::Reader<'a>, i16, Type) -> DecodeResult<bool>,
+{
+    reader.read_struct_begin()?;
+    let mut offset = reader.position();
+    while let Some(field) = reader.read_field_begin()? {
+        if !f(reader, field.id, field.ty)? {
+            if reader.is_strict() {
+                return Err(DecodeError::UnknownField {
+                    struct_name: name,
+                    field_id: field.id,
+                    offset,
+                    actual: field.ty.name(),
+                });
+            }
+            reader.skip(field.ty)?;
+        }
+        offset = reader.position();
+    }
+    reader.read_struct_end();
+    Ok(())
+}
+
+fn thrift_list<'a, T, F>(
+    reader: &mut wire::Reader<'a>,
+    name: &'static str,
+    f: F,
+) -> DecodeResult<Vec<T>>
+where
+    F: Fn(&mut wire::Reader<'a>) -> DecodeResult<T>,
+{
+    let offset = reader.position();
+    let (ty, size) = reader.read_list_begin()?;
+    if ty != Type::Struct {
+        return Err(DecodeError::UnexpectedElementType {
+            list: name,
+            offset,
+            expected: Type::Struct.name(),
+            actual: ty.name(),
+        });
+    }
+    let mut list = Vec::with_capacity(size.min(1024));
+    reader.enter()?;
+    for _ in 0..size {
+        list.push(f(reader)?);
+    }
+    reader.leave();
+    Ok(list)
+}
+
+/// The values of the core annotations and the kinds of the spans which they indicate.
+const CORE_ANNOTATIONS: &[(&str, &str)] = &[
+    ("cs", "client"),
+    ("cr", "client"),
+    ("sr", "server"),
+    ("ss", "server"),
+    ("ms", "producer"),
+    ("mr", "consumer"),
+];
+
+fn thrift_span(reader: &mut wire::Reader) -> DecodeResult<ZipkinSpan> {
+    let mut span = ZipkinSpan::default();
+    let mut trace_id = None;
+    let mut id = None;
+    let mut timestamp = None;
+    let mut duration = None;
+    let mut annotations = Vec::new();
+    let mut binary_annotations = Vec::new();
+    thrift_struct(reader, "Span", |reader, field_id, ty| {
+        match (field_id, ty) {
+            (1, Type::I64) => trace_id = Some(reader.read_i64()?),
+            (3, Type::Binary) => span.name = reader.read_str()?.to_owned(),
+            (4, Type::I64) => id = Some(reader.read_i64()?),
+            (5, Type::I64) => span.parent_id = Some(SpanId(reader.read_i64()?)),
+            (6, Type::List) => {
+                annotations = thrift_list(reader, "Span.annotations", thrift_annotation)?
+            }
+            (8, Type::List) => {
+                binary_annotations =
+                    thrift_list(reader, "Span.binary_annotations", thrift_binary_annotation)?
+            }
+            (9, Type::Bool) => span.debug = reader.read_bool()?,
+            (10, Type::I64) => timestamp = Some(reader.read_i64()?),
+            (11, Type::I64) => duration = Some(reader.read_i64()?),
+            (12, Type::I64) => span.trace_id.high = reader.read_i64()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    span.trace_id.low = trace_id.ok_or(DecodeError::MissingField {
+        field: "Span.trace_id",
+    })?;
+    span.id = SpanId(id.ok_or(DecodeError::MissingField { field: "Span.id" })?);
+
+    // The span lacks the timestamp and the duration if it was reported by
+    // an old client, so they are derived from the annotations
+    let first = annotations.iter().map(|a| a.0).min().unwrap_or(0);
+    let last = annotations.iter().map(|a| a.0).max().unwrap_or(0);
+    span.timestamp = timestamp.unwrap_or(first);
+    span.duration = duration.unwrap_or(last - first);
+
+    let mut hosts = Vec::new();
+    for (timestamp, value, host) in annotations {
+        match CORE_ANNOTATIONS.iter().find(|a| a.0 == value) {
+            Some(&(_, kind)) => {
+                span.kind = Some(kind);
+                if let Some(host) = host {
+                    hosts.insert(0, host);
+                }
+            }
+            None => {
+                hosts.extend(host);
+                span.annotations.push((timestamp, value));
+            }
+        }
+    }
+    for (key, value, host) in binary_annotations {
+        match (key.as_str(), host) {
+            ("sa", Some(host)) | ("ca", Some(host)) | ("ma", Some(host)) => {
+                span.remote_endpoint = Some(host);
+            }
+            (_, host) => {
+                hosts.extend(host);
+                span.tags.push((key, value));
+            }
+        }
+    }
+    span.local_endpoint = hosts.into_iter().next().unwrap_or_default();
+    Ok(span)
+}
+
+fn thrift_annotation(reader: &mut wire::Reader) -> DecodeResult<(i64, String, Option<Endpoint>)> {
+    let mut annotation = (0, String::new(), None);
+    thrift_struct(reader, "Annotation", |reader, id, ty| {
+        match (id, ty) {
+            (1, Type::I64) => annotation.0 = reader.read_i64()?,
+            (2, Type::Binary) => annotation.1 = reader.read_str()?.to_owned(),
+            (3, Type::Struct) => annotation.2 = Some(thrift_endpoint(reader)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(annotation)
+}
+
+fn thrift_binary_annotation(
+    reader: &mut wire::Reader,
+) -> DecodeResult<(String, TagValue, Option<Endpoint>)> {
+    let mut key = String::new();
+    let mut value = Vec::new();
+    let mut annotation_type = 6; // STRING
+    let mut host = None;
+    thrift_struct(reader, "BinaryAnnotation", |reader, id, ty| {
+        match (id, ty) {
+            (1, Type::Binary) => key = reader.read_str()?.to_owned(),
+            (2, Type::Binary) => value = reader.read_binary()?.to_owned(),
+            (3, Type::I32) => annotation_type = reader.read_i32()?,
+            (4, Type::Struct) => host = Some(thrift_endpoint(reader)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+
+    // The values are encoded in big-endian
+    let value = match (annotation_type, value.len()) {
+        (0, 1) => TagValue::Bool(value[0] != 0),
+        (2, 2) => TagValue::Long(i64::from(i16::from_be_bytes([value[0], value[1]]))),
+        (3, 4) => TagValue::Long(i64::from(be_i32(&value))),
+        (4, 8) => TagValue::Long(be_i64(&value)),
+        (5, 8) => TagValue::Double(f64::from_bits(be_i64(&value) as u64)),
+        (6, _) => match String::from_utf8(value) {
+            Ok(s) => TagValue::Str(s),
+            Err(e) => TagValue::Binary(e.into_bytes()),
+        },
+        _ => TagValue::Binary(value),
+    };
+    Ok((key, value, host))
+}
+
+fn thrift_endpoint(reader: &mut wire::Reader) -> DecodeResult<Endpoint> {
+    let mut endpoint = Endpoint::default();
+    thrift_struct(reader, "Endpoint", |reader, id, ty| {
+        match (id, ty) {
+            (1, Type::I32) => {
+                endpoint.ipv4 = Some(reader.read_i32()?)
+                    .filter(|&ip| ip != 0)
+                    .map(|ip| Ipv4Addr::from(ip as u32));
+            }
+            (2, Type::I16) => {
+                endpoint.port = Some(i64::from(reader.read_i16()? as u16)).filter(|&p| p != 0);
+            }
+            (3, Type::Binary) => endpoint.service_name = reader.read_str()?.to_owned(),
+            (4, Type::Binary) => {
+                let ip = reader.read_binary()?;
+                if ip.len() == 16 {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(ip);
+                    endpoint.ipv6 = Some(Ipv6Addr::from(octets));
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(endpoint)
+}
+
+fn be_i32(bytes: &[u8]) -> i32 {
+    let mut b = [0; 4];
+    b.copy_from_slice(bytes);
+    i32::from_be_bytes(b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::SpanRef;
+
+    fn decode<P: WireProtocol>(protocol: P, buf: &[u8]) -> DecodeResult<Batch> {
+        let limits = DecodeLimits::default();
+        let (message, _) = protocol.decode_batch(buf, DecodeMode::Strict, &limits)?;
+        Ok(message.batch)
+    }
+
+    fn log(timestamp: i64, event: &str) -> Log {
+        Log {
+            timestamp,
+            fields: vec![Tag::new("event", event)],
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    fn span(trace_id: TraceId, span_id: i64) -> Span {
+        Span {
+            trace_id,
+            span_id: SpanId(span_id),
+            parent_span_id: None,
+            operation_name: "get".to_owned(),
+            references: Vec::new(),
+            flags: 1,
+            start_time: 0,
+            duration: 0,
+            tags: Vec::new(),
+            logs: Vec::new(),
+            unknown_fields: UnknownFields::new(),
+        }
+    }
+
+    /// An `emitZipkinBatch` message of a client span (reported by an old client, so
+    /// without a timestamp and a duration) and the server span sharing its ID.
+    const THRIFT_MESSAGE: [u8; 313] = [
+        0x82, 0x81, 0x00, 0x0f, 0x65, 0x6d, 0x69, 0x74, 0x5a, 0x69, 0x70, 0x6b, 0x69, 0x6e, 0x42,
+        0x61, 0x74, 0x63, 0x68, 0x19, 0x2c, 0x16, 0x02, 0x28, 0x03, 0x67, 0x65, 0x74, 0x16, 0x06,
+        0x16, 0x08, 0x19, 0x3c, 0x16, 0xc8, 0x01, 0x18, 0x02, 0x63, 0x73, 0x1c, 0x15, 0xfd, 0xff,
+        0xbf, 0xf5, 0x07, 0x14, 0xa0, 0x7e, 0x18, 0x03, 0x66, 0x6f, 0x6f, 0x00, 0x00, 0x16, 0xac,
+        0x02, 0x18, 0x05, 0x72, 0x65, 0x74, 0x72, 0x79, 0x1c, 0x15, 0xfd, 0xff, 0xbf, 0xf5, 0x07,
+        0x14, 0xa0, 0x7e, 0x18, 0x03, 0x66, 0x6f, 0x6f, 0x00, 0x00, 0x16, 0xd8, 0x04, 0x18, 0x02,
+        0x63, 0x72, 0x1c, 0x15, 0xfd, 0xff, 0xbf, 0xf5, 0x07, 0x14, 0xa0, 0x7e, 0x18, 0x03, 0x66,
+        0x6f, 0x6f, 0x00, 0x00, 0x29, 0x8c, 0x18, 0x02, 0x73, 0x61, 0x18, 0x01, 0x01, 0x15, 0x00,
+        0x1c, 0x15, 0x84, 0x80, 0x80, 0xa0, 0x01, 0x14, 0xf0, 0x54, 0x18, 0x02, 0x64, 0x62, 0x18,
+        0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x18, 0x04, 0x62, 0x6f, 0x6f, 0x6c, 0x18, 0x01, 0x01, 0x15, 0x00,
+        0x00, 0x18, 0x03, 0x69, 0x31, 0x36, 0x18, 0x02, 0xff, 0xfe, 0x15, 0x04, 0x00, 0x18, 0x03,
+        0x69, 0x33, 0x32, 0x18, 0x04, 0xff, 0xff, 0xff, 0xfd, 0x15, 0x06, 0x00, 0x18, 0x03, 0x69,
+        0x36, 0x34, 0x18, 0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x15, 0x08, 0x00,
+        0x18, 0x06, 0x64, 0x6f, 0x75, 0x62, 0x6c, 0x65, 0x18, 0x08, 0x3f, 0xe0, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x15, 0x0a, 0x00, 0x18, 0x03, 0x73, 0x74, 0x72, 0x18, 0x01, 0x78, 0x15,
+        0x0c, 0x00, 0x18, 0x05, 0x62, 0x79, 0x74, 0x65, 0x73, 0x18, 0x02, 0x00, 0xff, 0x15, 0x02,
+        0x00, 0x11, 0x36, 0x04, 0x00, 0x16, 0x02, 0x28, 0x03, 0x67, 0x65, 0x74, 0x16, 0x06, 0x16,
+        0x08, 0x19, 0x2c, 0x16, 0xdc, 0x01, 0x18, 0x02, 0x73, 0x72, 0x1c, 0x38, 0x03, 0x62, 0x61,
+        0x72, 0x00, 0x00, 0x16, 0xc4, 0x04, 0x18, 0x02, 0x73, 0x73, 0x1c, 0x38, 0x03, 0x62, 0x61,
+        0x72, 0x00, 0x00, 0x46, 0xdc, 0x01, 0x16, 0xe8, 0x02, 0x16, 0x04, 0x00, 0x00,
+    ];
+
+    /// A proto3 `ListOfSpans` of a client span and the (shared) server span of another service.
+    const PROTO_SPANS: [u8; 172] = [
+        0x0a, 0x6e, 0x0a, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x1a, 0x08, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x20, 0x01, 0x2a, 0x03, 0x67, 0x65, 0x74, 0x31,
+        0x64, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0xc8, 0x01, 0x42, 0x0e, 0x0a, 0x03,
+        0x66, 0x6f, 0x6f, 0x12, 0x04, 0xc0, 0xa8, 0x00, 0x01, 0x20, 0x90, 0x3f, 0x4a, 0x19, 0x0a,
+        0x02, 0x64, 0x62, 0x1a, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x20, 0xb8, 0x2a, 0x52, 0x10, 0x09, 0x96, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x12, 0x05, 0x72, 0x65, 0x74, 0x72, 0x79, 0x5a, 0x06, 0x0a,
+        0x01, 0x6b, 0x12, 0x01, 0x76, 0x60, 0x01, 0x0a, 0x3a, 0x0a, 0x08, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x01, 0x12, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x1a,
+        0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x20, 0x02, 0x2a, 0x03, 0x67, 0x65,
+        0x74, 0x31, 0x6e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0xb4, 0x01, 0x42, 0x05,
+        0x0a, 0x03, 0x62, 0x61, 0x72, 0x68, 0x01,
+    ];
+
+    #[test]
+    fn thrift_message_is_decoded() {
+        let batch = decode(ZipkinThrift, &THRIFT_MESSAGE).unwrap();
+        assert_eq!(batch.process.service_name, "foo");
+        assert_eq!(batch.process.tags, vec![Tag::new("ip", "192.168.0.1")]);
+        assert_eq!(batch.spans.len(), 2);
+
+        // The core annotations determine the kind, the endpoints and the times
+        let client = &batch.spans[0];
+        assert_eq!(client.trace_id, TraceId { high: 2, low: 1 });
+        assert_eq!(client.span_id, SpanId(3));
+        assert_eq!(client.parent_span_id, Some(SpanId(4)));
+        assert_eq!(client.operation_name, "get");
+        assert_eq!(client.flags, 3);
+        assert_eq!((client.start_time, client.duration), (100, 200));
+        assert_eq!(
+            client.tags,
+            vec![
+                Tag::new("bool", true),
+                Tag::new("i16", -2i64),
+                Tag::new("i32", -3i64),
+                Tag::new("i64", 1i64 << 40),
+                Tag::new("double", 0.5),
+                Tag::new("str", "x"),
+                Tag::new("bytes", vec![0, 255]),
+                Tag::new("span.kind", "client"),
+                Tag::new("peer.service", "db"),
+                Tag::new("peer.ipv4", "10.0.0.2"),
+                Tag::new("peer.ipv6", "::1"),
+                Tag::new("peer.port", 5432i64),
+            ]
+        );
+        assert_eq!(client.logs, vec![log(150, "retry")]);
+
+        // The server span shares the ID of the client span
+        let server = &batch.spans[1];
+        assert_eq!(server.trace_id, client.trace_id);
+        assert_eq!(server.span_id, client.span_id);
+        assert_eq!(server.parent_span_id, client.parent_span_id);
+        assert_eq!(server.flags, 1);
+        assert_eq!((server.start_time, server.duration), (110, 180));
+        assert_eq!(
+            server.tags,
+            vec![Tag::new("span.kind", "server"), Tag::new("service.name", "bar")]
+        );
+        assert!(server.logs.is_empty());
+
+        for len in 0..THRIFT_MESSAGE.len() {
+            assert!(decode(ZipkinThrift, &THRIFT_MESSAGE[..len]).is_err(), "len={}", len);
+        }
+    }
+
+    #[test]
+    fn proto3_spans_are_decoded() {
+        let batch = decode(ZipkinV2, &PROTO_SPANS).unwrap();
+        assert_eq!(batch.process.service_name, "foo");
+        assert_eq!(batch.process.tags, vec![Tag::new("ip", "192.168.0.1")]);
+
+        let client = &batch.spans[0];
+        assert_eq!(client.trace_id, TraceId { high: 0, low: 1 });
+        assert_eq!(client.span_id, SpanId(3));
+        assert_eq!(client.parent_span_id, None);
+        assert_eq!(client.flags, 3);
+        assert_eq!((client.start_time, client.duration), (100, 200));
+        assert_eq!(
+            client.tags,
+            vec![
+                Tag::new("k", "v"),
+                Tag::new("span.kind", "client"),
+                Tag::new("peer.service", "db"),
+                Tag::new("peer.ipv6", "::1"),
+                Tag::new("peer.port", 5432i64),
+            ]
+        );
+        assert_eq!(client.logs, vec![log(150, "retry")]);
+
+        let server = &batch.spans[1];
+        assert_eq!(server.span_id, SpanId(3));
+        assert_eq!(server.parent_span_id, Some(SpanId(4)));
+        assert_eq!(
+            server.tags,
+            vec![Tag::new("span.kind", "server"), Tag::new("service.name", "bar")]
+        );
+    }
+
+    #[test]
+    fn json_spans_are_decoded() {
+        let body = br#"[
+            {"traceId": "5b8efff798038103d269b633813fc60c", "id": "eee19b7ec3c1b174",
+             "parentId": "0000000000000001", "kind": "CLIENT", "name": "get", "timestamp": 100,
+             "duration": 200, "debug": true,
+             "localEndpoint": {"serviceName": "foo", "ipv6": "2001:db8::1", "port": 8080},
+             "remoteEndpoint": {"serviceName": "db", "ipv4": "10.0.0.2", "port": 5432},
+             "annotations": [{"timestamp": 150, "value": "retry"}],
+             "tags": {"s": "x", "n": 1, "b": true}},
+            {"traceId": "d269b633813fc60c", "id": "eee19b7ec3c1b174", "parentId": "1",
+             "kind": "SERVER", "shared": true, "name": "get", "timestamp": 110, "duration": 180,
+             "localEndpoint": {"serviceName": "bar"}},
+            {"traceId": "1", "id": "2", "kind": "FOO"}
+        ]"#;
+        let batch = decode(ZipkinV2, body).unwrap();
+        assert_eq!(batch.process.service_name, "foo");
+        assert_eq!(batch.process.tags, vec![Tag::new("ip", "2001:db8::1")]);
+
+        let client = &batch.spans[0];
+        assert_eq!(
+            client.trace_id,
+            TraceId {
+                high: 0x5b8e_fff7_9803_8103,
+                low: 0xd269_b633_813f_c60c_u64 as i64,
+            }
+        );
+        assert_eq!(client.span_id, SpanId(0xeee1_9b7e_c3c1_b174_u64 as i64));
+        assert_eq!(client.parent_span_id, Some(SpanId(1)));
+        assert_eq!(client.flags, 3);
+        assert_eq!((client.start_time, client.duration), (100, 200));
+        assert_eq!(
+            client.tags,
+            vec![
+                Tag::new("b", "true"),
+                Tag::new("n", "1"),
+                Tag::new("s", "x"),
+                Tag::new("span.kind", "client"),
+                Tag::new("peer.service", "db"),
+                Tag::new("peer.ipv4", "10.0.0.2"),
+                Tag::new("peer.port", 5432i64),
+            ]
+        );
+        assert_eq!(client.logs, vec![log(150, "retry")]);
+
+        // A shared span is an ordinary span (with the ID of the client span)
+        let server = &batch.spans[1];
+        assert_eq!(server.trace_id, TraceId { high: 0, low: client.trace_id.low });
+        assert_eq!(server.span_id, client.span_id);
+        assert_eq!(server.flags, 1);
+        assert_eq!(
+            server.tags,
+            vec![Tag::new("span.kind", "server"), Tag::new("service.name", "bar")]
+        );
+
+        // Unknown kinds are dropped, and spans without a local endpoint have no service
+        let other = &batch.spans[2];
+        assert_eq!(other.tags, vec![Tag::new("service.name", NO_SERVICE_NAME)]);
+    }
+
+    #[test]
+    fn invalid_json_spans_are_rejected() {
+        for body in &[
+            r#"{"traceId": "1", "id": "2"}"#,
+            r#"[{"id": "2"}]"#,
+            r#"[{"traceId": "1"}]"#,
+            r#"[{"traceId": "1", "id": "00000000000000002"}]"#,
+            r#"[{"traceId": "5b8efff798038103d269b633813fc60c0", "id": "2"}]"#,
+            r#"[{"traceId": "xyz", "id": "2"}]"#,
+            r#"[{"traceId": "1", "id": "2", "parentId": "-1"}]"#,
+            r#"[{"traceId": "1", "id": "2", "timestamp": "100"}]"#,
+            r#"[{"traceId": "1", "id": "2", "name": 1}]"#,
+        ] {
+            assert!(decode(ZipkinV2, body.as_bytes()).is_err(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn batch_is_encoded_into_json() {
+        let mut client = span(TraceId { high: 0, low: 1 }, 3);
+        client.references = vec![
+            SpanRef {
+                ref_type: SpanRefType::FollowsFrom,
+                trace_id: client.trace_id,
+                span_id: SpanId(5),
+                unknown_fields: UnknownFields::new(),
+            },
+            SpanRef {
+                ref_type: SpanRefType::ChildOf,
+                trace_id: client.trace_id,
+                span_id: SpanId(4),
+                unknown_fields: UnknownFields::new(),
+            },
+        ];
+        client.flags = 3;
+        client.start_time = 100;
+        client.duration = 200;
+        client.tags = vec![
+            Tag::new("span.kind", "client"),
+            Tag::new("peer.service", "db"),
+            Tag::new("peer.ipv4", 0x0A00_0002i64),
+            Tag::new("peer.ipv6", "::1"),
+            Tag::new("peer.port", 5432i64),
+            Tag::new("s", "x"),
+            Tag::new("b", true),
+            Tag::new("d", 0.5),
+            Tag::new("y", vec![0, 255]),
+        ];
+        client.logs = vec![
+            log(150, "retry"),
+            Log {
+                timestamp: 160,
+                fields: vec![Tag::new("event", "error"), Tag::new("code", 500i64)],
+                unknown_fields: UnknownFields::new(),
+            },
+        ];
+        let mut server = span(TraceId { high: 2, low: 1 }, 3);
+        server.parent_span_id = Some(SpanId(4));
+        server.start_time = 110;
+        server.duration = 180;
+        server.tags = vec![
+            Tag::new("span.kind", "server"),
+            Tag::new("service.name", "bar"),
+            Tag::new("peer.port", 70_000i64),
+        ];
+        let mut internal = span(TraceId { high: 0, low: 5 }, 6);
+        internal.parent_span_id = Some(SpanId(0));
+        internal.tags = vec![Tag::new("span.kind", "internal")];
+
+        let batch = Batch {
+            process: Process {
+                service_name: "foo".to_owned(),
+                tags: vec![
+                    Tag::new("ip", "192.168.0.1"),
+                    Tag::new("hostname", "h"),
+                    Tag::new("pid", 42i64),
+                ],
+                unknown_fields: UnknownFields::new(),
+            },
+            spans: vec![client, server, internal],
+            seq_no: None,
+            stats: None,
+            unknown_fields: UnknownFields::new(),
+        };
+        let expected: Value = serde_json::from_str(
+            r#"[
+                {"traceId": "0000000000000001", "id": "0000000000000003",
+                 "parentId": "0000000000000004", "kind": "CLIENT", "name": "get",
+                 "timestamp": 100, "duration": 200, "debug": true,
+                 "localEndpoint": {"serviceName": "foo", "ipv4": "192.168.0.1"},
+                 "remoteEndpoint": {"serviceName": "db", "ipv4": "10.0.0.2", "ipv6": "::1",
+                                    "port": 5432},
+                 "annotations": [
+                     {"timestamp": 150, "value": "retry"},
+                     {"timestamp": 160, "value": "{\"code\":\"500\",\"event\":\"error\"}"}
+                 ],
+                 "tags": {"hostname": "h", "pid": "42", "s": "x", "b": "true", "d": "0.5",
+                          "y": "AP8="}},
+                {"traceId": "00000000000000020000000000000001", "id": "0000000000000003",
+                 "parentId": "0000000000000004", "kind": "SERVER", "name": "get",
+                 "timestamp": 110, "duration": 180,
+                 "localEndpoint": {"serviceName": "bar", "ipv4": "192.168.0.1"},
+                 "annotations": [],
+                 "tags": {"hostname": "h", "pid": "42", "peer.port": "70000"}},
+                {"traceId": "0000000000000005", "id": "0000000000000006", "name": "get",
+                 "timestamp": 0, "duration": 0,
+                 "localEndpoint": {"serviceName": "foo", "ipv4": "192.168.0.1"},
+                 "annotations": [],
+                 "tags": {"hostname": "h", "pid": "42"}}
+            ]"#,
+        ).unwrap();
+        assert_eq!(to_json(&batch), expected);
+
+        // The encoded spans are decoded into the same IDs, kinds and endpoints
+        let decoded = decode(ZipkinV2, to_json(&batch).to_string().as_bytes()).unwrap();
+        assert_eq!(decoded.process.service_name, "foo");
+        let tag = |span: &Span, key: &str| {
+            span.tags
+                .iter()
+                .find(|t| t.key == key)
+                .map(|t| t.value.clone())
+        };
+        for (decoded, span) in decoded.spans.iter().zip(&batch.spans) {
+            assert_eq!(decoded.trace_id, span.trace_id);
+            assert_eq!(decoded.span_id, span.span_id);
+            assert_eq!(decoded.flags, span.flags);
+            assert_eq!(tag(decoded, "service.name"), tag(span, "service.name"));
+        }
+        let client = &decoded.spans[0];
+        assert_eq!(client.parent_span_id, Some(SpanId(4)));
+        assert_eq!(tag(client, "span.kind"), Some(TagValue::from("client")));
+        assert_eq!(tag(client, "peer.ipv4"), Some(TagValue::from("10.0.0.2")));
+        assert_eq!(tag(client, "peer.port"), Some(TagValue::Long(5432)));
+        assert_eq!(tag(&decoded.spans[1], "span.kind"), Some(TagValue::from("server")));
+        assert_eq!(decoded.spans[2].parent_span_id, None);
+        assert_eq!(tag(&decoded.spans[2], "span.kind"), None);
+    }
+}
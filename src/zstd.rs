@@ -0,0 +1,1035 @@
+//! A decoder of the Zstandard format ([RFC 8878]), which decompresses the bodies of HTTP
+//! requests with `Content-Encoding: zstd`.
+//!
+//! Frames using dictionaries are not supported, and content checksums are not verified.
+//!
+//! [RFC 8878]: https://www.rfc-editor.org/rfc/rfc8878
+use error::{DecodeError, DecodeResult};
+
+const FRAME_MAGIC: u32 = 0xFD2F_B528;
+const SKIPPABLE_FRAME_MAGIC: u32 = 0x184D_2A50;
+
+const MAX_HUFFMAN_BITS: u8 = 11;
+const MAX_LITERAL_LENGTH_CODE: usize = 35;
+const MAX_MATCH_LENGTH_CODE: usize = 52;
+const MAX_OFFSET_CODE: usize = 31;
+
+const LITERAL_LENGTH_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1,
+    1, -1, -1, -1, -1,
+];
+const MATCH_LENGTH_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OFFSET_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// The predefined distribution and the maximum accuracy log of a code of the sequences.
+struct SequenceCode {
+    default: &'static [i16],
+    default_accuracy_log: u8,
+    max_accuracy_log: u8,
+}
+
+const LITERAL_LENGTH_CODE: SequenceCode = SequenceCode {
+    default: &LITERAL_LENGTH_DEFAULT,
+    default_accuracy_log: 6,
+    max_accuracy_log: 9,
+};
+const MATCH_LENGTH_CODE: SequenceCode = SequenceCode {
+    default: &MATCH_LENGTH_DEFAULT,
+    default_accuracy_log: 6,
+    max_accuracy_log: 9,
+};
+const OFFSET_CODE: SequenceCode = SequenceCode {
+    default: &OFFSET_DEFAULT,
+    default_accuracy_log: 5,
+    max_accuracy_log: 8,
+};
+
+/// The baselines and the numbers of the extra bits of the literal length codes 16 and above.
+const LITERAL_LENGTHS: [(u32, u8); 20] = [
+    (16, 1), (18, 1), (20, 1), (22, 1), (24, 2), (28, 2), (32, 3), (40, 3), (48, 4), (64, 6),
+    (128, 7), (256, 8), (512, 9), (1024, 10), (2048, 11), (4096, 12), (8192, 13), (16384, 14),
+    (32768, 15), (65536, 16),
+];
+
+/// The baselines and the numbers of the extra bits of the match length codes 32 and above.
+const MATCH_LENGTHS: [(u32, u8); 21] = [
+    (35, 1), (37, 1), (39, 1), (41, 1), (43, 2), (47, 2), (51, 3), (59, 3), (67, 4), (83, 4),
+    (99, 5), (131, 7), (259, 8), (515, 9), (1027, 10), (2051, 11), (4099, 12), (8195, 13),
+    (16387, 14), (32771, 15), (65539, 16),
+];
+
+/// Decompresses the Zstandard frames in `buf` into at most `max_size` bytes.
+pub fn decompress(buf: &[u8], max_size: usize) -> DecodeResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let magic = le_u32(buf, pos)?;
+        if magic & 0xFFFF_FFF0 == SKIPPABLE_FRAME_MAGIC {
+            let size = le_u32(buf, pos + 4)? as usize;
+            pos += 8 + size;
+            if buf.len() < pos {
+                return Err(DecodeError::Truncated { offset: buf.len() });
+            }
+            continue;
+        }
+        if magic != FRAME_MAGIC {
+            return Err(invalid(pos, "Not a Zstandard frame"));
+        }
+        let mut frame = Frame::new(max_size - out.len());
+        pos = frame.decode(buf, pos + 4)?;
+        out.extend_from_slice(&frame.out);
+    }
+    Ok(out)
+}
+
+/// The state of the decoding of a frame, which is carried over between its blocks.
+struct Frame {
+    out: Vec<u8>,
+    max_size: usize,
+    offsets: [usize; 3],
+    huffman: Option<HuffmanTable>,
+    literal_lengths: Option<FseTable>,
+    offset_codes: Option<FseTable>,
+    match_lengths: Option<FseTable>,
+}
+impl Frame {
+    fn new(max_size: usize) -> Self {
+        Frame {
+            out: Vec::new(),
+            max_size,
+            offsets: [1, 4, 8],
+            huffman: None,
+            literal_lengths: None,
+            offset_codes: None,
+            match_lengths: None,
+        }
+    }
+
+    /// Decodes the frame following the magic number at `pos`,
+    /// and returns the offset of the byte following the frame.
+    fn decode(&mut self, buf: &[u8], mut pos: usize) -> DecodeResult<usize> {
+        let descriptor = byte(buf, pos)?;
+        if descriptor & 0x08 != 0 {
+            return Err(invalid(pos, "Reserved bit is set"));
+        }
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let dictionary_id_size = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let content_size_size = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => 0,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+        pos += 1;
+        if !single_segment {
+            // Window descriptor
+            pos += 1;
+        }
+        let dictionary_id = le_uint(buf, pos, dictionary_id_size)?;
+        if dictionary_id != 0 {
+            return Err(invalid(pos, "Dictionaries are not supported"));
+        }
+        pos += dictionary_id_size;
+        let _ = le_uint(buf, pos, content_size_size)?;
+        pos += content_size_size;
+
+        loop {
+            let header = le_uint(buf, pos, 3)? as usize;
+            pos += 3;
+            let last = header & 1 == 1;
+            let size = header >> 3;
+            match (header >> 1) & 3 {
+                0 => {
+                    let block = slice(buf, pos, size)?;
+                    self.reserve(size, pos)?;
+                    self.out.extend_from_slice(block);
+                    pos += size;
+                }
+                1 => {
+                    let b = byte(buf, pos)?;
+                    self.reserve(size, pos)?;
+                    self.out.resize(self.out.len() + size, b);
+                    pos += 1;
+                }
+                2 => {
+                    let block = slice(buf, pos, size)?;
+                    self.decode_block(block, pos)?;
+                    pos += size;
+                }
+                _ => return Err(invalid(pos - 3, "Reserved block type")),
+            }
+            if last {
+                break;
+            }
+        }
+        if has_checksum {
+            slice(buf, pos, 4)?;
+            pos += 4;
+        }
+        Ok(pos)
+    }
+
+    fn decode_block(&mut self, block: &[u8], offset: usize) -> DecodeResult<()> {
+        let (literals, pos) = self.decode_literals(block, offset)?;
+        let sequences = &block[pos..];
+        let offset = offset + pos;
+
+        let mut pos = 0;
+        let count = match byte(sequences, pos)? {
+            0 => 0,
+            b @ 1..=127 => {
+                pos += 1;
+                usize::from(b)
+            }
+            b @ 128..=254 => {
+                pos += 2;
+                (usize::from(b - 128) << 8) + usize::from(byte(sequences, 1)?)
+            }
+            _ => {
+                pos += 3;
+                le_uint(sequences, 1, 2)? as usize + 0x7F00
+            }
+        };
+        if count == 0 {
+            self.reserve(literals.len(), offset)?;
+            self.out.extend_from_slice(&literals);
+            return Ok(());
+        }
+        let modes = byte(sequences, pos)?;
+        if modes & 0x03 != 0 {
+            return Err(invalid(offset + pos, "Reserved bits are set"));
+        }
+        pos += 1;
+        pos += FseTable::update(
+            &mut self.literal_lengths,
+            modes >> 6,
+            &LITERAL_LENGTH_CODE,
+            sequences,
+            pos,
+            offset,
+        )?;
+        pos += FseTable::update(
+            &mut self.offset_codes,
+            (modes >> 4) & 3,
+            &OFFSET_CODE,
+            sequences,
+            pos,
+            offset,
+        )?;
+        pos += FseTable::update(
+            &mut self.match_lengths,
+            (modes >> 2) & 3,
+            &MATCH_LENGTH_CODE,
+            sequences,
+            pos,
+            offset,
+        )?;
+        let (literal_lengths, offset_codes, match_lengths) = match (
+            self.literal_lengths.as_ref(),
+            self.offset_codes.as_ref(),
+            self.match_lengths.as_ref(),
+        ) {
+            (Some(l), Some(o), Some(m)) => (l, o, m),
+            _ => return Err(invalid(offset + pos, "No previous sequence table")),
+        };
+
+        let offset = offset + pos;
+        let mut bits = BackwardBits::new(&sequences[pos..], offset)?;
+        let mut literal_length_state = bits.read(literal_lengths.accuracy_log);
+        let mut offset_state = bits.read(offset_codes.accuracy_log);
+        let mut match_length_state = bits.read(match_lengths.accuracy_log);
+        let mut literals = &literals[..];
+        for i in 0..count {
+            let offset_code = offset_codes.symbols[offset_state as usize];
+            let literal_length_code = literal_lengths.symbols[literal_length_state as usize];
+            let match_length_code = match_lengths.symbols[match_length_state as usize];
+            if usize::from(offset_code) > MAX_OFFSET_CODE
+                || usize::from(literal_length_code) > MAX_LITERAL_LENGTH_CODE
+                || usize::from(match_length_code) > MAX_MATCH_LENGTH_CODE
+            {
+                return Err(invalid(offset, "Invalid sequence code"));
+            }
+            let offset_value = (1usize << offset_code) + bits.read(offset_code) as usize;
+            let match_length = match match_length_code as usize {
+                code @ 0..=31 => code + 3,
+                code => {
+                    let (base, extra_bits) = MATCH_LENGTHS[code - 32];
+                    (base + bits.read(extra_bits)) as usize
+                }
+            };
+            let literal_length = match literal_length_code as usize {
+                code @ 0..=15 => code,
+                code => {
+                    let (base, extra_bits) = LITERAL_LENGTHS[code - 16];
+                    (base + bits.read(extra_bits)) as usize
+                }
+            };
+            if i + 1 < count {
+                literal_length_state = literal_lengths.next_state(literal_length_state, &mut bits);
+                match_length_state = match_lengths.next_state(match_length_state, &mut bits);
+                offset_state = offset_codes.next_state(offset_state, &mut bits);
+            }
+
+            if literals.len() < literal_length {
+                return Err(invalid(offset, "Too long literal length"));
+            }
+            self.reserve(literal_length + match_length, offset)?;
+            self.out.extend_from_slice(&literals[..literal_length]);
+            literals = &literals[literal_length..];
+
+            let distance = resolve_offset(&mut self.offsets, offset_value, literal_length);
+            if distance == 0 || distance > self.out.len() {
+                return Err(invalid(offset, "Too far offset"));
+            }
+            let start = self.out.len() - distance;
+            for j in 0..match_length {
+                let b = self.out[start + j];
+                self.out.push(b);
+            }
+        }
+        if bits.offset != 0 {
+            return Err(invalid(offset, "Sequence bitstream is not fully consumed"));
+        }
+        self.reserve(literals.len(), offset)?;
+        self.out.extend_from_slice(literals);
+        Ok(())
+    }
+
+    /// Decodes the literals section at the beginning of `block`,
+    /// and returns the literals and the size of the section.
+    fn decode_literals(&mut self, block: &[u8], offset: usize) -> DecodeResult<(Vec<u8>, usize)> {
+        let b0 = byte(block, 0)?;
+        let block_type = b0 & 3;
+        let size_format = (b0 >> 2) & 3;
+        if block_type < 2 {
+            let (header_size, size) = match size_format {
+                0 | 2 => (1, usize::from(b0 >> 3)),
+                1 => (2, le_uint(block, 0, 2)? as usize >> 4),
+                _ => (3, le_uint(block, 0, 3)? as usize >> 4),
+            };
+            let literals = if block_type == 0 {
+                slice(block, header_size, size)?.to_owned()
+            } else {
+                vec![byte(block, header_size)?; size]
+            };
+            let section_size = header_size + if block_type == 0 { size } else { 1 };
+            return Ok((literals, section_size));
+        }
+
+        let (header_size, streams, field_bits) = match size_format {
+            0 => (3, 1, 10),
+            1 => (3, 4, 10),
+            2 => (4, 4, 14),
+            _ => (5, 4, 18),
+        };
+        let header = le_uint(block, 0, header_size)? >> 4;
+        let mask = (1 << field_bits) - 1;
+        let regenerated_size = (header & mask) as usize;
+        let compressed_size = ((header >> field_bits) & mask) as usize;
+        let data = slice(block, header_size, compressed_size)?;
+        let offset = offset + header_size;
+        if regenerated_size > self.max_size {
+            return Err(limit_exceeded(regenerated_size, self.max_size, offset));
+        }
+
+        let mut pos = 0;
+        if block_type == 2 {
+            let (table, size) = HuffmanTable::read(data, offset)?;
+            self.huffman = Some(table);
+            pos = size;
+        }
+        let table = match self.huffman {
+            Some(ref table) => table,
+            None => return Err(invalid(offset, "No previous Huffman table")),
+        };
+        let mut literals = Vec::with_capacity(regenerated_size);
+        if streams == 1 {
+            table.decode(&data[pos..], regenerated_size, offset + pos, &mut literals)?;
+        } else {
+            let jump_table = slice(data, pos, 6)?;
+            pos += 6;
+            let mut sizes = [0; 4];
+            for (i, size) in sizes.iter_mut().take(3).enumerate() {
+                *size = le_uint(jump_table, i * 2, 2)? as usize;
+            }
+            let total = sizes[0] + sizes[1] + sizes[2];
+            if data.len() < pos + total {
+                return Err(invalid(offset, "Invalid jump table"));
+            }
+            sizes[3] = data.len() - pos - total;
+            let stream_size = regenerated_size.div_ceil(4);
+            for (i, &size) in sizes.iter().enumerate() {
+                let count = if i < 3 {
+                    stream_size
+                } else {
+                    regenerated_size.saturating_sub(stream_size * 3)
+                };
+                table.decode(&data[pos..pos + size], count, offset + pos, &mut literals)?;
+                pos += size;
+            }
+            if literals.len() != regenerated_size {
+                return Err(invalid(offset, "Invalid regenerated size"));
+            }
+        }
+        Ok((literals, header_size + compressed_size))
+    }
+
+    fn reserve(&self, size: usize, offset: usize) -> DecodeResult<()> {
+        if self.out.len() + size > self.max_size {
+            return Err(limit_exceeded(self.out.len() + size, self.max_size, offset));
+        }
+        Ok(())
+    }
+}
+
+/// Converts an offset value to the distance of a match, updating the repeat offsets.
+fn resolve_offset(offsets: &mut [usize; 3], offset_value: usize, literal_length: usize) -> usize {
+    if offset_value > 3 {
+        let distance = offset_value - 3;
+        *offsets = [distance, offsets[0], offsets[1]];
+        return distance;
+    }
+    let mut index = offset_value - 1;
+    if literal_length == 0 {
+        index += 1;
+    }
+    if index == 0 {
+        return offsets[0];
+    }
+    let distance = if index == 3 {
+        offsets[0].wrapping_sub(1)
+    } else {
+        offsets[index]
+    };
+    if index > 1 {
+        offsets[2] = offsets[1];
+    }
+    offsets[1] = offsets[0];
+    offsets[0] = distance;
+    distance
+}
+
+/// A decoding table of the Huffman-coded literals.
+struct HuffmanTable {
+    max_bits: u8,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+}
+impl HuffmanTable {
+    /// Reads a Huffman tree description, and returns the table and the size of the description.
+    fn read(buf: &[u8], offset: usize) -> DecodeResult<(Self, usize)> {
+        let header = byte(buf, 0)?;
+        let (mut weights, size) = if header < 128 {
+            let data = slice(buf, 1, usize::from(header))?;
+            let max_symbol = MAX_HUFFMAN_BITS as usize;
+            let (table, table_size) = FseTable::read(data, 6, max_symbol, offset + 1)?;
+            let mut bits = BackwardBits::new(&data[table_size..], offset + 1 + table_size)?;
+            let mut state1 = bits.read(table.accuracy_log);
+            let mut state2 = bits.read(table.accuracy_log);
+            let mut weights = Vec::new();
+            loop {
+                weights.push(table.symbols[state1 as usize]);
+                state1 = table.next_state(state1, &mut bits);
+                if bits.offset < 0 {
+                    weights.push(table.symbols[state2 as usize]);
+                    break;
+                }
+                weights.push(table.symbols[state2 as usize]);
+                state2 = table.next_state(state2, &mut bits);
+                if bits.offset < 0 {
+                    weights.push(table.symbols[state1 as usize]);
+                    break;
+                }
+                if weights.len() > 255 {
+                    return Err(invalid(offset, "Too many Huffman weights"));
+                }
+            }
+            (weights, 1 + usize::from(header))
+        } else {
+            let count = usize::from(header) - 127;
+            let data = slice(buf, 1, count.div_ceil(2))?;
+            let weights = (0..count)
+                .map(|i| (data[i / 2] >> if i % 2 == 0 { 4 } else { 0 }) & 0x0F)
+                .collect::<Vec<_>>();
+            (weights, 1 + data.len())
+        };
+        if weights.len() > 255 || weights.iter().any(|&w| w > MAX_HUFFMAN_BITS) {
+            return Err(invalid(offset, "Invalid Huffman weights"));
+        }
+
+        // The weight of the last symbol is implied by the others
+        let weight_sum = weights
+            .iter()
+            .filter(|&&w| w > 0)
+            .map(|&w| 1u32 << (w - 1))
+            .sum::<u32>();
+        if weight_sum == 0 {
+            return Err(invalid(offset, "Invalid Huffman weights"));
+        }
+        let max_bits = highest_bit(weight_sum) + 1;
+        let left_over = (1 << max_bits) - weight_sum;
+        if max_bits > MAX_HUFFMAN_BITS || !left_over.is_power_of_two() {
+            return Err(invalid(offset, "Invalid Huffman weights"));
+        }
+        weights.push(highest_bit(left_over) + 1);
+
+        let lengths = weights
+            .iter()
+            .map(|&w| if w > 0 { max_bits + 1 - w } else { 0 })
+            .collect::<Vec<_>>();
+        let mut rank_counts = [0usize; MAX_HUFFMAN_BITS as usize + 1];
+        for &length in &lengths {
+            rank_counts[length as usize] += 1;
+        }
+        let table_size = 1 << max_bits;
+        let mut rank_starts = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for length in (1..=max_bits as usize).rev() {
+            let count = rank_counts[length] << (max_bits as usize - length);
+            rank_starts[length - 1] = rank_starts[length] + count;
+        }
+        let mut symbols = vec![0; table_size];
+        let mut bits = vec![0; table_size];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let start = rank_starts[length as usize];
+            let end = start + (1 << (max_bits - length));
+            for i in start..end {
+                symbols[i] = symbol as u8;
+                bits[i] = length;
+            }
+            rank_starts[length as usize] = end;
+        }
+        Ok((HuffmanTable { max_bits, symbols, bits }, size))
+    }
+
+    /// Decodes `count` literals from a Huffman-coded stream.
+    fn decode(
+        &self,
+        buf: &[u8],
+        count: usize,
+        offset: usize,
+        out: &mut Vec<u8>,
+    ) -> DecodeResult<()> {
+        let mut bits = BackwardBits::new(buf, offset)?;
+        let mask = (1 << self.max_bits) - 1;
+        let mut state = bits.read(self.max_bits) as usize;
+        for _ in 0..count {
+            out.push(self.symbols[state]);
+            let length = self.bits[state];
+            state = ((state << length) + bits.read(length) as usize) & mask;
+        }
+        if bits.offset != -isize::from(self.max_bits) {
+            return Err(invalid(offset, "Literal stream is not fully consumed"));
+        }
+        Ok(())
+    }
+}
+
+/// A decoding table of a finite state entropy (FSE) code.
+struct FseTable {
+    accuracy_log: u8,
+    symbols: Vec<u8>,
+    bits: Vec<u8>,
+    bases: Vec<u32>,
+}
+impl FseTable {
+    /// Updates `table` according to a compression `mode` of the sequences section,
+    /// and returns the number of the bytes read from `buf[pos..]`.
+    fn update(
+        table: &mut Option<Self>,
+        mode: u8,
+        code: &SequenceCode,
+        buf: &[u8],
+        pos: usize,
+        offset: usize,
+    ) -> DecodeResult<usize> {
+        match mode {
+            0 => {
+                let accuracy_log = code.default_accuracy_log;
+                let new_table = FseTable::new(code.default, accuracy_log, offset + pos)?;
+                *table = Some(new_table);
+                Ok(0)
+            }
+            1 => {
+                let symbol = byte(buf, pos)?;
+                *table = Some(FseTable {
+                    accuracy_log: 0,
+                    symbols: vec![symbol],
+                    bits: vec![0],
+                    bases: vec![0],
+                });
+                Ok(1)
+            }
+            2 => {
+                let max_symbol = code.default.len() - 1;
+                let (new_table, size) =
+                    FseTable::read(&buf[pos..], code.max_accuracy_log, max_symbol, offset + pos)?;
+                *table = Some(new_table);
+                Ok(size)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    /// Reads an FSE table description, and returns the table and the size of the description.
+    fn read(
+        buf: &[u8],
+        max_accuracy_log: u8,
+        max_symbol: usize,
+        offset: usize,
+    ) -> DecodeResult<(Self, usize)> {
+        let mut bits = ForwardBits { buf, offset: 0 };
+        let accuracy_log = bits.read(4, offset)? as u8 + 5;
+        if accuracy_log > max_accuracy_log {
+            return Err(invalid(offset, "Too large accuracy log"));
+        }
+        let mut remaining = 1i32 << accuracy_log;
+        let mut probabilities = Vec::new();
+        while remaining > 0 && probabilities.len() <= max_symbol {
+            let count = highest_bit(remaining as u32 + 1) + 1;
+            let mut value = bits.read(count, offset)? as i32;
+            let lower_mask = (1 << (count - 1)) - 1;
+            let threshold = (1 << count) - 1 - (remaining + 1);
+            if (value & lower_mask) < threshold {
+                bits.offset -= 1;
+                value &= lower_mask;
+            } else if value > lower_mask {
+                value -= threshold;
+            }
+            let probability = value - 1;
+            remaining -= probability.abs();
+            probabilities.push(probability as i16);
+            if probability == 0 {
+                loop {
+                    let repeat = bits.read(2, offset)?;
+                    let len = probabilities.len() + repeat as usize;
+                    probabilities.resize(len, 0);
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 0 || probabilities.len() > max_symbol + 1 {
+            return Err(invalid(offset, "Invalid FSE table description"));
+        }
+        let table = FseTable::new(&probabilities, accuracy_log, offset)?;
+        Ok((table, bits.offset.div_ceil(8)))
+    }
+
+    fn new(probabilities: &[i16], accuracy_log: u8, offset: usize) -> DecodeResult<Self> {
+        let size = 1usize << accuracy_log;
+        let mut symbols = vec![0; size];
+        let mut next = vec![0u32; probabilities.len()];
+
+        // Symbols with a "less than 1" probability are placed at the end of the table
+        let mut high_threshold = size;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            if probability == -1 {
+                if high_threshold == 0 {
+                    return Err(invalid(offset, "Invalid FSE probabilities"));
+                }
+                high_threshold -= 1;
+                symbols[high_threshold] = symbol as u8;
+                next[symbol] = 1;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mask = size - 1;
+        let mut pos = 0;
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            if probability <= 0 {
+                continue;
+            }
+            next[symbol] = probability as u32;
+            for _ in 0..probability {
+                symbols[pos] = symbol as u8;
+                pos = (pos + step) & mask;
+                while pos >= high_threshold {
+                    pos = (pos + step) & mask;
+                }
+            }
+        }
+        if pos != 0 {
+            return Err(invalid(offset, "Invalid FSE probabilities"));
+        }
+
+        let mut bits = vec![0; size];
+        let mut bases = vec![0; size];
+        for i in 0..size {
+            let state = next[symbols[i] as usize];
+            next[symbols[i] as usize] += 1;
+            bits[i] = accuracy_log - highest_bit(state);
+            bases[i] = (state << bits[i]) - size as u32;
+        }
+        Ok(FseTable {
+            accuracy_log,
+            symbols,
+            bits,
+            bases,
+        })
+    }
+
+    fn next_state(&self, state: u32, bits: &mut BackwardBits) -> u32 {
+        let i = state as usize;
+        self.bases[i] + bits.read(self.bits[i])
+    }
+}
+
+/// A reader of a bitstream which is read from its end (the highest bit of its last byte
+/// below the padding marker) to its beginning.
+///
+/// Reading past the beginning yields zeros and makes `offset` negative.
+struct BackwardBits<'a> {
+    buf: &'a [u8],
+    offset: isize,
+}
+impl<'a> BackwardBits<'a> {
+    fn new(buf: &'a [u8], offset: usize) -> DecodeResult<Self> {
+        match buf.last() {
+            Some(&last) if last != 0 => Ok(BackwardBits {
+                buf,
+                offset: (buf.len() as isize - 1) * 8 + isize::from(highest_bit(u32::from(last))),
+            }),
+            _ => Err(invalid(offset, "Invalid bitstream padding")),
+        }
+    }
+
+    fn read(&mut self, count: u8) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        self.offset -= isize::from(count);
+        if self.offset >= 0 {
+            read_bits(self.buf, self.offset as usize, count)
+        } else if self.offset + isize::from(count) > 0 {
+            let available = (self.offset + isize::from(count)) as u8;
+            read_bits(self.buf, 0, available) << (-self.offset) as u32
+        } else {
+            0
+        }
+    }
+}
+
+/// A reader of a bitstream from its beginning (the lowest bit of its first byte).
+struct ForwardBits<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+impl<'a> ForwardBits<'a> {
+    fn read(&mut self, count: u8, offset: usize) -> DecodeResult<u32> {
+        if self.buf.len() * 8 < self.offset + usize::from(count) {
+            return Err(DecodeError::Truncated {
+                offset: offset + self.buf.len(),
+            });
+        }
+        let value = read_bits(self.buf, self.offset, count);
+        self.offset += usize::from(count);
+        Ok(value)
+    }
+}
+
+/// Reads `count` (at most 32) bits at the bit offset `start` in little-endian order.
+fn read_bits(buf: &[u8], start: usize, count: u8) -> u32 {
+    let mut word = 0u64;
+    for (i, &b) in buf[start / 8..].iter().take(8).enumerate() {
+        word |= u64::from(b) << (i * 8);
+    }
+    ((word >> (start % 8)) & ((1u64 << count) - 1)) as u32
+}
+
+fn highest_bit(value: u32) -> u8 {
+    31 - value.leading_zeros() as u8
+}
+
+fn byte(buf: &[u8], pos: usize) -> DecodeResult<u8> {
+    buf.get(pos)
+        .cloned()
+        .ok_or(DecodeError::Truncated { offset: buf.len() })
+}
+
+fn slice(buf: &[u8], pos: usize, size: usize) -> DecodeResult<&[u8]> {
+    buf.get(pos..pos + size)
+        .ok_or(DecodeError::Truncated { offset: buf.len() })
+}
+
+fn le_uint(buf: &[u8], pos: usize, size: usize) -> DecodeResult<u64> {
+    let bytes = slice(buf, pos, size)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &b| value << 8 | u64::from(b)))
+}
+
+fn le_u32(buf: &[u8], pos: usize) -> DecodeResult<u32> {
+    le_uint(buf, pos, 4).map(|value| value as u32)
+}
+
+fn limit_exceeded(size: usize, max_size: usize, offset: usize) -> DecodeError {
+    DecodeError::LimitExceeded {
+        limit: "max_body_size",
+        offset,
+        size: size as u64,
+        max: max_size,
+    }
+}
+
+fn invalid(offset: usize, reason: &str) -> DecodeError {
+    DecodeError::InvalidValue {
+        offset,
+        reason: reason.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &[u8] = b"hello hello hello world";
+
+    /// `TEXT` compressed by `zstd -19` (a compressed block with raw literals).
+    const TEXT_FRAME: [u8; 30] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0x8d, 0x00, 0x00, 0x58, 0x68, 0x65, 0x6c, 0x6c, 0x6f,
+        0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x01, 0x00, 0xe1, 0x4a, 0x11, 0x29, 0x57, 0x97, 0x6a,
+    ];
+
+    /// `bytes()` compressed by `zstd -1` (a raw block).
+    const RAW_FRAME: [u8; 42] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x48, 0xe9, 0x00, 0x00, 0x00, 0x07, 0x0e, 0x15, 0x1c, 0x23,
+        0x2a, 0x31, 0x38, 0x3f, 0x46, 0x4d, 0x54, 0x5b, 0x62, 0x69, 0x70, 0x77, 0x7e, 0x85, 0x8c,
+        0x93, 0x9a, 0xa1, 0xa8, 0xaf, 0xb6, 0xbd, 0xc4, 0xe8, 0x89, 0x12, 0x0b,
+    ];
+
+    /// 100 `a`s in an RLE block, with the content size in a byte.
+    const RLE_FRAME: [u8; 10] = [0x28, 0xb5, 0x2f, 0xfd, 0x20, 100, 0x23, 0x03, 0x00, b'a'];
+
+    /// `pattern()` compressed by `zstd -19` (a compressed block with Huffman-coded literals
+    /// and FSE-coded sequences).
+    const PATTERN_FRAME: [u8; 114] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x68, 0x2d, 0x03, 0x00, 0x52, 0x44, 0x0d, 0x0f, 0xb0, 0x79,
+        0x64, 0x89, 0x66, 0x7b, 0x58, 0x25, 0x4b, 0x76, 0x93, 0x3d, 0x3b, 0x06, 0xc0, 0xfa, 0xde,
+        0xbc, 0x9a, 0x8a, 0x7a, 0x0a, 0xde, 0x9b, 0x57, 0x33, 0x31, 0x2f, 0xc1, 0x7b, 0xf3, 0x6a,
+        0x22, 0xe2, 0x21, 0x78, 0x6f, 0x5e, 0xcd, 0x44, 0xb4, 0x70, 0x46, 0x86, 0x48, 0xcb, 0xca,
+        0xb1, 0x33, 0x23, 0x90, 0x0a, 0x2a, 0xa8, 0x11, 0x10, 0xbf, 0xfe, 0x1b, 0xc0, 0xa3, 0x9c,
+        0x03, 0x11, 0x34, 0x04, 0x10, 0x21, 0x22, 0x38, 0x82, 0xd9, 0x07, 0x5b, 0xe2, 0x8f, 0xd5,
+        0xaa, 0x1a, 0xf4, 0x06, 0x85, 0xfe, 0x7c, 0x5e, 0xd4, 0x9f, 0xa8, 0x72, 0x1a, 0x0f, 0x2b,
+        0x22, 0x37, 0xa0, 0xa9, 0x01, 0x84, 0xa9, 0x6f, 0x75,
+    ];
+
+    /// `text(300_000)` compressed by `zstd -19` (three blocks, whose matches cross them).
+    const BLOCKS_FRAME: [u8; 124] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0xa4, 0xe0, 0x93, 0x04, 0x00, 0xa4, 0x02, 0x00, 0x32, 0x03, 0x0b,
+        0x0f, 0xb0, 0x79, 0x64, 0x89, 0x66, 0x7b, 0x58, 0x25, 0x4b, 0x76, 0x93, 0x3d, 0x3b, 0x06,
+        0xc0, 0x06, 0xde, 0x9b, 0x57, 0x13, 0x0f, 0xbc, 0x37, 0xaf, 0x26, 0x1e, 0x78, 0x6f, 0x5e,
+        0xcd, 0x44, 0xb4, 0x70, 0x46, 0x86, 0x48, 0xcb, 0xca, 0xb1, 0x33, 0x23, 0x90, 0x0a, 0x21,
+        0xa8, 0x11, 0xf0, 0xeb, 0xdf, 0x01, 0xe0, 0xa5, 0xe6, 0x11, 0x34, 0x04, 0x14, 0xa1, 0x22,
+        0x30, 0xc2, 0xf5, 0xfd, 0xff, 0xff, 0x0e, 0xc3, 0xfd, 0x3d, 0x6a, 0x3d, 0x8d, 0x39, 0xdd,
+        0x09, 0x22, 0x60, 0x03, 0x76, 0x15, 0x54, 0x00, 0x00, 0x00, 0x01, 0x00, 0xfd, 0xff, 0xe5,
+        0xfd, 0xb9, 0x06, 0x02, 0x45, 0x00, 0x00, 0x00, 0x01, 0x00, 0xdd, 0x13, 0x1d, 0x00, 0x01,
+        0x70, 0x01, 0x82, 0x71,
+    ];
+
+    /// `window()` compressed by `zstd -19 --zstd=wlog=10 --no-content-size` (a frame with
+    /// a 1 KiB window and a checksum, whose matches are as far as the window).
+    const WINDOW_FRAME: [u8; 322] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x04, 0x00, 0xac, 0x08, 0x00, 0x0a, 0x40, 0x40, 0x04, 0x05, 0xe0,
+        0x0f, 0x69, 0xb0, 0x0e, 0x41, 0x00, 0x41, 0x00, 0x41, 0x00, 0xd6, 0xac, 0x3c, 0xf0, 0x6d,
+        0xe0, 0xd2, 0x33, 0x98, 0xbc, 0xe0, 0x5a, 0xcc, 0xe9, 0x64, 0x17, 0xed, 0xf4, 0x81, 0x0f,
+        0x7d, 0x0a, 0xba, 0xf6, 0x55, 0xd4, 0xdf, 0xb1, 0x75, 0x0c, 0x81, 0x96, 0x6f, 0x29, 0x21,
+        0x66, 0xac, 0xb9, 0xb9, 0xb1, 0x4d, 0x11, 0x3d, 0xe4, 0x16, 0x8a, 0x6a, 0xb1, 0xfe, 0xb5,
+        0x3a, 0xa4, 0xe7, 0x07, 0x94, 0xaf, 0x81, 0xed, 0xaf, 0x76, 0x07, 0x27, 0xfb, 0xa7, 0x01,
+        0x05, 0x33, 0x53, 0x9d, 0x70, 0x28, 0xe9, 0x85, 0x90, 0x5c, 0x9c, 0xcc, 0x6b, 0x69, 0xcb,
+        0x22, 0x71, 0x5d, 0xf9, 0xfd, 0x95, 0x57, 0xd2, 0x8d, 0xa1, 0xc5, 0xdf, 0x3c, 0x66, 0x11,
+        0xaa, 0x36, 0x34, 0x12, 0xfa, 0x79, 0x19, 0x1a, 0x26, 0xac, 0xde, 0x9b, 0x43, 0xf4, 0x5c,
+        0xf4, 0x50, 0x25, 0xdb, 0xf3, 0xc4, 0x01, 0xb6, 0xf9, 0x56, 0xe8, 0x27, 0x77, 0x06, 0x9b,
+        0x5e, 0x93, 0x76, 0xac, 0x01, 0x34, 0x57, 0x42, 0x4b, 0x94, 0x23, 0xe1, 0x56, 0x4b, 0x78,
+        0x4b, 0x37, 0x0b, 0x2e, 0x9b, 0x7c, 0xf1, 0xc1, 0x7d, 0xec, 0xcd, 0xdc, 0x2e, 0x63, 0xbd,
+        0x36, 0xd8, 0xeb, 0x1a, 0x2a, 0x4e, 0x55, 0x8d, 0xdf, 0x77, 0xbd, 0x97, 0xa8, 0x44, 0x98,
+        0x3c, 0x1c, 0x94, 0x84, 0x52, 0xde, 0x5a, 0xd9, 0xf5, 0xf1, 0xd6, 0x99, 0x74, 0xd8, 0x09,
+        0x29, 0x9a, 0x0e, 0xac, 0xbc, 0xe9, 0x0e, 0xb5, 0x76, 0x01, 0x73, 0xa6, 0x65, 0x29, 0xab,
+        0x5b, 0xb4, 0xd8, 0x02, 0x54, 0x27, 0x6d, 0xe7, 0xee, 0x2b, 0x4a, 0x75, 0xaa, 0xa5, 0xe2,
+        0x65, 0x28, 0x0a, 0x0a, 0xce, 0x67, 0xd8, 0x62, 0x0b, 0xcf, 0x23, 0xf4, 0x66, 0x89, 0x0c,
+        0xf4, 0x00, 0x46, 0xf5, 0x80, 0x51, 0x42, 0xd9, 0x50, 0x9b, 0x38, 0x43, 0x4e, 0xaf, 0xf0,
+        0xb0, 0x32, 0x06, 0x8f, 0xcf, 0x55, 0x40, 0xd5, 0x06, 0x9d, 0x33, 0x8d, 0xf3, 0x7f, 0x01,
+        0x00, 0x44, 0x00, 0x00, 0x00, 0x01, 0x00, 0xfd, 0x07, 0xa8, 0x32, 0x10, 0x44, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0xfd, 0x07, 0xa8, 0x32, 0x10, 0x3d, 0x00, 0x00, 0x00, 0x01, 0x00, 0xfd,
+        0x2b, 0x00, 0x04, 0x96, 0xa9, 0xb7, 0x8a,
+    ];
+
+    /// An empty content compressed by `zstd --no-check`.
+    const EMPTY_FRAME: [u8; 9] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x20, 0x00, 0x01, 0x00, 0x00,
+    ];
+
+    /// `text(255)` compressed by `zstd -19 --no-check`.
+    const TEXT_255_FRAME: [u8; 71] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x20, 0xff, 0xf5, 0x01, 0x00, 0x14, 0x02, 0x73, 0x70, 0x61, 0x6e,
+        0x20, 0x30, 0x20, 0x6f, 0x66, 0x20, 0x74, 0x72, 0x61, 0x63, 0x65, 0x20, 0x30, 0x3b, 0x20,
+        0x31, 0x31, 0x32, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0x31, 0x32, 0x10,
+        0xa8, 0x30, 0x7a, 0xff, 0x0e, 0xb0, 0xe3, 0x16, 0x03, 0x10, 0x1a, 0x81, 0x12, 0x95, 0x89,
+        0xfd, 0xc1, 0xc8, 0x08, 0x72, 0xec, 0x8d, 0xca, 0x0d, 0x34, 0x19,
+    ];
+
+    /// `text(256)` compressed by `zstd -19 --no-check`.
+    const TEXT_256_FRAME: [u8; 72] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x60, 0x00, 0x00, 0xf5, 0x01, 0x00, 0x14, 0x02, 0x73, 0x70, 0x61,
+        0x6e, 0x20, 0x30, 0x20, 0x6f, 0x66, 0x20, 0x74, 0x72, 0x61, 0x63, 0x65, 0x20, 0x30, 0x3b,
+        0x20, 0x31, 0x31, 0x32, 0x32, 0x33, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x30, 0x31, 0x32,
+        0x10, 0xa8, 0x30, 0x7a, 0xff, 0x0e, 0xb0, 0xe3, 0x16, 0x03, 0x10, 0x1a, 0x81, 0x12, 0x95,
+        0x89, 0xfd, 0x41, 0xcb, 0x08, 0x72, 0xec, 0x8d, 0xca, 0x0d, 0x34, 0x19,
+    ];
+
+    /// `text(65791)` compressed by `zstd -19 --no-check`.
+    const TEXT_65791_FRAME: [u8; 94] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x60, 0xff, 0xff, 0xa5, 0x02, 0x00, 0x32, 0x03, 0x0b, 0x0f, 0xb0,
+        0x79, 0x64, 0x89, 0x66, 0x7b, 0x58, 0x25, 0x4b, 0x76, 0x93, 0x3d, 0x3b, 0x06, 0xc0, 0x06,
+        0xde, 0x9b, 0x57, 0x13, 0x0f, 0xbc, 0x37, 0xaf, 0x26, 0x1e, 0x78, 0x6f, 0x5e, 0xcd, 0x44,
+        0xb4, 0x70, 0x46, 0x86, 0x48, 0xcb, 0xca, 0xb1, 0x33, 0x23, 0x90, 0x0a, 0x21, 0xa8, 0x11,
+        0xf0, 0xeb, 0xdf, 0x01, 0xe0, 0xa5, 0xe6, 0x11, 0x34, 0x04, 0x14, 0xa1, 0x22, 0x30, 0xc2,
+        0xf5, 0xfd, 0xff, 0xff, 0x0d, 0xc2, 0xfe, 0x1e, 0xb5, 0x9e, 0xc6, 0x9c, 0xee, 0x04, 0x11,
+        0xb0, 0x01, 0xbb, 0x0a,
+    ];
+
+    /// `text(65792)` compressed by `zstd -19 --no-check`.
+    const TEXT_65792_FRAME: [u8; 96] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0xa0, 0x00, 0x01, 0x01, 0x00, 0xa5, 0x02, 0x00, 0x32, 0x03, 0x0b,
+        0x0f, 0xb0, 0x79, 0x64, 0x89, 0x66, 0x7b, 0x58, 0x25, 0x4b, 0x76, 0x93, 0x3d, 0x3b, 0x06,
+        0xc0, 0x06, 0xde, 0x9b, 0x57, 0x13, 0x0f, 0xbc, 0x37, 0xaf, 0x26, 0x1e, 0x78, 0x6f, 0x5e,
+        0xcd, 0x44, 0xb4, 0x70, 0x46, 0x86, 0x48, 0xcb, 0xca, 0xb1, 0x33, 0x23, 0x90, 0x0a, 0x21,
+        0xa8, 0x11, 0xf0, 0xeb, 0xdf, 0x01, 0xe0, 0xa5, 0xe6, 0x11, 0x34, 0x04, 0x14, 0xa1, 0x22,
+        0x30, 0xc2, 0xf5, 0xfd, 0xff, 0xff, 0x0d, 0xc3, 0xfe, 0x1e, 0xb5, 0x9e, 0xc6, 0x9c, 0xee,
+        0x04, 0x11, 0xb0, 0x01, 0xbb, 0x0a,
+    ];
+
+    fn bytes() -> Vec<u8> {
+        (0..200).step_by(7).map(|b| b as u8).collect()
+    }
+
+    fn pattern() -> Vec<u8> {
+        (0..40)
+            .flat_map(|i| format!("span {} of trace {}; ", i, i % 3).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn decompress_works() {
+        assert_eq!(decompress(&TEXT_FRAME, 1024).unwrap(), TEXT);
+        assert_eq!(decompress(&RAW_FRAME, 1024).unwrap(), bytes());
+        assert_eq!(decompress(&RLE_FRAME, 1024).unwrap(), vec![b'a'; 100]);
+        assert_eq!(decompress(&PATTERN_FRAME, 1024).unwrap(), pattern());
+
+        // Concatenated frames, and a skippable frame between them
+        let mut buf = TEXT_FRAME.to_vec();
+        buf.extend_from_slice(&[0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+        buf.extend_from_slice(&PATTERN_FRAME);
+        assert_eq!(decompress(&buf, 1024).unwrap(), [TEXT, &pattern()].concat());
+    }
+
+    fn text(len: usize) -> Vec<u8> {
+        (0..)
+            .flat_map(|i| format!("span {} of trace {}; ", i % 10, i % 3).into_bytes())
+            .take(len)
+            .collect()
+    }
+
+    /// 1 KiB of pseudo-random letters, repeated four times.
+    fn window() -> Vec<u8> {
+        let mut state = 1u32;
+        let letters = (0..1024)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                b'a' + ((state >> 16) % 4) as u8
+            })
+            .collect::<Vec<_>>();
+        letters.repeat(4)
+    }
+
+    #[test]
+    fn boundaries_work() {
+        assert_eq!(decompress(&BLOCKS_FRAME, 300_000).unwrap(), text(300_000));
+        assert_eq!(decompress(&WINDOW_FRAME, 4096).unwrap(), window());
+
+        // The content size fields of each size
+        assert_eq!(decompress(&EMPTY_FRAME, 0).unwrap(), b"");
+        assert_eq!(decompress(&TEXT_255_FRAME, 255).unwrap(), text(255));
+        assert_eq!(decompress(&TEXT_256_FRAME, 256).unwrap(), text(256));
+        assert_eq!(decompress(&TEXT_65791_FRAME, 65791).unwrap(), text(65791));
+        assert_eq!(decompress(&TEXT_65792_FRAME, 65792).unwrap(), text(65792));
+
+        // Each frame is decoded on its own, and a truncated one is rejected
+        let frames: [&[u8]; 5] = [
+            &EMPTY_FRAME,
+            &TEXT_256_FRAME,
+            &BLOCKS_FRAME,
+            &WINDOW_FRAME,
+            &TEXT_65792_FRAME,
+        ];
+        let contents = [Vec::new(), text(256), text(300_000), window(), text(65792)];
+        let buf = frames.concat();
+        let mut end = 0;
+        for (i, frame) in frames.iter().enumerate() {
+            end += frame.len();
+            let content = contents[..=i].concat();
+            assert_eq!(decompress(&buf[..end], content.len()).unwrap(), content);
+            assert!(decompress(&buf[..end - 1], content.len()).is_err(), "frame={}", i);
+        }
+    }
+
+    #[test]
+    fn corrupt_input_is_rejected() {
+        let mut buf = TEXT_FRAME;
+        buf[0] ^= 1; // The magic number
+        assert!(matches!(decompress(&buf, 1024), Err(DecodeError::InvalidValue { .. })));
+
+        let mut buf = RLE_FRAME;
+        buf[6] |= 0x06; // The reserved block type
+        assert!(decompress(&buf, 1024).is_err());
+
+        for len in 1..PATTERN_FRAME.len() {
+            assert!(decompress(&PATTERN_FRAME[..len], 1024).is_err(), "len={}", len);
+        }
+
+        // Flipping any bit never panics
+        for i in 0..PATTERN_FRAME.len() * 8 {
+            let mut buf = PATTERN_FRAME;
+            buf[i / 8] ^= 1 << (i % 8);
+            let _ = decompress(&buf, 1024);
+        }
+    }
+
+    #[test]
+    fn max_size_is_enforced() {
+        assert!(decompress(&RLE_FRAME, 100).is_ok());
+        assert!(matches!(decompress(&RLE_FRAME, 99), Err(DecodeError::LimitExceeded { .. })));
+
+        let size = pattern().len();
+        assert!(decompress(&PATTERN_FRAME, size).is_ok());
+        assert!(matches!(
+            decompress(&PATTERN_FRAME, size - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+
+        // The limit is enforced within a block following others
+        assert!(matches!(
+            decompress(&BLOCKS_FRAME, 300_000 - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+
+        // The limit applies to all the frames in total
+        let frames = [&TEXT_FRAME[..], &TEXT_FRAME[..]].concat();
+        assert!(decompress(&frames, TEXT.len() * 2).is_ok());
+        assert!(matches!(
+            decompress(&frames, TEXT.len() * 2 - 1),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,173 @@
+extern crate jaegercat;
+extern crate serde_json;
+
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use jaegercat::emit::{Emitter, SpanBuilder};
+use jaegercat::handler::{BatchFormatter, BatchHandler, Format, Formatter};
+use jaegercat::hook::DecodeHook;
+use jaegercat::model::Tag;
+use jaegercat::protocol::CollectorThrift;
+use jaegercat::server::{ReceiveMeta, Server, ServerBuilder, MAX_TCP_CONNECTIONS};
+use jaegercat::thrift::{EmitBatchNotification, Protocol};
+use serde_json::Value;
+
+/// Starts a server on ephemeral ports of the loopback address, which has the compact, binary and
+/// collector HTTP listeners in this order.
+fn start<H: BatchHandler + Send + 'static>(builder: &mut ServerBuilder, handler: H) -> Server {
+    builder
+        .bind_address([127, 0, 0, 1].into())
+        .compact_thrift_port(0)
+        .binary_thrift_port(0)
+        .http_endpoint(0, "/api/traces", CollectorThrift);
+    builder.build(handler).unwrap()
+}
+
+/// Returns the address of the listener of `protocol`.
+fn listener_addr(server: &Server, protocol: Protocol) -> SocketAddr {
+    match protocol {
+        Protocol::Compact => server.local_addrs()[0],
+        Protocol::Binary => server.local_addrs()[1],
+    }
+}
+
+fn collector_addr(server: &Server) -> SocketAddr {
+    server.local_addrs()[2]
+}
+
+#[derive(Debug, Default)]
+struct PanicOnce(AtomicBool);
+impl DecodeHook for PanicOnce {
+    fn on_decoded(&self, _bytes: usize, _spans: usize) {
+        if !self.0.swap(true, Ordering::SeqCst) {
+            panic!("Panicked on purpose");
+        }
+    }
+}
+
+#[test]
+fn decode_worker_survives_panic() {
+    let (tx, rx) = mpsc::channel();
+    let mut builder = ServerBuilder::new();
+    builder.decode_hook(PanicOnce::default());
+    let handler = move |batch: &EmitBatchNotification, _: &ReceiveMeta| {
+        let _ = tx.send(batch.clone());
+    };
+    let server = start(&mut builder, handler);
+
+    let addr = listener_addr(&server, Protocol::Compact);
+    let emitter = Emitter::new(addr, Protocol::Compact, "foo").unwrap();
+    emitter.emit(vec![SpanBuilder::new("first").build()]).unwrap();
+    emitter.emit(vec![SpanBuilder::new("second").build()]).unwrap();
+
+    // The first batch is dropped, and the (only) worker goes on with the second one
+    let batch = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert_eq!(batch.batch.spans[0].operation_name, "second");
+}
+
+#[test]
+fn server_outputs_batches_of_every_listener() {
+    let (tx, rx) = mpsc::channel();
+    let mut formatter = Formatter::new(Format::Json);
+    formatter.include_peer(true);
+    let handler = move |batch: &EmitBatchNotification, meta: &ReceiveMeta| {
+        let mut out = Vec::new();
+        formatter.format(batch, meta, &mut out).unwrap();
+        let _ = tx.send(String::from_utf8(out).unwrap());
+    };
+    let server = start(&mut ServerBuilder::new(), handler);
+
+    let udp = |protocol| Emitter::new(listener_addr(&server, protocol), protocol, "foo").unwrap();
+    let emitters = vec![
+        ("compact", udp(Protocol::Compact)),
+        ("binary", udp(Protocol::Binary)),
+        ("collector-thrift", Emitter::http(collector_addr(&server), "foo")),
+    ];
+    for (protocol, mut emitter) in emitters {
+        emitter.process_tag("hostname", "localhost");
+        let parent = SpanBuilder::new("parent").duration(2000).build();
+        let child = SpanBuilder::new("child")
+            .child_of(&parent)
+            .tag("retry", 3)
+            .log(parent.start_time + 1, vec![Tag::new("event", "error")])
+            .build();
+        emitter.emit(vec![parent.clone(), child]).unwrap();
+
+        let output = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        let output: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(output["peer"]["protocol"], protocol);
+
+        let batch = &output["emit_batch"];
+        assert_eq!(batch["process"]["service_name"], "foo");
+        assert_eq!(batch["process"]["tags"]["hostname"], "localhost");
+        assert_eq!(batch["spans"][0]["operation_name"], "parent");
+        assert_eq!(batch["spans"][0]["duration"], 0.002);
+
+        let child = &batch["spans"][1];
+        assert_eq!(child["operation_name"], "child");
+        assert_eq!(child["trace_id"], format!("{}", parent.trace_id));
+        assert_eq!(child["parent_span_id"], format!("{}", parent.span_id));
+        assert_eq!(child["tags"]["retry"], 3);
+        assert_eq!(child["logs"][0]["fields"]["event"], "error");
+    }
+}
+
+/// Returns whether the server closes `stream` (instead of waiting for a frame on it).
+fn is_closed(mut stream: TcpStream) -> bool {
+    stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Ok(_) => panic!("Unexpected data"),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => false,
+        Err(_) => true,
+    }
+}
+
+#[test]
+fn tcp_connections_are_limited() {
+    let mut builder = ServerBuilder::new();
+    builder.tcp_listener(0, Protocol::Compact);
+    let server = start(&mut builder, |_: &EmitBatchNotification, _: &ReceiveMeta| {});
+    let addr = server.local_addrs()[2];
+
+    let connections = (0..MAX_TCP_CONNECTIONS)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect::<Vec<_>>();
+    assert!(is_closed(TcpStream::connect(addr).unwrap()));
+
+    // The connections are counted until they are closed
+    drop(connections);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while is_closed(TcpStream::connect(addr).unwrap()) {
+        assert!(Instant::now() < deadline);
+    }
+}
+
+#[test]
+fn http_connections_are_limited() {
+    let server = start(&mut ServerBuilder::new(), |_: &EmitBatchNotification, _: &ReceiveMeta| {});
+    let addr = collector_addr(&server);
+
+    let _connections = (0..MAX_TCP_CONNECTIONS)
+        .map(|_| TcpStream::connect(addr).unwrap())
+        .collect::<Vec<_>>();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+}
+
+#[test]
+fn idle_http_connections_are_closed() {
+    let mut builder = ServerBuilder::new();
+    builder.http_timeout(Duration::from_millis(100));
+    let server = start(&mut builder, |_: &EmitBatchNotification, _: &ReceiveMeta| {});
+
+    let mut stream = TcpStream::connect(collector_addr(&server)).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+}